@@ -6,6 +6,8 @@ mkdir dist
 cp -r assets dist/
 cp -r pkg dist/
 cp index.html dist/
+cp sw.js dist/
+cp manifest.webmanifest dist/
 curl -L -o butler.zip https://broth.itch.ovh/butler/windows-amd64/LATEST/archive/default
 unzip butler.zip
 chmod +x butler.exe
@@ -0,0 +1,159 @@
+//! Keeping finished games' logs on disk for a while, so players can download them later
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::meta_net::GameID;
+use crate::unix_time;
+
+/// How long replays are kept when `REPLAY_RETENTION_DAYS` isn't set
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Seconds in a day
+const DAY: u64 = 60 * 60 * 24;
+
+/// A directory of replays, one file per game, named for the game's ID, the day it ended, and a secret
+///
+/// Everything here that touches the disk blocks, so it belongs off the server's thread.
+#[derive(Clone)]
+pub struct ReplayArchive {
+    dir: PathBuf,
+    retention: Duration,
+}
+
+impl ReplayArchive {
+    /// Reads the directory to keep replays in from `REPLAY_DIR`, if it's set, and how many days to
+    /// keep them for from `REPLAY_RETENTION_DAYS`
+    pub fn from_env() -> Option<ReplayArchive> {
+        let dir = std::env::var("REPLAY_DIR").ok().filter(|dir| !dir.is_empty())?;
+        let days = match std::env::var("REPLAY_RETENTION_DAYS") {
+            Ok(days) => days.parse().unwrap_or_else(|_| {
+                eprintln!("Bad REPLAY_RETENTION_DAYS {}, keeping replays {} days", days, DEFAULT_RETENTION_DAYS);
+                DEFAULT_RETENTION_DAYS
+            }),
+            Err(_) => DEFAULT_RETENTION_DAYS,
+        };
+        if let Err(err) = fs::create_dir_all(&dir) {
+            eprintln!("Couldn't create REPLAY_DIR {}, not keeping replays: {}", dir, err);
+            return None;
+        }
+        Some(ReplayArchive {
+            dir: PathBuf::from(dir),
+            retention: Duration::from_secs(days * DAY),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Makes the key to keep the given game's replay under, from its ID, today's date, and the
+    /// given secret
+    ///
+    /// The secret keeps a game that gets the same ID later in the day from replacing this one, and
+    /// keeps anyone who wasn't told the key from finding it.
+    pub fn key(game: GameID, secret: u128) -> String {
+        let today = date(unix_time(SystemTime::now()));
+        format!("{}-{}-{:032x}", game, today, secret)
+    }
+
+    /// Keeps a replay under the given key, and clears out any that are past their time
+    pub fn save(&self, key: &str, replay: &str) -> io::Result<()> {
+        fs::write(self.path(key), replay)?;
+        // the replay's already safe, so a stale one hanging around is no reason to fail
+        if let Err(err) = self.prune() {
+            eprintln!("Couldn't clear out old replays: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Gets the replay kept under the given key, if there is one
+    pub fn load(&self, key: &str) -> Option<String> {
+        if !is_key(key) {
+            return None;
+        }
+        fs::read_to_string(self.path(key)).ok()
+    }
+
+    /// Deletes any replay that's been kept longer than the retention period, leaving alone
+    /// anything else that's ended up in the directory
+    fn prune(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let is_replay = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .map_or(false, is_key);
+            if !is_replay || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+            if age > self.retention {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the given text is shaped like a key `ReplayArchive::key` would make,
+/// `{game}-{YYYY}-{MM}-{DD}-{secret}`
+fn is_key(key: &str) -> bool {
+    let digits = |part: &str, len: usize| part.len() == len && part.bytes().all(|c| c.is_ascii_digit());
+    let parts: Vec<&str> = key.split('-').collect();
+    match parts[..] {
+        [game, year, month, day, secret] => {
+            game.bytes().all(|c| c.is_ascii_digit())
+                && game.parse::<GameID>().is_ok()
+                && digits(year, 4)
+                && digits(month, 2)
+                && digits(day, 2)
+                && secret.len() == 32
+                && secret.bytes().all(|c| c.is_ascii_hexdigit())
+        }
+        _ => false,
+    }
+}
+
+/// Formats a Unix time as a UTC date, `YYYY-MM-DD`
+fn date(unix_secs: u64) -> String {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (unix_secs / DAY) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_keys_look_like_keys() {
+        let key = ReplayArchive::key(1234, 0xfeed);
+        assert!(is_key(&key), "{}", key);
+        assert!(is_key("7-2020-03-15-0123456789abcdef0123456789abcdef"));
+        assert!(!is_key("7-2020-03-15-0123456789abcdef"));
+        assert!(!is_key("70000-2020-03-15-0123456789abcdef0123456789abcdef"));
+        assert!(!is_key("+7-2020-03-15-0123456789abcdef0123456789abcdef"));
+        assert!(!is_key("notes"));
+        assert!(!is_key("-"));
+        assert!(!is_key(""));
+    }
+
+    #[test]
+    fn dates_are_utc_days() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(1_584_230_400), "2020-03-15");
+        assert_eq!(date(951_782_400), "2000-02-29");
+    }
+}
@@ -0,0 +1,212 @@
+//! Soak test for the relay server
+//!
+//! Opens a bunch of connections, splits them up into games, and has every client send
+//! messages at a steady rate (or by replaying a recorded trace) for a while, then reports
+//! how much actually made it through.
+//!
+//! Usage: `soak [url] [clients] [clients per game] [messages per second] [seconds] [trace file]`
+//!
+//! A trace file has one message per line, as `<seconds since start> <payload bytes>`.
+//! When one is given, messages per second is instead how many times faster than real time
+//! the trace gets played back.
+//!
+//! There's no TLS support here, so point it at a `ws://` URL.
+
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use actix::*;
+use actix::io::{SinkWrite, WriteHandler};
+use actix_codec::Framed;
+use actix_web_actors::ws::ProtocolError;
+use awc::{BoxedSocket, Client, ws};
+use bincode::serialize;
+use futures::stream::{SplitSink, StreamExt};
+use rand::{self, Rng};
+
+use meta_net::*;
+
+// only a couple of the messages get sent from here
+#[allow(dead_code)]
+#[path = "../../../src/meta_net.rs"]
+mod meta_net;
+
+/// What each client sends
+enum Plan {
+    /// Messages of the given size at the given rate
+    Steady { rate: f64, size: usize },
+    /// Recorded (time, size) messages, played back at the given speed
+    Trace { messages: Vec<(f64, usize)>, speed: f64 },
+}
+
+impl Plan {
+    fn load_trace(path: &str, speed: f64) -> Plan {
+        let trace = fs::read_to_string(path).expect("Failed to read trace");
+        let messages = trace
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let time = parts.next()?.parse().ok()?;
+                let size = parts.next()?.parse().ok()?;
+                Some((time, size))
+            })
+            .collect();
+        Plan::Trace { messages, speed }
+    }
+}
+
+/// Totals across every client
+#[derive(Default)]
+struct Stats {
+    connected: AtomicUsize,
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    bytes_received: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+type WsSink = SplitSink<Framed<BoxedSocket, ws::Codec>, ws::Message>;
+
+/// One fake player
+struct SoakClient {
+    sink: SinkWrite<ws::Message, WsSink>,
+    game: GameID,
+    plan: Arc<Plan>,
+    stats: Arc<Stats>,
+}
+
+impl SoakClient {
+    fn send(&mut self, message: &MetaMessage) -> bool {
+        let data = serialize(message).unwrap();
+        let ok = self.sink.write(ws::Message::Binary(data.into())).is_ok();
+        if !ok {
+            self.stats.errors.fetch_add(1, Ordering::SeqCst);
+        }
+        ok
+    }
+
+    fn send_payload(&mut self, size: usize) {
+        // the relay never looks inside, so the contents don't matter
+        if self.send(&MetaMessage::Message(vec![0; size])) {
+            self.stats.sent.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Actor for SoakClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.send(&MetaMessage::Join(self.game));
+        let plan = self.plan.clone();
+        match *plan {
+            Plan::Steady { rate, size } => {
+                let interval = Duration::from_secs_f64(1.0 / rate);
+                ctx.run_interval(interval, move |act, _| act.send_payload(size));
+            }
+            Plan::Trace { ref messages, speed } => {
+                for &(time, size) in messages {
+                    let delay = Duration::from_secs_f64(time.max(0.0) / speed);
+                    ctx.run_later(delay, move |act, _| act.send_payload(size));
+                }
+            }
+        }
+    }
+}
+
+impl WriteHandler<ProtocolError> for SoakClient {}
+
+impl StreamHandler<Result<ws::Frame, ProtocolError>> for SoakClient {
+    fn handle(&mut self, msg: Result<ws::Frame, ProtocolError>, _: &mut Context<Self>) {
+        match msg {
+            Ok(ws::Frame::Binary(data)) => {
+                self.stats.received.fetch_add(1, Ordering::SeqCst);
+                self.stats.bytes_received.fetch_add(data.len(), Ordering::SeqCst);
+            }
+            Ok(ws::Frame::Ping(data)) => {
+                // the server drops anyone who doesn't answer its heartbeat
+                let _ = self.sink.write(ws::Message::Pong(data));
+            }
+            Ok(_) => (),
+            Err(_) => {
+                self.stats.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        ctx.stop();
+    }
+}
+
+fn arg<T: std::str::FromStr>(args: &[String], index: usize, default: T) -> T {
+    args.get(index)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(default)
+}
+
+#[actix_rt::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let url = arg(&args, 1, "ws://127.0.0.1:8080/ws/".to_string());
+    let clients: usize = arg(&args, 2, 100);
+    let per_game: usize = arg(&args, 3, 4).max(1);
+    let rate: f64 = arg(&args, 4, 2.0).max(0.01);
+    let seconds: u64 = arg(&args, 5, 60);
+    let plan = match args.get(6) {
+        Some(path) => Plan::load_trace(path, rate),
+        None => Plan::Steady { rate, size: 256 },
+    };
+    let plan = Arc::new(plan);
+    let stats = Arc::new(Stats::default());
+
+    println!("Connecting {} clients to {}, {} per game", clients, url, per_game);
+    let start = Instant::now();
+    let mut rng = rand::thread_rng();
+    let mut game = rng.gen();
+    for i in 0..clients {
+        if i % per_game == 0 {
+            game = rng.gen();
+        }
+        match Client::new().ws(url.as_str()).connect().await {
+            Ok((_, framed)) => {
+                let (sink, stream) = framed.split();
+                let plan = plan.clone();
+                let client_stats = stats.clone();
+                SoakClient::create(move |ctx| {
+                    ctx.add_stream(stream);
+                    SoakClient {
+                        sink: SinkWrite::new(sink, ctx),
+                        game,
+                        plan,
+                        stats: client_stats,
+                    }
+                });
+                stats.connected.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(e) => {
+                eprintln!("Failed to connect: {}", e);
+                stats.errors.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+    println!("Connected in {:.1}s, running for {}s", start.elapsed().as_secs_f64(), seconds);
+
+    actix_rt::time::delay_for(Duration::from_secs(seconds)).await;
+
+    let sent = stats.sent.load(Ordering::SeqCst);
+    let received = stats.received.load(Ordering::SeqCst);
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Connected: {}", stats.connected.load(Ordering::SeqCst));
+    println!("Sent: {} ({:.1}/s)", sent, sent as f64 / elapsed);
+    // every message should reach everyone else in the same game
+    println!(
+        "Received: {} of up to {} ({} bytes)",
+        received,
+        sent * (per_game - 1),
+        stats.bytes_received.load(Ordering::SeqCst)
+    );
+    println!("Errors: {}", stats.errors.load(Ordering::SeqCst));
+}
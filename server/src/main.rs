@@ -1,27 +1,40 @@
-use std::collections::{HashMap, HashSet};
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use actix::*;
+use actix::prelude::SendError;
 use actix_web::{App, Error, HttpRequest, HttpResponse, HttpServer, web};
 use actix_web_actors::ws;
-use bincode::deserialize;
+use bincode::{deserialize, serialize};
 use rand::{self, Rng, rngs::ThreadRng};
 
+use archive::ReplayArchive;
 use meta_net::*;
+use registry::{GameRegistry, InstanceID, LinkChanged, LocalRegistry, PeerRegistry, RelayFrame, Remote};
+use webhook::WebhookHosts;
 
 type ClientID = usize;
 
+mod archive;
 #[path = "../../src/meta_net.rs"]
 mod meta_net;
+mod registry;
+mod webhook;
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Message(pub Vec<u8>);
 
+/// Tells a session it's fallen too far behind and has to go
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick;
+
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    pub kick: Recipient<Kick>,
 }
 
 #[derive(Message)]
@@ -34,8 +47,11 @@ pub struct Disconnect {
 #[rtype(result = "()")]
 pub struct ClientMessage {
     pub id: ClientID,
-    pub msg: Vec<u8>,
+    /// `MetaMessage::Message`, `MetaMessage::Text`, or `MetaMessage::Deflated` to pass along
+    pub msg: MetaMessage,
     pub game_id: GameID,
+    /// Peers to pass it along to, or everyone if this is `None`
+    pub to: Option<Vec<PeerID>>,
 }
 
 #[derive(Message)]
@@ -51,36 +67,476 @@ pub struct Leave {
     pub id: ClientID,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Query {
+    pub id: ClientID,
+    pub game_id: GameID,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Start {
+    pub game_id: GameID,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct KeepAlive {
+    pub id: ClientID,
+    pub game_id: GameID,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetWebhook {
+    pub id: ClientID,
+    pub game_id: GameID,
+    pub url: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Finish {
+    pub id: ClientID,
+    pub game_id: GameID,
+    pub winner: String,
+    pub scores: Vec<(String, u8)>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Archive {
+    pub id: ClientID,
+    pub game_id: GameID,
+    /// The game's log, JSON-encoded
+    pub replay: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<String>")]
+pub struct GetReplay {
+    /// What the server told the game when it kept the replay
+    pub key: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Identify {
+    pub id: ClientID,
+    pub peer: PeerID,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Latency {
+    pub id: ClientID,
+    pub ms: u32,
+}
+
+#[derive(Message)]
+#[rtype(result = "String")]
+pub struct GetStats;
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CloseGame {
+    pub game_id: GameID,
+    pub reason: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Broadcast {
+    pub text: String,
+}
+
+/// How many messages can sit in a session's mailbox before it stops taking more
+const SESSION_MAILBOX: usize = 16;
+/// How many more messages get held for a session once its mailbox is full
+const SESSION_BACKLOG: usize = 256;
+/// How often held messages get another try at going out
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do with a session that can't keep up with its game
+#[derive(Clone, Copy, PartialEq)]
+pub enum OverflowPolicy {
+    /// Lose the oldest held message to make room for each new one
+    DropOldest,
+    /// Cut the session off
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    /// Reads `OVERFLOW_POLICY`, which is `drop-oldest` (the default) or `disconnect`
+    fn from_env() -> OverflowPolicy {
+        match std::env::var("OVERFLOW_POLICY").as_ref().map(String::as_str) {
+            Ok("disconnect") => OverflowPolicy::Disconnect,
+            Ok("drop-oldest") | Err(_) => OverflowPolicy::DropOldest,
+            Ok(other) => {
+                eprintln!("Unknown OVERFLOW_POLICY {}, dropping oldest", other);
+                OverflowPolicy::DropOldest
+            }
+        }
+    }
+}
+
+/// A session's address, plus whatever didn't fit in its mailbox yet
+struct Outbox {
+    addr: Recipient<Message>,
+    kick: Recipient<Kick>,
+    backlog: VecDeque<Message>,
+    /// How many messages this session never got
+    dropped: usize,
+}
+
+impl Outbox {
+    fn new(addr: Recipient<Message>, kick: Recipient<Kick>) -> Outbox {
+        Outbox {
+            addr,
+            kick,
+            backlog: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Hand over as much of the backlog as the session has room for
+    fn flush(&mut self) {
+        while let Some(msg) = self.backlog.pop_front() {
+            match self.addr.try_send(msg) {
+                Ok(()) => (),
+                Err(SendError::Full(msg)) => {
+                    self.backlog.push_front(msg);
+                    break;
+                }
+                // it'll be disconnecting soon anyway
+                Err(SendError::Closed(_)) => {
+                    self.backlog.clear();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// How long stats stick around after everyone has left a game
+const STATS_RETENTION: Duration = Duration::from_secs(60 * 60);
+/// How long a game that hasn't started can sit with one session and no traffic before it's closed
+///
+/// Clients close their own idle lobbies sooner than this, so this only catches ones that didn't.
+const IDLE_LOBBY_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+/// How often to look for idle lobbies
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// How often each game hears its sessions' round trip times
+const LATENCY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Usage numbers for a single game
+pub struct GameStats {
+    relayed: usize,
+    bytes: usize,
+    /// Messages lost on the way to sessions that couldn't keep up
+    dropped: usize,
+    peak_sessions: usize,
+    created: SystemTime,
+    last_active: SystemTime,
+}
+
+impl GameStats {
+    fn new() -> GameStats {
+        let now = SystemTime::now();
+        GameStats {
+            relayed: 0,
+            bytes: 0,
+            dropped: 0,
+            peak_sessions: 0,
+            created: now,
+            last_active: now,
+        }
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
 pub struct GameServer {
-    sessions: HashMap<ClientID, Recipient<Message>>,
+    sessions: HashMap<ClientID, Outbox>,
     games: HashMap<GameID, HashSet<ClientID>>,
+    started: HashSet<GameID>,
+    peers: HashMap<ClientID, PeerID>,
+    stats: HashMap<GameID, GameStats>,
+    /// The session that started each game here, which is its host unless the game started elsewhere
+    creators: HashMap<GameID, ClientID>,
+    /// Where each game's events get POSTed, if its host asked, and the session that asked
+    webhooks: HashMap<GameID, (ClientID, String)>,
+    /// Hosts webhooks may point at, or `None` if this deployment doesn't send any
+    webhook_hosts: Option<WebhookHosts>,
+    /// Where finished games' logs are kept, if anywhere
+    archive: Option<ReplayArchive>,
+    /// Games whose log has already been kept, so nobody can replace it
+    archived: HashSet<GameID>,
+    /// Each session's latest round trip time, in milliseconds, until the next report goes out
+    latencies: HashMap<ClientID, u32>,
+    registry: Box<dyn GameRegistry>,
+    overflow: OverflowPolicy,
     rng: ThreadRng,
 }
 
-impl Default for GameServer {
-    fn default() -> GameServer {
-        // default room
-        let games = HashMap::new();
-
+impl GameServer {
+    pub fn new(registry: Box<dyn GameRegistry>) -> GameServer {
         GameServer {
             sessions: HashMap::new(),
-            games,
+            games: HashMap::new(),
+            started: HashSet::new(),
+            peers: HashMap::new(),
+            stats: HashMap::new(),
+            creators: HashMap::new(),
+            webhooks: HashMap::new(),
+            webhook_hosts: WebhookHosts::from_env(),
+            archive: ReplayArchive::from_env(),
+            archived: HashSet::new(),
+            latencies: HashMap::new(),
+            registry,
+            overflow: OverflowPolicy::from_env(),
             rng: rand::thread_rng(),
         }
     }
-}
 
-impl GameServer {
-    /// Send message to all users in the game
-    fn send_message(&self, game: GameID, message: &[u8], skip_id: ClientID) {
-        if let Some(sessions) = self.games.get(&game) {
-            for id in sessions {
-                if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(Message(message.to_vec()));
-                    }
+    /// Send message to a single session, returns how many messages it lost for not keeping up
+    fn deliver(&mut self, id: ClientID, message: &[u8]) -> usize {
+        let outbox = match self.sessions.get_mut(&id) {
+            Some(outbox) => outbox,
+            None => return 0,
+        };
+        outbox.backlog.push_back(Message(message.to_vec()));
+        outbox.flush();
+        if outbox.backlog.len() <= SESSION_BACKLOG {
+            return 0;
+        }
+        match self.overflow {
+            OverflowPolicy::DropOldest => {
+                outbox.backlog.pop_front();
+                outbox.dropped += 1;
+                1
+            }
+            OverflowPolicy::Disconnect => {
+                let lost = outbox.backlog.len();
+                outbox.dropped += lost;
+                self.kick(id);
+                lost
+            }
+        }
+    }
+
+    /// Send message to the given sessions in the game, counting anything they lose
+    fn deliver_in_game(&mut self, game: GameID, sessions: Vec<ClientID>, message: &[u8]) {
+        let dropped: usize = sessions.into_iter().map(|id| self.deliver(id, message)).sum();
+        if let Some(stats) = self.stats.get_mut(&game) {
+            stats.dropped += dropped;
+        }
+    }
+
+    /// Checks if the given session is one of the given peers, or if there's no list of peers
+    fn addressed(&self, id: ClientID, to: Option<&[PeerID]>) -> bool {
+        match to {
+            Some(to) => matches!(self.peers.get(&id), Some(peer) if to.contains(peer)),
+            None => true,
+        }
+    }
+
+    /// Send message to all users in the game, or only the given peers
+    fn send_message(&mut self, game: GameID, message: &[u8], skip_id: ClientID, to: Option<&[PeerID]>) {
+        let sessions = match self.games.get(&game) {
+            Some(sessions) => sessions
+                .iter()
+                .cloned()
+                .filter(|id| *id != skip_id && self.addressed(*id, to))
+                .collect(),
+            None => return,
+        };
+        self.deliver_in_game(game, sessions, message);
+    }
+
+    /// Send message to every local user in the game, or only the given peers, without skipping anyone
+    fn send_to_all(&mut self, game: GameID, message: &[u8], to: Option<&[PeerID]>) {
+        let sessions = match self.games.get(&game) {
+            Some(sessions) => sessions.iter().cloned().filter(|id| self.addressed(*id, to)).collect(),
+            None => return,
+        };
+        self.deliver_in_game(game, sessions, message);
+    }
+
+    /// Forget a session, letting everyone else in its game know it left
+    fn remove_session(&mut self, id: ClientID) -> Option<Outbox> {
+        let outbox = self.sessions.remove(&id)?;
+        // remove session from all games
+        self.leave_games(id);
+        self.peers.remove(&id);
+        self.latencies.remove(&id);
+        Some(outbox)
+    }
+
+    /// Drop a session that's fallen too far behind
+    fn kick(&mut self, id: ClientID) {
+        if let Some(outbox) = self.remove_session(id) {
+            println!("Someone fell too far behind, disconnecting");
+            // this skips the mailbox limit, so it gets there whenever the session catches up
+            let _ = outbox.kick.do_send(Kick);
+        }
+    }
+
+    /// Remove a session from every game it's in, letting everyone else know it left
+    fn leave_games(&mut self, id: ClientID) {
+        let left: Vec<GameID> = self
+            .games
+            .iter_mut()
+            .filter_map(|(game, sessions)| if sessions.remove(&id) { Some(*game) } else { None })
+            .collect();
+        if let Some(peer) = self.peers.get(&id) {
+            let message = serialize(&MetaMessage::PeerLeft(*peer)).unwrap();
+            for game in &left {
+                self.relay(*game, &message, id);
+            }
+        }
+        self.forget_empty_games();
+        for game in left {
+            self.announce(game);
+        }
+    }
+
+    /// Send message to all users in the game, here and on other instances
+    fn relay(&mut self, game: GameID, message: &[u8], skip_id: ClientID) {
+        self.relay_to(game, message, skip_id, None);
+    }
+
+    /// Send message to the given peers in the game, or everyone if there's no list, here and on
+    /// other instances
+    fn relay_to(&mut self, game: GameID, message: &[u8], skip_id: ClientID, to: Option<&[PeerID]>) {
+        self.send_message(game, message, skip_id, to);
+        self.registry.publish(game, message, to);
+    }
+
+    /// Drop games nobody is in anymore
+    fn forget_empty_games(&mut self) {
+        let empty: Vec<GameID> = self
+            .games
+            .iter()
+            .filter(|(_, sessions)| sessions.is_empty())
+            .map(|(game, _)| *game)
+            .collect();
+        for game in empty {
+            self.games.remove(&game);
+        }
+        let games = &self.games;
+        self.started.retain(|game| games.contains_key(game));
+        self.creators.retain(|game, _| games.contains_key(game));
+        self.webhooks.retain(|game, _| games.contains_key(game));
+        self.archived.retain(|game| games.contains_key(game));
+        self.stats.retain(|game, stats| {
+            let idle = stats.last_active.elapsed().unwrap_or_default();
+            games.contains_key(game) || idle < STATS_RETENTION
+        });
+    }
+
+    /// Tell everyone in the game here why it's over, then forget it, returns whether the game existed
+    fn close_game(&mut self, game_id: GameID, reason: String) -> bool {
+        println!("Closing game {}: {}", game_id, reason);
+        let message = serialize(&MetaMessage::GameClosed(reason)).unwrap();
+        let sessions = match self.games.remove(&game_id) {
+            Some(sessions) => sessions,
+            None => return false,
+        };
+        for id in sessions {
+            self.deliver(id, &message);
+        }
+        self.forget_empty_games();
+        self.announce(game_id);
+        true
+    }
+
+    /// Let other instances know how many sessions are in the game here and whether it's started
+    fn announce(&mut self, game: GameID) {
+        let sessions = self.games.get(&game).map_or(0, HashSet::len);
+        let started = self.started.contains(&game);
+        self.registry.announce(game, sessions, started);
+    }
+
+    /// POSTs an event to the game's webhook, if it has one, without waiting to hear back
+    fn post_webhook(&self, game: GameID, event: &str, mut body: serde_json::Value) {
+        let url = match self.webhooks.get(&game) {
+            Some((_, url)) => url.clone(),
+            None => return,
+        };
+        let (host, port) = match self.webhook_hosts.as_ref().and_then(|hosts| hosts.check(&url)) {
+            Some(target) => target,
+            None => return,
+        };
+        body["event"] = event.into();
+        body["game"] = game.into();
+        body["time"] = unix_time(SystemTime::now()).into();
+        actix_rt::spawn(async move {
+            // the name gets looked up every time, in case it's been pointed somewhere it shouldn't since
+            let lookup_host = host.clone();
+            match web::block(move || webhook::resolves_publicly(&lookup_host, port)).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    eprintln!("Webhook for game {} points at {}, which isn't public", game, host);
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Webhook for game {} couldn't look up {}: {}", game, host, e);
+                    return;
                 }
             }
+            if let Err(e) = awc::Client::new().post(url.as_str()).send_json(&body).await {
+                eprintln!("Webhook for game {} failed: {}", game, e);
+            }
+        });
+    }
+
+    /// Let everyone in each game know how laggy its sessions here are, one message per game
+    fn report_latencies(&mut self) {
+        let reports: Vec<(GameID, Vec<(PeerID, u32)>)> = self
+            .games
+            .iter()
+            .filter_map(|(game, sessions)| {
+                let times: Vec<(PeerID, u32)> = sessions
+                    .iter()
+                    .filter_map(|id| Some((*self.peers.get(id)?, *self.latencies.get(id)?)))
+                    .collect();
+                if times.is_empty() {
+                    None
+                } else {
+                    Some((*game, times))
+                }
+            })
+            .collect();
+        for (game, times) in reports {
+            let message = serialize(&MetaMessage::Latency(times)).unwrap();
+            self.send_to_all(game, &message, None);
+            self.registry.publish(game, &message, None);
+        }
+    }
+
+    /// Close lobbies nobody else has joined in a long time, so forgotten tabs don't pile up
+    fn reap_idle_lobbies(&mut self) {
+        let registry = &self.registry;
+        let idle: Vec<GameID> = self
+            .games
+            .iter()
+            .filter(|(game, sessions)| {
+                let (remote_sessions, remote_started) = registry.remote_presence(**game);
+                sessions.len() + remote_sessions <= 1 && !self.started.contains(game) && !remote_started
+            })
+            .filter(|(game, _)| {
+                self.stats.get(game).map_or(false, |stats| {
+                    stats.last_active.elapsed().unwrap_or_default() >= IDLE_LOBBY_TIMEOUT
+                })
+            })
+            .map(|(game, _)| *game)
+            .collect();
+        for game in idle {
+            self.close_game(game, "The lobby sat idle for too long".to_string());
         }
     }
 }
@@ -89,6 +545,17 @@ impl Actor for GameServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.registry.start(ctx.address());
+        ctx.run_interval(FLUSH_INTERVAL, |act, _| {
+            for outbox in act.sessions.values_mut() {
+                outbox.flush();
+            }
+        });
+        ctx.run_interval(REAP_INTERVAL, |act, _| act.reap_idle_lobbies());
+        ctx.run_interval(LATENCY_INTERVAL, |act, _| act.report_latencies());
+    }
 }
 
 /// Handler for Connect message.
@@ -102,7 +569,7 @@ impl Handler<Connect> for GameServer {
 
         // register session with random id
         let id = self.rng.gen::<usize>();
-        self.sessions.insert(id, msg.addr);
+        self.sessions.insert(id, Outbox::new(msg.addr, msg.kick));
 
         // send id back
         id
@@ -114,13 +581,11 @@ impl Handler<Disconnect> for GameServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Someone disconnected");
-
-        // remove address
-        if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all games
-            for sessions in self.games.values_mut() {
-                sessions.remove(&msg.id);
+        if let Some(outbox) = self.remove_session(msg.id) {
+            if outbox.dropped > 0 {
+                println!("Someone disconnected, after missing {} messages", outbox.dropped);
+            } else {
+                println!("Someone disconnected");
             }
         }
     }
@@ -131,7 +596,66 @@ impl Handler<ClientMessage> for GameServer {
     type Result = ();
 
     fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
-        self.send_message(msg.game_id, &msg.msg, msg.id);
+        // the game might have been closed out from under them
+        let in_game = self
+            .games
+            .get(&msg.game_id)
+            .map_or(false, |sessions| sessions.contains(&msg.id));
+        if !in_game {
+            return;
+        }
+        let message = serialize(&msg.msg).unwrap();
+        if let Some(stats) = self.stats.get_mut(&msg.game_id) {
+            stats.relayed += 1;
+            stats.bytes += message.len();
+            stats.last_active = SystemTime::now();
+        }
+        self.relay_to(msg.game_id, &message, msg.id, msg.to.as_deref());
+    }
+}
+
+/// Handler for Remote message.
+///
+/// Pass along a message from another instance to everyone in the game here
+impl Handler<Remote> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Remote, _: &mut Context<Self>) {
+        match msg.0 {
+            RelayFrame::Message { game, message, to } => {
+                // someone elsewhere is still using the game, so it isn't idle
+                if let Some(stats) = self.stats.get_mut(&game) {
+                    stats.last_active = SystemTime::now();
+                }
+                self.send_to_all(game, &message, to.as_deref());
+            }
+            RelayFrame::Presence { from, game, sessions, started } => {
+                self.registry.observe(from, game, sessions, started);
+            }
+            RelayFrame::Close { game, reason } => {
+                self.close_game(game, reason);
+            }
+            // only ever sent back down a link, never to the relay route
+            RelayFrame::Hello(_) => (),
+        }
+    }
+}
+
+/// Handler for LinkChanged message.
+///
+/// Keep track of who's on the other end of each link, and tell whoever's new what's going on here
+impl Handler<LinkChanged> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: LinkChanged, _: &mut Context<Self>) {
+        let LinkChanged { link, instance } = msg;
+        self.registry.link_changed(link, instance);
+        if instance.is_some() {
+            let games: Vec<GameID> = self.games.keys().cloned().collect();
+            for game in games {
+                self.announce(game);
+            }
+        }
     }
 }
 
@@ -144,14 +668,19 @@ impl Handler<Join> for GameServer {
         let Join { id, game_id } = msg;
 
         // remove session from all games
-        for sessions in self.games.values_mut() {
-            sessions.remove(&id);
-        }
+        self.leave_games(id);
 
         if self.games.get_mut(&game_id).is_none() {
             self.games.insert(game_id.clone(), HashSet::new());
+            self.creators.insert(game_id, id);
         }
-        self.games.get_mut(&game_id).unwrap().insert(id);
+        let sessions = self.games.get_mut(&game_id).unwrap();
+        sessions.insert(id);
+
+        let stats = self.stats.entry(game_id).or_insert_with(GameStats::new);
+        stats.peak_sessions = stats.peak_sessions.max(sessions.len());
+        stats.last_active = SystemTime::now();
+        self.announce(game_id);
     }
 }
 
@@ -162,9 +691,235 @@ impl Handler<Leave> for GameServer {
     fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
         println!("Someone left");
 
-        // remove session from all games
-        for sessions in self.games.values_mut() {
-            sessions.remove(&msg.id);
+        // remove session from all games, forgetting any that are empty now
+        self.leave_games(msg.id);
+        self.deliver(msg.id, &serialize(&MetaMessage::LeftAck).unwrap());
+    }
+}
+
+/// Handler for Query message.
+///
+/// Tell the session how full the game is and whether it's started, without joining it
+impl Handler<Query> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Query, _: &mut Context<Self>) {
+        let Query { id, game_id } = msg;
+        let (remote_sessions, remote_started) = self.registry.remote_presence(game_id);
+        let info = MetaMessage::GameInfo {
+            game: game_id,
+            players: self.games.get(&game_id).map_or(0, |sessions| sessions.len()) + remote_sessions,
+            started: self.started.contains(&game_id) || remote_started,
+        };
+        self.deliver(id, &serialize(&info).unwrap());
+    }
+}
+
+/// Handler for SetWebhook message.
+///
+/// Remember where to send the game's events, as long as it's from whoever created the lobby, it
+/// doesn't have one yet, and this deployment allows the URL
+impl Handler<SetWebhook> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetWebhook, _: &mut Context<Self>) {
+        let SetWebhook { id, game_id, url } = msg;
+        let from_creator = self.creators.get(&game_id) == Some(&id);
+        let allowed = self.webhook_hosts.as_ref().map_or(false, |hosts| hosts.check(&url).is_some());
+        if !from_creator || !allowed || self.started.contains(&game_id) || self.webhooks.contains_key(&game_id) {
+            return;
+        }
+        self.webhooks.insert(game_id, (id, url));
+        self.post_webhook(game_id, "lobby_created", serde_json::json!({}));
+    }
+}
+
+/// Handler for Finish message.
+///
+/// Pass the final scores on to the game's webhook, once, if they come from whoever set it up
+impl Handler<Finish> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Finish, _: &mut Context<Self>) {
+        let Finish { id, game_id, winner, scores } = msg;
+        let from_host = self.webhooks.get(&game_id).map_or(false, |(host, _)| *host == id);
+        if !from_host || !self.started.contains(&game_id) {
+            return;
+        }
+        let scores: Vec<_> = scores
+            .into_iter()
+            .map(|(name, score)| serde_json::json!({ "name": name, "score": score }))
+            .collect();
+        self.post_webhook(game_id, "game_ended", serde_json::json!({ "winner": winner, "scores": scores }));
+        // nothing else happens to a game after it ends
+        self.webhooks.remove(&game_id);
+    }
+}
+
+/// Handler for Archive message.
+///
+/// Keep a finished game's log, once, if replays are being kept and it comes from the host, then
+/// tell everyone in the game the key to download it with
+impl Handler<Archive> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Archive, ctx: &mut Context<Self>) {
+        let Archive { id, game_id, replay } = msg;
+        let archive = match self.archive {
+            Some(ref archive) => archive.clone(),
+            None => return,
+        };
+        // the host settles everything else about the game, so their log is the one that counts
+        let from_host = self.creators.get(&game_id) == Some(&id);
+        if !from_host || !self.started.contains(&game_id) || !self.archived.insert(game_id) {
+            return;
+        }
+        let key = ReplayArchive::key(game_id, self.rng.gen());
+        // every game here would wait on the disk, so it's written from a thread of its own
+        let saved = web::block(move || archive.save(&key, &replay).map(|()| key));
+        ctx.spawn(saved.into_actor(self).map(move |result, act, _| match result {
+            Ok(key) => {
+                let message = serialize(&MetaMessage::Archived(key)).unwrap();
+                act.send_to_all(game_id, &message, None);
+                act.registry.publish(game_id, &message, None);
+            }
+            Err(err) => eprintln!("Couldn't keep replay for game {}: {}", game_id, err),
+        }));
+    }
+}
+
+/// Handler for GetReplay message.
+impl Handler<GetReplay> for GameServer {
+    type Result = ResponseFuture<Option<String>>;
+
+    fn handle(&mut self, msg: GetReplay, _: &mut Context<Self>) -> Self::Result {
+        let archive = match self.archive {
+            Some(ref archive) => archive.clone(),
+            None => return Box::pin(async { None }),
+        };
+        Box::pin(async move { web::block(move || archive.load(&msg.key).ok_or(())).await.ok() })
+    }
+}
+
+/// Handler for Identify message.
+///
+/// Remember who the session is, and let the rest of its game know they're here
+impl Handler<Identify> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Identify, _: &mut Context<Self>) {
+        let Identify { id, peer } = msg;
+        self.peers.insert(id, peer);
+        let message = serialize(&MetaMessage::PeerJoined(peer)).unwrap();
+        let games: Vec<GameID> = self
+            .games
+            .iter()
+            .filter(|(_, sessions)| sessions.contains(&id))
+            .map(|(game, _)| *game)
+            .collect();
+        for game in games {
+            self.relay(game, &message, id);
+        }
+    }
+}
+
+/// Handler for Latency message.
+///
+/// Remember how laggy the session is, for the next report to its game
+impl Handler<Latency> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Latency, _: &mut Context<Self>) {
+        if self.sessions.contains_key(&msg.id) {
+            self.latencies.insert(msg.id, msg.ms);
+        }
+    }
+}
+
+/// Handler for GetStats message.
+///
+/// Summarize every game we know about, one line each
+impl Handler<GetStats> for GameServer {
+    type Result = String;
+
+    fn handle(&mut self, _: GetStats, _: &mut Context<Self>) -> Self::Result {
+        let mut games: Vec<_> = self.stats.iter().collect();
+        games.sort_by_key(|(_, stats)| stats.created);
+        let mut report = "game\trelayed\tbytes\tdropped\tsessions\tpeak\tstarted\tcreated\tlast_active\n".to_string();
+        for (game, stats) in games {
+            let sessions = self.games.get(game).map_or(0, |sessions| sessions.len());
+            report.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                game,
+                stats.relayed,
+                stats.bytes,
+                stats.dropped,
+                sessions,
+                stats.peak_sessions,
+                self.started.contains(game),
+                unix_time(stats.created),
+                unix_time(stats.last_active),
+            ));
+        }
+        report
+    }
+}
+
+/// Handler for CloseGame message.
+impl Handler<CloseGame> for GameServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: CloseGame, _: &mut Context<Self>) -> Self::Result {
+        // the game's players might be spread across instances, so they all get told
+        let elsewhere = self.registry.close(msg.game_id, &msg.reason);
+        let here = self.close_game(msg.game_id, msg.reason);
+        here || elsewhere
+    }
+}
+
+/// Handler for Broadcast message.
+impl Handler<Broadcast> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, _: &mut Context<Self>) {
+        println!("Broadcasting: {}", msg.text);
+        let message = serialize(&MetaMessage::Notice(msg.text)).unwrap();
+        let sessions: Vec<ClientID> = self.sessions.keys().cloned().collect();
+        for id in sessions {
+            self.deliver(id, &message);
+        }
+    }
+}
+
+/// Handler for Start message.
+impl Handler<Start> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Start, _: &mut Context<Self>) {
+        let players = match self.games.get(&msg.game_id) {
+            Some(sessions) => sessions.len(),
+            None => return,
+        };
+        if self.started.insert(msg.game_id) {
+            self.announce(msg.game_id);
+            self.post_webhook(msg.game_id, "game_started", serde_json::json!({ "players": players }));
+        }
+    }
+}
+
+/// Handler for KeepAlive message.
+///
+/// Count the game as active, so it isn't closed for sitting idle, as long as the session is in it
+impl Handler<KeepAlive> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: KeepAlive, _: &mut Context<Self>) {
+        let in_game = self
+            .games
+            .get(&msg.game_id)
+            .map_or(false, |sessions| sessions.contains(&msg.id));
+        if let (true, Some(stats)) = (in_game, self.stats.get_mut(&msg.game_id)) {
+            stats.last_active = SystemTime::now();
         }
     }
 }
@@ -180,26 +935,186 @@ async fn game_route(
     stream: web::Payload,
     srv: web::Data<Addr<GameServer>>,
 ) -> Result<HttpResponse, Error> {
-    ws::start(
+    // the "json" subprotocol gets text frames, so the protocol can be poked at by hand
+    let json = req
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.split(',').any(|protocol| protocol.trim() == "json"));
+    // browsers offer permessage-deflate on their own, but actix-web-actors has no way to set the
+    // compressed bit on a frame, so the handshake leaves Sec-WebSocket-Extensions out; clients
+    // opened with `?deflate` shrink big game messages themselves instead, and they get passed along as-is
+    ws::start_with_protocols(
         GameSession {
             id: 0,
             hb: Instant::now(),
+            ping_sent: Instant::now(),
             game: None,
+            json,
             addr: srv.get_ref().clone(),
         },
+        &["json"],
         &req,
         stream,
     )
 }
 
+/// Checks that the request has the token in `ADMIN_TOKEN`
+fn is_admin(req: &HttpRequest) -> bool {
+    let token = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // no token set means nobody gets in
+    !token.is_empty() && given == Some(token.as_str())
+}
+
+/// Per-game stats, for admins
+async fn stats_route(
+    req: HttpRequest,
+    srv: web::Data<Addr<GameServer>>,
+) -> HttpResponse {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    match srv.send(GetStats).await {
+        Ok(report) => HttpResponse::Ok().content_type("text/plain").body(report),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Shut down a game, with the request body as the reason given to its players
+async fn close_route(
+    req: HttpRequest,
+    game_id: web::Path<GameID>,
+    reason: String,
+    srv: web::Data<Addr<GameServer>>,
+) -> HttpResponse {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let reason = if reason.is_empty() {
+        "Closed by the server admin".to_string()
+    } else {
+        reason
+    };
+    let msg = CloseGame {
+        game_id: game_id.into_inner(),
+        reason,
+    };
+    match srv.send(msg).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Send the request body to everyone connected, e.g. before a restart
+async fn broadcast_route(
+    req: HttpRequest,
+    text: String,
+    srv: web::Data<Addr<GameServer>>,
+) -> HttpResponse {
+    if !is_admin(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    if text.is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+    srv.do_send(Broadcast { text });
+    HttpResponse::Ok().finish()
+}
+
+/// Downloads the replay kept under the given key, which only the game's players were told
+async fn replay_route(
+    key: web::Path<String>,
+    srv: web::Data<Addr<GameServer>>,
+) -> HttpResponse {
+    let key = key.into_inner();
+    let filename = format!("dynamaze-{}.json", key);
+    match srv.send(GetReplay { key }).await {
+        Ok(Some(replay)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+            .body(replay),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Where other instances send messages for games with players here
+async fn relay_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    srv: web::Data<Addr<GameServer>>,
+    instance: web::Data<InstanceID>,
+) -> Result<HttpResponse, Error> {
+    let token = std::env::var("RELAY_TOKEN").unwrap_or_default();
+    let given = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if token.is_empty() || given != Some(token.as_str()) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    ws::start(
+        RelaySession {
+            addr: srv.get_ref().clone(),
+            instance: *instance.get_ref(),
+        },
+        &req,
+        stream,
+    )
+}
+
+/// Incoming connection from another instance
+struct RelaySession {
+    addr: Addr<GameServer>,
+    /// This instance, to tell the other end who it's reached
+    instance: InstanceID,
+}
+
+impl Actor for RelaySession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.binary(serialize(&RelayFrame::Hello(self.instance)).unwrap());
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for RelaySession {
+    fn handle(
+        &mut self,
+        msg: Result<ws::Message, ws::ProtocolError>,
+        ctx: &mut Self::Context,
+    ) {
+        match msg {
+            Ok(ws::Message::Binary(data)) => match deserialize::<RelayFrame>(&data) {
+                Ok(frame) => self.addr.do_send(Remote(frame)),
+                Err(e) => eprintln!("Got bad relay frame: {}", e),
+            },
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(_)) | Err(_) => ctx.stop(),
+            Ok(_) => (),
+        }
+    }
+}
+
 struct GameSession {
     /// unique session id
     id: ClientID,
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
     hb: Instant,
+    /// When the last ping went out, to time the pong
+    ping_sent: Instant,
     /// joined game
     game: Option<GameID>,
+    /// Whether the client asked for JSON text frames instead of bincode
+    json: bool,
     /// Chat server
     addr: Addr<GameServer>,
 }
@@ -213,6 +1128,9 @@ impl Actor for GameSession {
         // we'll start heartbeat process on session start.
         self.hb(ctx);
 
+        // this only gets emptied as fast as the client reads, so it's how we notice slow ones
+        ctx.set_mailbox_capacity(SESSION_MAILBOX);
+
         // register self in chat server. `AsyncContext::wait` register
         // future within context, but context waits until this future resolves
         // before processing any other events.
@@ -221,7 +1139,8 @@ impl Actor for GameSession {
         let addr = ctx.address();
         self.addr
             .send(Connect {
-                addr: addr.recipient(),
+                addr: addr.clone().recipient(),
+                kick: addr.recipient(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -247,10 +1166,29 @@ impl Handler<Message> for GameSession {
     type Result = ();
 
     fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
+        if self.json {
+            if let Ok(message) = deserialize::<MetaMessage>(&msg.0) {
+                ctx.text(serde_json::to_string(&message).unwrap());
+                return;
+            }
+        }
         ctx.binary(msg.0)
     }
 }
 
+/// Handle being cut off by the chat server
+impl Handler<Kick> for GameSession {
+    type Result = ();
+
+    fn handle(&mut self, _: Kick, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("Fell too far behind".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
 /// WebSocket message handler
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameSession {
     fn handle(
@@ -273,41 +1211,19 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameSession {
             }
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
+                if self.game.is_some() {
+                    let ms = self.hb.duration_since(self.ping_sent).as_millis() as u32;
+                    self.addr.do_send(Latency { id: self.id, ms });
+                }
             }
-            ws::Message::Text(_) => {
-                // TODO handle?
+            ws::Message::Text(text) => {
+                let message = serde_json::from_str::<MetaMessage>(&text).map_err(|e| e.to_string());
+                self.handle_meta(message);
             }
             ws::Message::Binary(data) => {
-                let message = deserialize::<MetaMessage>(&data);
-                println!("WEBSOCKET MESSAGE: {:?}", message);
-                match message {
-                    Ok(MetaMessage::Join(game)) => {
-                        self.game = Some(game);
-                        self.addr.do_send(Join {
-                            id: self.id,
-                            game_id: game,
-                        });
-                    }
-                    Ok(MetaMessage::Leave) => {
-                        self.game = None;
-                        self.addr.do_send(Leave {
-                            id: self.id,
-                        });
-                    }
-                    Ok(MetaMessage::Message(data)) => {
-                        if let Some(game) = self.game {
-                            self.addr.do_send(ClientMessage {
-                                id: self.id,
-                                msg: data,
-                                game_id: game,
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Got bad message: {}", e);
-                    }
-                }
-            },
+                let message = deserialize::<MetaMessage>(&data).map_err(|e| e.to_string());
+                self.handle_meta(message);
+            }
             ws::Message::Close(_) => {
                 ctx.stop();
             }
@@ -320,6 +1236,118 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for GameSession {
 }
 
 impl GameSession {
+    /// Handle a message from the client, however it was encoded
+    fn handle_meta(&mut self, message: Result<MetaMessage, String>) {
+        println!("WEBSOCKET MESSAGE: {:?}", message);
+        match message {
+            Ok(MetaMessage::Join(game)) => {
+                self.game = Some(game);
+                self.addr.do_send(Join {
+                    id: self.id,
+                    game_id: game,
+                });
+            }
+            Ok(MetaMessage::Leave) => {
+                self.game = None;
+                self.addr.do_send(Leave {
+                    id: self.id,
+                });
+            }
+            Ok(msg @ MetaMessage::Message(_))
+            | Ok(msg @ MetaMessage::Text(_))
+            | Ok(msg @ MetaMessage::Deflated(_)) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(ClientMessage {
+                        id: self.id,
+                        msg,
+                        game_id: game,
+                        to: None,
+                    });
+                }
+            }
+            Ok(MetaMessage::To(to, msg)) => {
+                let msg = *msg;
+                let wrapped = matches!(msg, MetaMessage::Message(_) | MetaMessage::Text(_) | MetaMessage::Deflated(_));
+                if let (true, Some(game)) = (wrapped, self.game) {
+                    self.addr.do_send(ClientMessage {
+                        id: self.id,
+                        msg,
+                        game_id: game,
+                        to: Some(to),
+                    });
+                }
+            }
+            Ok(MetaMessage::QueryGame(game)) => {
+                self.addr.do_send(Query {
+                    id: self.id,
+                    game_id: game,
+                });
+            }
+            Ok(MetaMessage::Started) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(Start {
+                        game_id: game,
+                    });
+                }
+            }
+            Ok(MetaMessage::KeepAlive) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(KeepAlive {
+                        id: self.id,
+                        game_id: game,
+                    });
+                }
+            }
+            Ok(MetaMessage::SetWebhook(url)) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(SetWebhook {
+                        id: self.id,
+                        game_id: game,
+                        url,
+                    });
+                }
+            }
+            Ok(MetaMessage::Finished { winner, scores }) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(Finish {
+                        id: self.id,
+                        game_id: game,
+                        winner,
+                        scores,
+                    });
+                }
+            }
+            Ok(MetaMessage::Replay(replay)) => {
+                if let Some(game) = self.game {
+                    self.addr.do_send(Archive {
+                        id: self.id,
+                        game_id: game,
+                        replay,
+                    });
+                }
+            }
+            Ok(MetaMessage::Identify(peer)) => {
+                self.addr.do_send(Identify {
+                    id: self.id,
+                    peer,
+                });
+            }
+            Ok(MetaMessage::GameInfo { .. })
+            | Ok(MetaMessage::PeerJoined(_))
+            | Ok(MetaMessage::PeerLeft(_))
+            | Ok(MetaMessage::GameClosed(_))
+            | Ok(MetaMessage::Notice(_))
+            | Ok(MetaMessage::Latency(..))
+            | Ok(MetaMessage::Archived(_))
+            | Ok(MetaMessage::LeftAck) => {
+                eprintln!("Got server-only message from client");
+            }
+            Err(e) => {
+                eprintln!("Got bad message: {}", e);
+            }
+        }
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
@@ -340,6 +1368,7 @@ impl GameSession {
                 return;
             }
 
+            act.ping_sent = Instant::now();
             ctx.ping(b"");
         });
     }
@@ -347,8 +1376,15 @@ impl GameSession {
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
+    // share games with other instances, if there are any
+    let instance = InstanceID::random();
+    let registry: Box<dyn GameRegistry> = match PeerRegistry::from_env(instance) {
+        Some(registry) => Box::new(registry),
+        None => Box::new(LocalRegistry),
+    };
+
     // Start chat server actor
-    let server = GameServer::default().start();
+    let server = GameServer::new(registry).start();
 
     let addr = ("0.0.0.0", std::env::var("PORT").unwrap_or_else(|_| "8080".to_string()).parse().unwrap());
 
@@ -358,8 +1394,18 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .data(server.clone())
+            .data(instance)
             // websocket
             .service(web::resource("/ws/").to(game_route))
+            // other instances
+            .service(web::resource("/relay/").to(relay_route))
+            // usage stats
+            .service(web::resource("/admin/stats").route(web::get().to(stats_route)))
+            // moderation
+            .service(web::resource("/admin/games/{game_id}/close").route(web::post().to(close_route)))
+            .service(web::resource("/admin/broadcast").route(web::post().to(broadcast_route)))
+            // finished games
+            .service(web::resource("/replays/{key}").route(web::get().to(replay_route)))
     })
         .bind(addr)?
         .run()
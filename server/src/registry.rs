@@ -0,0 +1,342 @@
+//! Sharing games between several relay instances
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix::*;
+use actix::io::{SinkWrite, WriteHandler};
+use actix_codec::Framed;
+use actix_web_actors::ws::ProtocolError;
+use awc::{BoxedSocket, Client, ws};
+use bincode::{deserialize, serialize};
+use futures::stream::{SplitSink, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::GameServer;
+use crate::meta_net::{GameID, PeerID};
+
+/// How long to wait before trying a dropped peer again
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Identifies a relay instance, picked at random each time one starts
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceID(u64);
+
+impl InstanceID {
+    /// Picks an ID for this instance
+    pub fn random() -> InstanceID {
+        InstanceID(rand::random())
+    }
+}
+
+/// A message passed from one relay instance to another
+#[derive(Serialize, Deserialize)]
+pub enum RelayFrame {
+    /// Sent back down a link by the instance on the other end, so the link knows who it reaches
+    Hello(InstanceID),
+    /// How many sessions the sending instance has in a game and whether it's started there, with
+    /// no sessions meaning it's done with the game
+    Presence {
+        from: InstanceID,
+        game: GameID,
+        sessions: usize,
+        started: bool,
+    },
+    /// A message for members of a game
+    Message {
+        game: GameID,
+        /// Serialized `MetaMessage`, ready to hand to local sessions as-is
+        message: Vec<u8>,
+        /// Peers to hand it to, or everyone in the game if this is `None`
+        to: Option<Vec<PeerID>>,
+    },
+    /// A game an admin closed, which every instance should close too
+    Close { game: GameID, reason: String },
+}
+
+/// Message from another instance, for local members of a game
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Remote(pub RelayFrame);
+
+/// A link to another instance found out which instance it reaches, or lost it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LinkChanged {
+    pub link: usize,
+    pub instance: Option<InstanceID>,
+}
+
+/// Keeps track of which instances care about which games, and gets messages between them
+pub trait GameRegistry {
+    /// Starts talking to other instances, letting the server know as links come and go with
+    /// `LinkChanged`
+    fn start(&mut self, server: Addr<GameServer>);
+
+    /// Tells other instances how many sessions this one has in the game and whether it's started here
+    fn announce(&mut self, _game: GameID, _sessions: usize, _started: bool) {}
+
+    /// Remembers what another instance said about one of its games
+    fn observe(&mut self, _from: InstanceID, _game: GameID, _sessions: usize, _started: bool) {}
+
+    /// Keeps track of which instance the given link reaches, if any
+    fn link_changed(&mut self, _link: usize, _instance: Option<InstanceID>) {}
+
+    /// Gets how many sessions other instances have in the game, and whether any of them started it
+    fn remote_presence(&self, _game: GameID) -> (usize, bool) {
+        (0, false)
+    }
+
+    /// Sends a serialized message to everyone in the given game on other instances, or only the
+    /// given peers
+    fn publish(&mut self, game: GameID, message: &[u8], to: Option<&[PeerID]>);
+
+    /// Asks the other instances with sessions in the game to close it, returns whether there were any
+    fn close(&mut self, _game: GameID, _reason: &str) -> bool {
+        false
+    }
+}
+
+/// Keeps everything in this process, for when there's only one instance
+pub struct LocalRegistry;
+
+impl GameRegistry for LocalRegistry {
+    fn start(&mut self, _: Addr<GameServer>) {}
+
+    fn publish(&mut self, _: GameID, _: &[u8], _: Option<&[PeerID]>) {}
+}
+
+/// Shares games with a fixed list of other instances
+///
+/// Each instance needs every other instance in its `RELAY_PEERS`, since links only carry
+/// messages one way. Every instance hears how many sessions every other one has in each game,
+/// but messages for a game only go to the instances with sessions in it.
+pub struct PeerRegistry {
+    instance: InstanceID,
+    urls: Vec<String>,
+    token: String,
+    /// Each link, and the instance on the other end once it's said who it is
+    links: Vec<(Addr<PeerLink>, Option<InstanceID>)>,
+    /// How many sessions each other instance has in each game, and whether it's started there
+    presence: HashMap<GameID, HashMap<InstanceID, (usize, bool)>>,
+}
+
+impl PeerRegistry {
+    /// Reads peer relay URLs from `RELAY_PEERS` (comma-separated), if there are any,
+    /// and the secret they share from `RELAY_TOKEN`
+    pub fn from_env(instance: InstanceID) -> Option<PeerRegistry> {
+        let urls: Vec<String> = std::env::var("RELAY_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        if urls.is_empty() {
+            return None;
+        }
+        Some(PeerRegistry {
+            instance,
+            urls,
+            token: std::env::var("RELAY_TOKEN").unwrap_or_default(),
+            links: vec![],
+            presence: HashMap::new(),
+        })
+    }
+
+    /// Sends a frame down every link to an instance with sessions in the game
+    fn send_to_game(&self, game: GameID, frame: &RelayFrame) -> bool {
+        let instances = match self.presence.get(&game) {
+            Some(instances) => instances,
+            None => return false,
+        };
+        let data = serialize(frame).unwrap();
+        let mut sent = false;
+        for (link, instance) in &self.links {
+            if matches!(instance, Some(instance) if instances.contains_key(instance)) {
+                link.do_send(Publish(data.clone()));
+                sent = true;
+            }
+        }
+        sent
+    }
+}
+
+impl GameRegistry for PeerRegistry {
+    fn start(&mut self, server: Addr<GameServer>) {
+        let token = &self.token;
+        self.links = self
+            .urls
+            .iter()
+            .enumerate()
+            .map(|(index, url)| {
+                let link = PeerLink {
+                    index,
+                    url: url.clone(),
+                    token: token.clone(),
+                    server: server.clone(),
+                    sink: None,
+                }
+                    .start();
+                (link, None)
+            })
+            .collect();
+    }
+
+    fn announce(&mut self, game: GameID, sessions: usize, started: bool) {
+        let frame = RelayFrame::Presence {
+            from: self.instance,
+            game,
+            sessions,
+            started,
+        };
+        // everyone hears about every game, so anyone can answer a query about it
+        let data = serialize(&frame).unwrap();
+        for (link, _) in &self.links {
+            link.do_send(Publish(data.clone()));
+        }
+    }
+
+    fn observe(&mut self, from: InstanceID, game: GameID, sessions: usize, started: bool) {
+        if sessions > 0 {
+            self.presence.entry(game).or_default().insert(from, (sessions, started));
+        } else if let Some(instances) = self.presence.get_mut(&game) {
+            instances.remove(&from);
+            if instances.is_empty() {
+                self.presence.remove(&game);
+            }
+        }
+    }
+
+    fn link_changed(&mut self, link: usize, instance: Option<InstanceID>) {
+        let old = match self.links.get_mut(link) {
+            Some((_, old)) => std::mem::replace(old, instance),
+            None => return,
+        };
+        // whatever an instance we can't reach said about its games doesn't count anymore
+        if let (Some(old), None) = (old, instance) {
+            for instances in self.presence.values_mut() {
+                instances.remove(&old);
+            }
+            self.presence.retain(|_, instances| !instances.is_empty());
+        }
+    }
+
+    fn remote_presence(&self, game: GameID) -> (usize, bool) {
+        self.presence.get(&game).map_or((0, false), |instances| {
+            let sessions = instances.values().map(|(sessions, _)| sessions).sum();
+            let started = instances.values().any(|(_, started)| *started);
+            (sessions, started)
+        })
+    }
+
+    fn publish(&mut self, game: GameID, message: &[u8], to: Option<&[PeerID]>) {
+        let frame = RelayFrame::Message {
+            game,
+            message: message.to_vec(),
+            to: to.map(|to| to.to_vec()),
+        };
+        self.send_to_game(game, &frame);
+    }
+
+    fn close(&mut self, game: GameID, reason: &str) -> bool {
+        let frame = RelayFrame::Close {
+            game,
+            reason: reason.to_string(),
+        };
+        self.send_to_game(game, &frame)
+    }
+}
+
+/// Serialized `RelayFrame` for a link to send along
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Publish(Vec<u8>);
+
+type WsSink = SplitSink<Framed<BoxedSocket, ws::Codec>, ws::Message>;
+
+/// Outgoing connection to another instance's relay route
+pub struct PeerLink {
+    /// Where the link is in the registry's list
+    index: usize,
+    url: String,
+    token: String,
+    /// Server to tell which instance is on the other end
+    server: Addr<GameServer>,
+    sink: Option<SinkWrite<ws::Message, WsSink>>,
+}
+
+impl PeerLink {
+    fn connect(&mut self, ctx: &mut Context<Self>) {
+        let request = Client::new()
+            .ws(self.url.as_str())
+            .bearer_auth(&self.token)
+            .connect();
+        ctx.spawn(request.into_actor(self).map(|result, act, ctx| match result {
+            Ok((_, framed)) => {
+                println!("Connected to peer {}", act.url);
+                let (sink, stream) = framed.split();
+                ctx.add_stream(stream);
+                act.sink = Some(SinkWrite::new(sink, ctx));
+            }
+            Err(e) => {
+                eprintln!("Failed to connect to peer {}: {}", act.url, e);
+                ctx.run_later(RECONNECT_DELAY, |act, ctx| act.connect(ctx));
+            }
+        }));
+    }
+}
+
+impl Actor for PeerLink {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        self.connect(ctx);
+    }
+}
+
+impl Handler<Publish> for PeerLink {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _: &mut Context<Self>) {
+        // anything sent while the peer is down is lost, same as for a client that drops
+        if let Some(sink) = &mut self.sink {
+            if sink.write(ws::Message::Binary(msg.0.into())).is_err() {
+                eprintln!("Failed to send to peer {}", self.url);
+            }
+        }
+    }
+}
+
+impl WriteHandler<ProtocolError> for PeerLink {}
+
+impl StreamHandler<Result<ws::Frame, ProtocolError>> for PeerLink {
+    fn handle(&mut self, msg: Result<ws::Frame, ProtocolError>, _: &mut Context<Self>) {
+        match msg {
+            Ok(ws::Frame::Ping(data)) => {
+                if let Some(sink) = &mut self.sink {
+                    let _ = sink.write(ws::Message::Pong(data));
+                }
+            }
+            Ok(ws::Frame::Binary(data)) => match deserialize(&data) {
+                Ok(RelayFrame::Hello(instance)) => self.server.do_send(LinkChanged {
+                    link: self.index,
+                    instance: Some(instance),
+                }),
+                Ok(_) => eprintln!("Peer {} sent something other than hello", self.url),
+                Err(e) => eprintln!("Got bad relay frame from peer {}: {}", self.url, e),
+            },
+            _ => (),
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Context<Self>) {
+        eprintln!("Lost connection to peer {}", self.url);
+        self.sink = None;
+        self.server.do_send(LinkChanged {
+            link: self.index,
+            instance: None,
+        });
+        ctx.run_later(RECONNECT_DELAY, |act, ctx| act.connect(ctx));
+    }
+}
@@ -0,0 +1,125 @@
+//! Deciding where games' events are allowed to be POSTed
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// The hosts webhooks may point at on this deployment
+pub struct WebhookHosts {
+    hosts: Vec<String>,
+}
+
+impl WebhookHosts {
+    /// Reads the hosts webhooks may point at from `WEBHOOK_HOSTS` (comma-separated), if there are any,
+    /// since without it nobody gets a webhook
+    pub fn from_env() -> Option<WebhookHosts> {
+        let hosts: Vec<String> = std::env::var("WEBHOOK_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        if hosts.is_empty() {
+            None
+        } else {
+            Some(WebhookHosts { hosts })
+        }
+    }
+
+    /// Gets the host and port the given URL points at, if it's HTTPS and the host is on the list
+    pub fn check(&self, url: &str) -> Option<(String, u16)> {
+        let rest = url.strip_prefix("https://")?;
+        let authority = rest.split(&['/', '?', '#'][..]).next()?;
+        // credentials and IP literals have no business in a webhook URL
+        if authority.contains('@') || authority.starts_with('[') {
+            return None;
+        }
+        let (host, port) = match authority.rfind(':') {
+            Some(colon) => (&authority[..colon], authority[colon + 1..].parse().ok()?),
+            None => (authority, 443),
+        };
+        let host = host.to_ascii_lowercase();
+        if self.hosts.contains(&host) {
+            Some((host, port))
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks that the host only resolves to addresses out on the internet, so a webhook can't reach
+/// the server's own network
+///
+/// This blocks while the name is looked up, so it belongs off the server's thread.
+pub fn resolves_publicly(host: &str, port: u16) -> io::Result<bool> {
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    Ok(!addrs.is_empty() && addrs.iter().all(|addr| is_public(addr.ip())))
+}
+
+/// Checks that the address isn't loopback, link-local, private, or otherwise kept off the internet
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is carrier-grade NAT, which is as good as private
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || shared
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // fc00::/7 is unique local and fe80::/10 is link-local
+            let unique_local = (first & 0xfe00) == 0xfc00;
+            let link_local = (first & 0xffc0) == 0xfe80;
+            if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local {
+                return false;
+            }
+            // an IPv4 address in IPv6 clothing is only as public as the IPv4 address
+            match ip.to_ipv4() {
+                Some(ip) => is_public(IpAddr::V4(ip)),
+                None => true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts() -> WebhookHosts {
+        WebhookHosts {
+            hosts: vec!["hooks.example.com".to_string()],
+        }
+    }
+
+    #[test]
+    fn only_https_to_listed_hosts() {
+        let hosts = hosts();
+        assert_eq!(hosts.check("https://hooks.example.com/dynamaze"), Some(("hooks.example.com".to_string(), 443)));
+        assert_eq!(hosts.check("https://Hooks.Example.com:8443?x"), Some(("hooks.example.com".to_string(), 8443)));
+        assert_eq!(hosts.check("http://hooks.example.com/dynamaze"), None);
+        assert_eq!(hosts.check("https://other.example.com/dynamaze"), None);
+        assert_eq!(hosts.check("https://hooks.example.com@169.254.169.254/"), None);
+        assert_eq!(hosts.check("https://[::1]/"), None);
+    }
+
+    #[test]
+    fn private_addresses_arent_public() {
+        for ip in &["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &["::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
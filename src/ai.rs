@@ -0,0 +1,112 @@
+//! Computer players, who take their own turns on the host
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{bot, BoardController, Direction, PlayerID};
+use crate::board_controller::TurnState;
+
+/// Seconds a computer player waits before taking its turn, so everyone can follow along
+pub const THINK_TIME: f64 = 1.2;
+
+/// How well a computer player plays
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Difficulty {
+    /// Pushes tiles in wherever more often than not
+    Easy,
+    /// Usually finds the best push, but not always
+    #[default]
+    Medium,
+    /// Always finds the best push
+    Hard,
+}
+
+impl Difficulty {
+    /// Gets the difficulty after this one, for cycling through them from the lobby
+    pub fn next(self) -> Difficulty {
+        match self {
+            Difficulty::Easy => Difficulty::Medium,
+            Difficulty::Medium => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    /// Describes this difficulty for the lobby
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// Chance of pushing the loose tile in at random instead of searching for the best push
+    fn blunder_chance(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.6,
+            Difficulty::Medium => 0.25,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+/// A computer player in a game
+#[derive(Debug, Clone, Copy)]
+pub struct BotPlayer {
+    /// Which player it is
+    pub id: PlayerID,
+    /// How well it plays
+    pub difficulty: Difficulty,
+}
+
+impl BotPlayer {
+    /// Gets the computer player whose turn it is, if it's one of theirs and not a party mode round
+    pub fn up_next(controller: &BoardController) -> Option<BotPlayer> {
+        if controller.party.is_some() {
+            return None;
+        }
+        let player = controller.active_player();
+        player.bot.map(|difficulty| BotPlayer {
+            id: player.id,
+            difficulty,
+        })
+    }
+
+    /// Gets every computer player still in the game
+    pub fn all(controller: &BoardController) -> Vec<BotPlayer> {
+        controller
+            .turn_order
+            .iter()
+            .filter_map(|id| {
+                controller.players[id].bot.map(|difficulty| BotPlayer {
+                    id: *id,
+                    difficulty,
+                })
+            })
+            .collect()
+    }
+
+    /// Picks a move for this player on the board as it stands, searching every insert position
+    /// unless it blunders
+    pub fn plan(&self, controller: &BoardController, rng: &mut impl Rng) -> bot::Move {
+        if let TurnState::MoveToken = controller.turn_state {
+            // the tile's already in, so all that's left is where to go
+            return bot::best_destination(controller, self.id);
+        }
+        if !rng.gen_bool(self.difficulty.blunder_chance()) {
+            return bot::best_move(controller, self.id);
+        }
+        let sides = Direction::all();
+        let side = sides[rng.gen_range(0, sides.len())];
+        let guides = match side {
+            Direction::North | Direction::South => controller.board.width() / 2,
+            Direction::East | Direction::West => controller.board.height() / 2,
+        };
+        if guides == 0 {
+            return bot::best_move(controller, self.id);
+        }
+        let guide = rng.gen_range(0, guides);
+        let orientation = sides[rng.gen_range(0, sides.len())];
+        bot::move_with_insert(controller, self.id, ((side, guide), orientation))
+    }
+}
@@ -1,13 +1,13 @@
-use std::collections::VecDeque;
-use std::f64::consts::FRAC_PI_2;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::f64::consts::{FRAC_PI_2, PI};
 
 use serde::{Deserialize, Serialize};
 
-use crate::Direction;
-use crate::net::{Message, MetaMessage};
+use crate::{Direction, PlayerID};
+use crate::net::{Message, Outbox};
 
 /// Tracks state of the target stripe animation
+#[derive(Debug, Clone)]
 pub struct TargetStripeState {
     pub offset: f64,
 }
@@ -29,7 +29,7 @@ impl TargetStripeState {
 }
 
 /// Checks the direction in which the tile rotate animation spins
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum RotateDir {
     /// Clockwise
     CW,
@@ -38,6 +38,7 @@ pub enum RotateDir {
 }
 
 /// Tracks state of the loose tile rotate animation
+#[derive(Debug, Clone)]
 pub struct LooseRotateState {
     pub angle: f64,
 }
@@ -71,6 +72,7 @@ impl LooseRotateState {
 }
 
 /// Tracks state of loose tile insert animation
+#[derive(Debug, Clone)]
 pub struct LooseInsertState {
     /// Direction in which the tiles are currently offset
     /// (same as the edge on which the loose tile started)
@@ -105,6 +107,11 @@ impl LooseInsertState {
         self.distance_left = (self.distance_left - ticks / Self::LENGTH).max(0.0);
     }
 
+    /// Gets how many seconds are left in the animation
+    pub fn time_left(&self) -> f64 {
+        self.distance_left * Self::LENGTH
+    }
+
     pub fn applies_to_pos(&self, (row, col): (usize, usize)) -> bool {
         if self.distance_left == 0.0 {
             return false;
@@ -128,21 +135,316 @@ impl LooseInsertState {
     }
 }
 
-/// Tracks state of all currently running animations
-pub struct AnimGlobalState {
+/// Tracks tokens sliding over from where they were, for moves that showed up all at once
+#[derive(Debug, Clone)]
+pub struct TokenMoveState {
+    /// Where each sliding token came from (row, col), and seconds left in its slide, which can be
+    /// longer than the slide itself if it's waiting on something else to finish first
+    moves: BTreeMap<PlayerID, ((usize, usize), f64)>,
+}
+
+impl TokenMoveState {
+    const LENGTH: f64 = 0.3;
+
+    fn new() -> TokenMoveState {
+        TokenMoveState {
+            moves: BTreeMap::new(),
+        }
+    }
+
+    /// Slides the given player's token over from the given (row, col), after the given delay
+    pub fn start(&mut self, player: PlayerID, from: (usize, usize), delay: f64) {
+        self.moves.insert(player, (from, Self::LENGTH + delay));
+    }
+
+    fn advance_by(&mut self, ticks: f64) {
+        for (_, time_left) in self.moves.values_mut() {
+            *time_left -= ticks;
+        }
+        self.moves.retain(|_, &mut (_, time_left)| time_left > 0.0);
+    }
+
+    /// Gets where the given player's token is sliding over from, if it is, and what fraction of
+    /// the way it still has to go
+    pub fn sliding_from(&self, player: PlayerID) -> Option<((usize, usize), f64)> {
+        let &(from, time_left) = self.moves.get(&player)?;
+        Some((from, (time_left / Self::LENGTH).min(1.0)))
+    }
+}
+
+/// Tracks state of the insert guide hover and press animations
+#[derive(Debug, Clone)]
+pub struct GuideState {
+    /// Guide the cursor is over, if any
+    pub hovered: Option<(Direction, usize)>,
+    /// How far the hovered guide has grown, from 0 to 1
+    pub grow: f64,
+    /// Guide that was last pressed
+    pub pressed: (Direction, usize),
+    /// Fraction of the press animation remaining
+    pub press_left: f64,
+}
+
+impl GuideState {
+    const GROW_LENGTH: f64 = 0.15;
+    const PRESS_LENGTH: f64 = 0.3;
+
+    fn new() -> GuideState {
+        GuideState {
+            hovered: None,
+            grow: 0.0,
+            pressed: (Direction::North, 0),
+            press_left: 0.0,
+        }
+    }
+
+    /// Sets which guide the cursor is over
+    pub fn hover(&mut self, guide: Option<(Direction, usize)>) {
+        if guide != self.hovered {
+            self.hovered = guide;
+            self.grow = 0.0;
+        }
+    }
+
+    /// Starts the press animation on the given guide
+    pub fn press(&mut self, guide: (Direction, usize)) {
+        self.pressed = guide;
+        self.press_left = 1.0;
+    }
+
+    fn advance_by(&mut self, ticks: f64) {
+        if self.hovered.is_some() {
+            self.grow = (self.grow + ticks / Self::GROW_LENGTH).min(1.0);
+        }
+        self.press_left = (self.press_left - ticks / Self::PRESS_LENGTH).max(0.0);
+    }
+
+    /// Gets how much the given guide should be scaled up by
+    pub fn hover_scale(&self, guide: (Direction, usize)) -> f64 {
+        if self.hovered == Some(guide) {
+            1.0 + 0.1 * self.grow
+        } else {
+            1.0
+        }
+    }
+
+    /// Gets how far along the press animation is on the given guide, from 0 (done) to 1 (just pressed)
+    pub fn press_amount(&self, guide: (Direction, usize)) -> f64 {
+        if self.pressed == guide {
+            self.press_left
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Tracks the time left in the current turn, when there's a time limit
+#[derive(Debug, Clone)]
+pub struct TurnTimerState {
+    /// Seconds left in the turn
+    pub time_left: Option<f64>,
+    /// Turn the timer was started for
+    turn: Option<usize>,
+    /// Last whole second a warning tick went off for
+    last_tick: Option<u32>,
+}
+
+impl TurnTimerState {
+    /// Seconds left when warnings start
+    pub const WARNING: f64 = 10.0;
+    const PULSE_LENGTH: f64 = 1.0;
+
+    fn new() -> TurnTimerState {
+        TurnTimerState {
+            time_left: None,
+            turn: None,
+            last_tick: None,
+        }
+    }
+
+    /// Starts the timer over if the given turn isn't the one it's running for
+    pub fn start(&mut self, turn: usize, limit: u16) {
+        if self.turn == Some(turn) {
+            return;
+        }
+        self.turn = Some(turn);
+        self.last_tick = None;
+        self.time_left = if limit > 0 {
+            Some(f64::from(limit))
+        } else {
+            None
+        };
+    }
+
+    /// Stops the timer, so it doesn't run out
+    pub fn stop(&mut self) {
+        self.time_left = None;
+    }
+
+    /// Stops the timer and forgets which turn it was for
+    pub fn reset(&mut self) {
+        self.stop();
+        self.turn = None;
+    }
+
+    fn advance_by(&mut self, ticks: f64) {
+        if let Some(ref mut time_left) = self.time_left {
+            *time_left = (*time_left - ticks).max(0.0);
+        }
+    }
+
+    /// Checks if time has run out
+    pub fn expired(&self) -> bool {
+        self.time_left == Some(0.0)
+    }
+
+    /// Checks if a warning tick is due, which happens once a second near the end of the turn
+    pub fn tick_due(&mut self) -> bool {
+        match self.time_left {
+            Some(time_left) if time_left > 0.0 && time_left <= Self::WARNING => {
+                let second = time_left.ceil() as u32;
+                let due = self.last_tick != Some(second);
+                self.last_tick = Some(second);
+                due
+            }
+            _ => false,
+        }
+    }
+
+    /// Gets how strongly to show the warning, from 0 to 1, if it should be shown at all
+    pub fn warning_pulse(&self) -> Option<f64> {
+        let time_left = self.time_left?;
+        if time_left > Self::WARNING {
+            return None;
+        }
+        // pulses once a second, getting stronger as time runs out
+        let phase = (time_left % Self::PULSE_LENGTH) / Self::PULSE_LENGTH;
+        let urgency = 1.0 - time_left / Self::WARNING;
+        Some((phase * PI).sin() * (0.5 + urgency / 2.0))
+    }
+}
+
+/// Tracks the pulse around the token of whoever's turn it is, while we watch someone else play
+#[derive(Debug, Clone)]
+pub struct TurnPulseState {
+    phase: f64,
+}
+
+impl TurnPulseState {
+    const LENGTH: f64 = 1.5;
+
+    fn new() -> TurnPulseState {
+        TurnPulseState { phase: 0.0 }
+    }
+
+    fn advance_by(&mut self, ticks: f64) {
+        self.phase = (self.phase + ticks) % Self::LENGTH;
+    }
+
+    /// Gets how far the pulse has spread out, from 0 to 1, starting over once it gets there
+    pub fn spread(&self) -> f64 {
+        self.phase / Self::LENGTH
+    }
+}
+
+/// Number of quick-chat phrases
+pub const QUICK_CHAT_COUNT: usize = 6;
+
+/// Quick-chat phrases in each language there's a translation for, English first, which get sent by
+/// number so each player sees them in their own language
+const QUICK_CHATS: &[(&str, [&str; QUICK_CHAT_COUNT])] = &[
+    ("en", ["Good game", "Nice move", "One sec", "Oops", "Thanks", "Hurry up"]),
+    ("es", ["Buena partida", "Buena jugada", "Un momento", "Uy", "Gracias", "Date prisa"]),
+    ("fr", ["Bonne partie", "Joli coup", "Une seconde", "Oups", "Merci", "Dépêche-toi"]),
+    ("de", ["Gutes Spiel", "Guter Zug", "Moment", "Hoppla", "Danke", "Beeil dich"]),
+    ("pt", ["Bom jogo", "Boa jogada", "Um segundo", "Ops", "Obrigado", "Anda logo"]),
+];
+
+lazy_static! {
+    static ref LOCAL_QUICK_CHATS: &'static [&'static str; QUICK_CHAT_COUNT] = {
+        let language = web_sys::window().and_then(|window| window.navigator().language());
+        quick_chats_in(language.as_deref().unwrap_or("en"))
+    };
+}
+
+/// Gets the quick-chat phrases for the given language tag, like `pt-BR`, or English if there's no
+/// translation
+fn quick_chats_in(language: &str) -> &'static [&'static str; QUICK_CHAT_COUNT] {
+    let language = language.split('-').next().unwrap_or_default().to_ascii_lowercase();
+    let (_, phrases) = QUICK_CHATS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .unwrap_or(&QUICK_CHATS[0]);
+    phrases
+}
+
+/// Gets the quick-chat phrases in the browser's language
+pub fn quick_chats() -> &'static [&'static str; QUICK_CHAT_COUNT] {
+    *LOCAL_QUICK_CHATS
+}
+
+/// Tracks quick-chat bubbles that are still showing
+#[derive(Debug, Clone)]
+pub struct QuickChatState {
+    /// Phrase each player last sent, and seconds until it goes away
+    bubbles: BTreeMap<PlayerID, (usize, f64)>,
+}
+
+impl QuickChatState {
+    const LENGTH: f64 = 4.0;
+    const FADE: f64 = 0.5;
+
+    fn new() -> QuickChatState {
+        QuickChatState {
+            bubbles: BTreeMap::new(),
+        }
+    }
+
+    fn say(&mut self, player: PlayerID, phrase: usize) {
+        if phrase < QUICK_CHAT_COUNT {
+            self.bubbles.insert(player, (phrase, Self::LENGTH));
+        }
+    }
+
+    fn advance_by(&mut self, ticks: f64) {
+        for (_, time_left) in self.bubbles.values_mut() {
+            *time_left -= ticks;
+        }
+        self.bubbles.retain(|_, &mut (_, time_left)| time_left > 0.0);
+    }
+
+    /// Gets what the given player is saying, if anything, and how opaque to draw it as it fades out
+    pub fn bubble(&self, player: PlayerID) -> Option<(&'static str, f64)> {
+        let &(phrase, time_left) = self.bubbles.get(&player)?;
+        Some((quick_chats()[phrase], (time_left / Self::FADE).min(1.0)))
+    }
+}
+
+/// Tracks state of all of a board's currently running animations
+#[derive(Debug, Clone)]
+pub struct AnimState {
     pub target_stripe: TargetStripeState,
     pub loose_rotate: LooseRotateState,
     pub loose_insert: LooseInsertState,
-    net_queue: Option<Arc<Mutex<VecDeque<MetaMessage>>>>,
+    pub turn_timer: TurnTimerState,
+    pub guides: GuideState,
+    pub quick_chat: QuickChatState,
+    pub token_moves: TokenMoveState,
+    pub turn_pulse: TurnPulseState,
 }
 
-impl AnimGlobalState {
-    fn new() -> AnimGlobalState {
-        AnimGlobalState {
+impl AnimState {
+    /// Creates a state with nothing animating
+    pub fn new() -> AnimState {
+        AnimState {
             target_stripe: TargetStripeState::new(),
             loose_rotate: LooseRotateState::new(),
             loose_insert: LooseInsertState::new(),
-            net_queue: None,
+            turn_timer: TurnTimerState::new(),
+            guides: GuideState::new(),
+            quick_chat: QuickChatState::new(),
+            token_moves: TokenMoveState::new(),
+            turn_pulse: TurnPulseState::new(),
         }
     }
 
@@ -150,25 +452,37 @@ impl AnimGlobalState {
         self.target_stripe.advance_by(ticks);
         self.loose_rotate.advance_by(ticks);
         self.loose_insert.advance_by(ticks);
+        self.turn_timer.advance_by(ticks);
+        self.guides.advance_by(ticks);
+        self.quick_chat.advance_by(ticks);
+        self.token_moves.advance_by(ticks);
+        self.turn_pulse.advance_by(ticks);
     }
 
-    pub fn set_send(&mut self, send: Arc<Mutex<VecDeque<MetaMessage>>>) {
-        self.net_queue = Some(send)
+    /// Takes on the turn timer and quick-chat bubbles from the given state, since those belong to
+    /// the whole game rather than any one copy of the board
+    pub fn follow(&mut self, other: &AnimState) {
+        self.turn_timer = other.turn_timer.clone();
+        self.quick_chat = other.quick_chat.clone();
     }
 
     pub fn apply(&mut self, msg: AnimSync) {
         match msg {
             AnimSync::Rotate(dir) => self.loose_rotate.reset(dir),
             AnimSync::Insert(dir, x) => self.loose_insert.reset(dir, x),
+            AnimSync::QuickChat(player, phrase) => self.quick_chat.say(player, phrase),
         }
     }
 
-    pub fn apply_send(&mut self, sync: AnimSync) {
+    pub fn apply_send(&mut self, sync: AnimSync, outbox: &mut Outbox) {
         self.apply(sync.clone());
-        if let Some(ref mut send) = self.net_queue {
-            let message = Message::Anim(sync);
-            send.lock().unwrap().push_back(message.into());
-        }
+        outbox.push(Message::Anim(sync));
+    }
+}
+
+impl Default for AnimState {
+    fn default() -> AnimState {
+        AnimState::new()
     }
 }
 
@@ -176,8 +490,12 @@ impl AnimGlobalState {
 pub enum AnimSync {
     Rotate(RotateDir),
     Insert(Direction, usize),
+    QuickChat(PlayerID, usize),
 }
 
-lazy_static! {
-    pub static ref STATE: RwLock<AnimGlobalState> = { RwLock::new(AnimGlobalState::new()) };
+impl AnimSync {
+    /// Gets the animation for pushing the loose tile in at the given position
+    pub fn insert_at((dir, guide_idx): (Direction, usize)) -> AnimSync {
+        AnimSync::Insert(dir * Direction::South, 2 * guide_idx + 1)
+    }
 }
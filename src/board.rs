@@ -1,6 +1,7 @@
 //! Board logic
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Entry;
 
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -8,8 +9,12 @@ use serde::{Deserialize, Serialize};
 use crate::{Direction, Player, PlayerID, Shape, Tile};
 use crate::anim;
 use crate::demo;
+use crate::item::{self, Item};
 use crate::tutorial;
 
+/// Smallest the board can get from collapsing, in either direction
+const MIN_COLLAPSE_SIZE: usize = 5;
+
 /// Information about a player's token on the board
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct PlayerToken {
@@ -17,8 +22,24 @@ pub struct PlayerToken {
     pub player_id: PlayerID,
     /// Position of token (row, col)
     pub position: (usize, usize),
-    /// Number of targets reached
+    /// Number of targets reached, or points earned from them if targets are worth points
     pub score: u8,
+    /// Points the token's current target is worth, fixed when the target was placed
+    #[serde(default)]
+    pub target_value: u8,
+    /// Steps the token has the energy to move, if moving costs energy
+    #[serde(default)]
+    pub energy: u8,
+    /// Layer the token is on, in portal mode
+    #[serde(default)]
+    pub layer: usize,
+    /// Target cards still to come, with the next one in front, which go round to the back as
+    /// they're drawn so the deck never runs out
+    #[serde(default)]
+    pub deck: VecDeque<Item>,
+    /// Card for the token's current target, if it was dealt a deck
+    #[serde(default)]
+    pub card: Option<Item>,
 }
 
 impl PlayerToken {
@@ -28,20 +49,61 @@ impl PlayerToken {
             player_id: player.id,
             position,
             score: 0,
+            target_value: 0,
+            energy: 0,
+            layer: 0,
+            deck: VecDeque::new(),
+            card: None,
+        }
+    }
+
+    /// Turns over the next card in the token's deck, if it has one
+    pub fn draw_card(&mut self) {
+        if let Some(card) = self.deck.pop_front() {
+            self.deck.push_back(card);
+            self.card = Some(card);
         }
     }
 
-    /// Indicate that a player has reached their target
-    pub fn reached_target(&mut self) {
-        self.score += 1;
+    /// Indicate that a player has reached their target, which scores double if it was on a golden tile
+    pub fn reached_target(&mut self, scoring: Scoring, golden: bool) {
+        let points = match scoring {
+            Scoring::Targets => 1,
+            Scoring::Points => self.target_value.max(1),
+        };
+        let points = if golden { points.saturating_mul(2) } else { points };
+        self.score = self.score.saturating_add(points);
+    }
+
+    /// Indicate that a player has earned a bonus point
+    pub fn earned_bonus(&mut self) {
+        self.score = self.score.saturating_add(1);
     }
 }
 
+/// What a player's score counts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scoring {
+    /// Every target is worth one
+    #[default]
+    Targets,
+    /// Targets are worth more the farther away they were placed
+    Points,
+}
+
+/// Most points a target can be worth
+pub const MAX_TARGET_VALUE: u8 = 5;
+
+/// Turns' worth of energy a token can bank, if moving costs energy
+pub const ENERGY_BANK_TURNS: u8 = 3;
+
 /// Information about board state
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Board {
-    /// Cells
-    pub cells: Vec<Vec<Tile>>,
+    /// Cells, one row after another
+    pub cells: Vec<Tile>,
+    /// Number of cells in each row
+    pub width: usize,
     /// Loose tile
     pub loose_tile: Tile,
     /// Loose tile position
@@ -50,11 +112,46 @@ pub struct Board {
     pub player_tokens: BTreeMap<PlayerID, PlayerToken>,
     /// Step in tutorial, if any
     pub tutorial_step: Option<tutorial::TutorialStep>,
+    /// What reaching a target is worth
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Whether landing on someone else's target locks it until their next turn is over
+    #[serde(default)]
+    pub target_steal: bool,
+    /// Energy each token gets back at the end of its turn, or 0 if moving is free
+    #[serde(default)]
+    pub energy_per_turn: u8,
+    /// Which layer of the world this is, in portal mode
+    #[serde(default)]
+    pub layer: usize,
+}
+
+/// How far new targets can be from the player's token, counting tiles across plus tiles down
+#[derive(Clone, Copy, Debug)]
+pub struct TargetDistance {
+    /// Closest a target can be
+    pub min: usize,
+    /// Farthest a target can be, if there's a limit
+    pub max: Option<usize>,
 }
 
-fn avoid_path(tile: &mut Tile, target: Direction) {
+impl TargetDistance {
+    /// Anywhere on the board
+    pub const ANY: TargetDistance = TargetDistance { min: 0, max: None };
+
+    fn allows(&self, (row_a, col_a): (usize, usize), (row_b, col_b): (usize, usize)) -> bool {
+        let distance = (row_a as isize - row_b as isize).abs() + (col_a as isize - col_b as isize).abs();
+        let distance = distance as usize;
+        match self.max {
+            Some(max) => distance >= self.min && distance <= max,
+            None => distance >= self.min,
+        }
+    }
+}
+
+fn avoid_path(tile: &mut Tile, target: Direction, rng: &mut impl Rng) {
     while tile.paths().contains(&target) {
-        *tile = random();
+        *tile = rng.gen();
     }
 }
 
@@ -69,64 +166,73 @@ fn valid_move(ind: (usize, usize), dir: Direction, (width, height): (usize, usiz
 }
 
 impl Board {
-    /// Creates a new board
-    pub fn new(width: usize, height: usize, players: &BTreeMap<PlayerID, Player>) -> Board {
+    /// Creates a new board, with everything random drawn from the given generator
+    pub fn new(
+        width: usize,
+        height: usize,
+        players: &BTreeMap<PlayerID, Player>,
+        distance: TargetDistance,
+        rng: &mut impl Rng,
+    ) -> Board {
         if demo::is_demo() {
             return demo::new_board(players);
         }
-        let mut rng = rand::thread_rng();
         // build tiles
         let loose_tile: Tile = rng.gen();
-        let mut cells = vec![];
-        for _ in 0..height {
-            let mut row = vec![];
-            for _ in 0..width {
-                row.push(rng.gen());
-            }
-            cells.push(row);
-        }
+        let mut cells: Vec<Tile> = (0..width * height).map(|_| rng.gen()).collect();
+        let index = |row: usize, col: usize| row * width + col;
         // set corners
-        cells[0][0] = Tile {
+        cells[index(0, 0)] = Tile {
             shape: Shape::L,
             orientation: Direction::East,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         };
-        cells[0][width - 1] = Tile {
+        cells[index(0, width - 1)] = Tile {
             shape: Shape::L,
             orientation: Direction::South,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         };
-        cells[height - 1][0] = Tile {
+        cells[index(height - 1, 0)] = Tile {
             shape: Shape::L,
             orientation: Direction::North,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         };
-        cells[height - 1][width - 1] = Tile {
+        cells[index(height - 1, width - 1)] = Tile {
             shape: Shape::L,
             orientation: Direction::West,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         };
         // ensure top/bottom fixed tiles point inwards
         for i in 0..width {
             if i % 2 == 0 {
-                avoid_path(&mut cells[0][i], Direction::North);
-                avoid_path(&mut cells[height - 1][i], Direction::South);
+                avoid_path(&mut cells[index(0, i)], Direction::North, rng);
+                avoid_path(&mut cells[index(height - 1, i)], Direction::South, rng);
             }
         }
         // ensure left/right fixed tiles point inwards
-        #[allow(clippy::needless_range_loop)]
-            for i in 0..height {
+        for i in 0..height {
             if i % 2 == 0 {
-                avoid_path(&mut cells[i][0], Direction::West);
-                avoid_path(&mut cells[i][width - 1], Direction::East);
+                avoid_path(&mut cells[index(i, 0)], Direction::West, rng);
+                avoid_path(&mut cells[index(i, width - 1)], Direction::East, rng);
             }
         }
         // create tokens
         let player_tokens = players
             .values()
             .enumerate()
-            .map(move |(i, player)| {
-                let mut rng = thread_rng();
+            .map(|(i, player)| {
                 let position = match i {
                     0 => (0, 0),
                     1 => (height - 1, width - 1),
@@ -134,7 +240,11 @@ impl Board {
                     3 => (height - 1, 0),
                     _ => (rng.gen_range(0, height), rng.gen_range(0, width)),
                 };
-                (player.id, PlayerToken::new(player, position))
+                let token = PlayerToken {
+                    deck: item::deal(rng),
+                    ..PlayerToken::new(player, position)
+                };
+                (player.id, token)
             })
             .collect();
         let loose_tile_edge = rng.gen();
@@ -145,14 +255,19 @@ impl Board {
         // assign next locations
         let mut result = Board {
             cells,
+            width,
             loose_tile,
             loose_tile_position: (loose_tile_edge, loose_tile_spot),
             player_tokens,
             tutorial_step: None,
+            scoring: Scoring::Targets,
+            target_steal: false,
+            energy_per_turn: 0,
+            layer: 0,
         };
         let player_ids = result.player_tokens.keys().cloned().collect::<Vec<_>>();
         for player in &player_ids {
-            result.assign_next_target(*player);
+            result.assign_next_target(*player, distance, rng);
         }
         result
     }
@@ -177,68 +292,125 @@ impl Board {
             .collect()
     }
 
+    /// Replaces the cells with the given rows, which should all be the same length
+    pub fn set_rows(&mut self, rows: Vec<Vec<Tile>>) {
+        self.width = rows[0].len();
+        self.cells = rows.into_iter().flatten().collect();
+    }
+
+    fn index(&self, (row, col): (usize, usize)) -> usize {
+        row * self.width + col
+    }
+
     /// Gets a cell from the board
     pub fn get(&self, ind: [usize; 2]) -> &Tile {
-        &self.cells[ind[1]][ind[0]]
+        &self.cells[self.index((ind[1], ind[0]))]
+    }
+
+    /// Gets a cell from the board to change
+    pub fn get_mut(&mut self, ind: [usize; 2]) -> &mut Tile {
+        let index = self.index((ind[1], ind[0]));
+        &mut self.cells[index]
     }
 
     /// Gets the width of the board
     pub fn width(&self) -> usize {
-        self.cells[0].len()
+        self.width
     }
 
     /// Gets the height of the board
     pub fn height(&self) -> usize {
-        self.cells.len()
+        self.cells.len() / self.width
+    }
+
+    /// Inserts the loose tile at its current position, wrapping pushed-off tokens around if asked to,
+    /// and gives back the animation for it
+    pub fn insert_loose_tile(&mut self, wrap_tokens: bool) -> anim::AnimSync {
+        let sync = anim::AnimSync::insert_at(self.loose_tile_position);
+        self.push_loose_tile(wrap_tokens);
+        sync
     }
 
-    /// Inserts the loose tile at its current position
-    pub fn insert_loose_tile(&mut self) {
+    /// Gets the direction things move when the loose tile is pushed in, and the row or column that moves
+    fn push_line(&self) -> (Direction, usize) {
         let (dir, guide_idx) = self.loose_tile_position;
+        (dir * Direction::South, 2 * guide_idx + 1)
+    }
+
+    /// Checks if the given (row, col) moves when the loose tile is pushed in at its current position
+    pub fn is_pushed(&self, (row, col): (usize, usize)) -> bool {
+        let (move_dir, target_idx) = self.push_line();
+        match move_dir {
+            Direction::North | Direction::South => col == target_idx,
+            Direction::East | Direction::West => row == target_idx,
+        }
+    }
+
+    /// Gets where a token at the given (row, col) ends up when the loose tile is pushed in at its current position
+    pub fn pushed_position(&self, pos: (usize, usize), wrap_tokens: bool) -> (usize, usize) {
+        if !self.is_pushed(pos) {
+            return pos;
+        }
+        let (move_dir, _) = self.push_line();
         let dimensions = (self.width(), self.height());
+        if valid_move(pos, move_dir, dimensions) {
+            return pos + move_dir;
+        }
+        if !wrap_tokens {
+            // stay at the edge, on whatever tile got pushed underneath
+            return pos;
+        }
+        // wrap around onto the inserted tile, at the opposite edge
+        let (row, col) = pos;
         let (width, height) = dimensions;
+        match move_dir {
+            Direction::South => (0, col),
+            Direction::North => (height - 1, col),
+            Direction::East => (row, 0),
+            Direction::West => (row, width - 1),
+        }
+    }
+
+    /// Pushes the loose tile in at its current position, without animating anything
+    pub fn push_loose_tile(&mut self, wrap_tokens: bool) {
+        // move all tokens on this layer, while the loose tile is still where it's being pushed from
+        let new_positions: Vec<_> = self
+            .player_tokens
+            .iter()
+            .filter(|(_, token)| token.layer == self.layer)
+            .map(|(&id, token)| (id, self.pushed_position(token.position, wrap_tokens)))
+            .collect();
+        for (id, position) in new_positions {
+            self.move_player(id, position);
+        }
+        let (dir, guide_idx) = self.loose_tile_position;
+        let (width, height) = (self.width(), self.height());
         let target_idx = 2 * guide_idx + 1;
-        let sync = anim::AnimSync::Insert(dir * Direction::South, target_idx);
-        anim::STATE.write().unwrap().apply_send(sync);
-        // general process: copy into the current position, so start opposite correct margin
-        let (mut j, mut i) = match dir {
-            Direction::North => (height - 1, target_idx),
-            Direction::South => (0, target_idx),
-            Direction::West => (target_idx, width - 1),
-            Direction::East => (target_idx, 0),
-        };
-        let next_loose_tile = self.cells[j][i].clone();
-        while valid_move((j, i), dir, dimensions) {
-            let (next_j, next_i) = (j, i) + dir;
-            self.cells[j][i] = self.cells[next_j][next_i].clone();
-            j = next_j;
-            i = next_i;
-        }
-        self.cells[j][i] = self.loose_tile.clone();
-        self.loose_tile = next_loose_tile;
-        self.loose_tile_position.0 *= Direction::South;
-        // move all tokens
-        let move_dir = dir * Direction::South;
-        for token in self.player_tokens.values_mut() {
-            let (old_row, old_col) = token.position;
-            let should_be_target_idx = match move_dir {
-                Direction::North | Direction::South => old_col,
-                Direction::East | Direction::West => old_row,
-            };
-            if should_be_target_idx != target_idx {
-                continue;
-            }
-            token.position = if valid_move(token.position, move_dir, dimensions) {
-                token.position + (dir * Direction::South)
-            } else {
-                let (old_row, old_col) = token.position;
-                let (new_row, new_col) = match move_dir {
-                    Direction::East | Direction::West => (old_row, (old_col + width)) + move_dir,
-                    Direction::North | Direction::South => ((old_row + height), old_col) + move_dir,
+        match dir {
+            Direction::West | Direction::East => {
+                let row = &mut self.cells[target_idx * width..(target_idx + 1) * width];
+                // rotate the tile falling off round to where the loose tile goes in, then swap them
+                let entry = if dir == Direction::West {
+                    row.rotate_right(1);
+                    0
+                } else {
+                    row.rotate_left(1);
+                    width - 1
                 };
-                (new_row % height, new_col % width)
-            };
+                std::mem::swap(&mut row[entry], &mut self.loose_tile);
+            }
+            Direction::North | Direction::South => {
+                // a column isn't contiguous, so hand the loose tile down it one cell at a time instead
+                let mut indices: Vec<usize> = (0..height).map(|row| row * width + target_idx).collect();
+                if dir == Direction::South {
+                    indices.reverse();
+                }
+                for index in indices {
+                    std::mem::swap(&mut self.cells[index], &mut self.loose_tile);
+                }
+            }
         }
+        self.loose_tile_position.0 *= Direction::South;
     }
 
     /// Gets the (row, col) position of the given player
@@ -249,6 +421,25 @@ impl Board {
             .position
     }
 
+    /// Checks if the tile at the given (row, col) is one that never gets pushed,
+    /// since there's no insert guide along its row or its column
+    pub fn is_fixed(&self, (row, col): (usize, usize)) -> bool {
+        row % 2 == 0 && col % 2 == 0
+    }
+
+    /// Checks if the given player's token is on this layer
+    pub fn is_here(&self, id: PlayerID) -> bool {
+        matches!(self.player_tokens.get(&id), Some(token) if token.layer == self.layer)
+    }
+
+    /// Gets every player's (row, col)
+    pub fn player_positions(&self) -> BTreeMap<PlayerID, (usize, usize)> {
+        self.player_tokens
+            .iter()
+            .map(|(&id, token)| (id, token.position))
+            .collect()
+    }
+
     /// Moves the given player to the given (row, col)
     pub fn move_player(&mut self, id: PlayerID, pos: (usize, usize)) {
         self.player_tokens
@@ -257,34 +448,67 @@ impl Board {
             .position = pos;
     }
 
-    fn add_reachable_coords(&self, from: (usize, usize), result: &mut HashSet<(usize, usize)>) {
+    /// Gets the (row, col) of every tile with a path connecting straight to the given (row, col)
+    fn connected_coords(&self, (row, col): (usize, usize)) -> Vec<(usize, usize)> {
         let dimensions = (self.width(), self.height());
+        let mut result = vec![];
+        // for each reachable direction...
+        for dir in self.get([col, row]).paths() {
+            // if it doesn't fall off the board...
+            if valid_move((row, col), dir, dimensions) {
+                // find the connecting tile
+                let (next_row, next_col) = (row, col) + dir;
+                // if that tile connects up as well...
+                if self
+                    .get([next_col, next_row])
+                    .paths()
+                    .contains(&(dir * Direction::South))
+                {
+                    result.push((next_row, next_col));
+                }
+            }
+        }
+        result
+    }
+
+    fn add_reachable_coords(&self, from: (usize, usize), result: &mut HashSet<(usize, usize)>) {
         // result contains everything seen, frontier contains only things not yet scanned
         result.insert(from);
         let mut frontier = vec![from];
         // while frontier is nonempty...
-        while let Some((curr_row, curr_col)) = frontier.pop() {
-            // for each reachable direction...
-            for dir in self.cells[curr_row][curr_col].paths() {
-                // if it doesn't fall off the board...
-                if valid_move((curr_row, curr_col), dir, dimensions) {
-                    // find the connecting tile
-                    let (next_row, next_col) = (curr_row, curr_col) + dir;
-                    // if that tile connects up as well...
-                    if self.cells[next_row][next_col]
-                        .paths()
-                        .contains(&(dir * Direction::South))
-                    {
-                        // if we've never seen that location before...
-                        if !result.contains(&(next_row, next_col)) {
-                            // add it to frontier and result
-                            frontier.push((next_row, next_col));
-                            result.insert((next_row, next_col));
-                        }
+        while let Some(curr) = frontier.pop() {
+            for next in self.connected_coords(curr) {
+                // if we've never seen that location before...
+                if !result.contains(&next) {
+                    // add it to frontier and result
+                    frontier.push(next);
+                    result.insert(next);
+                }
+            }
+        }
+    }
+
+    /// Gets all the coordinates reachable from the given (row, col) in at most the given number of steps
+    pub fn coords_within(&self, from: (usize, usize), steps: usize) -> HashSet<(usize, usize)> {
+        let mut result = HashSet::new();
+        result.insert(from);
+        let mut frontier = vec![from];
+        // one step further out each time around
+        for _ in 0..steps {
+            let mut next_frontier = vec![];
+            for curr in frontier {
+                for next in self.connected_coords(curr) {
+                    if result.insert(next) {
+                        next_frontier.push(next);
                     }
                 }
             }
+            if next_frontier.is_empty() {
+                break;
+            }
+            frontier = next_frontier;
         }
+        result
     }
 
     /// Gets all the coordinates reachable from the given (row, col)
@@ -294,6 +518,34 @@ impl Board {
         result
     }
 
+    /// Gets the shortest way along the paths from one (row, col) to another, both ends included,
+    /// if there is one
+    pub fn path_between(&self, from: (usize, usize), to: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        let mut came_from = HashMap::new();
+        came_from.insert(from, from);
+        let mut frontier = VecDeque::new();
+        frontier.push_back(from);
+        while let Some(curr) = frontier.pop_front() {
+            if curr == to {
+                let mut path = vec![curr];
+                let mut step = curr;
+                while step != from {
+                    step = came_from[&step];
+                    path.push(step);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in self.connected_coords(curr) {
+                if let Entry::Vacant(entry) = came_from.entry(next) {
+                    entry.insert(curr);
+                    frontier.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
     /// Gets all the coordinates reachable from the given (row, col) or one tile nearby
     pub fn nearly_reachable_coords(&self, from: (usize, usize)) -> HashSet<(usize, usize)> {
         let dimensions = (self.width(), self.height());
@@ -320,8 +572,7 @@ impl Board {
         result
     }
 
-    fn assign_next_target(&mut self, player_id: PlayerID) {
-        let mut rng = rand::thread_rng();
+    fn assign_next_target(&mut self, player_id: PlayerID, distance: TargetDistance, rng: &mut impl Rng) {
         let (old_row, old_col) = self.player_tokens[&player_id].position;
         let all_targets = (0..self.height())
             .flat_map(|row| (0..self.width()).map(move |col| (row, col)))
@@ -336,26 +587,373 @@ impl Board {
             .cloned()
             .collect::<HashSet<_>>();
         let all_targets = &all_targets - &banned_targets;
+        // keep to the allowed distance, unless nothing on the board is at that distance
+        let fair_targets = all_targets
+            .iter()
+            .filter(|&&pos| distance.allows((old_row, old_col), pos))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let all_targets = if fair_targets.is_empty() {
+            all_targets
+        } else {
+            fair_targets
+        };
         let easy_targets = self.nearly_reachable_coords((old_row, old_col));
         let valid_targets = if all_targets.len() > easy_targets.len() {
             &all_targets - &easy_targets
         } else {
             all_targets
         };
-        let (row, col) = valid_targets
-            .into_iter()
-            .choose(&mut rng)
+        // HashSet order changes from run to run, so sort first to get the same pick from the same seed
+        let mut valid_targets = valid_targets.into_iter().collect::<Vec<_>>();
+        valid_targets.sort();
+        let (row, col) = *valid_targets
+            .choose(rng)
             .expect("Failed to choose next target");
-        self.cells[row][col].whose_target = Some(player_id);
+        self.place_target(player_id, (row, col));
+        self.draw_card(player_id);
+    }
+
+    /// Turns over the next card in the given player's deck, for the target they were just given
+    pub fn draw_card(&mut self, player_id: PlayerID) {
+        if let Some(token) = self.player_tokens.get_mut(&player_id) {
+            token.draw_card();
+        }
+    }
+
+    /// Puts the given player's target at the given (row, col) position, worth more points the farther
+    /// it is from their token
+    pub fn place_target(&mut self, player_id: PlayerID, (row, col): (usize, usize)) {
+        self.value_target(player_id, (row, col));
+        self.get_mut([col, row]).whose_target = Some(player_id);
+    }
+
+    /// Works out what the given player's target is worth if it's at the given (row, col) position,
+    /// which is more the farther it is from their token
+    pub fn value_target(&mut self, player_id: PlayerID, (row, col): (usize, usize)) {
+        let span = (self.width() + self.height()).saturating_sub(2).max(1);
+        if let Some(token) = self.player_tokens.get_mut(&player_id) {
+            let (token_row, token_col) = token.position;
+            let distance = (token_row as isize - row as isize).abs() + (token_col as isize - col as isize).abs();
+            let most_extra = usize::from(MAX_TARGET_VALUE - 1);
+            let extra = (distance as usize * most_extra / span).min(most_extra);
+            token.target_value = 1 + extra as u8;
+        }
+    }
+
+    /// Adds a token for a player joining partway through, at a free corner if there is one,
+    /// and gives them a target
+    pub fn add_player(&mut self, player: &Player, distance: TargetDistance, rng: &mut impl Rng) {
+        let height = self.height();
+        let width = self.width();
+        let corners = [(0, 0), (height - 1, width - 1), (0, width - 1), (height - 1, 0)];
+        let position = corners
+            .iter()
+            .cloned()
+            .find(|corner| self.player_tokens.values().all(|token| token.position != *corner))
+            .unwrap_or_else(|| (rng.gen_range(0, height), rng.gen_range(0, width)));
+        let token = PlayerToken {
+            energy: self.energy_per_turn,
+            layer: self.layer,
+            deck: item::deal(rng),
+            ..PlayerToken::new(player, position)
+        };
+        self.player_tokens.insert(player.id, token);
+        self.assign_next_target(player.id, distance, rng);
+    }
+
+    /// Checks if the board is big enough to lose its outer ring of tiles
+    pub fn can_collapse(&self) -> bool {
+        self.width() >= MIN_COLLAPSE_SIZE + 2 && self.height() >= MIN_COLLAPSE_SIZE + 2
+    }
+
+    /// Takes away the outer ring of tiles, moving tokens on it one step inward, and returns the players
+    /// whose targets went with it
+    ///
+    /// Doesn't give those players new targets, so a replay can put them back where they went the first time.
+    pub fn shrink(&mut self) -> Vec<PlayerID> {
+        let (width, height) = (self.width(), self.height());
+        let mut lost_targets = vec![];
+        let mut cells = Vec::with_capacity((width - 2) * (height - 2));
+        for (index, tile) in self.cells.drain(..).enumerate() {
+            if (1..height - 1).contains(&(index / width)) && (1..width - 1).contains(&(index % width)) {
+                cells.push(tile);
+            } else if let Some(id) = tile.whose_target {
+                lost_targets.push(id);
+            }
+        }
+        self.cells = cells;
+        self.width = width - 2;
+        for token in self.player_tokens.values_mut() {
+            let (row, col) = token.position;
+            token.position = (row.clamp(1, height - 2) - 1, col.clamp(1, width - 2) - 1);
+        }
+        // there's one fewer guide on every side, so the loose tile might have to move over
+        let (dir, guide_idx) = self.loose_tile_position;
+        let guides = match dir {
+            Direction::North | Direction::South => self.width() / 2,
+            Direction::East | Direction::West => self.height() / 2,
+        };
+        self.loose_tile_position = (dir, guide_idx.min(guides - 1));
+        lost_targets
+    }
+
+    /// Takes away the outer ring of tiles, giving new targets to anyone whose target went with it,
+    /// and returns where those new targets are
+    pub fn collapse(
+        &mut self,
+        distance: TargetDistance,
+        rng: &mut impl Rng,
+    ) -> Vec<(PlayerID, (usize, usize))> {
+        let mut new_targets = vec![];
+        for id in self.shrink() {
+            // a player who's left has nobody to give a target to
+            if !self.player_tokens.contains_key(&id) {
+                continue;
+            }
+            self.assign_next_target(id, distance, rng);
+            if let Some(target) = self.target_pos(id) {
+                new_targets.push((id, target));
+            }
+        }
+        new_targets
+    }
+
+    /// Takes a player's token and target off the board
+    pub fn remove_player(&mut self, id: PlayerID) {
+        self.player_tokens.remove(&id);
+        for tile in &mut self.cells {
+            if tile.whose_target == Some(id) {
+                tile.whose_target = None;
+                tile.locked = false;
+            }
+        }
+    }
+
+    /// Gets the (row, col) position of the given player's target, if it's on the board
+    pub fn target_pos(&self, id: PlayerID) -> Option<(usize, usize)> {
+        self.cells
+            .iter()
+            .position(|tile| tile.whose_target == Some(id))
+            .map(|index| (index / self.width, index % self.width))
+    }
+
+    /// Checks if the given player's target is locked, so they can't reach it this turn
+    pub fn target_locked(&self, id: PlayerID) -> bool {
+        self.cells
+            .iter()
+            .chain(std::iter::once(&self.loose_tile))
+            .any(|tile| tile.whose_target == Some(id) && tile.locked)
+    }
+
+    /// Ends the given player's turn, unlocking their target, locking whoever else's target
+    /// they ended up on, if they moved and targets can be stolen, and giving them back some energy
+    pub fn finish_turn(&mut self, id: PlayerID, pos: Option<(usize, usize)>) {
+        for tile in self.cells.iter_mut().chain(std::iter::once(&mut self.loose_tile)) {
+            if tile.whose_target == Some(id) {
+                tile.locked = false;
+            }
+        }
+        if let (true, Some((row, col))) = (self.target_steal, pos) {
+            let tile = self.get_mut([col, row]);
+            if matches!(tile.whose_target, Some(owner) if owner != id) {
+                tile.locked = true;
+            }
+        }
+        let (per_turn, cap) = (self.energy_per_turn, self.energy_cap());
+        if let Some(token) = self.player_tokens.get_mut(&id) {
+            token.energy = token.energy.saturating_add(per_turn).min(cap);
+        }
+    }
+
+    /// Makes moving cost the given energy per turn, or nothing if it's 0, and gives every token a turn's worth
+    pub fn set_energy_per_turn(&mut self, per_turn: u8) {
+        self.energy_per_turn = per_turn;
+        for token in self.player_tokens.values_mut() {
+            token.energy = per_turn;
+        }
+    }
+
+    /// Gets the most energy a token can bank
+    pub fn energy_cap(&self) -> u8 {
+        self.energy_per_turn.saturating_mul(ENERGY_BANK_TURNS)
+    }
+
+    /// Gets how many steps the given player has the energy to move, or `None` if moving is free
+    pub fn energy_left(&self, id: PlayerID) -> Option<u8> {
+        match self.energy_per_turn {
+            0 => None,
+            _ => Some(self.player_tokens.get(&id).map_or(0, |token| token.energy)),
+        }
+    }
+
+    /// Gets all the coordinates the given player can reach from their token, as far as their energy goes
+    pub fn reachable_by(&self, id: PlayerID) -> HashSet<(usize, usize)> {
+        let from = self.player_pos(id);
+        match self.energy_left(id) {
+            Some(steps) => self.coords_within(from, usize::from(steps)),
+            None => self.reachable_coords(from),
+        }
+    }
+
+    /// Uses up the energy it takes the given player to go the shortest way from one (row, col) to another,
+    /// which has to be done before they move
+    pub fn spend_energy(&mut self, id: PlayerID, from: (usize, usize), to: (usize, usize)) {
+        use std::convert::TryFrom;
+        if self.energy_per_turn == 0 {
+            return;
+        }
+        let steps = self.path_between(from, to).map_or(0, |path| path.len() - 1);
+        if let Some(token) = self.player_tokens.get_mut(&id) {
+            token.energy = token.energy.saturating_sub(u8::try_from(steps).unwrap_or(u8::MAX));
+        }
+    }
+
+    /// Makes the given number of random tiles golden, so targets reached on them score double
+    pub fn gild_tiles(&mut self, count: usize, rng: &mut impl Rng) {
+        let count = count.min(self.cells.len());
+        for i in rand::seq::index::sample(rng, self.cells.len(), count).into_iter() {
+            self.cells[i].golden = true;
+        }
+    }
+
+    /// Makes the given number of random tiles into portals
+    pub fn place_portals(&mut self, count: usize, rng: &mut impl Rng) {
+        let count = count.min(self.cells.len());
+        for i in rand::seq::index::sample(rng, self.cells.len(), count).into_iter() {
+            self.cells[i].portal = true;
+        }
     }
 
     /// Indicates that the given player has reached their target
-    pub fn player_reached_target(&mut self, player_id: PlayerID) {
+    pub fn player_reached_target(&mut self, player_id: PlayerID, distance: TargetDistance, rng: &mut impl Rng) {
         if let Some(token) = self.player_tokens.get_mut(&player_id) {
             let (row, col) = token.position;
-            self.cells[row][col].whose_target = None;
-            token.score += 1;
-            self.assign_next_target(player_id);
+            let cell = &mut self.cells[row * self.width + col];
+            cell.whose_target = None;
+            token.reached_target(self.scoring, cell.golden);
+            self.assign_next_target(player_id, distance, rng);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    /// Builds a board of crossroads, wider than it is tall, with the loose tile waiting at the given position
+    fn board(loose_tile_position: (Direction, usize)) -> Board {
+        let (width, height) = (9, 7);
+        let cross = || Tile::try_from('┼').unwrap();
+        Board {
+            cells: (0..width * height).map(|_| cross()).collect(),
+            width,
+            loose_tile: cross(),
+            loose_tile_position,
+            player_tokens: BTreeMap::new(),
+            tutorial_step: None,
+            scoring: Scoring::default(),
+            target_steal: false,
+            energy_per_turn: 0,
+            layer: 0,
+        }
+    }
+
+    #[test]
+    fn push_from_west() {
+        // the second guide on the west side pushes row 3 east
+        let board = board((Direction::West, 1));
+        assert_eq!(board.pushed_position((3, 2), false), (3, 3));
+        assert_eq!(board.pushed_position((2, 2), false), (2, 2));
+        assert_eq!(board.pushed_position((3, 8), false), (3, 8));
+        assert_eq!(board.pushed_position((3, 8), true), (3, 0));
+    }
+
+    #[test]
+    fn push_from_east() {
+        // the first guide on the east side pushes row 1 west
+        let board = board((Direction::East, 0));
+        assert_eq!(board.pushed_position((1, 5), false), (1, 4));
+        assert_eq!(board.pushed_position((3, 5), false), (3, 5));
+        assert_eq!(board.pushed_position((1, 0), false), (1, 0));
+        assert_eq!(board.pushed_position((1, 0), true), (1, 8));
+    }
+
+    #[test]
+    fn push_from_north() {
+        // the fourth guide on the north side pushes column 7 south
+        let board = board((Direction::North, 3));
+        assert_eq!(board.pushed_position((2, 7), false), (3, 7));
+        assert_eq!(board.pushed_position((2, 6), false), (2, 6));
+        assert_eq!(board.pushed_position((6, 7), false), (6, 7));
+        assert_eq!(board.pushed_position((6, 7), true), (0, 7));
+    }
+
+    #[test]
+    fn push_from_south() {
+        // the third guide on the south side pushes column 5 north
+        let board = board((Direction::South, 2));
+        assert_eq!(board.pushed_position((4, 5), false), (3, 5));
+        assert_eq!(board.pushed_position((4, 3), false), (4, 3));
+        assert_eq!(board.pushed_position((0, 5), false), (0, 5));
+        assert_eq!(board.pushed_position((0, 5), true), (6, 5));
+    }
+
+    #[test]
+    fn targets_draw_from_the_deck() {
+        let mut rng = StdRng::seed_from_u64(4001);
+        let mut board = board((Direction::West, 0));
+        board.add_player(&Player::new_guest(1), TargetDistance::ANY, &mut rng);
+        let token = &board.player_tokens[&1];
+        let deck: Vec<Item> = token.deck.iter().copied().collect();
+        assert_eq!(deck.len(), Item::ALL.len());
+        // the card that was drawn went round to the back
+        assert_eq!(token.card, deck.last().copied());
+        board.player_reached_target(1, TargetDistance::ANY, &mut rng);
+        assert_eq!(board.player_tokens[&1].card, Some(deck[0]));
+    }
+
+    /// Measures bincode size and time for the cells alone, flat and as nested rows like they used to
+    /// be; run with `cargo test --release -- --ignored --nocapture cell_serialization`
+    #[test]
+    #[ignore]
+    fn cell_serialization() {
+        use std::time::Instant;
+
+        use bincode::{deserialize, serialize};
+
+        const RUNS: u32 = 100_000;
+
+        fn time<T>(mut run: impl FnMut() -> T) -> u128 {
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                std::hint::black_box(run());
+            }
+            start.elapsed().as_nanos() / u128::from(RUNS)
+        }
+
+        let mut rng = StdRng::seed_from_u64(3942);
+        for &size in &[7, 15] {
+            let flat: Vec<Tile> = (0..size * size).map(|_| rng.gen()).collect();
+            let nested: Vec<Vec<Tile>> = flat.chunks(size).map(<[Tile]>::to_vec).collect();
+            let flat_data = serialize(&(&flat, size)).unwrap();
+            let nested_data = serialize(&nested).unwrap();
+            println!(
+                "{0}x{0} nested: {1} bytes, serialize {2}ns, deserialize {3}ns",
+                size,
+                nested_data.len(),
+                time(|| serialize(&nested).unwrap()),
+                time(|| deserialize::<Vec<Vec<Tile>>>(&nested_data).unwrap()),
+            );
+            println!(
+                "{0}x{0} flat:   {1} bytes, serialize {2}ns, deserialize {3}ns",
+                size,
+                flat_data.len(),
+                time(|| serialize(&(&flat, size)).unwrap()),
+                time(|| deserialize::<(Vec<Tile>, usize)>(&flat_data).unwrap()),
+            );
         }
     }
 }
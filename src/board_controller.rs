@@ -1,14 +1,78 @@
 //! Board controller
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::Hasher;
+use std::mem;
+use std::sync::{Arc, Mutex};
 
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use web_sys::CanvasRenderingContext2d as Context;
 
 use crate::{Board, BoardView, Direction, Player, PlayerID};
-use crate::anim::{self, AnimSync, RotateDir};
+use crate::ai;
+use crate::anim::{AnimState, AnimSync, RotateDir};
+use crate::board::{Scoring, TargetDistance, ENERGY_BANK_TURNS, MAX_TARGET_VALUE};
+use crate::board_view::Cursor;
+use crate::bot;
 use crate::demo;
+use crate::input::InputAction;
+use crate::options;
+use crate::party::PartyRound;
+use crate::net::{Message, Outbox};
+use crate::replay::{GameEvent, GameLog};
+use crate::seasonal::{self, Modifier, SeasonalEvent};
+use crate::series::SeriesState;
+use crate::trace;
+use crate::world::World;
+
+/// Board size presets offered in the lobby, as (name, side length)
+pub const SIZE_PRESETS: [(&str, usize); 4] = [("Small", 7), ("Classic", 9), ("Large", 13), ("Huge", 21)];
+
+/// Name, board size, score limit, and seconds per turn, set all at once from the lobby
+pub type RulePreset = (&'static str, usize, u8, u16);
+
+/// Rule presets for the lobby
+pub const RULE_PRESETS: [RulePreset; 3] = [
+    ("Quick", 7, 5, 30),
+    ("Classic", 9, 10, 0),
+    ("Marathon", 13, 20, 0),
+];
+
+/// Smallest side length the board can have
+const MIN_SIZE: usize = 3;
+
+/// Largest side length the board can have
+const MAX_SIZE: usize = 21;
+
+/// How many times longer than it is wide the board can be
+const MAX_ASPECT_RATIO: usize = 3;
+
+/// Most seconds a turn can be limited to
+pub const MAX_TURN_TIME_LIMIT: u16 = 600;
+
+/// Most seconds a turn can take and still count toward a speed bonus
+pub const MAX_SPEED_BONUS: u16 = 120;
+
+/// Most hints each player can be allowed
+pub const MAX_HINT_LIMIT: u8 = 20;
+
+/// Seed for the lobby's board preview, when the settings don't pick one
+const PREVIEW_SEED: u64 = 0x5eed;
+
+/// Seconds a disconnected player gets to come back before their turn is skipped
+const AWAY_GRACE_PERIOD: f64 = 30.0;
+/// Seconds a disconnected player can stay gone before they're taken out of the game for good
+const AWAY_RETIRE_PERIOD: f64 = 300.0;
+
+/// Quick turns in a row it takes to earn a speed bonus
+pub const SPEED_STREAK: u8 = 3;
+
+/// Turns of the log sent along with the board mid-game, which is plenty for anyone who's been
+/// following along to pick up where their copy left off
+const BROADCAST_LOG_TURNS: usize = 8;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TurnState {
@@ -27,6 +91,55 @@ pub struct BoardSettings {
     pub height: usize,
     /// Score required to win
     pub score_limit: u8,
+    /// Seconds allowed for each turn, or 0 for no limit
+    pub turn_time_limit: u16,
+    /// Whether tokens pushed off the edge wrap around to the inserted tile, instead of staying put
+    pub wrap_tokens: bool,
+    /// Seed for everything random on the board, or `None` for a different game every time
+    pub seed: Option<u64>,
+    /// Fewest tiles (across plus down) a new target can be from the player's token
+    pub min_target_distance: u8,
+    /// Most tiles (across plus down) a new target can be from the player's token, or 0 for no limit
+    pub max_target_distance: u8,
+    /// Hints each player can ask for over the game
+    #[serde(default)]
+    pub hint_limit: u8,
+    /// Whether this is a practice sandbox, where tiles and tokens can be moved around freely
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Boards to play in a row, adding up scores across all of them
+    #[serde(default)]
+    pub series_length: u8,
+    /// Turns between the board's outer ring collapsing, or 0 for never
+    #[serde(default)]
+    pub collapse_every: u16,
+    /// Steps along the paths each player can see from their token, or 0 to see the whole board
+    #[serde(default)]
+    pub fog_distance: u8,
+    /// Whether everyone plans their turn at once, and the host plays the plans out in turn order
+    #[serde(default)]
+    pub party: bool,
+    /// Theme everyone draws the board in, if the host is making everyone use theirs
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Whether the score limit counts targets reached, or points from how far away they were
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Whether landing on someone else's target locks it, so they can't reach it on their next turn
+    #[serde(default)]
+    pub target_steal: bool,
+    /// Whether each player gets one turn a game where they insert the loose tile twice before moving
+    #[serde(default)]
+    pub double_push: bool,
+    /// Whether the game is played across two boards, linked by portal tiles
+    #[serde(default)]
+    pub portals: bool,
+    /// Seconds a turn has to be finished in to count toward a speed bonus, or 0 for no bonus
+    #[serde(default)]
+    pub speed_bonus: u16,
+    /// Energy each token gets back every turn, with each step costing one, or 0 for moving as far as the paths go
+    #[serde(default)]
+    pub energy_per_turn: u8,
     /// Version (increases monotonically, for replicating edits in lobby)
     pub version: usize,
 }
@@ -37,9 +150,347 @@ impl Default for BoardSettings {
             width: 7,
             height: 7,
             score_limit: 10,
+            turn_time_limit: 0,
+            wrap_tokens: true,
+            seed: None,
+            min_target_distance: 0,
+            max_target_distance: 0,
+            hint_limit: 0,
+            sandbox: false,
+            series_length: 1,
+            collapse_every: 0,
+            fog_distance: 0,
+            party: false,
+            theme: None,
+            scoring: Scoring::Targets,
+            target_steal: false,
+            double_push: false,
+            portals: false,
+            speed_bonus: 0,
+            energy_per_turn: 0,
+            version: 0,
+        }
+    }
+}
+
+impl BoardSettings {
+    /// Checks if a board can be built with the given dimensions, explaining why not if it can't
+    pub fn check_size(width: usize, height: usize) -> Result<(), String> {
+        for &(name, size) in &[("Width", width), ("Height", height)] {
+            if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+                return Err(format!("{} must be between {} and {}", name, MIN_SIZE, MAX_SIZE));
+            }
+            // fixed tiles sit on even rows and columns, so an even size would leave an edge without any
+            if size % 2 == 0 {
+                return Err(format!("{} must be odd", name));
+            }
+        }
+        if width.max(height) > width.min(height) * MAX_ASPECT_RATIO {
+            return Err(format!(
+                "The board can be at most {} times as long as it is wide",
+                MAX_ASPECT_RATIO
+            ));
+        }
+        Ok(())
+    }
+
+    /// Gets the name of the size preset these settings match, if any
+    pub fn size_preset(&self) -> Option<&'static str> {
+        SIZE_PRESETS
+            .iter()
+            .find(|&&(_, size)| self.width == size && self.height == size)
+            .map(|&(name, _)| name)
+    }
+
+    /// Checks if these settings match the given rule preset
+    pub fn matches_rule_preset(&self, (_, size, score_limit, turn_time_limit): RulePreset) -> bool {
+        self.width == size
+            && self.height == size
+            && self.score_limit == score_limit
+            && self.turn_time_limit == turn_time_limit
+    }
+
+    /// Packs these settings into a short code to paste into another lobby, leaving out the seed and
+    /// version like saved lobby settings do
+    pub fn share_code(&self) -> String {
+        let settings = BoardSettings {
+            seed: None,
             version: 0,
+            ..self.clone()
+        };
+        // JSON leaves out nothing a later version can't fill in with its defaults
+        let data = serde_json::to_vec(&settings).expect("Failed to serialize settings");
+        // btoa takes each byte as a character of its own
+        let binary: String = data.into_iter().map(char::from).collect();
+        web_sys::window()
+            .and_then(|window| window.btoa(&binary).ok())
+            .unwrap_or_default()
+    }
+
+    /// Unpacks settings from a code made by `share_code`, explaining why not if it can't
+    pub fn from_share_code(code: &str) -> Result<BoardSettings, String> {
+        let invalid = || "That isn't a settings code from this version of DynaMaze".to_string();
+        let binary = web_sys::window()
+            .and_then(|window| window.atob(code.trim()).ok())
+            .ok_or_else(invalid)?;
+        let data = binary
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let settings: BoardSettings = serde_json::from_slice(&data).map_err(|_| invalid())?;
+        BoardSettings::check_size(settings.width, settings.height)?;
+        settings.check_limits()?;
+        Ok(settings)
+    }
+
+    /// Checks that the score limit, hint limit, and time limits are ones the lobby form could have
+    /// set, explaining why not if they aren't
+    pub fn check_limits(&self) -> Result<(), String> {
+        if !(1..=self.max_score_limit()).contains(&self.score_limit) {
+            return Err(format!("Score limit must be between 1 and {}", self.max_score_limit()));
+        }
+        if self.hint_limit > MAX_HINT_LIMIT {
+            return Err(format!("Hint limit can be at most {}", MAX_HINT_LIMIT));
+        }
+        if self.turn_time_limit > MAX_TURN_TIME_LIMIT {
+            return Err(format!("Turn time limit can be at most {} seconds", MAX_TURN_TIME_LIMIT));
+        }
+        if self.speed_bonus > MAX_SPEED_BONUS {
+            return Err(format!("Speed bonus time can be at most {} seconds", MAX_SPEED_BONUS));
+        }
+        Ok(())
+    }
+
+    /// Sets the size, score limit, and turn time limit to the given rule preset
+    pub fn apply_rule_preset(&mut self, (_, size, score_limit, turn_time_limit): RulePreset) {
+        self.width = size;
+        self.height = size;
+        self.score_limit = score_limit;
+        self.turn_time_limit = turn_time_limit;
+    }
+
+    /// Gets how far new targets can be from the player's token
+    pub fn target_distance(&self) -> TargetDistance {
+        TargetDistance {
+            min: self.min_target_distance as usize,
+            max: match self.max_target_distance {
+                0 => None,
+                max => Some(max as usize),
+            },
+        }
+    }
+
+    /// Builds a board like these settings would make, with nobody on it, for the lobby's preview
+    pub fn preview_board(&self) -> Board {
+        let mut rng = StdRng::seed_from_u64(self.seed.unwrap_or(PREVIEW_SEED));
+        Board::new(self.width, self.height, &BTreeMap::new(), self.target_distance(), &mut rng)
+    }
+
+    /// Describes what happens to tokens pushed off the edge of the board
+    pub fn wrap_rule(&self) -> &'static str {
+        if self.wrap_tokens {
+            "Tokens pushed off the edge wrap around onto the inserted tile"
+        } else {
+            "Tokens at the far edge stay put when their row or column is pushed"
+        }
+    }
+
+    /// Gets the highest score limit worth offering, which is higher when targets are worth points
+    pub fn max_score_limit(&self) -> u8 {
+        match self.scoring {
+            Scoring::Targets => 20,
+            Scoring::Points => 100,
         }
     }
+
+    /// Lists the rules these settings make for, in plain language
+    pub fn rules(&self) -> Vec<String> {
+        if self.sandbox {
+            return vec![
+                "No turns and no scoring, just practice".to_string(),
+                "Click a tile to turn it clockwise, or right click to turn it the other way".to_string(),
+                "Drag your token to put it anywhere".to_string(),
+                "Insert the loose tile from any guide, as often as you like".to_string(),
+                self.wrap_rule().to_string(),
+            ];
+        }
+        let size = match self.size_preset() {
+            Some(name) => format!("{} board, {} by {} tiles", name, self.width, self.height),
+            None => format!("{} by {} board", self.width, self.height),
+        };
+        let turn_time = match self.turn_time_limit {
+            0 => "No time limit on turns".to_string(),
+            limit => format!(
+                "{} seconds per turn, after which the tile goes in and the token stays put",
+                limit
+            ),
+        };
+        let distance = match (self.min_target_distance, self.max_target_distance) {
+            (0, 0) => "New targets can be anywhere on the board".to_string(),
+            (min, 0) => format!("New targets are at least {} tiles away", min),
+            (0, max) => format!("New targets are at most {} tiles away", max),
+            (min, max) => format!("New targets are {} to {} tiles away", min, max),
+        };
+        vec![
+            match self.scoring {
+                Scoring::Targets => format!("First to reach {} targets wins", self.score_limit),
+                Scoring::Points => format!(
+                    "First to {} points wins, with targets worth 1 to {} points depending on how far away they are",
+                    self.score_limit, MAX_TARGET_VALUE
+                ),
+            },
+            size,
+            turn_time,
+            self.wrap_rule().to_string(),
+            distance,
+            if self.target_steal {
+                "Ending a move on someone else's target locks it, so they can't reach it on their next turn".to_string()
+            } else {
+                "Targets can only be reached, not stolen".to_string()
+            },
+            match self.hint_limit {
+                0 => "No hints".to_string(),
+                _ if self.party => "No hints in party mode".to_string(),
+                1 => "Each player gets one hint".to_string(),
+                limit => format!("Each player gets {} hints", limit),
+            },
+            if self.double_push {
+                "Once a game, each player can push twice before moving".to_string()
+            } else {
+                "One push per turn".to_string()
+            },
+            match self.speed_bonus {
+                0 => "No bonus for quick turns".to_string(),
+                _ if self.party => "No speed bonus in party mode".to_string(),
+                seconds => format!(
+                    "Finishing {} turns in a row within {} seconds each earns a bonus point",
+                    SPEED_STREAK, seconds
+                ),
+            },
+            match self.energy_per_turn {
+                0 => "Tokens can move as far as the paths go".to_string(),
+                energy => format!(
+                    "Each step costs one energy, and tokens get {} back every turn, banking up to {}",
+                    energy,
+                    energy.saturating_mul(ENERGY_BANK_TURNS)
+                ),
+            },
+            "If two players reach the score limit, whoever got there first wins".to_string(),
+            if self.party {
+                "Party mode: everyone plans their turn at once, then the plans play out in turn order, \
+                 so later plans may have to make do with a shifted board"
+                    .to_string()
+            } else {
+                "Players take their turns one at a time".to_string()
+            },
+            match self.fog_distance {
+                0 => "Everyone can see the whole board".to_string(),
+                1 => "Players can only see one step along the paths from their token, until the game's over".to_string(),
+                distance => format!(
+                    "Players can only see {} steps along the paths from their token, until the game's over",
+                    distance
+                ),
+            },
+            match (self.portals, self.party) {
+                (false, _) => "Just the one board, with no portals".to_string(),
+                (true, true) => "No portals in party mode".to_string(),
+                (true, false) => "Two boards linked by portal tiles: ending a move on a portal takes your token \
+                                  through to the same spot on the other board, and each new target is on the board \
+                                  you're not on"
+                    .to_string(),
+            },
+            match self.collapse_every {
+                0 => "The board stays the same size".to_string(),
+                _ if self.portals && !self.party => "The boards stay the same size in portal mode".to_string(),
+                turns => format!(
+                    "Every {} turns the outer ring of tiles collapses, pushing tokens on it inward",
+                    turns
+                ),
+            },
+            match self.series_length {
+                0 | 1 => "Just the one board".to_string(),
+                length => format!(
+                    "{} boards in a row, and whoever reaches the most targets over all of them is champion",
+                    length
+                ),
+            },
+        ]
+    }
+}
+
+/// What each key does, for the rules screen
+pub const CONTROL_HINTS: &[&str] = &[
+    "Arrow keys or WASD: pick where to insert the tile, then where to move",
+    "Left/Right Shift: rotate the loose tile",
+    "Space: insert the tile, or move to the highlighted spot",
+    "Players sharing a screen can each get their own keys or gamepad in the lobby",
+    "Click: insert at a guide, rotate the loose tile, or move to a tile",
+    "C: show or hide coaching",
+    "L: show or hide the legend, which labels everything on the board",
+    "1 to 6: send a quick-chat phrase",
+    "H: show or hide these rules",
+    "R: turn the loose tile the suggested way, if the rotate assist is on in Options",
+    "G: ask for a hint, if there are any left",
+];
+
+/// Tiles reachable from each position, in however many steps, worked out as they're needed and forgotten when the tiles move
+///
+/// Boxed so it doesn't make every message with a board in it bigger.
+#[derive(Debug, Default)]
+pub struct ReachableCache(Box<Mutex<HashMap<ReachableKey, Reachable>>>);
+
+/// The set of tiles reachable from one position
+pub type Reachable = Arc<HashSet<(usize, usize)>>;
+
+/// Where reachable tiles were worked out from (row, col), and how many steps could be taken, if that's limited
+type ReachableKey = ((usize, usize), Option<u8>);
+
+impl Clone for ReachableCache {
+    fn clone(&self) -> Self {
+        ReachableCache(Box::new(Mutex::new(self.0.lock().unwrap().clone())))
+    }
+}
+
+/// Best loose tile orientation for each insert position, forgotten along with reachable tiles
+#[derive(Debug, Default)]
+pub struct SuggestionCache(Box<Mutex<HashMap<(Direction, usize), Direction>>>);
+
+impl Clone for SuggestionCache {
+    fn clone(&self) -> Self {
+        SuggestionCache(Box::new(Mutex::new(self.0.lock().unwrap().clone())))
+    }
+}
+
+/// How the winner beat anyone else who reached the score limit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WinReason {
+    /// Nobody else reached the score limit
+    Alone,
+    /// Reached the score limit on an earlier turn than anyone else who did
+    ReachedFirst,
+    /// Tied with someone else, and came earlier in the turn order
+    TurnOrder,
+}
+
+impl WinReason {
+    /// Explains how the tie was broken, if there was one
+    pub fn describe(self) -> Option<&'static str> {
+        match self {
+            WinReason::Alone => None,
+            WinReason::ReachedFirst => Some("Reached the score limit before anyone else"),
+            WinReason::TurnOrder => Some("Tied for the score limit, but came first in the turn order"),
+        }
+    }
+}
+
+/// Where each token was at the start of the last two turns
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TurnStarts {
+    /// Where each token was when the current turn started
+    pub current: BTreeMap<PlayerID, (usize, usize)>,
+    /// Where each token was when the previous turn started, for drawing ghosts
+    pub previous: BTreeMap<PlayerID, (usize, usize)>,
 }
 
 /// Handles events for DynaMaze game session
@@ -57,8 +508,77 @@ pub struct BoardController {
     pub turn_order: Vec<PlayerID>,
     /// Current turn state
     pub turn_state: TurnState,
-    /// Settings
-    pub settings: BoardSettings,
+    /// Settings, boxed to keep the board small
+    pub settings: Box<BoardSettings>,
+    /// Everything that's happened so far, for review after the game
+    pub log: Box<GameLog>,
+    /// Players whose client has disconnected, and when (by the host's clock)
+    pub away: BTreeMap<PlayerID, f64>,
+    /// Hints each player has asked for so far
+    pub hints_used: BTreeMap<PlayerID, u8>,
+    /// Hint for the current turn, if the active player asked for one, boxed to keep the board small
+    pub hint: Option<Box<bot::Move>>,
+    /// Players who've used their double push
+    #[serde(default)]
+    pub double_pushes_used: BTreeSet<PlayerID>,
+    /// Whether the active player is pushing twice this turn and hasn't made the first push yet
+    #[serde(default)]
+    pub double_pushing: bool,
+    /// Quick turns each player has finished in a row toward their next speed bonus
+    #[serde(default)]
+    pub speed_streaks: BTreeMap<PlayerID, u8>,
+    /// Limited-time events that were on when the game started, which stay on until it's over
+    #[serde(default)]
+    pub events: Vec<SeasonalEvent>,
+    /// Layers set aside, if this is a portal mode game
+    #[serde(default)]
+    pub world: Option<Box<World>>,
+    /// Scores from earlier boards, if this board is part of a series
+    #[serde(default)]
+    pub series: Option<Box<SeriesState>>,
+    /// Where tokens were at the start of recent turns, boxed to keep the board small
+    #[serde(default)]
+    pub turn_starts: Box<TurnStarts>,
+    /// Plans for the current round, if this is a party mode board
+    #[serde(default)]
+    pub party: Option<Box<PartyRound>>,
+    /// Whether this is a copy of a party mode board that a player is planning their turn on
+    #[serde(skip)]
+    draft: bool,
+    /// Reachable tiles, which only change when a tile gets inserted
+    #[serde(skip)]
+    reachable: ReachableCache,
+    /// Suggested loose tile orientations, which change along with reachable tiles
+    #[serde(skip)]
+    suggestions: SuggestionCache,
+    /// Whether the player's token is being dragged around the sandbox
+    #[serde(skip)]
+    dragging_token: bool,
+    /// Animations for everyone else, waiting for the game controller to send them
+    #[serde(skip)]
+    outbox: Outbox,
+    /// Animations running on this board, boxed to keep the board small
+    #[serde(skip)]
+    pub anim: Box<AnimState>,
+    /// Source of targets for the host, seeded from the settings if they have a seed
+    ///
+    /// This isn't sent anywhere, so deserializing leaves it empty until it's needed, and then a
+    /// seeded game picks it back up from the seed and how far along the log is.
+    #[serde(skip)]
+    rng: Option<Box<StdRng>>,
+}
+
+fn fresh_rng() -> Box<StdRng> {
+    Box::new(trace::rng().unwrap_or_else(StdRng::from_entropy))
+}
+
+/// Gets the source of targets, picking it back up if the board was just deserialized
+fn resume_rng(rng: &mut Option<Box<StdRng>>, seed: Option<u64>, events: usize) -> &mut StdRng {
+    rng.get_or_insert_with(|| match seed {
+        // mixed in so boards saved a few events apart don't share a stream
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed ^ (events as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))),
+        None => fresh_rng(),
+    })
 }
 
 impl BoardController {
@@ -70,13 +590,47 @@ impl BoardController {
     ) -> BoardController {
         let width = settings.width;
         let height = settings.height;
+        let mut rng = match settings.seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => fresh_rng(),
+        };
         let mut player_ids: Vec<PlayerID> = player_list.iter().map(|p| p.id).collect();
         if !demo::is_demo() {
-            player_ids.shuffle(&mut thread_rng());
+            player_ids.shuffle(&mut *rng);
+        }
+        let players: BTreeMap<PlayerID, Player> = player_list.into_iter().map(|p| (p.id, p)).collect();
+        let series_players: Vec<Player> = player_ids.iter().map(|id| players[id].clone()).collect();
+        let series = SeriesState::new(settings.series_length, &series_players).map(Box::new);
+        let mut board = Board::new(width, height, &players, settings.target_distance(), &mut *rng);
+        board.scoring = settings.scoring;
+        board.target_steal = settings.target_steal;
+        if !settings.sandbox {
+            board.set_energy_per_turn(settings.energy_per_turn);
+        }
+        // the demo should look the same whatever day it is
+        let events = if demo::is_demo() { vec![] } else { seasonal::active_events() };
+        for modifier in events.iter().flat_map(|event| &event.modifiers) {
+            if let Modifier::DoubleScoreTiles(count) = *modifier {
+                board.gild_tiles(usize::from(count), &mut *rng);
+            }
         }
-        let players = player_list.into_iter().map(|p| (p.id, p)).collect();
-        let board = Board::new(width, height, &players);
+        // planning turns on a copy of the board doesn't mix with swapping layers in and out
+        let world = if settings.portals && !settings.party && !settings.sandbox {
+            Some(Box::new(World::new(&mut board, settings.target_distance(), &mut *rng)))
+        } else {
+            None
+        };
         let highlighted_tile = board.player_pos(player_ids[0]);
+        let log = Box::new(GameLog::new(board.clone(), world.as_deref().cloned(), player_ids.clone()));
+        let turn_starts = Box::new(TurnStarts {
+            current: board.player_positions(),
+            previous: BTreeMap::new(),
+        });
+        let party = if settings.party && !settings.sandbox {
+            Some(Box::new(PartyRound::default()))
+        } else {
+            None
+        };
         BoardController {
             board,
             highlighted_tile,
@@ -84,21 +638,322 @@ impl BoardController {
             host_id,
             turn_order: player_ids,
             turn_state: TurnState::InsertTile,
-            settings,
+            settings: Box::new(settings),
+            log,
+            away: BTreeMap::new(),
+            hints_used: BTreeMap::new(),
+            hint: None,
+            double_pushes_used: BTreeSet::new(),
+            double_pushing: false,
+            speed_streaks: BTreeMap::new(),
+            events,
+            world,
+            series,
+            turn_starts,
+            party,
+            draft: false,
+            reachable: Default::default(),
+            suggestions: Default::default(),
+            dragging_token: false,
+            outbox: Outbox::default(),
+            anim: Default::default(),
+            rng: Some(rng),
+        }
+    }
+
+    /// Starts the game log over from the current board, for when the board is replaced wholesale
+    pub fn restart_log(&mut self) {
+        *self.log = GameLog::new(self.board.clone(), self.world.as_deref().cloned(), self.turn_order.clone());
+        *self.turn_starts = TurnStarts {
+            current: self.board.player_positions(),
+            previous: BTreeMap::new(),
+        };
+        self.reachable = Default::default();
+        self.suggestions = Default::default();
+    }
+
+    /// Rebuilds this game as it was after the given number of turns
+    pub fn replay_at(&self, turns: usize) -> BoardController {
+        let (board, world) = self.log.state_after(turns, self.settings.wrap_tokens);
+        let turn_order = self.log.turn_order_after(turns);
+        let highlighted_tile = board.player_pos(turn_order[0]);
+        let turn_starts = Box::new(TurnStarts {
+            current: board.player_positions(),
+            previous: match turns {
+                0 => BTreeMap::new(),
+                _ => self
+                    .log
+                    .board_after(turns - 1, self.settings.wrap_tokens)
+                    .player_positions(),
+            },
+        });
+        BoardController {
+            board,
+            highlighted_tile,
+            players: self.players.clone(),
+            host_id: self.host_id,
+            turn_order,
+            turn_state: TurnState::InsertTile,
+            settings: self.settings.clone(),
+            log: self.log.clone(),
+            away: BTreeMap::new(),
+            hints_used: BTreeMap::new(),
+            hint: None,
+            double_pushes_used: BTreeSet::new(),
+            double_pushing: false,
+            speed_streaks: BTreeMap::new(),
+            events: self.events.clone(),
+            world: world.map(Box::new),
+            series: self.series.clone(),
+            turn_starts,
+            party: None,
+            draft: false,
+            reachable: Default::default(),
+            suggestions: Default::default(),
+            dragging_token: false,
+            outbox: Outbox::default(),
+            anim: Default::default(),
+            rng: self.rng.clone(),
         }
     }
 
+    /// Gets every tile the given player can reach from their token, as far as their energy goes,
+    /// which is nowhere if their token's on a layer that's set aside
+    pub fn reachable_for(&self, id: PlayerID) -> Reachable {
+        if !self.board.is_here(id) {
+            return Default::default();
+        }
+        self.reachable_within(self.board.player_pos(id), self.board.energy_left(id))
+    }
+
+    /// Gets every tile reachable from the given (row, col) in at most the given number of steps,
+    /// or any number of steps if it's `None`
+    fn reachable_within(&self, pos: (usize, usize), steps: Option<u8>) -> Reachable {
+        let mut cache = self.reachable.0.lock().unwrap();
+        let board = &self.board;
+        cache
+            .entry((pos, steps))
+            .or_insert_with(|| {
+                Arc::new(match steps {
+                    Some(steps) => board.coords_within(pos, usize::from(steps)),
+                    None => board.reachable_coords(pos),
+                })
+            })
+            .clone()
+    }
+
+    /// Gets the way the winner went on the move that won the game, if it's over
+    pub fn winning_path(&self) -> Option<Vec<(usize, usize)>> {
+        let winner = self.winner()?.id;
+        let (from, to) = self.log.events.iter().rev().find_map(|event| match *event {
+            GameEvent::Move { player, from, to, .. } if player == winner => Some(Some((from, to))),
+            // the move was made on a layer that's since been set aside
+            GameEvent::SwitchLayer { .. } => Some(None),
+            _ => None,
+        })??;
+        // nothing moves after the winning move, so the board's still laid out the way it was
+        self.board.path_between(from, to)
+    }
+
+    /// Gets the tiles that can be seen from the given client, or `None` if the whole board can be seen
+    ///
+    /// Players sharing a screen can't help seeing what each other sees, so they all see it together.
+    pub fn visible_tiles(&self, client: PlayerID) -> Option<HashSet<(usize, usize)>> {
+        let steps = match self.settings.fog_distance {
+            0 => return None,
+            _ if self.settings.sandbox => return None,
+            steps => usize::from(steps),
+        };
+        let mut visible = HashSet::new();
+        let mut playing = false;
+        for (id, token) in &self.board.player_tokens {
+            let local = match self.players.get(id) {
+                Some(player) => player.played_from(client),
+                None => false,
+            };
+            if local {
+                playing = true;
+                // nothing can be seen from a layer that's set aside
+                if token.layer == self.board.layer {
+                    visible.extend(self.board.coords_within(token.position, steps));
+                }
+            }
+        }
+        // anyone only watching, like from a spectator link, isn't playing, so they see the whole board
+        if !playing {
+            return None;
+        }
+        Some(visible)
+    }
+
+    /// Gets the orientation of the loose tile, at its current position, that leaves the active player
+    /// the most tiles to reach once it's inserted, keeping the current orientation if nothing beats it
+    pub fn suggested_orientation(&self) -> Direction {
+        let mut cache = self.suggestions.0.lock().unwrap();
+        let board = &self.board;
+        let current = board.loose_tile.orientation;
+        let player_id = self.active_player_id();
+        let wrap_tokens = self.settings.wrap_tokens;
+        *cache.entry(board.loose_tile_position).or_insert_with(|| {
+            let mut best = (0, current);
+            // start from the current orientation, so it wins any ties
+            for &turn in Direction::all() {
+                let orientation = turn * current;
+                let mut board = board.clone();
+                board.loose_tile.orientation = orientation;
+                board.push_loose_tile(wrap_tokens);
+                let reachable = board.reachable_by(player_id).len();
+                if reachable > best.0 {
+                    best = (reachable, orientation);
+                }
+            }
+            best.1
+        })
+    }
+
+    /// Gets the way to turn the loose tile to reach the suggested orientation, if it's not there already
+    pub fn suggested_rotation(&self) -> Option<RotateDir> {
+        self.rotation_toward(self.suggested_orientation())
+    }
+
+    /// Gets the way to turn the loose tile to face the given way, if it doesn't already
+    pub fn rotation_toward(&self, suggested: Direction) -> Option<RotateDir> {
+        let current = self.board.loose_tile.orientation;
+        if suggested == current {
+            None
+        } else if suggested == Direction::West * current {
+            Some(RotateDir::CCW)
+        } else {
+            Some(RotateDir::CW)
+        }
+    }
+
+    /// Rotates the loose tile to the suggested orientation
+    fn accept_suggestion(&mut self) -> bool {
+        let mut dirty = false;
+        while let Some(dir) = self.suggested_rotation() {
+            dirty = self.rotate_loose_tile(dir) || dirty;
+        }
+        dirty
+    }
+
+    /// Gets how many more hints the given player can ask for
+    pub fn hints_left(&self, id: PlayerID) -> u8 {
+        // a hint would be worked out on a board that's going to change before the plan's played out
+        if self.settings.party {
+            return 0;
+        }
+        let used = self.hints_used.get(&id).cloned().unwrap_or(0);
+        let extra = self.modifiers().fold(0u8, |extra, modifier| match *modifier {
+            Modifier::ExtraHints(count) => extra.saturating_add(count),
+            _ => extra,
+        });
+        self.settings.hint_limit.saturating_add(extra).saturating_sub(used)
+    }
+
+    /// Gets the modifiers of every event on for this game
+    pub fn modifiers(&self) -> impl Iterator<Item = &Modifier> {
+        self.events.iter().flat_map(|event| &event.modifiers)
+    }
+
+    /// Checks if the given local player could ask for a hint right now
+    pub fn can_take_hint(&self, local_id: PlayerID) -> bool {
+        self.local_turn(local_id) && self.hint.is_none() && self.hints_left(self.active_player_id()) > 0
+    }
+
+    /// Works out a hint for the active player, if they haven't got one yet this turn and have any left
+    fn take_hint(&mut self) -> bool {
+        let id = self.active_player_id();
+        if self.hint.is_some() || self.hints_left(id) == 0 {
+            return false;
+        }
+        let hint = match self.turn_state {
+            TurnState::InsertTile => bot::best_move(self, id),
+            TurnState::MoveToken => bot::best_destination(self, id),
+        };
+        self.hint = Some(Box::new(hint));
+        *self.hints_used.entry(id).or_insert(0) += 1;
+        true
+    }
+
+    /// Checks if the given player can use their double push right now
+    pub fn can_double_push(&self, id: PlayerID) -> bool {
+        self.settings.double_push
+            && self.party.is_none()
+            && !self.double_pushing
+            && !self.double_pushes_used.contains(&id)
+            && self.active_player_id() == id
+            && matches!(self.turn_state, TurnState::InsertTile)
+    }
+
+    /// Uses up the active player's double push, so they insert twice before moving this turn
+    fn use_double_push(&mut self) -> bool {
+        let id = self.active_player_id();
+        if !self.can_double_push(id) {
+            return false;
+        }
+        self.double_pushes_used.insert(id);
+        self.double_pushing = true;
+        true
+    }
+
+    /// Counts the turn that just ended toward its player's speed bonus if it took no longer than the
+    /// limit, or breaks their streak if it didn't, returns whether anything changed
+    pub fn finish_timed_turn(&mut self, seconds: f64) -> bool {
+        let limit = self.settings.speed_bonus;
+        if limit == 0 || self.party.is_some() || self.settings.sandbox {
+            return false;
+        }
+        let last_turn = self.log.events.iter().rev().find_map(|event| match *event {
+            GameEvent::Move { player, .. } => Some((player, true)),
+            GameEvent::Skip { player } => Some((player, false)),
+            _ => None,
+        });
+        let (player, moved) = match last_turn {
+            Some(last_turn) => last_turn,
+            None => return false,
+        };
+        // the computer doesn't need a reason to play fast
+        if matches!(self.players.get(&player), Some(player) if player.bot.is_some()) {
+            return false;
+        }
+        let streak = self.speed_streaks.entry(player).or_insert(0);
+        if !moved || seconds > f64::from(limit) {
+            let broken = *streak > 0;
+            *streak = 0;
+            return broken;
+        }
+        *streak += 1;
+        if *streak >= SPEED_STREAK {
+            *streak = 0;
+            if let Some(token) = self.board.player_tokens.get_mut(&player) {
+                token.earned_bonus();
+            }
+            self.log.record(GameEvent::SpeedBonus { player });
+        }
+        true
+    }
+
     /// Gets the effective local ID (the player living here who will be moving soonest)
     pub fn effective_local_id(&self, local_id: PlayerID) -> PlayerID {
         for id in &self.turn_order {
             let player = &self.players[id];
-            if player.lives_with(local_id) {
+            if player.played_from(local_id) {
                 return player.id;
             }
         }
         local_id
     }
 
+    /// Hashes everything that matters for staying in sync, so clients can check they agree with the host
+    pub fn digest(&self) -> u64 {
+        let data = bincode::serialize(&(&self.board, &self.turn_order, &self.turn_state))
+            .expect("Failed to serialize board");
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&data);
+        hasher.finish()
+    }
+
     /// Gets the ID of the player whose turn it is
     pub fn active_player_id(&self) -> PlayerID {
         self.turn_order[0]
@@ -109,6 +964,341 @@ impl BoardController {
         &self.players[&self.active_player_id()]
     }
 
+    /// Gets the IDs of the clients the players are on, leaving out local players, which share their
+    /// host's client
+    pub fn peer_ids(&self) -> Vec<PlayerID> {
+        self.players.values().filter(|player| player.parent.is_none()).map(|player| player.id).collect()
+    }
+
+    /// Marks every player living with the given client as away or back, returns whether anything changed
+    pub fn set_away(&mut self, client: PlayerID, away: bool) -> bool {
+        let ids: Vec<PlayerID> = self
+            .players
+            .values()
+            .filter(|p| p.lives_with(client))
+            .map(|p| p.id)
+            .collect();
+        let mut changed = false;
+        for id in ids {
+            if away {
+                if let Entry::Vacant(entry) = self.away.entry(id) {
+                    entry.insert(crate::now());
+                    changed = true;
+                }
+            } else {
+                changed |= self.away.remove(&id).is_some();
+            }
+        }
+        changed
+    }
+
+    /// Lets a new player into the game at the back of the turn order, returns whether they got in
+    pub fn admit_player(&mut self, player: Player) -> bool {
+        if self.players.contains_key(&player.id) {
+            return false;
+        }
+        let rng = resume_rng(&mut self.rng, self.settings.seed, self.log.event_count());
+        self.board.add_player(&player, self.settings.target_distance(), rng);
+        let token = &self.board.player_tokens[&player.id];
+        self.log.record(GameEvent::Join {
+            player: player.id,
+            position: token.position,
+            target: self.board.target_pos(player.id),
+            deck: token.deck.clone(),
+            card: token.card,
+        });
+        self.turn_order.push(player.id);
+        self.players.insert(player.id, player);
+        true
+    }
+
+    /// Takes every player living with the given client out of the game for good,
+    /// returns whether anything changed
+    pub fn retire_client(&mut self, client: PlayerID) -> bool {
+        let ids = self
+            .turn_order
+            .iter()
+            .filter(|id| self.players[id].lives_with(client))
+            .cloned()
+            .collect();
+        self.retire_players(ids)
+    }
+
+    /// Takes anyone who's been away too long out of the game, returns whether anything changed
+    pub fn retire_away_players(&mut self) -> bool {
+        let now = crate::now();
+        let ids = self
+            .away
+            .iter()
+            .filter(|&(_, &since)| now - since >= AWAY_RETIRE_PERIOD)
+            .map(|(&id, _)| id)
+            .collect();
+        self.retire_players(ids)
+    }
+
+    fn retire_players(&mut self, ids: Vec<PlayerID>) -> bool {
+        let ids: Vec<PlayerID> = ids.into_iter().filter(|id| self.turn_order.contains(id)).collect();
+        // somebody has to be left to take a turn
+        if ids.is_empty() || ids.len() == self.turn_order.len() {
+            return false;
+        }
+        let was_active = ids.contains(&self.active_player_id());
+        for id in ids {
+            // their entry in players stays, so the log can still name them
+            self.board.remove_player(id);
+            if let Some(world) = self.world.as_deref_mut() {
+                world.remove_player(id);
+            }
+            self.turn_order.retain(|&p| p != id);
+            self.away.remove(&id);
+            self.log.record(GameEvent::Leave { player: id });
+        }
+        if was_active {
+            // anything they got done on their turn stays done, and the next player starts fresh
+            self.turn_state = TurnState::InsertTile;
+            self.bring_active_layer_into_play();
+            self.highlighted_tile = self.board.player_pos(self.active_player_id());
+        }
+        true
+    }
+
+    /// Checks if the given player's client has disconnected
+    pub fn is_away(&self, id: PlayerID) -> bool {
+        self.away.contains_key(&id)
+    }
+
+    /// Checks if the given player's client has been disconnected longer than the grace period
+    fn away_too_long(&self, id: PlayerID) -> bool {
+        match self.away.get(&id) {
+            Some(&since) => crate::now() - since >= AWAY_GRACE_PERIOD,
+            None => false,
+        }
+    }
+
+    /// Skips the active player's turn if they've been away longer than the grace period,
+    /// returns whether or not the state changed
+    pub fn skip_away_player(&mut self) -> bool {
+        let id = self.active_player_id();
+        // in party mode, anyone away just doesn't hand in a plan
+        if self.party.is_some() || !self.away_too_long(id) {
+            return false;
+        }
+        match self.turn_state {
+            TurnState::InsertTile => {
+                self.board.finish_turn(id, None);
+                if let Some(world) = self.world.as_deref_mut() {
+                    world.finish_turn(id);
+                }
+                self.log.record(GameEvent::Skip { player: id });
+                self.rotate_turn_order();
+                true
+            }
+            TurnState::MoveToken => {
+                // the tile's already in, so finish the turn where they stand
+                let pos = self.board.player_pos(id);
+                self.attempt_move(pos)
+            }
+        }
+    }
+
+    /// Checks if this is a party mode board, where everyone plans their turn at once
+    pub fn is_party(&self) -> bool {
+        self.party.is_some()
+    }
+
+    /// Checks if this is a copy of a party mode board that a player is planning their turn on
+    pub fn is_draft(&self) -> bool {
+        self.draft
+    }
+
+    /// Makes a copy of this party mode board for the given player to plan their turn on, as if it were
+    /// their turn right now
+    pub fn plan_draft(&self, id: PlayerID) -> BoardController {
+        let mut draft = self.clone();
+        draft.party = None;
+        draft.draft = true;
+        if let Some(i) = draft.turn_order.iter().position(|&p| p == id) {
+            draft.turn_order.rotate_left(i);
+        }
+        draft.turn_state = TurnState::InsertTile;
+        draft.hint = None;
+        draft.highlighted_tile = draft.board.player_pos(id);
+        draft
+    }
+
+    /// Gets the plan made on this draft since its log had the given number of events, once the turn's over
+    pub fn finished_plan(&self, start: usize) -> Option<bot::Move> {
+        let mut insert = None;
+        for event in self.log.events.iter().skip(start) {
+            match *event {
+                GameEvent::Insert { ref tile, position } => insert = Some((position, tile.orientation)),
+                GameEvent::Move { to, .. } => {
+                    return Some(bot::Move {
+                        insert,
+                        destination: to,
+                    })
+                }
+                GameEvent::Join { .. }
+                | GameEvent::Skip { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::NewRound
+                | GameEvent::SpeedBonus { .. }
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        None
+    }
+
+    /// Hands in the given player's plan for the given round, returns whether it was taken
+    pub fn submit_plan(&mut self, id: PlayerID, round: u32, plan: bot::Move) -> bool {
+        if !self.turn_order.contains(&id) {
+            return false;
+        }
+        match self.party.as_deref_mut() {
+            Some(party) if party.round == round && !party.is_resolving() => {
+                party.plans.insert(id, plan);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks if everyone's handed in a plan for this round, not counting anyone who's been away a while
+    pub fn ready_to_resolve(&self) -> bool {
+        match self.party.as_deref() {
+            Some(party) if !party.is_resolving() => self
+                .turn_order
+                .iter()
+                .all(|id| party.plans.contains_key(id) || self.away_too_long(*id)),
+            _ => false,
+        }
+    }
+
+    /// Starts playing out this round's plans, returns whether it started
+    pub fn start_resolving(&mut self) -> bool {
+        let pending = self.turn_order.iter().cloned().collect();
+        match self.party.as_deref_mut() {
+            Some(party) if !party.is_resolving() => {
+                party.pending = Some(pending);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Plays out the active player's plan, or wraps up the round once every plan has been played,
+    /// returns whether the state changed
+    pub fn resolve_step(&mut self) -> bool {
+        let id = self.active_player_id();
+        let turn_order = &self.turn_order;
+        let (plan, round_over) = match self.party.as_deref_mut() {
+            Some(PartyRound {
+                pending: Some(pending),
+                plans,
+                ..
+            }) => {
+                // anyone who left partway through the round won't be coming up
+                pending.retain(|id| turn_order.contains(id));
+                if pending.remove(&id) {
+                    (plans.remove(&id), false)
+                } else {
+                    (None, true)
+                }
+            }
+            _ => return false,
+        };
+        if round_over {
+            self.finish_round();
+            return true;
+        }
+        match plan {
+            Some(plan) => self.play_plan(plan),
+            None => {
+                // no plan means no turn, same as being away
+                self.board.finish_turn(id, None);
+                self.log.record(GameEvent::Skip { player: id });
+                self.rotate_turn_order();
+            }
+        }
+        true
+    }
+
+    /// Takes the given computer player's turn, if it's theirs, returns whether it was taken
+    pub fn play_bot_turn(&mut self, bot: ai::BotPlayer) -> bool {
+        if self.party.is_some() || self.active_player_id() != bot.id {
+            return false;
+        }
+        // the trace's generator, when there is one, so a replayed trace has the computer play the same way
+        let plan = bot.plan(self, &mut *fresh_rng());
+        if let TurnState::MoveToken = self.turn_state {
+            return self.attempt_move(plan.destination);
+        }
+        self.play_plan(plan);
+        true
+    }
+
+    /// Hands in plans for every computer player who hasn't planned this round of party mode yet,
+    /// returns whether any went in
+    pub fn plan_for_bots(&mut self) -> bool {
+        let round = match self.party.as_deref() {
+            Some(party) if !party.is_resolving() => party.round,
+            _ => return false,
+        };
+        let mut planned = false;
+        for bot in ai::BotPlayer::all(self) {
+            let waiting = match self.party.as_deref() {
+                Some(party) => !party.plans.contains_key(&bot.id),
+                None => false,
+            };
+            if waiting {
+                // everyone plans on the board as it stands at the start of the round
+                let plan = bot.plan(self, &mut *fresh_rng());
+                planned |= self.submit_plan(bot.id, round, plan);
+            }
+        }
+        planned
+    }
+
+    /// Plays out the given plan for the active player, as closely as the board now allows
+    fn play_plan(&mut self, plan: bot::Move) {
+        if let Some(((side, guide), orientation)) = plan.insert {
+            let guides = match side {
+                Direction::North | Direction::South => self.board.width() / 2,
+                Direction::East | Direction::West => self.board.height() / 2,
+            };
+            // the board may have shrunk since the plan was made, taking that guide with it
+            if guide < guides {
+                self.board.loose_tile_position = (side, guide);
+            }
+            self.board.loose_tile.orientation = orientation;
+        }
+        self.insert_loose_tile();
+        // earlier plans may have shifted things, so get as close as possible to where they meant to go
+        let id = self.active_player_id();
+        let from = self.board.player_pos(id);
+        let destination = self
+            .reachable_for(id)
+            .iter()
+            .cloned()
+            .min_by_key(|&pos| (bot::distance(pos, plan.destination), pos))
+            .unwrap_or(from);
+        self.attempt_move(destination);
+    }
+
+    /// Wraps up a round of party mode, moving the turn order along one for the next
+    fn finish_round(&mut self) {
+        self.log.record(GameEvent::NewRound);
+        // going first is an advantage, so everyone gets a go at it
+        self.turn_order.rotate_left(1);
+        self.highlighted_tile = self.board.player_pos(self.active_player_id());
+        if let Some(party) = self.party.as_deref_mut() {
+            party.round += 1;
+            party.plans.clear();
+            party.pending = None;
+        }
+    }
+
     fn move_loose_tile(&mut self, new_loose_tile_position: (Direction, usize)) -> bool {
         let old_loose_tile_position = self.board.loose_tile_position;
         self.board.loose_tile_position = new_loose_tile_position;
@@ -120,15 +1310,84 @@ impl BoardController {
             RotateDir::CW => Direction::East,
             RotateDir::CCW => Direction::West,
         });
-        let sync = AnimSync::Rotate(dir);
-        anim::STATE.write().unwrap().apply_send(sync);
+        self.animate(AnimSync::Rotate(dir));
         true
     }
 
-    /// Checks if the player whose turn it is lives with this player (equal to or child of)
+    /// Plays the given animation, and has everyone else play it too unless this is only a draft
+    fn animate(&mut self, sync: AnimSync) {
+        if self.is_draft() {
+            self.anim.apply(sync);
+        } else {
+            self.anim.apply_send(sync, &mut self.outbox);
+        }
+    }
+
+    /// Takes over the animations from an older copy of this game, and plays whatever's happened
+    /// since, so turns that arrive all at once don't just appear
+    pub fn reconcile(&mut self, old: &mut BoardController) {
+        mem::swap(&mut self.anim, &mut old.anim);
+        if self.log.initial_turn_order != old.log.initial_turn_order {
+            return;
+        }
+        // copies sent mid-game only carry the last few turns, so the rest comes from ours
+        self.log.splice(&old.log);
+        let new_events = match self.log.events_since(old.log.event_count()) {
+            Some(events) => events,
+            None => return,
+        };
+        let mut insert = None;
+        let mut moves = BTreeMap::new();
+        for event in new_events {
+            match *event {
+                GameEvent::Insert { position, .. } => insert = Some(position),
+                GameEvent::Move { player, from, to, .. } if from != to => {
+                    moves.insert(player, from);
+                }
+                _ => (),
+            }
+        }
+        if let Some(position) = insert {
+            // whoever pushed has usually sent the animation along already
+            if !self.anim.loose_insert.applies_to_loose(position) {
+                self.anim.apply(AnimSync::insert_at(position));
+            }
+        }
+        // tokens wait for the push to finish before they set off
+        let delay = self.anim.loose_insert.time_left();
+        for (player, from) in moves {
+            self.anim.token_moves.start(player, from, delay);
+        }
+    }
+
+    /// Shows the given quick-chat phrase over the given player's token, for everyone
+    pub fn quick_chat(&mut self, player: PlayerID, phrase: usize) {
+        self.anim.apply_send(AnimSync::QuickChat(player, phrase), &mut self.outbox);
+    }
+
+    /// Copies this board to send to everyone else mid-game, with only the last few turns of the log,
+    /// since they've been getting the rest all along
+    pub fn for_broadcast(&self) -> BoardController {
+        BoardController {
+            log: Box::new(self.log.tail(BROADCAST_LOG_TURNS)),
+            ..self.clone()
+        }
+    }
+
+    /// Takes the messages this board has for everyone else, for the game controller to send
+    pub fn take_outbox(&mut self) -> Vec<Message> {
+        self.outbox.take()
+    }
+
+    /// Checks if the player whose turn it is lives with this player (equal to or child of),
+    /// and isn't a computer player
     pub fn local_turn(&self, local_id: PlayerID) -> bool {
+        // in party mode, turns get planned on drafts and only the host plays them out
+        if self.party.is_some() {
+            return false;
+        }
         let active_player = self.active_player();
-        active_player.lives_with(local_id)
+        active_player.played_from(local_id)
     }
 
     /// Handles click event, returns whether or not the state may have changed
@@ -137,13 +1396,16 @@ impl BoardController {
         event: &web_sys::MouseEvent,
         local_id: PlayerID,
         view: &BoardView,
-        ctx: &Context,
     ) -> bool {
         // never do anything if this player is not the active player
         if !self.local_turn(local_id) {
             return false;
         }
 
+        if self.settings.sandbox {
+            return self.on_sandbox_click(event, view);
+        }
+
         let (should_insert, should_move) = match self.turn_state {
             TurnState::InsertTile => (true, false),
             TurnState::MoveToken => (false, true),
@@ -154,8 +1416,17 @@ impl BoardController {
         let button = event.button();
         let pos = [event.offset_x() as f64, event.offset_y() as f64];
 
-        // if clicked inside the loose tile and should be inserting...
-        if view.in_loose_tile(&pos, self, ctx) && should_insert {
+        if view.in_double_push_button(&pos) && self.can_double_push(self.active_player_id()) {
+            return self.use_double_push();
+        }
+
+        // if clicked on a rotate button and should be inserting...
+        let rotate_button = view.in_rotate_button(&pos, self);
+        if let (Some(dir), true) = (rotate_button, should_insert) {
+            // rotate the loose tile that way
+            self.rotate_loose_tile(dir);
+            dirty = true;
+        } else if view.in_loose_tile(&pos, self) && should_insert {
             // if this was the primary button
             if button == 0 {
                 // insert the tile
@@ -165,22 +1436,92 @@ impl BoardController {
                 self.rotate_loose_tile(RotateDir::CW);
             }
             dirty = true;
-        } else if let Some(pos) = view.in_tile(&pos, self, ctx) {
+        } else if let Some(pos) = view.in_tile(&pos, self) {
             // if clicked inside a tile, if we should be moving...
             if should_move {
                 dirty = dirty || self.attempt_move(pos);
             }
         }
 
+        self.advance_tutorial(dirty);
+
+        dirty
+    }
+
+    /// Moves the tutorial on to its next step once the current one's been won, remembering how far
+    /// it got so the main menu can pick it back up
+    fn advance_tutorial(&mut self, dirty: bool) {
         if let Some(tutorial_step) = &self.board.tutorial_step {
             if dirty && self.winner().is_some() {
                 if let Some(next_step) = tutorial_step.next() {
+                    options::save_continue_target(&options::Continue::Tutorial(next_step));
                     next_step.apply(&mut self.board);
+                    self.restart_log();
+                } else {
+                    options::clear_continue_target();
                 }
             }
         }
+    }
 
-        dirty
+    /// Handles click event in the sandbox, where anything goes
+    fn on_sandbox_click(&mut self, event: &web_sys::MouseEvent, view: &BoardView) -> bool {
+        let primary = event.button() == 0;
+        let pos = [event.offset_x() as f64, event.offset_y() as f64];
+        if let Some(dir) = view.in_rotate_button(&pos, self) {
+            self.rotate_loose_tile(dir)
+        } else if view.in_loose_tile(&pos, self) {
+            if primary {
+                self.insert_loose_tile()
+            } else {
+                self.rotate_loose_tile(RotateDir::CW)
+            }
+        } else if let Some(pos) = view.in_tile(&pos, self) {
+            if self.dragging_token {
+                // the click comes when the button's let go, so that's where the token gets dropped
+                self.dragging_token = false;
+                self.board.move_player(self.active_player_id(), pos);
+                true
+            } else {
+                self.rotate_tile(pos, if primary { RotateDir::CW } else { RotateDir::CCW })
+            }
+        } else {
+            // dropped off the board, so the token stays where it was
+            std::mem::replace(&mut self.dragging_token, false)
+        }
+    }
+
+    /// Turns the tile at the given (row, col) in place, which only the sandbox allows
+    fn rotate_tile(&mut self, (row, col): (usize, usize), dir: RotateDir) -> bool {
+        self.board.get_mut([col, row]).rotate(match dir {
+            RotateDir::CW => Direction::East,
+            RotateDir::CCW => Direction::West,
+        });
+        self.reachable = Default::default();
+        self.suggestions = Default::default();
+        true
+    }
+
+    /// Handles mousedown event, returns whether or not the state may have changed
+    pub fn on_mousedown(
+        &mut self,
+        event: &web_sys::MouseEvent,
+        local_id: PlayerID,
+        view: &BoardView,
+    ) -> bool {
+        // only tokens in the sandbox can be dragged
+        if !self.settings.sandbox || !self.local_turn(local_id) || event.button() != 0 {
+            return false;
+        }
+
+        let pos = [event.offset_x() as f64, event.offset_y() as f64];
+        let token_pos = self.board.player_pos(self.active_player_id());
+        if view.in_tile(&pos, self) == Some(token_pos) {
+            self.dragging_token = true;
+            self.highlighted_tile = token_pos;
+            return true;
+        }
+        false
     }
 
     /// Handles mousemove event, returns whether or not the state may have changed
@@ -188,9 +1529,10 @@ impl BoardController {
         &mut self,
         event: &web_sys::MouseEvent,
         local_id: PlayerID,
-        view: &BoardView,
-        ctx: &Context,
+        view: &mut BoardView,
     ) -> bool {
+        view.cursor = Cursor::Idle;
+
         // never do anything if this player is not the active player
         if !self.local_turn(local_id) {
             return false;
@@ -200,83 +1542,85 @@ impl BoardController {
             TurnState::InsertTile => (true, false),
             TurnState::MoveToken => (false, true),
         };
+        // a token being dragged around the sandbox lights up the tile it's over
+        let should_move = should_move || self.dragging_token;
 
         let mut dirty = false;
 
         let pos = [event.offset_x() as f64, event.offset_y() as f64];
+        if view.in_double_push_button(&pos) && self.can_double_push(self.active_player_id()) {
+            view.cursor = Cursor::Button;
+        }
         if should_insert {
-            if let Some(new_loose_tile_position) = view.in_insert_guide(&pos, self, ctx) {
+            let guide = view.in_insert_guide(&pos, self);
+            self.anim.guides.hover(guide);
+            if let Some(new_loose_tile_position) = guide {
                 dirty = dirty || self.move_loose_tile(new_loose_tile_position);
             }
+            // the loose tile follows the pointer onto guides, so it's checked once it's moved
+            view.cursor = if view.in_rotate_button(&pos, self).is_some() {
+                Cursor::Button
+            } else if view.in_loose_tile(&pos, self) {
+                Cursor::LooseTile
+            } else if let Some((side, _)) = guide {
+                Cursor::Guide(side)
+            } else {
+                view.cursor
+            };
         }
         if should_move {
             let old_highlighted_tile = self.highlighted_tile;
-            self.highlighted_tile = view
-                .in_tile(&pos, self, ctx)
-                .unwrap_or(self.highlighted_tile);
+            let tile = view.in_tile(&pos, self);
+            self.highlighted_tile = tile.unwrap_or(self.highlighted_tile);
             dirty = dirty || old_highlighted_tile != self.highlighted_tile;
+            // anywhere goes in the sandbox
+            let reachable = |tile| self.settings.sandbox || self.reachable_for(self.active_player_id()).contains(&tile);
+            view.cursor = match tile {
+                Some(tile) if reachable(tile) => Cursor::Reachable,
+                Some(_) => Cursor::Unreachable,
+                None => view.cursor,
+            };
         }
 
-        if let Some(tutorial_step) = &self.board.tutorial_step {
-            if dirty && self.winner().is_some() {
-                if let Some(next_step) = tutorial_step.next() {
-                    next_step.apply(&mut self.board);
-                }
-            }
-        }
+        self.advance_tutorial(dirty);
 
         dirty
     }
 
-    /// Handles keydown event, returns whether or not the state may have changed
-    pub fn on_keydown(&mut self, event: &web_sys::KeyboardEvent, local_id: PlayerID) -> bool {
+    /// Handles a key or button press, returns whether or not the state may have changed
+    pub fn on_input(&mut self, action: InputAction, local_id: PlayerID) -> bool {
         // never do anything if this player is not the active player
         if !self.local_turn(local_id) {
             return false;
         }
 
-        let (should_insert, should_move) = match self.turn_state {
-            TurnState::InsertTile => (true, false),
-            TurnState::MoveToken => (false, true),
+        // keys point the way they do on screen, however far the board's turned here
+        let action = match action {
+            InputAction::Move(dir) => InputAction::Move(dir * options::HANDLE.fetch().board_rotation.undo()),
+            action => action,
         };
-
-        let mut dirty = false;
-        let key = event.code();
-
-        // handle insert
-        if should_insert {
-            let newly_dirty = match key.as_str() {
-                "ArrowLeft" | "KeyA" => self.handle_insert_key_direction(Direction::West),
-                "ArrowRight" | "KeyD" => self.handle_insert_key_direction(Direction::East),
-                "ArrowUp" | "KeyW" => self.handle_insert_key_direction(Direction::North),
-                "ArrowDown" | "KeyS" => self.handle_insert_key_direction(Direction::South),
-                "ShiftLeft" => self.rotate_loose_tile(RotateDir::CCW),
-                "ShiftRight" => self.rotate_loose_tile(RotateDir::CW),
-                "Space" => self.insert_loose_tile(),
-                _ => false,
-            };
-            dirty = dirty || newly_dirty;
-        }
-        // handle move
-        if should_move {
-            let newly_dirty = match key.as_str() {
-                "ArrowLeft" | "KeyA" => self.handle_move_key_direction(Direction::West),
-                "ArrowRight" | "KeyD" => self.handle_move_key_direction(Direction::East),
-                "ArrowUp" | "KeyW" => self.handle_move_key_direction(Direction::North),
-                "ArrowDown" | "KeyS" => self.handle_move_key_direction(Direction::South),
-                "Space" => self.attempt_move(self.highlighted_tile),
-                _ => false,
-            };
-            dirty = dirty || newly_dirty;
-        }
-
-        if let Some(tutorial_step) = &self.board.tutorial_step {
-            if dirty && self.winner().is_some() {
-                if let Some(next_step) = tutorial_step.next() {
-                    next_step.apply(&mut self.board);
-                }
+        let action = match action {
+            _ if !self.modifiers().any(|modifier| *modifier == Modifier::InvertedControls) => action,
+            InputAction::Move(dir) => InputAction::Move(dir * Direction::South),
+            InputAction::Rotate(RotateDir::CW) => InputAction::Rotate(RotateDir::CCW),
+            InputAction::Rotate(RotateDir::CCW) => InputAction::Rotate(RotateDir::CW),
+            action => action,
+        };
+        let dirty = match (&self.turn_state, action) {
+            (TurnState::InsertTile, InputAction::Move(dir)) => self.handle_insert_key_direction(dir),
+            (TurnState::InsertTile, InputAction::Rotate(dir)) => self.rotate_loose_tile(dir),
+            (TurnState::InsertTile, InputAction::Confirm) => self.insert_loose_tile(),
+            (TurnState::InsertTile, InputAction::AcceptSuggestion) => {
+                options::HANDLE.fetch().rotate_assist && self.accept_suggestion()
             }
-        }
+            (TurnState::MoveToken, InputAction::AcceptSuggestion) => false,
+            (_, InputAction::Hint) => self.take_hint(),
+            (TurnState::MoveToken, InputAction::Move(dir)) => self.handle_move_key_direction(dir),
+            (TurnState::MoveToken, InputAction::Rotate(_)) => false,
+            (TurnState::MoveToken, InputAction::Confirm) => self.attempt_move(self.highlighted_tile),
+        };
+
+        self.advance_tutorial(dirty);
 
         dirty
     }
@@ -285,18 +1629,34 @@ impl BoardController {
         let (row, col) = pos;
         // if that tile is reachable from the active player's position...
         let id = self.active_player_id();
-        if self
-            .board
-            .reachable_coords(self.board.player_pos(id))
-            .contains(&pos)
-        {
-            // move the active player to the given position
+        let from = self.board.player_pos(id);
+        if self.reachable_for(id).contains(&pos) {
+            // move the active player to the given position, using up the energy it takes
+            self.board.spend_energy(id, from, pos);
             self.board.move_player(id, pos);
-            // if the player has reached their target...
-            if self.board.get([col, row]).whose_target == Some(id) {
+            // if the player has reached their target, and it isn't locked...
+            let mut next_target = None;
+            if self.board.get([col, row]).whose_target == Some(id) && !self.board.target_locked(id) {
                 // advance the player to the next target
-                self.board.player_reached_target(id);
+                let rng = resume_rng(&mut self.rng, self.settings.seed, self.log.event_count());
+                self.board.player_reached_target(id, self.settings.target_distance(), rng);
+                next_target = self.board.target_pos(id);
             }
+            self.board.finish_turn(id, Some(pos));
+            if let Some(world) = self.world.as_deref_mut() {
+                world.finish_turn(id);
+                world.take_portal(&mut self.board, id);
+                // the next target goes on the layer after the one they ended up on
+                if next_target.is_some() {
+                    next_target = world.send_target(&mut self.board, id);
+                }
+            }
+            self.log.record(GameEvent::Move {
+                player: id,
+                from,
+                to: pos,
+                next_target,
+            });
             // advance turn order
             self.turn_state = TurnState::InsertTile;
             self.rotate_turn_order();
@@ -305,10 +1665,37 @@ impl BoardController {
         false
     }
 
+    /// Finishes the current turn as it stands, for when time runs out
+    pub fn auto_complete_turn(&mut self) -> bool {
+        if let TurnState::InsertTile = self.turn_state {
+            self.insert_loose_tile();
+        }
+        // staying put is always a legal move
+        let pos = self.board.player_pos(self.active_player_id());
+        self.attempt_move(pos)
+    }
+
     fn insert_loose_tile(&mut self) -> bool {
-        self.board.insert_loose_tile();
-        // advance turn state
-        self.turn_state = TurnState::MoveToken;
+        self.log.record(GameEvent::Insert {
+            tile: self.board.loose_tile.clone(),
+            position: self.board.loose_tile_position,
+        });
+        self.anim.guides.press(self.board.loose_tile_position);
+        let sync = self.board.insert_loose_tile(self.settings.wrap_tokens);
+        self.animate(sync);
+        self.reachable = Default::default();
+        self.suggestions = Default::default();
+        // the tile may not have gone where the hint said, so the hint moves on to where to go from here
+        if self.hint.is_some() {
+            self.hint = Some(Box::new(bot::best_destination(self, self.active_player_id())));
+        }
+        // advance turn state, unless this is the sandbox, where it's always time for another tile,
+        // or the first push of a double push
+        if self.double_pushing {
+            self.double_pushing = false;
+        } else if !self.settings.sandbox {
+            self.turn_state = TurnState::MoveToken;
+        }
         true
     }
 
@@ -389,21 +1776,113 @@ impl BoardController {
         orig_highlight != new_highlight
     }
 
+    /// Gets how many more turns until the board's outer ring collapses, counting this one,
+    /// or `None` if it never will
+    pub fn turns_until_collapse(&self) -> Option<usize> {
+        let every = usize::from(self.settings.collapse_every);
+        // layers all have to stay the same size, so they don't collapse in portal mode
+        if every == 0 || self.settings.sandbox || self.world.is_some() || !self.board.can_collapse() {
+            return None;
+        }
+        Some(every - self.log.turn_count() % every)
+    }
+
+    /// Collapses the board's outer ring if the turn that just ended was the one it was due after,
+    /// returns whether it did
+    fn collapse_if_due(&mut self) -> bool {
+        // the turn that just ended has already been counted, so a collapse due now looks a whole cycle away
+        let every = usize::from(self.settings.collapse_every);
+        if self.log.turn_count() == 0 || self.turns_until_collapse() != Some(every) {
+            return false;
+        }
+        let rng = resume_rng(&mut self.rng, self.settings.seed, self.log.event_count());
+        let targets = self.board.collapse(self.settings.target_distance(), rng);
+        self.log.record(GameEvent::Collapse { targets });
+        self.reachable = Default::default();
+        self.suggestions = Default::default();
+        true
+    }
+
+    /// Brings the layer the active player's token is on into play, if it's portal mode and it isn't already,
+    /// returns whether it did
+    fn bring_active_layer_into_play(&mut self) -> bool {
+        let layer = match self.board.player_tokens.get(&self.active_player_id()) {
+            Some(token) => token.layer,
+            None => return false,
+        };
+        let world = match self.world.as_deref_mut() {
+            Some(world) => world,
+            None => return false,
+        };
+        if !world.bring_into_play(&mut self.board, layer) {
+            return false;
+        }
+        self.log.record(GameEvent::SwitchLayer { layer });
+        self.reachable = Default::default();
+        self.suggestions = Default::default();
+        true
+    }
+
     fn rotate_turn_order(&mut self) {
+        let collapsed = self.collapse_if_due();
         let mut rest = self.turn_order.split_off(1);
         rest.append(&mut self.turn_order);
         self.turn_order = rest;
+        self.hint = None;
+        self.double_pushing = false;
+        // whatever happened this turn gets left behind as ghosts
+        let switched = self.bring_active_layer_into_play();
+        let positions = self.board.player_positions();
+        self.turn_starts.previous = mem::replace(&mut self.turn_starts.current, positions);
+        if collapsed || switched {
+            // ghosts from before the board shrank, or from another layer, would be in the wrong place
+            self.turn_starts.previous.clear();
+        }
         // reset the highlighted tile
         self.highlighted_tile = self.board.player_pos(self.turn_order[0]);
     }
 
     /// Gets the player who has no targets remaining, if one exists
     pub fn winner(&self) -> Option<&Player> {
-        self.board
+        self.win().map(|(winner, _)| winner)
+    }
+
+    /// Gets the player who has won, if anyone has, and how they beat anyone else at the score limit
+    ///
+    /// If several players are at the limit, whoever got there on the earliest turn wins. If the log
+    /// can't tell (say it was started over partway through), whoever's next in the turn order wins.
+    pub fn win(&self) -> Option<(&Player, WinReason)> {
+        let limit = self.settings.score_limit;
+        let finishers: Vec<PlayerID> = self
+            .board
             .player_tokens
             .iter()
-            .filter(|(_, token)| token.score >= self.settings.score_limit)
-            .nth(0)
-            .map(|(id, _)| &self.players[id])
+            .filter(|(_, token)| token.score >= limit)
+            .map(|(&id, _)| id)
+            .collect();
+        if finishers.len() <= 1 {
+            return finishers.first().map(|id| (&self.players[id], WinReason::Alone));
+        }
+        let mut reached: Vec<(usize, PlayerID)> = finishers
+            .iter()
+            .filter_map(|&id| self.log.turn_reaching_score(id, limit, self.settings.wrap_tokens).map(|turn| (turn, id)))
+            .collect();
+        reached.sort();
+        if let [(first, id), (second, _), ..] = reached.as_slice() {
+            if first < second && reached.len() == finishers.len() {
+                return Some((&self.players[id], WinReason::ReachedFirst));
+            }
+        }
+        // players who've left aren't in the turn order, so they lose ties to everyone who is
+        let turn_position = |id: &PlayerID| {
+            self.turn_order
+                .iter()
+                .position(|p| p == id)
+                .unwrap_or(self.turn_order.len())
+        };
+        finishers
+            .iter()
+            .min_by_key(|&id| (turn_position(id), *id))
+            .map(|id| (&self.players[id], WinReason::TurnOrder))
     }
 }
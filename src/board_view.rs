@@ -1,17 +1,23 @@
 //! Board view
 
 use std::cmp;
+use std::collections::{BTreeMap, HashSet};
 use std::ops;
 
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d as Context;
 
 use crate::{
-    BoardController,
-    colors::{self, Color}, Direction, PlayerID, Tile,
+    Board, BoardController,
+    colors::{self, Color, Pattern, Theme, WallStyle}, Direction, Player, PlayerID, Tile,
 };
-use crate::anim;
-use crate::board_controller::TurnState;
+use crate::anim::RotateDir;
+use crate::board::{Scoring, ENERGY_BANK_TURNS};
+use crate::options;
+use crate::board_controller::{TurnState, SPEED_STREAK};
+use crate::bot;
+use crate::replay::GameEvent;
+use crate::world::World;
 
 #[derive(Clone, Debug)]
 struct Diagonal {
@@ -163,6 +169,16 @@ impl PartialOrd<Extents> for [f64; 2] {
 
 /// Stores board view settings
 pub struct BoardViewSettings {
+    /// Name of the theme the colors and styles came from
+    pub theme: &'static str,
+    /// Color behind the board
+    pub backdrop_color: Color,
+    /// Color of the pattern over the backdrop
+    pub pattern_color: Color,
+    /// What's drawn over the backdrop
+    pub pattern: Pattern,
+    /// Space between repeats of the pattern
+    pub pattern_spacing: f64,
     /// Background color
     pub background_color: Color,
     /// Reachable background color
@@ -181,10 +197,50 @@ pub struct BoardViewSettings {
     pub text_color: Color,
     /// Wall color
     pub wall_color: Color,
+    /// How walls are drawn
+    pub wall_style: WallStyle,
     /// Tile wall width as percentage of tile size
     pub wall_width: f64,
     /// Insert guide color
     pub insert_guide_color: Color,
+    /// Color of the warning around the board when time is running out
+    pub timer_warning_color: Color,
+    /// Width of the timer warning as percentage of tile size
+    pub timer_warning_width: f64,
+    /// Color of the outlines showing a hint
+    pub hint_color: Color,
+    /// Opacity of each player's color in coach view
+    pub coach_alpha: f64,
+    /// Opacity of the outlines showing where tokens will be pushed to
+    pub push_preview_alpha: f64,
+    /// Color of tiles hidden by fog of war
+    pub fog_color: Color,
+    /// Opacity of the outlines showing where tokens were a turn ago
+    pub ghost_alpha: f64,
+    /// Opacity of the trails behind tokens as they get pushed
+    pub trail_alpha: f64,
+    /// Color of the warning over the outer ring when it's about to collapse
+    pub collapse_color: Color,
+    /// Color of the coins on golden tiles and the badges for events that are on
+    pub golden_color: Color,
+    /// Color of the rings on portal tiles
+    pub portal_color: Color,
+    /// Opacity of the collapse warning on the turn the outer ring collapses
+    pub collapse_alpha: f64,
+    /// How many turns ahead the collapse warning starts showing
+    pub collapse_warning_turns: usize,
+    /// How many turns back recent moves are drawn for
+    pub history_turns: usize,
+    /// Opacity of the arrows showing the most recent moves, which fade out from there
+    pub history_alpha: f64,
+    /// Round trip time in milliseconds above which a player is shown as lagging
+    pub lag_threshold: u32,
+    /// Color of a lagging player's name
+    pub lag_color: Color,
+    /// Opacity of the insert guides while someone else is taking their turn
+    pub watching_guide_alpha: f64,
+    /// Contrast ratio that target stripes and token outlines keep against the tile under them
+    pub min_contrast: f32,
     /// UI margin size, south pane
     pub ui_margin_south: f64,
     /// UI margin size, east pane
@@ -196,23 +252,67 @@ pub struct BoardViewSettings {
 impl BoardViewSettings {
     /// Creates new board view settings
     pub fn new() -> BoardViewSettings {
+        let theme = &colors::THEMES[0];
         BoardViewSettings {
-            background_color: colors::TEAL,
-            reachable_background_color: colors::LIGHT,
-            border_color: colors::DARK,
-            board_edge_color: colors::DARK,
-            cell_edge_color: colors::DARK,
+            theme: theme.name,
+            backdrop_color: theme.backdrop,
+            pattern_color: theme.pattern_color,
+            pattern: theme.pattern,
+            pattern_spacing: 24.0,
+            background_color: theme.tile,
+            reachable_background_color: theme.reachable,
+            border_color: theme.ink,
+            board_edge_color: theme.ink,
+            cell_edge_color: theme.ink,
             board_edge_radius: 3.0,
             cell_edge_radius: 1.0,
-            text_color: colors::DARK,
-            wall_color: colors::BLUE,
+            text_color: theme.ink,
+            wall_color: theme.wall,
+            wall_style: theme.wall_style,
             wall_width: 0.3,
-            insert_guide_color: colors::PURPLE,
+            insert_guide_color: theme.guide,
+            timer_warning_color: colors::RED,
+            timer_warning_width: 0.15,
+            hint_color: colors::GOLD,
+            coach_alpha: 0.45,
+            push_preview_alpha: 0.8,
+            fog_color: theme.ink,
+            ghost_alpha: 0.5,
+            trail_alpha: 0.4,
+            collapse_color: colors::RED,
+            golden_color: colors::GOLD,
+            portal_color: colors::PURPLE,
+            collapse_alpha: 0.5,
+            collapse_warning_turns: 3,
+            history_turns: 4,
+            history_alpha: 0.7,
+            lag_threshold: 300,
+            lag_color: colors::RED,
+            watching_guide_alpha: 0.3,
+            min_contrast: 1.8,
             ui_margin_south: 100.0,
             ui_margin_east: 300.0,
             font_size: 25,
         }
     }
+
+    /// Switches to the colors and styles of the given theme
+    pub fn apply_theme(&mut self, theme: &'static Theme) {
+        self.theme = theme.name;
+        self.backdrop_color = theme.backdrop;
+        self.pattern_color = theme.pattern_color;
+        self.pattern = theme.pattern;
+        self.background_color = theme.tile;
+        self.reachable_background_color = theme.reachable;
+        self.border_color = theme.ink;
+        self.board_edge_color = theme.ink;
+        self.cell_edge_color = theme.ink;
+        self.text_color = theme.ink;
+        self.wall_color = theme.wall;
+        self.wall_style = theme.wall_style;
+        self.insert_guide_color = theme.guide;
+        self.fog_color = theme.ink;
+    }
 }
 
 impl Default for BoardViewSettings {
@@ -221,75 +321,269 @@ impl Default for BoardViewSettings {
     }
 }
 
+/// Tiles that can be seen through the fog of war, or `None` if there's no fog
+type Visible<'a> = Option<&'a HashSet<(usize, usize)>>;
+
+/// Gets whoever's taking their turn, if it's someone we can only watch, like a player on another screen
+fn watching(controller: &BoardController, local_id: PlayerID) -> Option<PlayerID> {
+    // everyone plans at once in party mode, so there's nobody to watch
+    if controller.is_party() || controller.local_turn(local_id) {
+        None
+    } else {
+        Some(controller.active_player_id())
+    }
+}
+
+fn can_see(visible: Visible, pos: (usize, usize)) -> bool {
+    match visible {
+        Some(visible) => visible.contains(&pos),
+        None => true,
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 enum DrawMode {
     All,
     OnlySelf,
 }
 
+/// What the pointer's over on the board, which picks the cursor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cursor {
+    /// Nothing that does anything, or it isn't our turn
+    Idle,
+    /// A button, like the rotate buttons
+    Button,
+    /// The loose tile, which can be inserted or turned
+    LooseTile,
+    /// An insert guide on the given side
+    Guide(Direction),
+    /// A tile our token can reach
+    Reachable,
+    /// A tile our token can't reach
+    Unreachable,
+}
+
+impl Cursor {
+    /// Gets the CSS cursor to show for this
+    pub fn css(self) -> &'static str {
+        match self {
+            Cursor::Idle => "default",
+            Cursor::LooseTile => "grab",
+            Cursor::Button | Cursor::Guide(_) | Cursor::Reachable => "pointer",
+            Cursor::Unreachable => "not-allowed",
+        }
+    }
+}
+
 /// Stores visual information about a board
 pub struct BoardView {
     /// Stores board view settings
     pub settings: BoardViewSettings,
+    /// Whether to show every player's reachable tiles, not just our own
+    pub coach_view: bool,
+    /// Whether to label what everything on the board is
+    pub legend: bool,
+    /// Round trip times to the server in milliseconds, by client
+    pub latencies: BTreeMap<PlayerID, u32>,
+    /// Size of the canvas in CSS pixels, which everything gets laid out in
+    pub size: [f64; 2],
+    /// Device pixels per CSS pixel
+    pub scale: f64,
+    /// Where the board's been panned and zoomed to
+    pub camera: Camera,
+    /// How far the board's turned on this screen
+    pub rotation: options::BoardRotation,
+    /// What the pointer's over
+    pub cursor: Cursor,
+    /// Where the pointer is over the canvas, in CSS pixels, if it is
+    pub pointer: Option<[f64; 2]>,
+}
+
+/// How far the board's been panned and zoomed, for looking around a finished game
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    /// How far the board's been dragged, in CSS pixels
+    pub pan: [f64; 2],
+    /// How many times its usual size the board's drawn at
+    pub zoom: f64,
+}
+
+impl Camera {
+    const MIN_ZOOM: f64 = 0.5;
+    const MAX_ZOOM: f64 = 4.0;
+
+    /// Zooms in or out by the given factor, keeping whatever's under the given point (in CSS
+    /// pixels) where it is
+    pub fn zoom_at(&mut self, factor: f64, [x, y]: [f64; 2], [width, height]: [f64; 2]) {
+        let [center_x, center_y] = [width / 2.0, height / 2.0];
+        let [pan_x, pan_y] = self.pan;
+        // where the point would be with no camera at all
+        let board_x = (x - center_x - pan_x) / self.zoom;
+        let board_y = (y - center_y - pan_y) / self.zoom;
+        self.zoom = (self.zoom * factor).clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+        self.pan = [x - center_x - board_x * self.zoom, y - center_y - board_y * self.zoom];
+    }
+
+    /// Moves the board by the given distance in CSS pixels
+    pub fn drag(&mut self, [dx, dy]: [f64; 2]) {
+        self.pan = [self.pan[0] + dx, self.pan[1] + dy];
+    }
+
+    fn apply(&self, [width, height]: [f64; 2], ctx: &Context) {
+        let [pan_x, pan_y] = self.pan;
+        ctx.translate(width / 2.0 + pan_x, height / 2.0 + pan_y).unwrap_throw();
+        ctx.scale(self.zoom, self.zoom).unwrap_throw();
+        ctx.translate(-width / 2.0, -height / 2.0).unwrap_throw();
+    }
+
+    /// Gets where a point on the board ends up on screen, in CSS pixels
+    fn screen_point(&self, [x, y]: [f64; 2], [width, height]: [f64; 2]) -> [f64; 2] {
+        let [pan_x, pan_y] = self.pan;
+        [
+            (x - width / 2.0) * self.zoom + width / 2.0 + pan_x,
+            (y - height / 2.0) * self.zoom + height / 2.0 + pan_y,
+        ]
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            pan: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
 }
 
 impl BoardView {
     /// Creates a new board view
     pub fn new(settings: BoardViewSettings) -> BoardView {
-        BoardView { settings }
+        BoardView {
+            settings,
+            coach_view: false,
+            legend: false,
+            latencies: BTreeMap::new(),
+            size: [0.0, 0.0],
+            scale: 1.0,
+            camera: Camera::default(),
+            rotation: options::BoardRotation::default(),
+            cursor: Cursor::Idle,
+            pointer: None,
+        }
+    }
+
+    /// Lays everything out again for a canvas of the given size in CSS pixels,
+    /// drawn at the given number of device pixels per CSS pixel
+    pub fn resize(&mut self, width: f64, height: f64, scale: f64) {
+        self.size = [width, height];
+        self.scale = scale;
     }
 
     /// Gets the size of an individual tile and the x and y padding values
-    fn tile_padding(&self, controller: &BoardController, ctx: &Context) -> (f64, f64, f64) {
+    fn tile_padding(&self, controller: &BoardController) -> (f64, f64, f64) {
         let settings = &self.settings;
-        let canvas = ctx.canvas().unwrap_throw();
-        let cell_max_height = (canvas.height() as f64 - settings.ui_margin_south)
-            / (controller.board.height() as f64 + 2.0);
-        let cell_max_width = (canvas.width() as f64 - settings.ui_margin_east)
-            / (controller.board.width() as f64 + 2.0);
+        let [width, height] = self.size;
+        // a board on its side takes up its height across the screen, and its width down it
+        let (tiles_across, tiles_down) = if self.rotation.is_sideways() {
+            (controller.board.height(), controller.board.width())
+        } else {
+            (controller.board.width(), controller.board.height())
+        };
+        let cell_max_height = (height - settings.ui_margin_south)
+            / (tiles_down as f64 + 2.0);
+        let cell_max_width = (width - settings.ui_margin_east)
+            / (tiles_across as f64 + 2.0);
         if cell_max_height < cell_max_width {
             let space_used_x =
-                cell_max_height * (controller.board.width() as f64 + 2.0) + settings.ui_margin_east;
+                cell_max_height * (tiles_across as f64 + 2.0) + settings.ui_margin_east;
             (
                 cell_max_height,
-                (canvas.width() as f64 - space_used_x) / 2.0,
+                (width - space_used_x) / 2.0,
                 0.0,
             )
         } else {
-            let space_used_y = cell_max_width * (controller.board.height() as f64 + 2.0)
+            let space_used_y = cell_max_width * (tiles_down as f64 + 2.0)
                 + settings.ui_margin_south;
             (
                 cell_max_width,
                 0.0,
-                (canvas.height() as f64 - space_used_y) / 2.0,
+                (height - space_used_y) / 2.0,
             )
         }
     }
 
-    /// Gets the extents of the game and board
-    fn game_extents(&self, controller: &BoardController, ctx: &Context) -> (Extents, Extents) {
+    /// Gets the extents of the game and board, as they're laid out before being turned
+    fn game_extents(&self, controller: &BoardController) -> (Extents, Extents) {
         let settings = &self.settings;
-        let canvas = ctx.canvas().unwrap_throw();
-        let (cell_size, x_padding, y_padding) = self.tile_padding(controller, ctx);
-        let game = Extents {
+        let [width, height] = self.size;
+        let (cell_size, x_padding, y_padding) = self.tile_padding(controller);
+        let space = Extents {
             west: x_padding,
-            east: canvas.width() as f64 - x_padding - settings.ui_margin_east,
+            east: width - x_padding - settings.ui_margin_east,
             north: y_padding,
-            south: canvas.height() as f64 - y_padding - settings.ui_margin_south,
+            south: height - y_padding - settings.ui_margin_south,
+        };
+        let game = if self.rotation.is_sideways() {
+            // laid out upright in the middle of the space, so it fills it once it's turned
+            let [x, y] = space.center();
+            let half_width = (space.south - space.north) / 2.0;
+            let half_height = (space.east - space.west) / 2.0;
+            Extents {
+                west: x - half_width,
+                east: x + half_width,
+                north: y - half_height,
+                south: y + half_height,
+            }
+        } else {
+            space
         };
         let board = game.clone() - cell_size;
         (game, board)
     }
 
+    /// Turns everything drawn after this around the middle of the game, however far this screen's
+    /// board is turned
+    fn apply_rotation(&self, controller: &BoardController, ctx: &Context) {
+        if self.rotation.quarter_turns() == 0 {
+            return;
+        }
+        let (game, _) = self.game_extents(controller);
+        let [x, y] = game.center();
+        ctx.translate(x, y).unwrap_throw();
+        ctx.rotate(self.rotation.angle()).unwrap_throw();
+        ctx.translate(-x, -y).unwrap_throw();
+    }
+
+    /// Turns a point around the middle of the game by the given angle, clockwise
+    fn turn_point(&self, [x, y]: [f64; 2], angle: f64, controller: &BoardController) -> [f64; 2] {
+        let (game, _) = self.game_extents(controller);
+        let [center_x, center_y] = game.center();
+        let (dx, dy) = (x - center_x, y - center_y);
+        let (sin, cos) = angle.sin_cos();
+        [center_x + dx * cos - dy * sin, center_y + dx * sin + dy * cos]
+    }
+
+    /// Gets where a point on the board as it's laid out ends up once it's turned
+    fn rotated(&self, pos: [f64; 2], controller: &BoardController) -> [f64; 2] {
+        self.turn_point(pos, self.rotation.angle(), controller)
+    }
+
+    /// Gets where a point on screen was on the board before it got turned, for working out what
+    /// the pointer's over
+    fn unrotated(&self, pos: &[f64; 2], controller: &BoardController) -> [f64; 2] {
+        self.turn_point(*pos, -self.rotation.angle(), controller)
+    }
+
     /// Gets the extents of the south and east UI panels
-    fn ui_extents(&self, ctx: &Context) -> (Extents, Extents) {
+    fn ui_extents(&self) -> (Extents, Extents) {
         let settings = &self.settings;
-        let canvas = ctx.canvas().unwrap_throw();
+        let [width, height] = self.size;
         let global = Extents {
             north: 0.0,
-            south: canvas.height() as f64,
+            south: height,
             west: 0.0,
-            east: canvas.width() as f64,
+            east: width,
         };
         let south = Extents {
             north: global.south - settings.ui_margin_south,
@@ -308,6 +602,15 @@ impl BoardView {
 
     /// Draw board
     pub fn draw(&self, controller: &BoardController, local_id: PlayerID, ctx: &Context) {
+        // everything's laid out in CSS pixels, but the canvas has a pixel for every device pixel
+        ctx.set_transform(self.scale, 0.0, 0.0, self.scale, 0.0, 0.0).unwrap_throw();
+
+        self.draw_backdrop(ctx);
+
+        // everyone at this screen sees through the fog together, so this comes first
+        let visible = controller.visible_tiles(local_id);
+        let visible = visible.as_ref();
+
         // if a child is coming up soon, pretend we are them instead
         let local_id = controller.effective_local_id(local_id);
 
@@ -315,17 +618,27 @@ impl BoardView {
         let board_tile_height = controller.board.height();
 
         let settings = &self.settings;
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
+        let (cell_size, _, _) = self.tile_padding(controller);
 
         // draw board
-        let (game, board) = self.game_extents(controller, ctx);
+        let (game, board) = self.game_extents(controller);
         let board_width = cell_size * board_tile_width as f64;
         let board_height = cell_size * board_tile_height as f64;
 
         ctx.save();
+        self.camera.apply(self.size, ctx);
+        self.apply_rotation(controller, ctx);
 
         // draw the tiles
-        self.draw_tiles(controller, local_id, ctx);
+        self.draw_tiles(controller, local_id, visible, ctx);
+
+        // draw everyone's reachable tiles, unless the fog is meant to be hiding them
+        if self.coach_view && visible.is_none() {
+            self.draw_coach_view(controller, ctx);
+        }
+
+        // warn about the outer ring collapsing
+        self.draw_collapse_warning(controller, ctx);
 
         // draw tile edges
         ctx.set_line_width(settings.cell_edge_radius);
@@ -350,30 +663,139 @@ impl BoardView {
         ctx.set_stroke_style(&settings.board_edge_color.into());
         ctx.stroke_rect(board.west, board.north, board_width, board_height);
 
+        // draw turn timer warning
+        if let Some(pulse) = controller.anim.turn_timer.warning_pulse() {
+            ctx.save();
+            ctx.set_global_alpha(pulse);
+            ctx.set_line_width(cell_size * settings.timer_warning_width);
+            ctx.set_stroke_style(&settings.timer_warning_color.into());
+            ctx.stroke_rect(board.west, board.north, board_width, board_height);
+            ctx.restore();
+        }
+
         // draw insert guides
         self.draw_insert_guides(controller, local_id, ctx);
 
+        // show what's happened over the last few turns
+        if options::HANDLE.fetch().move_history {
+            self.draw_move_history(controller, visible, ctx);
+        }
+
+        // show where tokens would get pushed to
+        if controller.local_turn(local_id) {
+            if let TurnState::InsertTile = controller.turn_state {
+                self.draw_push_preview(controller, visible, ctx);
+            }
+        }
+
+        // show the hint, if the player asked for one
+        if controller.local_turn(local_id) {
+            self.draw_hint(controller, ctx);
+        }
+
         // draw player tokens
-        self.draw_player_tokens(DrawMode::All, controller, local_id, ctx);
+        self.draw_player_tokens(DrawMode::All, controller, local_id, visible, ctx);
 
         // draw own token on top of others
-        self.draw_player_tokens(DrawMode::OnlySelf, controller, local_id, ctx);
+        self.draw_player_tokens(DrawMode::OnlySelf, controller, local_id, visible, ctx);
 
-        // draw UI
+        ctx.restore();
+
+        // draw UI, which stays put wherever the camera goes
         self.draw_ui(controller, local_id, ctx);
 
+        // label everything last, so nothing covers the labels
+        if self.legend {
+            self.draw_legend(controller, visible, ctx);
+        }
+
+        // the cursor goes on top of everything, even the labels
+        if let Some(pointer) = self.pointer {
+            self.draw_cursor(controller, local_id, pointer, ctx);
+        }
+    }
+
+    /// Draws a badge beside the pointer that says what clicking would do, on top of the CSS cursor
+    fn draw_cursor(&self, controller: &BoardController, local_id: PlayerID, [x, y]: [f64; 2], ctx: &Context) {
+        let radius = 6.0;
+        // down and to the right, so it's clear of the CSS cursor
+        let [x, y] = [x + 18.0, y + 18.0];
+        let color = controller
+            .players
+            .get(&controller.active_player_id())
+            .filter(|_| controller.local_turn(local_id))
+            .map_or(self.settings.text_color, |player| player.color);
+        ctx.save();
+        ctx.set_line_width(2.0);
+        match self.cursor {
+            Cursor::Idle | Cursor::Button => {}
+            Cursor::LooseTile => {
+                // it turns with a right click
+                ctx.set_fill_style(&self.settings.text_color.into());
+                ctx.set_font(&format!("{}px sans-serif", radius * 3.0));
+                ctx.set_text_align("center");
+                ctx.set_text_baseline("middle");
+                ctx.fill_text("\u{27f3}", x, y).unwrap_throw();
+            }
+            Cursor::Guide(side) => {
+                // which way the tile would get pushed, as it looks on this screen
+                let [dx, dy] = [0.0, radius * 1.5] * side * self.rotation.turn();
+                ctx.set_stroke_style(&color.into());
+                draw_arrow([x - dx, y - dy], [x + dx, y + dy], radius, ctx);
+            }
+            Cursor::Reachable => {
+                ctx.set_fill_style(&color.into());
+                ctx.begin_path();
+                ctx.arc(x, y, radius, 0.0, ::std::f64::consts::PI * 2.0).unwrap_throw();
+                ctx.fill();
+            }
+            Cursor::Unreachable => {
+                ctx.set_stroke_style(&self.settings.collapse_color.into());
+                ctx.begin_path();
+                ctx.arc(x, y, radius, 0.0, ::std::f64::consts::PI * 2.0).unwrap_throw();
+                let slash = radius * ::std::f64::consts::FRAC_1_SQRT_2;
+                ctx.move_to(x - slash, y - slash);
+                ctx.line_to(x + slash, y + slash);
+                ctx.stroke();
+            }
+        }
         ctx.restore();
     }
 
-    fn tile_extents(
-        &self,
-        controller: &BoardController,
-        row: usize,
-        col: usize,
-        ctx: &Context,
-    ) -> Extents {
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
-        let (_, board) = self.game_extents(controller, ctx);
+    /// Traces the given path across the board in the given color, ending in an arrowhead
+    pub fn draw_path(&self, controller: &BoardController, path: &[(usize, usize)], color: Color, ctx: &Context) {
+        let points: Vec<[f64; 2]> = path
+            .iter()
+            .map(|&(row, col)| self.tile_extents(controller, row, col).center())
+            .collect();
+        let (last, rest) = match points.split_last() {
+            Some((last, rest)) if !rest.is_empty() => (last, rest),
+            _ => return,
+        };
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let line_width = cell_size * self.settings.wall_width;
+
+        ctx.save();
+        ctx.set_transform(self.scale, 0.0, 0.0, self.scale, 0.0, 0.0).unwrap_throw();
+        self.camera.apply(self.size, ctx);
+        self.apply_rotation(controller, ctx);
+        ctx.set_line_width(line_width);
+        ctx.set_line_cap("round");
+        ctx.set_line_join("round");
+        ctx.set_stroke_style(&color.into());
+        ctx.begin_path();
+        ctx.move_to(rest[0][0], rest[0][1]);
+        for &[x, y] in &rest[1..] {
+            ctx.line_to(x, y);
+        }
+        ctx.stroke();
+        draw_arrow(rest[rest.len() - 1], *last, line_width * 2.0, ctx);
+        ctx.restore();
+    }
+
+    fn tile_extents(&self, controller: &BoardController, row: usize, col: usize) -> Extents {
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (_, board) = self.game_extents(controller);
         let north = board.north + row as f64 * cell_size;
         let south = north + cell_size;
         let west = board.west + col as f64 * cell_size;
@@ -386,54 +808,104 @@ impl BoardView {
         }
     }
 
+    /// Gets the (row, col) of the cell a position falls in, counting from the board's top left tile,
+    /// so the insert guides around the edge are at -1 and the board's width or height
+    fn grid_pos(&self, pos: &[f64; 2], controller: &BoardController) -> (isize, isize) {
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (_, board) = self.game_extents(controller);
+        let [x, y] = self.unrotated(pos, controller);
+        let row = ((y - board.north) / cell_size).floor() as isize;
+        let col = ((x - board.west) / cell_size).floor() as isize;
+        (row, col)
+    }
+
     /// Checks if a given position is within a tile, and returns that tile's (row, col)
     pub fn in_tile(
         &self,
         pos: &[f64; 2],
         controller: &BoardController,
-        ctx: &Context,
     ) -> Option<(usize, usize)> {
-        // TODO don't do this dumb thing
-
-        let board_tile_width = controller.board.width();
-        let board_tile_height = controller.board.height();
+        let height = controller.board.height() as isize;
+        let width = controller.board.width() as isize;
+        match self.grid_pos(pos, controller) {
+            (row, col) if (0..height).contains(&row) && (0..width).contains(&col) => {
+                Some((row as usize, col as usize))
+            }
+            _ => None,
+        }
+    }
 
-        for j in 0..board_tile_height {
-            for i in 0..board_tile_width {
-                let cell = self.tile_extents(controller, j, i, ctx);
-                if pos < &cell {
-                    return Some((j, i));
+    /// Fills the whole canvas with the theme's backdrop and pattern
+    fn draw_backdrop(&self, ctx: &Context) {
+        let settings = &self.settings;
+        let [width, height] = self.size;
+        let spacing = settings.pattern_spacing;
+        ctx.save();
+        ctx.set_fill_style(&settings.backdrop_color.into());
+        ctx.fill_rect(0.0, 0.0, width, height);
+        ctx.set_fill_style(&settings.pattern_color.into());
+        ctx.set_stroke_style(&settings.pattern_color.into());
+        match settings.pattern {
+            Pattern::Plain => (),
+            Pattern::Dots => {
+                let mut y = spacing / 2.0;
+                let mut odd_row = false;
+                while y < height {
+                    // every other row is shifted over half a step, so the dots stagger
+                    let mut x = if odd_row { spacing } else { spacing / 2.0 };
+                    while x < width {
+                        ctx.begin_path();
+                        ctx.arc(x, y, spacing / 8.0, 0.0, ::std::f64::consts::PI * 2.0)
+                            .unwrap_throw();
+                        ctx.fill();
+                        x += spacing;
+                    }
+                    y += spacing / 2.0;
+                    odd_row = !odd_row;
+                }
+            }
+            Pattern::Stripes => {
+                ctx.set_line_width(spacing / 4.0);
+                // each stripe runs down and to the left, so they carry on past the right edge to cover the bottom right corner
+                let mut x = 0.0;
+                while x < width + height {
+                    ctx.begin_path();
+                    ctx.move_to(x, 0.0);
+                    ctx.line_to(x - height, height);
+                    ctx.stroke();
+                    x += spacing;
                 }
             }
         }
-        None
+        ctx.restore();
     }
 
-    fn draw_tiles(&self, controller: &BoardController, local_id: PlayerID, ctx: &Context) {
+    fn draw_tiles(&self, controller: &BoardController, local_id: PlayerID, visible: Visible, ctx: &Context) {
         let board_tile_width = controller.board.width();
         let board_tile_height = controller.board.height();
 
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
-        let current_player_pos = controller.board.player_pos(local_id);
-        let reachable = controller.board.reachable_coords(current_player_pos);
-        let loose_insert = &anim::STATE.read().unwrap().loose_insert;
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let reachable = controller.reachable_for(local_id);
+        let loose_insert = &controller.anim.loose_insert;
 
         let [offset_x, offset_y] =
             [0.0, loose_insert.distance_left * cell_size] * loose_insert.offset_dir;
 
         for j in 0..board_tile_height {
             for i in 0..board_tile_width {
-                let cell = self.tile_extents(controller, j, i, ctx);
-                let color = if reachable.contains(&(j, i)) {
-                    self.settings.reachable_background_color
-                } else {
-                    self.settings.background_color
-                };
+                let cell = self.tile_extents(controller, j, i);
+                let color = self.tile_background(&reachable, (j, i));
                 let is_highlighted = controller.highlighted_tile == (j, i);
                 ctx.save();
                 if loose_insert.applies_to_pos((j, i)) {
                     ctx.translate(offset_x, offset_y).unwrap_throw();
                 };
+                if !can_see(visible, (j, i)) {
+                    ctx.set_fill_style(&self.settings.fog_color.into());
+                    ctx.fill_rect(cell.west, cell.north, cell.east - cell.west, cell.south - cell.north);
+                    ctx.restore();
+                    continue;
+                }
                 self.draw_tile(
                     controller.board.get([i, j]),
                     cell,
@@ -444,11 +916,90 @@ impl BoardView {
                     local_id,
                     ctx,
                 );
+                if controller.board.is_fixed((j, i)) {
+                    self.draw_fixed_marker(controller, j, i, ctx);
+                }
                 ctx.restore();
             }
         }
     }
 
+    /// Gets the color of the tile at the given (row, col), given what's reachable from here
+    fn tile_background(&self, reachable: &HashSet<(usize, usize)>, pos: (usize, usize)) -> Color {
+        if reachable.contains(&pos) {
+            self.settings.reachable_background_color
+        } else {
+            self.settings.background_color
+        }
+    }
+
+    /// Gets the middle of the marker in the corner of a tile that never moves
+    fn fixed_marker_center(&self, controller: &BoardController, row: usize, col: usize) -> [f64; 2] {
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let cell = self.tile_extents(controller, row, col);
+        [cell.west + cell_size / 4.0, cell.north + cell_size / 4.0]
+    }
+
+    /// Draws a little rivet in the corner of a tile that never moves, opposite the golden coin
+    fn draw_fixed_marker(&self, controller: &BoardController, row: usize, col: usize, ctx: &Context) {
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let [x, y] = self.fixed_marker_center(controller, row, col);
+        let size = cell_size / 10.0;
+        ctx.save();
+        ctx.set_fill_style(&self.settings.cell_edge_color.into());
+        ctx.fill_rect(x - size / 2.0, y - size / 2.0, size, size);
+        ctx.restore();
+    }
+
+    fn draw_coach_view(&self, controller: &BoardController, ctx: &Context) {
+        let board_tile_width = controller.board.width();
+        let board_tile_height = controller.board.height();
+
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let wall_width = cell_size * self.settings.wall_width;
+        let loose_insert = &controller.anim.loose_insert;
+
+        let [offset_x, offset_y] =
+            [0.0, loose_insert.distance_left * cell_size] * loose_insert.offset_dir;
+
+        let reachable: Vec<_> = controller
+            .turn_order
+            .iter()
+            .map(|id| {
+                (controller.players[id].color, controller.reachable_for(*id))
+            })
+            .collect();
+
+        ctx.save();
+        ctx.set_global_alpha(self.settings.coach_alpha);
+        for j in 0..board_tile_height {
+            for i in 0..board_tile_width {
+                let colors: Vec<Color> = reachable
+                    .iter()
+                    .filter(|(_, coords)| coords.contains(&(j, i)))
+                    .map(|(color, _)| *color)
+                    .collect();
+                if colors.is_empty() {
+                    continue;
+                }
+                let inner = self.tile_extents(controller, j, i) - wall_width;
+                // one band per player, so overlapping regions stay readable
+                let band_height = (inner.south - inner.north) / colors.len() as f64;
+                ctx.save();
+                if loose_insert.applies_to_pos((j, i)) {
+                    ctx.translate(offset_x, offset_y).unwrap_throw();
+                };
+                for (k, color) in colors.into_iter().enumerate() {
+                    ctx.set_fill_style(&color.into());
+                    let north = inner.north + k as f64 * band_height;
+                    ctx.fill_rect(inner.west, north, inner.east - inner.west, band_height);
+                }
+                ctx.restore();
+            }
+        }
+        ctx.restore();
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn draw_tile(
         &self,
@@ -463,9 +1014,9 @@ impl BoardView {
     ) {
         let settings = &self.settings;
 
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
+        let (cell_size, _, _) = self.tile_padding(controller);
         let wall_width = cell_size * settings.wall_width;
-        let anim_state = anim::STATE.read().unwrap();
+        let anim_state = &controller.anim;
 
         ctx.save();
 
@@ -485,7 +1036,10 @@ impl BoardView {
         ctx.fill_rect(outer.west, outer.north, cell_size, cell_size);
 
         if let Some(whose_target) = tile.whose_target {
-            let color = controller.players[&whose_target].color;
+            // a color close to the tile's would leave the stripes all but invisible
+            let color = controller.players[&whose_target]
+                .color
+                .contrasting(background_color, settings.min_contrast);
 
             // TODO tilt based on something so less reliant on color
 
@@ -521,19 +1075,123 @@ impl BoardView {
         }
 
         ctx.set_fill_style(&settings.wall_color.into());
-        ctx.fill_rect(outer.west, outer.north, wall_width, wall_width);
-        ctx.fill_rect(inner.east, outer.north, wall_width, wall_width);
-        ctx.fill_rect(outer.west, inner.south, wall_width, wall_width);
-        ctx.fill_rect(inner.east, inner.south, wall_width, wall_width);
+        // a crossroads has no walls, so it's only the corner posts
         let walled_directions = tile.walls();
-        for d in walled_directions {
-            let (x, y, w, h) = match d {
-                Direction::North => (outer.west, outer.north, cell_size, wall_width),
-                Direction::South => (outer.west, inner.south, cell_size, wall_width),
-                Direction::East => (inner.east, outer.north, wall_width, cell_size),
-                Direction::West => (outer.west, outer.north, wall_width, cell_size),
-            };
-            ctx.fill_rect(x, y, w, h);
+        match settings.wall_style {
+            WallStyle::Solid => {
+                ctx.fill_rect(outer.west, outer.north, wall_width, wall_width);
+                ctx.fill_rect(inner.east, outer.north, wall_width, wall_width);
+                ctx.fill_rect(outer.west, inner.south, wall_width, wall_width);
+                ctx.fill_rect(inner.east, inner.south, wall_width, wall_width);
+                for d in walled_directions {
+                    let (x, y, w, h) = match d {
+                        Direction::North => (outer.west, outer.north, cell_size, wall_width),
+                        Direction::South => (outer.west, inner.south, cell_size, wall_width),
+                        Direction::East => (inner.east, outer.north, wall_width, cell_size),
+                        Direction::West => (outer.west, outer.north, wall_width, cell_size),
+                    };
+                    ctx.fill_rect(x, y, w, h);
+                }
+            }
+            WallStyle::Rounded => {
+                // walls run between the middles of the corner posts, with round caps reaching the edges
+                let half = wall_width / 2.0;
+                let nw = [outer.west + half, outer.north + half];
+                let ne = [outer.east - half, outer.north + half];
+                let sw = [outer.west + half, outer.south - half];
+                let se = [outer.east - half, outer.south - half];
+                for &[x, y] in &[nw, ne, sw, se] {
+                    ctx.begin_path();
+                    ctx.arc(x, y, half, 0.0, ::std::f64::consts::PI * 2.0).unwrap_throw();
+                    ctx.fill();
+                }
+                ctx.set_stroke_style(&settings.wall_color.into());
+                ctx.set_line_width(wall_width);
+                ctx.set_line_cap("round");
+                for d in walled_directions {
+                    let ([x1, y1], [x2, y2]) = match d {
+                        Direction::North => (nw, ne),
+                        Direction::South => (sw, se),
+                        Direction::East => (ne, se),
+                        Direction::West => (nw, sw),
+                    };
+                    ctx.begin_path();
+                    ctx.move_to(x1, y1);
+                    ctx.line_to(x2, y2);
+                    ctx.stroke();
+                }
+            }
+        }
+
+        // show what the target's worth, when that isn't always the same
+        let target_value = tile
+            .whose_target
+            .filter(|_| controller.board.scoring == Scoring::Points)
+            .and_then(|id| controller.board.player_tokens.get(&id))
+            .map(|token| token.target_value);
+        if let Some(value) = target_value {
+            ctx.save();
+            ctx.set_text_align("center");
+            ctx.set_text_baseline("middle");
+            ctx.set_font(&format!("bold {}px sans-serif", (cell_size / 3.0).round()));
+            ctx.set_fill_style(&settings.text_color.into());
+            // the board may be turned, but the number still reads the right way up
+            ctx.rotate(-self.rotation.angle()).unwrap_throw();
+            ctx.fill_text(&format!("{}", value), 0.0, 0.0).unwrap_throw();
+            ctx.restore();
+        } else {
+            // otherwise the target shows the treasure on its owner's card
+            let card = tile
+                .whose_target
+                .and_then(|id| controller.board.player_tokens.get(&id))
+                .and_then(|token| token.card);
+            if let Some(card) = card {
+                ctx.save();
+                ctx.set_text_align("center");
+                ctx.set_text_baseline("middle");
+                ctx.set_font(&format!("{}px sans-serif", (cell_size / 3.0).round()));
+                ctx.rotate(-self.rotation.angle()).unwrap_throw();
+                ctx.fill_text(card.symbol(), 0.0, 0.0).unwrap_throw();
+                ctx.restore();
+            }
+        }
+
+        // a coin in the corner of a golden tile, which scores double as a target
+        if tile.golden {
+            let radius = cell_size / 10.0;
+            ctx.set_fill_style(&settings.golden_color.into());
+            ctx.begin_path();
+            ctx.arc(
+                outer.west + radius * 4.0,
+                outer.south - radius * 4.0,
+                radius,
+                0.0,
+                ::std::f64::consts::PI * 2.0,
+            )
+                .unwrap_throw();
+            ctx.fill();
+        }
+
+        // a ring in the corner of a portal, which takes tokens through to the next layer
+        if tile.portal {
+            let radius = cell_size / 8.0;
+            ctx.set_stroke_style(&settings.portal_color.into());
+            ctx.set_line_width(radius / 2.0);
+            ctx.begin_path();
+            ctx.arc(
+                outer.east - radius * 3.0,
+                outer.north + radius * 3.0,
+                radius,
+                0.0,
+                ::std::f64::consts::PI * 2.0,
+            )
+                .unwrap_throw();
+            ctx.stroke();
+        }
+
+        // a padlock in the corner of a target someone landed on, until its owner's next turn is over
+        if matches!(tile.whose_target, Some(id) if controller.board.target_locked(id)) {
+            self.draw_lock(cell_size, &outer, ctx);
         }
 
         if draw_border {
@@ -549,15 +1207,26 @@ impl BoardView {
         ctx.restore();
     }
 
-    fn insert_guides(
-        &self,
-        controller: &BoardController,
-        ctx: &Context,
-    ) -> Vec<(Direction, Vec<Extents>)> {
+    fn draw_lock(&self, cell_size: f64, outer: &Extents, ctx: &Context) {
+        let size = cell_size / 4.0;
+        let [x, y] = [outer.east - size * 1.5, outer.north + size * 0.75];
+        ctx.save();
+        ctx.set_fill_style(&self.settings.text_color.into());
+        ctx.set_stroke_style(&self.settings.text_color.into());
+        ctx.set_line_width(size / 6.0);
+        ctx.begin_path();
+        ctx.arc(x + size / 2.0, y + size / 2.0, size / 3.0, ::std::f64::consts::PI, 0.0)
+            .unwrap_throw();
+        ctx.stroke();
+        ctx.fill_rect(x, y + size / 2.0, size, size * 0.6);
+        ctx.restore();
+    }
+
+    fn insert_guides(&self, controller: &BoardController) -> Vec<(Direction, Vec<Extents>)> {
         let board_tile_width = controller.board.width();
         let board_tile_height = controller.board.height();
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
-        let (game, board) = self.game_extents(controller, ctx);
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (game, board) = self.game_extents(controller);
 
         let mut result = vec![];
 
@@ -612,18 +1281,25 @@ impl BoardView {
         result
     }
 
-    fn draw_insert_guides(&self, controller: &BoardController, _local_id: PlayerID, ctx: &Context) {
+    fn draw_insert_guides(&self, controller: &BoardController, local_id: PlayerID, ctx: &Context) {
         let settings = &self.settings;
 
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
+        let (cell_size, _, _) = self.tile_padding(controller);
         let wall_width = cell_size * settings.wall_width;
+        let anim_state = &controller.anim;
 
         ctx.save();
 
-        ctx.set_fill_style(&settings.insert_guide_color.into());
-        for (dir, guides) in self.insert_guides(controller, ctx) {
-            for guide in guides {
-                let guide = guide - wall_width;
+        // there's nothing to push while someone else is taking their turn
+        if watching(controller, local_id).is_some() {
+            ctx.set_global_alpha(settings.watching_guide_alpha);
+        }
+
+        for (dir, guides) in self.insert_guides(controller) {
+            for (i, guide) in guides.into_iter().enumerate() {
+                // pressed guides pop out and light up for a moment
+                let press = anim_state.guides.press_amount((dir, i));
+                let guide = guide - wall_width * (1.0 - press);
                 let mid_x = (guide.east + guide.west) / 2.0;
                 let mid_y = (guide.north + guide.south) / 2.0;
                 let ((x0, y0), (x1, y1), (x2, y2)) = match dir {
@@ -653,7 +1329,15 @@ impl BoardView {
                 ctx.line_to(x1, y1);
                 ctx.line_to(x2, y2);
                 ctx.close_path();
+                ctx.set_fill_style(&settings.insert_guide_color.into());
                 ctx.fill();
+                if press > 0.0 {
+                    ctx.save();
+                    ctx.set_global_alpha(press * 0.6);
+                    ctx.set_fill_style(&settings.reachable_background_color.into());
+                    ctx.fill();
+                    ctx.restore();
+                }
             }
         }
 
@@ -665,21 +1349,34 @@ impl BoardView {
         &self,
         pos: &[f64; 2],
         controller: &BoardController,
-        ctx: &Context,
     ) -> Option<(Direction, usize)> {
-        for (dir, guides) in self.insert_guides(controller, ctx) {
-            for (i, guide) in guides.into_iter().enumerate() {
-                if pos < &guide {
-                    return Some((dir, i));
-                }
+        let height = controller.board.height() as isize;
+        let width = controller.board.width() as isize;
+        // guides sit just outside every other row and column, starting from the second
+        let guide = |i: isize, len: isize| {
+            if i % 2 == 1 && i / 2 < len / 2 {
+                Some(i as usize / 2)
+            } else {
+                None
             }
+        };
+        let (row, col) = self.grid_pos(pos, controller);
+        if row == -1 {
+            guide(col, width).map(|i| (Direction::North, i))
+        } else if row == height {
+            guide(col, width).map(|i| (Direction::South, i))
+        } else if col == -1 {
+            guide(row, height).map(|i| (Direction::West, i))
+        } else if col == width {
+            guide(row, height).map(|i| (Direction::East, i))
+        } else {
+            None
         }
-        None
     }
 
-    fn loose_tile_extents(&self, controller: &BoardController, ctx: &Context) -> Extents {
+    fn loose_tile_extents(&self, controller: &BoardController) -> Extents {
         let (target_dir, idx) = controller.board.loose_tile_position;
-        for (dir, guides) in self.insert_guides(controller, ctx) {
+        for (dir, guides) in self.insert_guides(controller) {
             if dir == target_dir {
                 return guides[idx].clone();
             }
@@ -687,15 +1384,308 @@ impl BoardView {
         unreachable!()
     }
 
+    /// Gets the extents of the counterclockwise and clockwise rotate buttons,
+    /// which sit in the gaps on either side of the loose tile
+    fn rotate_button_extents(&self, controller: &BoardController) -> (Extents, Extents) {
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let cell = self.loose_tile_extents(controller);
+        let (dx, dy) = match controller.board.loose_tile_position.0 {
+            Direction::North | Direction::South => (cell_size, 0.0),
+            Direction::East | Direction::West => (0.0, cell_size),
+        };
+        let shift = |dx: f64, dy: f64| {
+            Extents {
+                north: cell.north + dy,
+                south: cell.south + dy,
+                west: cell.west + dx,
+                east: cell.east + dx,
+            } - cell_size / 4.0
+        };
+        (shift(-dx, -dy), shift(dx, dy))
+    }
+
+    /// Draws a small picture of the given board, centered in a square of the given size, with just
+    /// the tiles and none of the game around them
+    pub fn draw_preview(&self, board: &Board, size: f64, ctx: &Context) {
+        ctx.clear_rect(0.0, 0.0, size, size);
+        self.draw_miniature(board, size, ctx);
+    }
+
+    /// Draws a small picture of the given board's tiles, centered in a square of the given size
+    /// at the origin, without clearing what's under it
+    fn draw_miniature(&self, board: &Board, size: f64, ctx: &Context) {
+        let settings = &self.settings;
+        let cell_size = size / board.width().max(board.height()) as f64;
+        let wall_width = cell_size * settings.wall_width;
+        let west = (size - cell_size * board.width() as f64) / 2.0;
+        let north = (size - cell_size * board.height() as f64) / 2.0;
+        ctx.save();
+        for row in 0..board.height() {
+            for col in 0..board.width() {
+                let x = west + col as f64 * cell_size;
+                let y = north + row as f64 * cell_size;
+                ctx.set_fill_style(&settings.wall_color.into());
+                ctx.fill_rect(x, y, cell_size, cell_size);
+                // carve the paths out of solid wall
+                ctx.set_fill_style(&settings.background_color.into());
+                let inner = cell_size - 2.0 * wall_width;
+                ctx.fill_rect(x + wall_width, y + wall_width, inner, inner);
+                for dir in board.get([col, row]).paths() {
+                    let (x, y, w, h) = match dir {
+                        Direction::North => (x + wall_width, y, inner, wall_width),
+                        Direction::South => (x + wall_width, y + cell_size - wall_width, inner, wall_width),
+                        Direction::East => (x + cell_size - wall_width, y + wall_width, wall_width, inner),
+                        Direction::West => (x, y + wall_width, wall_width, inner),
+                    };
+                    ctx.fill_rect(x, y, w, h);
+                }
+            }
+        }
+        ctx.restore();
+    }
+
+    /// Gets the extents of the double push button, at the east end of the south panel
+    fn double_push_button_extents(&self) -> Extents {
+        let (south_panel, _) = self.ui_extents();
+        Extents {
+            north: south_panel.north + 5.0,
+            south: south_panel.north + 35.0,
+            west: south_panel.east - 140.0,
+            east: south_panel.east - 10.0,
+        }
+    }
+
+    /// Checks if the given position is within the double push button
+    pub fn in_double_push_button(&self, pos: &[f64; 2]) -> bool {
+        pos < &self.double_push_button_extents()
+    }
+
+    fn draw_double_push_button(&self, ctx: &Context) {
+        let button = self.double_push_button_extents();
+        ctx.save();
+        ctx.set_fill_style(&self.settings.insert_guide_color.into());
+        ctx.fill_rect(button.west, button.north, button.east - button.west, button.south - button.north);
+        ctx.set_fill_style(&self.settings.reachable_background_color.into());
+        ctx.set_font("15px sans-serif");
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+        let [x, y] = button.center();
+        ctx.fill_text("Double Push", x, y).unwrap_throw();
+        ctx.restore();
+    }
+
+    /// Checks if the given position is within one of the loose tile rotate buttons
+    pub fn in_rotate_button(
+        &self,
+        pos: &[f64; 2],
+        controller: &BoardController,
+    ) -> Option<RotateDir> {
+        let (ccw, cw) = self.rotate_button_extents(controller);
+        let pos = &self.unrotated(pos, controller);
+        if pos < &ccw {
+            Some(RotateDir::CCW)
+        } else if pos < &cw {
+            Some(RotateDir::CW)
+        } else {
+            None
+        }
+    }
+
+    fn draw_hint(&self, controller: &BoardController, ctx: &Context) {
+        let hint = match &controller.hint {
+            Some(hint) => hint,
+            None => return,
+        };
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let outline = match (&controller.turn_state, hint.insert) {
+            (TurnState::InsertTile, Some(((side, guide), _))) => self
+                .insert_guides(controller)
+                .into_iter()
+                .find(|&(dir, _)| dir == side)
+                .and_then(|(_, guides)| guides.get(guide).cloned()),
+            (TurnState::InsertTile, None) => None,
+            (TurnState::MoveToken, _) => {
+                let (row, col) = hint.destination;
+                Some(self.tile_extents(controller, row, col))
+            }
+        };
+        if let Some(outline) = outline {
+            let outline = outline - cell_size * self.settings.wall_width / 2.0;
+            ctx.save();
+            ctx.set_line_width(cell_size * self.settings.wall_width / 2.0);
+            ctx.set_stroke_style(&self.settings.hint_color.into());
+            ctx.stroke_rect(
+                outline.west,
+                outline.north,
+                outline.east - outline.west,
+                outline.south - outline.north,
+            );
+            ctx.restore();
+        }
+    }
+
+    fn draw_rotate_buttons(&self, controller: &BoardController, suggested: Option<(RotateDir, Color)>, ctx: &Context) {
+        let (ccw, cw) = self.rotate_button_extents(controller);
+        ctx.save();
+        self.apply_rotation(controller, ctx);
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+        for (button, label, dir) in &[(ccw, "\u{27f2}", RotateDir::CCW), (cw, "\u{27f3}", RotateDir::CW)] {
+            let [x, y] = button.center();
+            let radius = (button.east - button.west) / 2.0;
+            ctx.begin_path();
+            ctx.set_fill_style(&self.settings.insert_guide_color.into());
+            ctx.ellipse(x, y, radius, radius, 0.0, 0.0, ::std::f64::consts::PI * 2.0)
+                .unwrap_throw();
+            ctx.fill();
+            // ring whichever way the rotate assist suggests turning
+            if let Some((_, color)) = suggested.filter(|&(suggested, _)| suggested == *dir) {
+                ctx.set_stroke_style(&color.into());
+                ctx.set_line_width(radius / 4.0);
+                ctx.stroke();
+            }
+            ctx.set_fill_style(&self.settings.reachable_background_color.into());
+            ctx.set_font(&format!("{}px sans-serif", (radius * 1.5).round()));
+            ctx.fill_text(label, x, y).unwrap_throw();
+        }
+        ctx.restore();
+    }
+
     /// Check if the given position is within the loose tile area
     pub fn in_loose_tile(
         &self,
         pos: &[f64; 2],
         controller: &BoardController,
-        ctx: &Context,
     ) -> bool {
-        let cell = self.loose_tile_extents(controller, ctx);
-        pos < &cell
+        let cell = self.loose_tile_extents(controller);
+        self.unrotated(pos, controller) < cell
+    }
+
+    fn draw_collapse_warning(&self, controller: &BoardController, ctx: &Context) {
+        let settings = &self.settings;
+        let turns_left = match controller.turns_until_collapse() {
+            Some(turns_left) if turns_left <= settings.collapse_warning_turns => turns_left,
+            _ => return,
+        };
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (_, board) = self.game_extents(controller);
+        let width = board.east - board.west;
+        let height = board.south - board.north;
+
+        ctx.save();
+        // it gets redder as it gets closer
+        let closeness = (settings.collapse_warning_turns + 1 - turns_left) as f64
+            / settings.collapse_warning_turns as f64;
+        ctx.set_global_alpha(settings.collapse_alpha * closeness);
+        ctx.set_fill_style(&settings.collapse_color.into());
+        // one path, so the corners where the strips overlap don't get filled twice
+        ctx.begin_path();
+        ctx.rect(board.west, board.north, width, cell_size);
+        ctx.rect(board.west, board.south - cell_size, width, cell_size);
+        ctx.rect(board.west, board.north, cell_size, height);
+        ctx.rect(board.east - cell_size, board.north, cell_size, height);
+        ctx.fill();
+        ctx.restore();
+    }
+
+    fn draw_move_history(&self, controller: &BoardController, visible: Visible, ctx: &Context) {
+        let settings = &self.settings;
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (_, board) = self.game_extents(controller);
+        let line_width = cell_size * settings.wall_width / 2.0;
+        let guides = self.insert_guides(controller);
+
+        ctx.save();
+        ctx.set_line_width(line_width);
+        ctx.set_line_cap("round");
+        // oldest first, so newer arrows go on top
+        for (age, event) in controller.log.recent_moves(settings.history_turns).into_iter().rev() {
+            let fade = 1.0 - age as f64 / (settings.history_turns + 1) as f64;
+            ctx.set_global_alpha(settings.history_alpha * fade);
+            match *event {
+                GameEvent::Insert { position: (side, guide), .. } => {
+                    let guide = match guides.iter().find(|&&(dir, _)| dir == side) {
+                        Some((_, guides)) => match guides.get(guide) {
+                            Some(guide) => guide,
+                            None => continue,
+                        },
+                        None => continue,
+                    };
+                    // the arrow runs from the guide all the way across the row or column it pushed
+                    let [x, y] = guide.center();
+                    let end = match side {
+                        Direction::North => [x, board.south],
+                        Direction::South => [x, board.north],
+                        Direction::West => [board.east, y],
+                        Direction::East => [board.west, y],
+                    };
+                    ctx.set_stroke_style(&settings.insert_guide_color.into());
+                    draw_arrow([x, y], end, line_width * 2.0, ctx);
+                }
+                GameEvent::Move { player, from, to, .. } => {
+                    // staying put doesn't need an arrow, and moves in the fog stay hidden
+                    if from == to || !can_see(visible, from) || !can_see(visible, to) {
+                        continue;
+                    }
+                    let color = match controller.players.get(&player) {
+                        Some(player) => player.color,
+                        None => continue,
+                    };
+                    let (from_row, from_col) = from;
+                    let (to_row, to_col) = to;
+                    let start = self.tile_extents(controller, from_row, from_col).center();
+                    let end = self.tile_extents(controller, to_row, to_col).center();
+                    ctx.set_stroke_style(&color.into());
+                    draw_arrow(start, end, line_width * 2.0, ctx);
+                }
+                GameEvent::Join { .. }
+                | GameEvent::Skip { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::NewRound
+                | GameEvent::SpeedBonus { .. }
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        ctx.restore();
+    }
+
+    fn draw_push_preview(&self, controller: &BoardController, visible: Visible, ctx: &Context) {
+        let settings = &self.settings;
+
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let wall_width = cell_size * settings.wall_width;
+        let token_radius = cell_size / 2.0 - wall_width;
+        let board = &controller.board;
+
+        ctx.save();
+        ctx.set_global_alpha(settings.push_preview_alpha);
+        ctx.set_line_width(wall_width / 2.0);
+        for token in board.player_tokens.values() {
+            if token.layer != board.layer || !board.is_pushed(token.position) || !can_see(visible, token.position) {
+                continue;
+            }
+            let player = match controller.players.get(&token.player_id) {
+                Some(x) => x,
+                None => continue,
+            };
+            let (row, col) = board.pushed_position(token.position, controller.settings.wrap_tokens);
+            let [x, y] = self.tile_extents(controller, row, col).center();
+            ctx.begin_path();
+            ctx.set_stroke_style(&player.color.into());
+            ctx.ellipse(
+                x,
+                y,
+                token_radius,
+                token_radius,
+                0.0,
+                0.0,
+                ::std::f64::consts::PI * 2.0,
+            )
+                .unwrap_throw();
+            ctx.stroke();
+        }
+        ctx.restore();
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -704,14 +1694,52 @@ impl BoardView {
         mode: DrawMode,
         controller: &BoardController,
         local_id: PlayerID,
+        visible: Visible,
         ctx: &Context,
     ) {
         let settings = &self.settings;
 
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
+        let (cell_size, _, _) = self.tile_padding(controller);
         let wall_width = cell_size * settings.wall_width;
-        let anim_state = anim::STATE.read().unwrap();
+        let anim_state = &controller.anim;
         let token_radius = cell_size / 2.0 - wall_width;
+        let reachable = controller.reachable_for(local_id);
+
+        // outline where each token was a turn ago, if it's gone anywhere since
+        if mode == DrawMode::All {
+            ctx.save();
+            ctx.set_global_alpha(settings.ghost_alpha);
+            ctx.set_line_width(wall_width / 2.0);
+            for token in controller.board.player_tokens.values() {
+                if token.layer != controller.board.layer {
+                    continue;
+                }
+                let (row, col) = match controller.turn_starts.previous.get(&token.player_id) {
+                    Some(&pos) if pos != token.position && can_see(visible, pos) => pos,
+                    _ => continue,
+                };
+                let player = match controller.players.get(&token.player_id) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let [x, y] = self.tile_extents(controller, row, col).center();
+                let color = player.color.contrasting(self.tile_background(&reachable, (row, col)), settings.min_contrast);
+                ctx.begin_path();
+                ctx.set_stroke_style(&color.into());
+                ctx.ellipse(
+                    x,
+                    y,
+                    token_radius,
+                    token_radius,
+                    0.0,
+                    0.0,
+                    ::std::f64::consts::PI * 2.0,
+                )
+                    .unwrap_throw();
+                ctx.stroke();
+            }
+            ctx.restore();
+        }
 
         for token in controller.board.player_tokens.values() {
             let (row, col) = token.position;
@@ -719,17 +1747,44 @@ impl BoardView {
                 Some(x) => x,
                 None => continue,
             };
-            let tile = self.tile_extents(controller, row, col, ctx);
+            if token.layer != controller.board.layer || !can_see(visible, token.position) {
+                continue;
+            }
+            let tile = self.tile_extents(controller, row, col);
 
             ctx.save();
 
-            if anim_state.loose_insert.applies_to_pos((row, col)) {
+            // a token sliding over from a move that came in all at once gets pushed along from there
+            let sliding = anim_state.token_moves.sliding_from(token.player_id);
+            let pushed_pos = sliding.map_or(token.position, |(from, _)| from);
+            if anim_state.loose_insert.applies_to_pos(pushed_pos) {
                 let [x, y] = [0.0, anim_state.loose_insert.distance_left * cell_size]
                     * anim_state.loose_insert.offset_dir;
                 ctx.translate(x, y).unwrap_throw();
             };
+            if let Some(((from_row, from_col), left)) = sliding {
+                let [x0, y0] = self.tile_extents(controller, from_row, from_col).center();
+                let [x1, y1] = tile.center();
+                ctx.translate((x0 - x1) * left, (y0 - y1) * left).unwrap_throw();
+            }
 
             let should = mode == DrawMode::All || token.player_id == local_id;
+            if should && anim_state.loose_insert.applies_to_pos(pushed_pos) {
+                // streak back to where the push started, fading as it goes
+                let [x, y] = tile.center();
+                let [dx, dy] = [0.0, (1.0 - anim_state.loose_insert.distance_left) * cell_size]
+                    * anim_state.loose_insert.offset_dir;
+                ctx.save();
+                ctx.set_global_alpha(settings.trail_alpha * anim_state.loose_insert.distance_left);
+                ctx.set_line_width(token_radius);
+                ctx.set_line_cap("round");
+                ctx.set_stroke_style(&player.color.into());
+                ctx.begin_path();
+                ctx.move_to(x + dx, y + dy);
+                ctx.line_to(x, y);
+                ctx.stroke();
+                ctx.restore();
+            }
             if should {
                 ctx.begin_path();
                 ctx.set_fill_style(&player.color.into());
@@ -745,6 +1800,14 @@ impl BoardView {
                 )
                     .unwrap_throw();
                 ctx.fill();
+                // the outline only shows up where the token's own color blends into the tile
+                let outline = player.color.contrasting(self.tile_background(&reachable, token.position), settings.min_contrast);
+                ctx.set_stroke_style(&outline.into());
+                ctx.set_line_width(wall_width / 3.0);
+                ctx.stroke();
+                if watching(controller, local_id) == Some(token.player_id) {
+                    self.draw_turn_pulse(controller, player, [x, y], token_radius, ctx);
+                }
                 if token.player_id == local_id {
                     let dot_radius = token_radius - wall_width / 2.0;
                     ctx.begin_path();
@@ -767,14 +1830,332 @@ impl BoardView {
         }
     }
 
+    /// Draws a ring spreading out from the token at the given point and a tag with its player's name
+    /// over it, for whoever's turn it is while we watch
+    fn draw_turn_pulse(
+        &self,
+        controller: &BoardController,
+        player: &Player,
+        [x, y]: [f64; 2],
+        token_radius: f64,
+        ctx: &Context,
+    ) {
+        let spread = controller.anim.turn_pulse.spread();
+        ctx.save();
+        ctx.set_global_alpha(1.0 - spread);
+        ctx.set_stroke_style(&player.color.into());
+        ctx.set_line_width(token_radius / 4.0);
+        ctx.begin_path();
+        ctx.arc(x, y, token_radius * (1.0 + spread * 0.6), 0.0, ::std::f64::consts::PI * 2.0)
+            .unwrap_throw();
+        ctx.stroke();
+        ctx.restore();
+
+        // the board may be turned, but the name still reads the right way up, just above the token
+        ctx.save();
+        ctx.translate(x, y).unwrap_throw();
+        ctx.rotate(-self.rotation.angle()).unwrap_throw();
+        ctx.set_font("bold 15px sans-serif");
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+        let width = ctx.measure_text(&player.name).unwrap_throw().width();
+        let padding = 4.0;
+        let tag_y = -token_radius * 1.6 - 7.5;
+        ctx.set_fill_style(&self.settings.background_color.into());
+        ctx.set_stroke_style(&player.color.into());
+        ctx.set_line_width(2.0);
+        ctx.fill_rect(-width / 2.0 - padding, tag_y - 7.5 - padding, width + padding * 2.0, 15.0 + padding * 2.0);
+        ctx.stroke_rect(-width / 2.0 - padding, tag_y - 7.5 - padding, width + padding * 2.0, 15.0 + padding * 2.0);
+        ctx.set_fill_style(&self.settings.text_color.into());
+        ctx.fill_text(&player.name, 0.0, tag_y).unwrap_throw();
+        ctx.restore();
+    }
+
+    /// Draws a row of pips under a player's score, filled in for each quick turn toward their next speed bonus
+    fn draw_streak_meter(&self, streak: u8, color: Color, [x, y]: [f64; 2], ctx: &Context) {
+        ctx.save();
+        ctx.set_line_width(1.0);
+        ctx.set_stroke_style(&self.settings.text_color.into());
+        ctx.set_fill_style(&color.into());
+        for i in 0..SPEED_STREAK {
+            let pip_x = x + f64::from(i) * 12.0;
+            if i < streak {
+                ctx.fill_rect(pip_x, y, 10.0, 5.0);
+            }
+            ctx.stroke_rect(pip_x, y, 10.0, 5.0);
+        }
+        ctx.restore();
+    }
+
+    /// Draws how much energy a token has banked, with a notch for each turn's worth, from the given corner
+    fn draw_energy_bar(&self, board: &Board, energy: u8, color: Color, [x, y]: [f64; 2], ctx: &Context) {
+        let cap = board.energy_cap().max(1);
+        let width = 60.0;
+        ctx.save();
+        ctx.set_line_width(1.0);
+        ctx.set_stroke_style(&self.settings.text_color.into());
+        ctx.set_fill_style(&color.into());
+        ctx.fill_rect(x, y, width * f64::from(energy.min(cap)) / f64::from(cap), 4.0);
+        ctx.stroke_rect(x, y, width, 4.0);
+        for turn in 1..ENERGY_BANK_TURNS {
+            let notch_x = x + width * f64::from(turn) / f64::from(ENERGY_BANK_TURNS);
+            ctx.begin_path();
+            ctx.move_to(notch_x, y);
+            ctx.line_to(notch_x, y + 4.0);
+            ctx.stroke();
+        }
+        ctx.restore();
+    }
+
+    /// Draws the layers set aside in portal mode, starting at the given point, each as a small map
+    /// with its portals, targets, and the tokens on it, unless the fog's hiding them
+    fn draw_layers(&self, controller: &BoardController, world: &World, fogged: bool, [x, y]: [f64; 2], ctx: &Context) {
+        let size = 120.0;
+        let mut y = y;
+        ctx.save();
+        ctx.set_fill_style(&self.settings.text_color.into());
+        let text = format!("Playing on layer {} of {}", controller.board.layer + 1, world.layer_count());
+        ctx.fill_text(&text, x, y).unwrap_throw();
+        for layer in &world.layers {
+            y += 25.0;
+            let text = if fogged {
+                format!("Layer {} is hidden in the fog", layer.layer + 1)
+            } else {
+                format!("Layer {}", layer.layer + 1)
+            };
+            ctx.fill_text(&text, x, y).unwrap_throw();
+            if fogged {
+                continue;
+            }
+            y += 10.0;
+            let cell_size = size / layer.width().max(layer.height()) as f64;
+            let west = x + (size - cell_size * layer.width() as f64) / 2.0;
+            let north = y + (size - cell_size * layer.height() as f64) / 2.0;
+            ctx.save();
+            ctx.translate(x, y).unwrap_throw();
+            self.draw_miniature(layer, size, ctx);
+            ctx.restore();
+            ctx.save();
+            ctx.set_line_width(1.0);
+            ctx.set_stroke_style(&self.settings.portal_color.into());
+            for row in 0..layer.height() {
+                for col in 0..layer.width() {
+                    let tile = layer.get([col, row]);
+                    let [cx, cy] = [
+                        west + (col as f64 + 0.5) * cell_size,
+                        north + (row as f64 + 0.5) * cell_size,
+                    ];
+                    if let Some(player) = tile.whose_target.and_then(|id| controller.players.get(&id)) {
+                        ctx.set_fill_style(&player.color.into());
+                        ctx.fill_rect(cx - cell_size / 4.0, cy - cell_size / 4.0, cell_size / 2.0, cell_size / 2.0);
+                    }
+                    if tile.portal {
+                        ctx.begin_path();
+                        ctx.arc(cx, cy, cell_size / 3.0, 0.0, ::std::f64::consts::PI * 2.0).unwrap_throw();
+                        ctx.stroke();
+                    }
+                }
+            }
+            for token in controller.board.player_tokens.values().filter(|token| token.layer == layer.layer) {
+                let player = match controller.players.get(&token.player_id) {
+                    Some(player) => player,
+                    None => continue,
+                };
+                let (row, col) = token.position;
+                ctx.set_fill_style(&player.color.into());
+                ctx.begin_path();
+                ctx.arc(
+                    west + (col as f64 + 0.5) * cell_size,
+                    north + (row as f64 + 0.5) * cell_size,
+                    cell_size / 3.0,
+                    0.0,
+                    ::std::f64::consts::PI * 2.0,
+                )
+                    .unwrap_throw();
+                ctx.fill();
+            }
+            ctx.restore();
+            y += size;
+        }
+        ctx.restore();
+    }
+
+    /// Draws a caption centered along the bottom of the board, on a box so it stands out from the tiles
+    fn draw_caption(&self, controller: &BoardController, text: &str, ctx: &Context) {
+        let (_, board) = self.game_extents(controller);
+        let font_size = f64::from(self.settings.font_size);
+        let padding = font_size / 2.0;
+        ctx.save();
+        ctx.set_font(&format!("bold {}px sans-serif", self.settings.font_size));
+        ctx.set_text_align("center");
+        ctx.set_text_baseline("middle");
+        let width = ctx.measure_text(text).unwrap_throw().width();
+        let [x, _] = board.center();
+        let y = board.south - font_size - padding;
+        ctx.set_global_alpha(0.85);
+        ctx.set_fill_style(&self.settings.text_color.into());
+        ctx.fill_rect(
+            x - width / 2.0 - padding,
+            y - font_size / 2.0 - padding,
+            width + padding * 2.0,
+            font_size + padding * 2.0,
+        );
+        ctx.set_global_alpha(1.0);
+        ctx.set_fill_style(&self.settings.background_color.into());
+        ctx.fill_text(text, x, y).unwrap_throw();
+        ctx.restore();
+    }
+
+    /// Draws a badge for an event that's on, with its text starting at the given point
+    fn draw_event_badge(&self, text: &str, [x, y]: [f64; 2], ctx: &Context) {
+        ctx.save();
+        let width = ctx.measure_text(text).unwrap_throw().width();
+        let padding = 4.0;
+        ctx.set_fill_style(&self.settings.golden_color.into());
+        ctx.fill_rect(
+            x - padding,
+            y - 15.0 - padding / 2.0,
+            width + padding * 2.0,
+            15.0 + padding * 2.0,
+        );
+        ctx.set_fill_style(&self.settings.text_color.into());
+        ctx.fill_text(text, x, y).unwrap_throw();
+        ctx.restore();
+    }
+
+    fn draw_quick_chat(&self, phrase: &str, alpha: f64, [x, y]: [f64; 2], ctx: &Context) {
+        ctx.save();
+        ctx.set_global_alpha(alpha);
+        let width = ctx.measure_text(phrase).unwrap_throw().width();
+        let padding = 4.0;
+        ctx.set_fill_style(&self.settings.text_color.into());
+        ctx.fill_rect(
+            x - padding,
+            y - 15.0 - padding / 2.0,
+            width + padding * 2.0,
+            15.0 + padding * 2.0,
+        );
+        ctx.set_fill_style(&self.settings.background_color.into());
+        ctx.fill_text(phrase, x, y).unwrap_throw();
+        ctx.restore();
+    }
+
+    /// Labels the insert guides, a fixed tile, everyone's targets and the loose tile, with lines out to
+    /// where they are on the board right now
+    fn draw_legend(&self, controller: &BoardController, visible: Visible, ctx: &Context) {
+        let board = &controller.board;
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let (_, board_extents) = self.game_extents(controller);
+        let to_screen = |point| self.camera.screen_point(self.rotated(point, controller), self.size);
+
+        // (label, what it points at on screen, color of its line)
+        let mut callouts: Vec<(String, [f64; 2], Color)> = vec![];
+
+        // any guide but the one the loose tile's sitting on
+        let guide = self
+            .insert_guides(controller)
+            .into_iter()
+            .flat_map(|(dir, guides)| guides.into_iter().enumerate().map(move |(i, guide)| (dir, i, guide)))
+            .find(|&(dir, i, _)| (dir, i) != board.loose_tile_position);
+        if let Some((_, _, guide)) = guide {
+            callouts.push((
+                "Insert guide: push the loose tile in here".to_string(),
+                to_screen(guide.center()),
+                self.settings.insert_guide_color,
+            ));
+        }
+
+        // the fixed tile nearest the middle, out of the ones we can see
+        let middle = (board.height() / 2, board.width() / 2);
+        let fixed = (0..board.height())
+            .flat_map(|row| (0..board.width()).map(move |col| (row, col)))
+            .filter(|&pos| board.is_fixed(pos) && can_see(visible, pos))
+            .min_by_key(|&pos| (bot::distance(pos, middle), pos));
+        if let Some((row, col)) = fixed {
+            callouts.push((
+                "Fixed tile: never gets pushed".to_string(),
+                to_screen(self.fixed_marker_center(controller, row, col)),
+                self.settings.cell_edge_color,
+            ));
+        }
+
+        for id in &controller.turn_order {
+            let player = &controller.players[id];
+            let target = board.target_pos(*id).filter(|&pos| can_see(visible, pos));
+            if let Some((row, col)) = target {
+                callouts.push((
+                    format!("{}'s target", player.name),
+                    to_screen(self.tile_extents(controller, row, col).center()),
+                    player.color,
+                ));
+            }
+        }
+
+        callouts.push((
+            "Loose tile: rotate it, then insert it".to_string(),
+            self.rotated(self.loose_tile_extents(controller).center(), controller),
+            self.settings.text_color,
+        ));
+
+        let [center_x, center_y] = to_screen(board_extents.center());
+        let [width, height] = self.size;
+        let reach = cell_size * 1.5;
+        let padding = 4.0;
+        ctx.save();
+        ctx.set_font("15px sans-serif");
+        ctx.set_text_baseline("middle");
+        ctx.set_line_width(2.0);
+        for (text, [x, y], color) in callouts {
+            // labels go out past whatever they point at, away from the middle of the board
+            let (dx, dy) = (x - center_x, y - center_y);
+            let length = dx.hypot(dy).max(1.0);
+            let text_width = ctx.measure_text(&text).unwrap_throw().width();
+            // a label wider than the screen has nowhere to go
+            if text_width + padding * 2.0 > width || height < 30.0 {
+                continue;
+            }
+            let label_x = (x + dx / length * reach - text_width / 2.0).max(padding).min(width - text_width - padding);
+            let label_y = (y + dy / length * reach).max(15.0).min(height - 15.0);
+
+            ctx.set_stroke_style(&color.into());
+            ctx.set_fill_style(&color.into());
+            ctx.begin_path();
+            ctx.move_to(label_x + text_width / 2.0, label_y);
+            ctx.line_to(x, y);
+            ctx.stroke();
+            ctx.begin_path();
+            ctx.arc(x, y, 3.0, 0.0, ::std::f64::consts::PI * 2.0).unwrap_throw();
+            ctx.fill();
+
+            ctx.set_fill_style(&self.settings.background_color.into());
+            ctx.fill_rect(
+                label_x - padding,
+                label_y - 7.5 - padding,
+                text_width + padding * 2.0,
+                15.0 + padding * 2.0,
+            );
+            ctx.stroke_rect(
+                label_x - padding,
+                label_y - 7.5 - padding,
+                text_width + padding * 2.0,
+                15.0 + padding * 2.0,
+            );
+            ctx.set_fill_style(&self.settings.text_color.into());
+            ctx.fill_text(&text, label_x, label_y).unwrap_throw();
+        }
+        ctx.restore();
+    }
+
     fn draw_ui(&self, controller: &BoardController, local_id: PlayerID, ctx: &Context) {
-        let (cell_size, _, _) = self.tile_padding(controller, ctx);
-        let anim_state = anim::STATE.read().unwrap();
+        let (cell_size, _, _) = self.tile_padding(controller);
+        let anim_state = &controller.anim;
 
         // draw loose tile
         {
-            let cell = self.loose_tile_extents(controller, ctx);
+            let cell = self.loose_tile_extents(controller);
             ctx.save();
+            // it sits on an insert guide, so it turns along with the board
+            self.apply_rotation(controller, ctx);
             if anim_state
                 .loose_insert
                 .applies_to_loose(controller.board.loose_tile_position)
@@ -783,11 +2164,26 @@ impl BoardView {
                     * anim_state.loose_insert.offset_dir;
                 ctx.translate(x, y).unwrap_throw();
             };
+            // the loose tile sits on whichever guide is hovered, so it's what grows
+            let inserting = match controller.turn_state {
+                TurnState::InsertTile => true,
+                TurnState::MoveToken => false,
+            };
+            let hovered = inserting
+                && controller.local_turn(local_id)
+                && anim_state.guides.hovered == Some(controller.board.loose_tile_position);
+            if hovered {
+                let scale = anim_state.guides.hover_scale(controller.board.loose_tile_position);
+                let [x, y] = cell.center();
+                ctx.translate(x, y).unwrap_throw();
+                ctx.scale(scale, scale).unwrap_throw();
+                ctx.translate(-x, -y).unwrap_throw();
+            }
             self.draw_tile(
                 &controller.board.loose_tile,
                 cell,
                 self.settings.background_color,
-                false,
+                hovered,
                 true,
                 controller,
                 local_id,
@@ -796,31 +2192,78 @@ impl BoardView {
             ctx.restore();
         }
 
+        // draw rotate buttons, for anyone without a right mouse button
+        if controller.local_turn(local_id) {
+            if let TurnState::InsertTile = controller.turn_state {
+                // a hint beats the rotate assist, since the player asked for it
+                let hinted = match controller.hint.as_deref() {
+                    Some(bot::Move { insert: Some((_, orientation)), .. }) => Some(*orientation),
+                    _ => None,
+                };
+                let suggested = match hinted {
+                    Some(orientation) => controller
+                        .rotation_toward(orientation)
+                        .map(|dir| (dir, self.settings.hint_color)),
+                    None if options::HANDLE.fetch().rotate_assist => controller
+                        .suggested_rotation()
+                        .map(|dir| (dir, self.settings.text_color)),
+                    None => None,
+                };
+                self.draw_rotate_buttons(controller, suggested, ctx);
+            }
+            if controller.can_double_push(controller.active_player_id()) {
+                self.draw_double_push_button(ctx);
+            }
+        }
+
         // draw player target
         {
-            let (south_panel, _) = self.ui_extents(ctx);
+            let (south_panel, _) = self.ui_extents();
             let my_turn = controller.local_turn(local_id);
             let whose_turn = controller.active_player();
             ctx.save();
 
             ctx.set_fill_style(&self.settings.text_color.into());
             ctx.set_font("20px sans-serif");
-            let text = format!("It is {}'s turn", whose_turn.name);
+            let whose = match controller.party.as_deref() {
+                Some(party) if party.is_resolving() => format!("Playing out {}'s plan", whose_turn.name),
+                Some(party) => format!(
+                    "Waiting on plans ({} of {} in)",
+                    party.plans.len(),
+                    controller.turn_order.len()
+                ),
+                None if controller.is_draft() => format!("Planning {}'s turn", whose_turn.name),
+                None if controller.double_pushing => format!("It is {}'s turn, and they're pushing twice", whose_turn.name),
+                None => match controller.board.player_tokens.get(&whose_turn.id).and_then(|token| token.card) {
+                    Some(card) => format!("It is {}'s turn, and they're after the {}", whose_turn.name, card.name()),
+                    None => format!("It is {}'s turn", whose_turn.name),
+                },
+            };
+            let text = match anim_state.turn_timer.time_left {
+                Some(time_left) => format!("{} ({}s left)", whose, time_left.ceil()),
+                None => whose,
+            };
             let x = south_panel.west;
             let y = south_panel.north + 20.0;
             ctx.fill_text(&text, x, y).unwrap_throw();
             if my_turn {
                 let text = match controller.turn_state {
                     TurnState::InsertTile => {
-                        "Right-click at a triangle to rotate, left-click to insert"
+                        "Click the arrows or right-click at a triangle to rotate, left-click to insert"
                     }
                     TurnState::MoveToken => "Click on any reachable tile, or yourself to not move",
                 };
                 let y = y + 30.0;
                 ctx.fill_text(&text, x, y).unwrap_throw();
             }
-            if let Some(tutorial_step) = &controller.board.tutorial_step {
-                let text = tutorial_step.text();
+            if let Some(turns_left) = controller.turns_until_collapse() {
+                let text = match turns_left {
+                    1 => "The outer ring collapses after this turn".to_string(),
+                    turns_left => format!("The outer ring collapses in {} turns", turns_left),
+                };
+                if turns_left <= self.settings.collapse_warning_turns {
+                    ctx.set_fill_style(&self.settings.collapse_color.into());
+                }
                 let y = y + 60.0;
                 ctx.fill_text(&text, x, y).unwrap_throw();
             }
@@ -828,9 +2271,14 @@ impl BoardView {
             ctx.restore();
         }
 
+        // caption the tutorial step big enough to read, over the bottom of the board
+        if let Some(tutorial_step) = controller.board.tutorial_step {
+            self.draw_caption(controller, tutorial_step.text(), ctx);
+        }
+
         // draw player list
         {
-            let (_, east_panel) = self.ui_extents(ctx);
+            let (_, east_panel) = self.ui_extents();
             ctx.save();
 
             ctx.set_font("15px sans-serif");
@@ -841,8 +2289,21 @@ impl BoardView {
                 let player = &controller.players[player_id];
                 let token = &controller.board.player_tokens[player_id];
 
-                ctx.set_fill_style(&self.settings.text_color.into());
-                ctx.fill_text(&player.name, x, y).unwrap_throw();
+                // local players share their parent's connection
+                let latency = self.latencies.get(&player.parent.unwrap_or(player.id));
+                let mut color = self.settings.text_color;
+                let text = match latency {
+                    _ if controller.is_away(*player_id) => format!("{} (away)", player.name),
+                    Some(&ms) => {
+                        if ms > self.settings.lag_threshold {
+                            color = self.settings.lag_color;
+                        }
+                        format!("{} ({}ms)", player.name, ms)
+                    }
+                    None => player.name.clone(),
+                };
+                ctx.set_fill_style(&color.into());
+                ctx.fill_text(&text, x, y).unwrap_throw();
                 y += 10.0;
 
                 ctx.begin_path();
@@ -859,12 +2320,69 @@ impl BoardView {
                     .unwrap_throw();
                 ctx.fill();
                 ctx.set_fill_style(&self.settings.text_color.into());
-                let text = format!("score: {}", token.score);
+                let text = match controller.board.scoring {
+                    Scoring::Targets => format!("score: {}", token.score),
+                    Scoring::Points => format!("points: {}", token.score),
+                };
+                let text = match token.card {
+                    Some(card) => format!("{} {}", text, card.symbol()),
+                    None => text,
+                };
                 ctx.fill_text(&text, x + 20.0, y + 10.0).unwrap_throw();
+                if controller.settings.speed_bonus > 0 && !controller.is_party() {
+                    let streak = controller.speed_streaks.get(player_id).copied().unwrap_or(0);
+                    self.draw_streak_meter(streak, player.color, [x + 20.0, y + 15.0], ctx);
+                }
+                if controller.board.energy_per_turn > 0 {
+                    self.draw_energy_bar(&controller.board, token.energy, player.color, [x + 20.0, y + 23.0], ctx);
+                }
+                // local players share their parent's connection, so they share its chat too
+                let bubble = anim_state.quick_chat.bubble(player.parent.unwrap_or(player.id));
+                if let Some((phrase, alpha)) = bubble {
+                    self.draw_quick_chat(phrase, alpha, [x + 100.0, y + 10.0], ctx);
+                }
                 y += 40.0;
             }
 
+            ctx.set_fill_style(&self.settings.text_color.into());
+            let text = if self.coach_view {
+                "Press C to hide coach view"
+            } else {
+                "Press C for coach view"
+            };
+            ctx.fill_text(text, x, y).unwrap_throw();
+            y += 20.0;
+            let text = if self.legend {
+                "Press L to hide the legend"
+            } else {
+                "Press L for the legend"
+            };
+            ctx.fill_text(text, x, y).unwrap_throw();
+
+            for event in &controller.events {
+                y += 30.0;
+                self.draw_event_badge(&event.badge(), [x, y], ctx);
+            }
+
+            if let Some(world) = controller.world.as_deref() {
+                let fogged = controller.visible_tiles(local_id).is_some();
+                self.draw_layers(controller, world, fogged, [x, y + 30.0], ctx);
+            }
+
             ctx.restore();
         }
     }
 }
+
+/// Strokes a straight arrow between two points, with a head of the given length
+fn draw_arrow([x0, y0]: [f64; 2], [x1, y1]: [f64; 2], head: f64, ctx: &Context) {
+    let angle = (y1 - y0).atan2(x1 - x0);
+    let spread = ::std::f64::consts::PI / 6.0;
+    ctx.begin_path();
+    ctx.move_to(x0, y0);
+    ctx.line_to(x1, y1);
+    ctx.move_to(x1 - head * (angle - spread).cos(), y1 - head * (angle - spread).sin());
+    ctx.line_to(x1, y1);
+    ctx.line_to(x1 - head * (angle + spread).cos(), y1 - head * (angle + spread).sin());
+    ctx.stroke();
+}
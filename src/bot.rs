@@ -0,0 +1,114 @@
+//! Move search, for suggesting moves to players
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Board, BoardController, Direction, PlayerID};
+
+/// A suggested move, as far as the turn has gotten
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Move {
+    /// Where to insert the loose tile and which way it should face, if it hasn't gone in yet
+    pub insert: Option<((Direction, usize), Direction)>,
+    /// Where to move to afterwards
+    pub destination: (usize, usize),
+}
+
+/// Counts tiles across plus tiles down between two (row, col) positions
+pub fn distance((row_a, col_a): (usize, usize), (row_b, col_b): (usize, usize)) -> usize {
+    let rows = row_a as isize - row_b as isize;
+    let cols = col_a as isize - col_b as isize;
+    (rows.abs() + cols.abs()) as usize
+}
+
+/// Picks the reachable tile closest to the given player's target, and how close it is
+fn closest_to_target(board: &Board, player_id: PlayerID) -> ((usize, usize), usize) {
+    let position = board.player_pos(player_id);
+    let target = match board.target_pos(player_id) {
+        Some(target) => target,
+        // the target's on the loose tile, so nowhere is any closer than anywhere else
+        None if board.loose_tile.whose_target == Some(player_id) => return (position, usize::MAX),
+        // the target's on another layer, so any portal is the way there, and still farther than any target here
+        None => {
+            return board
+                .reachable_by(player_id)
+                .into_iter()
+                .filter(|&(row, col)| board.get([col, row]).portal)
+                .map(|pos| (pos, board.width() + board.height()))
+                .min()
+                .unwrap_or((position, usize::MAX))
+        }
+    };
+    board
+        .reachable_by(player_id)
+        .into_iter()
+        .map(|pos| (pos, distance(pos, target)))
+        // ties go to the lowest (row, col), so every client suggests the same thing
+        .min_by_key(|&(pos, distance)| (distance, pos))
+        .unwrap_or((position, usize::MAX))
+}
+
+/// Finds the best place for the given player to move to on the board as it stands
+pub fn best_destination(controller: &BoardController, player_id: PlayerID) -> Move {
+    let (destination, _) = closest_to_target(&controller.board, player_id);
+    Move {
+        insert: None,
+        destination,
+    }
+}
+
+/// Works out where the given player would go after inserting the loose tile at the given position,
+/// facing the given way
+pub fn move_with_insert(
+    controller: &BoardController,
+    player_id: PlayerID,
+    insert: ((Direction, usize), Direction),
+) -> Move {
+    let ((side, guide), orientation) = insert;
+    let mut board = controller.board.clone();
+    board.loose_tile_position = (side, guide);
+    board.loose_tile.orientation = orientation;
+    board.push_loose_tile(controller.settings.wrap_tokens);
+    let (destination, _) = closest_to_target(&board, player_id);
+    Move {
+        insert: Some(insert),
+        destination,
+    }
+}
+
+/// Tries every insert position and orientation, finding the one that gets the given player
+/// closest to their target, and then the most room to move around
+pub fn best_move(controller: &BoardController, player_id: PlayerID) -> Move {
+    let board = &controller.board;
+    let wrap_tokens = controller.settings.wrap_tokens;
+    let mut best = None;
+    for &side in Direction::all() {
+        let guides = match side {
+            Direction::North | Direction::South => board.width() / 2,
+            Direction::East | Direction::West => board.height() / 2,
+        };
+        for guide in 0..guides {
+            for &orientation in Direction::all() {
+                let mut board = board.clone();
+                board.loose_tile_position = (side, guide);
+                board.loose_tile.orientation = orientation;
+                board.push_loose_tile(wrap_tokens);
+                let (destination, distance) = closest_to_target(&board, player_id);
+                let room = board.reachable_by(player_id).len();
+                // closer is better, and more room is better
+                let score = (distance, usize::MAX - room);
+                let candidate = Move {
+                    insert: Some(((side, guide), orientation)),
+                    destination,
+                };
+                match best {
+                    Some((best_score, _)) if best_score <= score => {}
+                    _ => best = Some((score, candidate)),
+                }
+            }
+        }
+    }
+    match best {
+        Some((_, best)) => best,
+        None => best_destination(controller, player_id),
+    }
+}
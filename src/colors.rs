@@ -12,6 +12,45 @@ impl Color {
     pub fn hex(&self) -> String {
         format!("#{:02x}{:02x}{:02x}", (self.0 * 255.0) as u8, (self.1 * 255.0) as u8, (self.2 * 255.0) as u8)
     }
+
+    /// Gets how bright this color looks, from 0 for black to 1 for white
+    pub fn luminance(&self) -> f32 {
+        // the usual sRGB to linear conversion, weighted by how sensitive eyes are to each channel
+        let linear = |c: f32| if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+
+    /// Gets the contrast ratio between this color and another, from 1 for none up to 21 for
+    /// black against white
+    pub fn contrast(&self, other: Color) -> f32 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Mixes the given fraction of another color into this one
+    pub fn mix(&self, other: Color, amount: f32) -> Color {
+        Color(
+            self.0 + (other.0 - self.0) * amount,
+            self.1 + (other.1 - self.1) * amount,
+            self.2 + (other.2 - self.2) * amount,
+        )
+    }
+
+    /// Darkens or lightens this color, whichever the background leaves more room for, just until it
+    /// stands out from the background by the given contrast ratio
+    pub fn contrasting(&self, background: Color, min_contrast: f32) -> Color {
+        if self.contrast(background) >= min_contrast {
+            return *self;
+        }
+        let black = Color(0.0, 0.0, 0.0);
+        let white = Color(1.0, 1.0, 1.0);
+        let toward = if background.contrast(black) > background.contrast(white) { black } else { white };
+        // a tenth at a time, so the color stays as close to the original as it can
+        (1..=10)
+            .map(|step| self.mix(toward, step as f32 / 10.0))
+            .find(|color| color.contrast(background) >= min_contrast)
+            .unwrap_or(toward)
+    }
 }
 
 impl Into<JsValue> for Color {
@@ -49,3 +88,143 @@ pub const LIGHT: Color = color!(0x82, 0xAE, 0xB1);
 pub const PURPLE: Color = color!(0x5F, 0x5A, 0xA2);
 pub const BLUE: Color = color!(0x35, 0x56, 0x91);
 pub const TEAL: Color = color!(0x66, 0x85, 0x86);
+pub const RED: Color = color!(0xC0, 0x39, 0x2B);
+pub const GOLD: Color = color!(0xE0, 0xB0, 0x3A);
+
+/// How tile walls are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WallStyle {
+    /// Square blocks
+    Solid,
+    /// Rounded off, like hedges
+    Rounded,
+}
+
+/// What's drawn behind the board
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// Nothing but the backdrop color
+    Plain,
+    /// A grid of dots
+    Dots,
+    /// Diagonal stripes
+    Stripes,
+}
+
+/// A look for the board, picked in Options
+pub struct Theme {
+    /// Name shown in Options, and synced when the host makes everyone use their theme
+    pub name: &'static str,
+    /// First and last month (1 to 12) the theme is picked automatically in, if it's seasonal
+    pub months: Option<(u32, u32)>,
+    /// Color behind the board
+    pub backdrop: Color,
+    /// Color of the pattern over the backdrop
+    pub pattern_color: Color,
+    /// Color of tiles that can't be reached
+    pub tile: Color,
+    /// Color of tiles that can be reached
+    pub reachable: Color,
+    /// Color of edges and text
+    pub ink: Color,
+    /// Color of tile walls
+    pub wall: Color,
+    /// Color of insert guides
+    pub guide: Color,
+    /// How the walls are drawn
+    pub wall_style: WallStyle,
+    /// What's drawn over the backdrop
+    pub pattern: Pattern,
+}
+
+impl Theme {
+    /// Checks if the given month (1 to 12) is in this theme's season
+    fn in_season(&self, month: u32) -> bool {
+        match self.months {
+            Some((first, last)) if first <= last => first <= month && month <= last,
+            // seasons can wrap around the end of the year
+            Some((first, last)) => month >= first || month <= last,
+            None => false,
+        }
+    }
+}
+
+pub const THEMES: [Theme; 5] = [
+    Theme {
+        name: "Classic",
+        months: None,
+        backdrop: LIGHT,
+        pattern_color: LIGHT,
+        tile: TEAL,
+        reachable: LIGHT,
+        ink: DARK,
+        wall: BLUE,
+        guide: PURPLE,
+        wall_style: WallStyle::Solid,
+        pattern: Pattern::Plain,
+    },
+    Theme {
+        name: "Spring",
+        months: Some((3, 5)),
+        backdrop: color!(0xD8, 0xEE, 0xC8),
+        pattern_color: color!(0xC6, 0xE2, 0xB2),
+        tile: color!(0x8F, 0xBF, 0x7A),
+        reachable: color!(0xF1, 0xF8, 0xE4),
+        ink: color!(0x2D, 0x3B, 0x22),
+        wall: color!(0x3E, 0x6B, 0x2F),
+        guide: color!(0xC2, 0x6A, 0x9C),
+        wall_style: WallStyle::Rounded,
+        pattern: Pattern::Dots,
+    },
+    Theme {
+        name: "Summer",
+        months: Some((6, 8)),
+        backdrop: color!(0xFF, 0xE8, 0xB0),
+        pattern_color: color!(0xF7, 0xD8, 0x94),
+        tile: color!(0x6C, 0xC4, 0xC8),
+        reachable: color!(0xFF, 0xF6, 0xDA),
+        ink: color!(0x22, 0x33, 0x44),
+        wall: color!(0xE0, 0x7A, 0x3F),
+        guide: color!(0x2B, 0x8C, 0xBE),
+        wall_style: WallStyle::Rounded,
+        pattern: Pattern::Stripes,
+    },
+    Theme {
+        name: "Autumn",
+        months: Some((9, 11)),
+        backdrop: color!(0xE8, 0xC5, 0x8F),
+        pattern_color: color!(0xDB, 0xB2, 0x78),
+        tile: color!(0xB5, 0x6A, 0x2E),
+        reachable: color!(0xF2, 0xD0, 0x8A),
+        ink: color!(0x3B, 0x24, 0x1A),
+        wall: color!(0x7A, 0x2E, 0x1B),
+        guide: color!(0xA8, 0x4A, 0x1F),
+        wall_style: WallStyle::Solid,
+        pattern: Pattern::Stripes,
+    },
+    Theme {
+        name: "Winter",
+        months: Some((12, 2)),
+        backdrop: color!(0xDD, 0xE8, 0xF0),
+        pattern_color: color!(0xFF, 0xFF, 0xFF),
+        tile: color!(0x9F, 0xB8, 0xC9),
+        reachable: color!(0xF4, 0xF8, 0xFB),
+        ink: color!(0x1F, 0x2A, 0x3A),
+        wall: color!(0x3C, 0x5A, 0x80),
+        guide: color!(0x6A, 0x8C, 0xB8),
+        wall_style: WallStyle::Solid,
+        pattern: Pattern::Dots,
+    },
+];
+
+/// Gets the theme with the given name, or the one for this time of year if there's none by that name
+pub fn pick_theme(name: &str) -> &'static Theme {
+    if let Some(theme) = THEMES.iter().find(|theme| theme.name == name) {
+        return theme;
+    }
+    let month = js_sys::Date::new_0().get_month() + 1;
+    THEMES
+        .iter()
+        .find(|theme| theme.in_season(month))
+        .unwrap_or(&THEMES[0])
+}
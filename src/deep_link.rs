@@ -0,0 +1,47 @@
+//! Links that open the game straight into somewhere other than the main menu, like `?host`,
+//! `?tutorial`, `?options`, `?join=<id>`, or `?spectate=<id>`
+
+use wasm_bindgen::prelude::*;
+
+use crate::net::GameID;
+
+/// Somewhere a link can take the game when it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeepLink {
+    /// Host a new lobby
+    Host,
+    /// Start the tutorial from the beginning
+    Tutorial,
+    /// Open the options
+    Options,
+    /// Join the given lobby
+    Join(GameID),
+    /// Watch the given lobby, read-only
+    Spectate(GameID),
+}
+
+impl DeepLink {
+    /// Reads a link from one `key` or `key=value` query parameter, if it's one
+    fn parse(param: &str) -> Option<DeepLink> {
+        let mut parts = param.splitn(2, '=');
+        let key = parts.next()?;
+        let game = parts.next().and_then(|game| game.parse().ok());
+        match (key, game) {
+            ("host", _) => Some(DeepLink::Host),
+            ("tutorial", _) => Some(DeepLink::Tutorial),
+            ("options", _) => Some(DeepLink::Options),
+            ("join", Some(game)) => Some(DeepLink::Join(game)),
+            // `watch` is what spectator links have always used
+            ("spectate", Some(game)) | ("watch", Some(game)) => Some(DeepLink::Spectate(game)),
+            _ => None,
+        }
+    }
+
+    /// Gets the link the page was opened with, if it was opened with one, ignoring anything else in
+    /// the query string
+    pub fn from_query() -> Option<DeepLink> {
+        let window = web_sys::window().unwrap_throw();
+        let search = window.location().search().unwrap_throw();
+        search.trim_start_matches('?').split('&').find_map(DeepLink::parse)
+    }
+}
@@ -1,22 +1,24 @@
 use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::sync::{Arc, RwLock};
+use std::sync::mpsc;
 
 use crate::{Board, Direction, Player, PlayerID};
-use crate::board::PlayerToken;
+use crate::board::{PlayerToken, Scoring};
 use crate::board_controller::{BoardController, BoardSettings};
 use crate::colors;
+use crate::embed;
 use crate::menu::{ConnectedState, GameState, NetGameState};
 use crate::menu_controller::GameController;
 use crate::net;
 
-/// Checks to see if the game was launched with the `--demo` argument.
+/// Checks to see if the game was launched with the `--demo` argument, or mounted asking for the demo
 pub fn is_demo() -> bool {
     use wasm_bindgen::prelude::*;
     let window = web_sys::window().unwrap_throw();
     let location = window.location();
     let search = location.search().unwrap_throw();
-    search == "?demo"
+    search == "?demo" || embed::is_demo()
 }
 
 /// Creates a demo-friendly GameController
@@ -26,6 +28,24 @@ pub fn new_controller() -> GameController {
         score_limit: 3,
         width: 0,
         height: 0,
+        turn_time_limit: 0,
+        wrap_tokens: true,
+        seed: None,
+        min_target_distance: 0,
+        max_target_distance: 0,
+        hint_limit: 3,
+        sandbox: false,
+        series_length: 1,
+        collapse_every: 0,
+        fog_distance: 0,
+        party: false,
+        theme: None,
+        scoring: Scoring::Targets,
+        target_steal: false,
+        double_push: false,
+        portals: false,
+        speed_bonus: 0,
+        energy_per_turn: 0,
         version: 0,
     };
     let players = vec![
@@ -56,7 +76,7 @@ pub fn new_controller() -> GameController {
     let board = BoardController::new(settings, players, player_id);
     let state = NetGameState::Active(board);
     let state = Arc::new(RwLock::new(state));
-    let sender = net::NetHandler::run_fake();
+    let sender = net::NetHandler::run_fake(player_id);
     let state = ConnectedState { sender, state };
     let state = GameState::InGame(state);
     let view = crate::GameView {
@@ -65,17 +85,51 @@ pub fn new_controller() -> GameController {
                 ui_margin_south: 0.0,
                 ui_margin_east: 0.0,
                 ..Default::default()
-            }
-        }
+            },
+            coach_view: false,
+            legend: false,
+            latencies: Default::default(),
+            size: [0.0, 0.0],
+            scale: 1.0,
+            camera: Default::default(),
+            rotation: Default::default(),
+            cursor: crate::board_view::Cursor::Idle,
+            pointer: None,
+        },
+        review_turn: None,
+        replayed: Default::default(),
     };
+    let (events, event_queue) = mpsc::channel();
     GameController {
         state,
         player_id,
+        own_id: player_id,
         view,
         last_player: None,
         sound_engine: Default::default(),
-        actions: Default::default(),
+        events,
+        event_queue,
         listeners: vec![],
+        settings_dirty: false,
+        settings_sent_at: 0.0,
+        connecting_to: None,
+        lobby_query: None,
+        size_error: None,
+        show_rules: false,
+        lonely_since: None,
+        input_sources: Default::default(),
+        gamepads: Default::default(),
+        party: Default::default(),
+        bot_clock: None,
+        spectating: false,
+        heard_events: None,
+        toasts: Default::default(),
+        confirming: None,
+        page_progress: Default::default(),
+        remembered_game: false,
+        spectators_turn: None,
+        turn_clock: None,
+        narrated_step: None,
     }
 }
 
@@ -122,10 +176,15 @@ pub fn new_board(players: &BTreeMap<PlayerID, Player>) -> Board {
         })
         .collect();
     Board {
-        cells,
+        cells: cells.into_iter().flatten().collect(),
+        width,
         loose_tile,
         loose_tile_position,
         player_tokens,
         tutorial_step: None,
+        scoring: Scoring::Targets,
+        target_steal: false,
+        energy_per_turn: 0,
+        layer: 0,
     }
 }
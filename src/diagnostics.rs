@@ -0,0 +1,135 @@
+//! Diagnostic report capture
+
+use std::collections::VecDeque;
+use std::panic::PanicInfo;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+
+/// Number of entries kept in the ring buffer
+const CAPACITY: usize = 64;
+
+/// A single recorded event
+#[derive(Serialize, Clone, Debug)]
+pub struct DiagnosticEntry {
+    /// Time of the event, in seconds since the epoch
+    pub time: f64,
+    /// What kind of event this was
+    pub kind: &'static str,
+    /// Summary of the event (never includes player names)
+    pub detail: String,
+}
+
+/// Ring buffer of recent state transitions and network messages
+pub struct DiagnosticLog {
+    entries: VecDeque<DiagnosticEntry>,
+    panic: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiagnosticReport<'a> {
+    version: &'static str,
+    user_agent: String,
+    panic: Option<&'a str>,
+    entries: Vec<&'a DiagnosticEntry>,
+    trace: Option<String>,
+}
+
+impl DiagnosticLog {
+    fn new() -> DiagnosticLog {
+        DiagnosticLog {
+            entries: VecDeque::with_capacity(CAPACITY),
+            panic: None,
+        }
+    }
+
+    fn push(&mut self, kind: &'static str, detail: String) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(DiagnosticEntry {
+            time: crate::now(),
+            kind,
+            detail,
+        });
+    }
+
+    fn report(&self) -> String {
+        let user_agent = web_sys::window()
+            .and_then(|window| window.navigator().user_agent().ok())
+            .unwrap_or_default();
+        let report = DiagnosticReport {
+            version: env!("CARGO_PKG_VERSION"),
+            user_agent,
+            panic: self.panic.as_deref(),
+            entries: self.entries.iter().collect(),
+            trace: crate::trace::export(),
+        };
+        toml::to_string_pretty(&report).unwrap_or_else(|e| format!("Failed to build report: {}", e))
+    }
+}
+
+lazy_static! {
+    static ref LOG: Mutex<DiagnosticLog> = Mutex::new(DiagnosticLog::new());
+}
+
+/// Records an event in the diagnostic ring buffer
+pub fn record(kind: &'static str, detail: String) {
+    if let Ok(mut log) = LOG.try_lock() {
+        log.push(kind, detail);
+    }
+}
+
+/// Builds a diagnostic report from the recorded events
+pub fn report() -> String {
+    match LOG.try_lock() {
+        Ok(log) => log.report(),
+        Err(_) => "Diagnostic log unavailable".to_string(),
+    }
+}
+
+/// Copies the contents of the given report box to the clipboard
+pub fn copy_report(report_box: &web_sys::HtmlTextAreaElement) {
+    report_box.select();
+    let document = report_box.owner_document().unwrap_throw();
+    if let Some(document) = document.dyn_ref::<web_sys::HtmlDocument>() {
+        let _ = document.exec_command("copy");
+    }
+}
+
+/// Records a panic and shows the diagnostic report, since the game is about to stop responding
+pub fn on_panic(info: &PanicInfo) {
+    if let Ok(mut log) = LOG.try_lock() {
+        log.panic = Some(info.to_string());
+    }
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => return,
+    };
+    let body = match document.body() {
+        Some(body) => body,
+        None => return,
+    };
+    // the wasm module is unusable after this, so the copy button has to be plain JS
+    let overlay = document.create_element("section").unwrap_throw();
+    overlay.set_class_name("diagnostic-overlay");
+    let header = document.create_element("h1").unwrap_throw();
+    header.set_text_content(Some("Something went wrong"));
+    overlay.append_with_node_1(&header).unwrap_throw();
+    let report_box = document.create_element("textarea").unwrap_throw();
+    report_box.set_id("diagnostic-report");
+    report_box.set_attribute("readonly", "").unwrap_throw();
+    report_box.set_text_content(Some(&report()));
+    overlay.append_with_node_1(&report_box).unwrap_throw();
+    let copy = document.create_element("button").unwrap_throw();
+    copy.set_text_content(Some("Copy diagnostic report"));
+    copy.set_attribute(
+        "onclick",
+        "var r = document.getElementById('diagnostic-report'); r.select(); document.execCommand('copy');",
+    )
+        .unwrap_throw();
+    overlay.append_with_node_1(&copy).unwrap_throw();
+    body.append_with_node_1(&overlay).unwrap_throw();
+}
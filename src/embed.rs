@@ -0,0 +1,123 @@
+//! Options for when the game is mounted into a page other than its own, as a widget, and the events
+//! it tells that page about
+
+use std::sync::RwLock;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+use crate::{BoardSettings, PlayerID};
+
+/// Class the element the game's mounted into always has, alongside one for the screen it's
+/// showing, so the stylesheet doesn't care what kind of element that is
+pub const ROOT_CLASS: &str = "dynamaze";
+
+/// What the page mounting the game asked for
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EmbedConfig {
+    /// WebSocket URL of the server to play through, instead of the usual one
+    pub server_url: Option<String>,
+    /// Whether to start straight into the demo, like `?demo`
+    pub demo: bool,
+    /// Board settings every game hosted from here has and can't change, by field name
+    pub locked_settings: Map<String, Value>,
+}
+
+impl EmbedConfig {
+    /// Reads the config from whatever object the page passed in, which can leave anything out
+    pub fn from_js(config: &JsValue) -> Result<EmbedConfig, String> {
+        if config.is_undefined() || config.is_null() {
+            return Ok(EmbedConfig::default());
+        }
+        let json: String = js_sys::JSON::stringify(config)
+            .map_err(|_| "config can't be turned into JSON".to_string())?
+            .into();
+        let config: EmbedConfig = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        // catch misspelled settings now, rather than failing to lock them later
+        let mut settings = serde_json::to_value(BoardSettings::default()).map_err(|e| e.to_string())?;
+        if let Some(fields) = settings.as_object_mut() {
+            for (name, value) in &config.locked_settings {
+                if name == "version" {
+                    return Err("the settings version can't be locked".to_string());
+                }
+                if !fields.contains_key(name) {
+                    return Err(format!("no setting called {}", name));
+                }
+                fields.insert(name.clone(), value.clone());
+            }
+        }
+        serde_json::from_value::<BoardSettings>(settings).map_err(|e| e.to_string())?;
+        Ok(config)
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<EmbedConfig> = RwLock::new(EmbedConfig::default());
+}
+
+/// How far along a game is, as far as the page the game's mounted in cares
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Progress {
+    /// Not playing
+    #[default]
+    Idle,
+    /// Playing, on the given turn, with the given player up (or nobody, while a party mode round is planned)
+    Playing(usize, Option<PlayerID>),
+    /// The game just finished
+    Over,
+}
+
+/// Fires `dynamaze:` and the given name as a bubbling event from the given element, with the given
+/// detail, for the page to listen for
+pub fn emit(target: &web_sys::Element, name: &str, detail: &serde_json::Value) {
+    let detail = js_sys::JSON::parse(&detail.to_string()).unwrap_or(JsValue::NULL);
+    let mut init = web_sys::CustomEventInit::new();
+    init.bubbles(true).detail(&detail);
+    let event_type = format!("dynamaze:{}", name);
+    if let Ok(event) = web_sys::CustomEvent::new_with_event_init_dict(&event_type, &init) {
+        let _ = target.dispatch_event(&event);
+    }
+}
+
+/// Uses the given config from here on
+pub fn configure(config: EmbedConfig) {
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Gets the server URL the page asked for, if it asked for one
+pub fn server_url() -> Option<String> {
+    CONFIG.read().unwrap().server_url.clone()
+}
+
+/// Checks if the page asked for the demo
+pub fn is_demo() -> bool {
+    CONFIG.read().unwrap().demo
+}
+
+/// Gets the field names of the settings the page locked
+pub fn locked_settings() -> Vec<String> {
+    CONFIG.read().unwrap().locked_settings.keys().cloned().collect()
+}
+
+/// Puts back any setting the page locked, leaving the rest as they are
+pub fn lock_settings(settings: &mut BoardSettings) {
+    let config = CONFIG.read().unwrap();
+    if config.locked_settings.is_empty() {
+        return;
+    }
+    let mut value = match serde_json::to_value(&*settings) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    if let Some(fields) = value.as_object_mut() {
+        for (name, locked) in &config.locked_settings {
+            fields.insert(name.clone(), locked.clone());
+        }
+    }
+    // the locks were checked when the config came in, so this goes through
+    if let Ok(locked) = serde_json::from_value(value) {
+        *settings = locked;
+    }
+}
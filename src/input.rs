@@ -0,0 +1,183 @@
+//! Input sources, so several players at one screen can each have their own controls
+
+use std::collections::BTreeMap;
+
+use wasm_bindgen::JsCast;
+
+use crate::Direction;
+use crate::anim::RotateDir;
+
+/// Something a player can do from a keyboard or gamepad
+#[derive(Debug, Clone)]
+pub enum InputAction {
+    /// Move the insert guide or the highlighted tile
+    Move(Direction),
+    /// Rotate the loose tile
+    Rotate(RotateDir),
+    /// Insert the loose tile, or move to the highlighted tile
+    Confirm,
+    /// Turn the loose tile to the rotate assist's suggestion
+    AcceptSuggestion,
+    /// Ask for a hint
+    Hint,
+}
+
+/// Where a local player's input comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputSource {
+    /// Every key and every gamepad
+    Any,
+    /// Arrow keys, with right Shift and right Ctrl to rotate, Enter to confirm, / to take the suggestion, and . for a hint
+    Arrows,
+    /// WASD, with Q and E to rotate, Space to confirm, R to take the suggestion, and G for a hint
+    Wasd,
+    /// The gamepad with the given index, using the standard button layout
+    Gamepad(u32),
+}
+
+/// How many gamepads can be picked as a player's input
+const MAX_GAMEPADS: u32 = 4;
+
+// standard gamepad layout, see https://w3c.github.io/gamepad/#remapping
+const BUTTON_CONFIRM: u32 = 0;
+const BUTTON_HINT: u32 = 2;
+const BUTTON_SUGGESTION: u32 = 3;
+const BUTTON_ROTATE_CCW: u32 = 4;
+const BUTTON_ROTATE_CW: u32 = 5;
+const BUTTON_UP: u32 = 12;
+const BUTTON_DOWN: u32 = 13;
+const BUTTON_LEFT: u32 = 14;
+const BUTTON_RIGHT: u32 = 15;
+
+impl InputSource {
+    /// Gets the input source after this one, for cycling through them from the lobby
+    pub fn next(self) -> InputSource {
+        match self {
+            InputSource::Any => InputSource::Arrows,
+            InputSource::Arrows => InputSource::Wasd,
+            InputSource::Wasd => InputSource::Gamepad(0),
+            InputSource::Gamepad(i) if i + 1 < MAX_GAMEPADS => InputSource::Gamepad(i + 1),
+            InputSource::Gamepad(_) => InputSource::Any,
+        }
+    }
+
+    /// Describes this input source for the lobby
+    pub fn name(self) -> String {
+        match self {
+            InputSource::Any => "Any keys or gamepad".to_string(),
+            InputSource::Arrows => "Arrow keys".to_string(),
+            InputSource::Wasd => "WASD".to_string(),
+            InputSource::Gamepad(i) => format!("Gamepad {}", i + 1),
+        }
+    }
+
+    /// Gets what the key with the given code does for a player using this input source
+    pub fn key_action(self, code: &str) -> Option<InputAction> {
+        let arrows = match code {
+            "ArrowUp" => Some(InputAction::Move(Direction::North)),
+            "ArrowDown" => Some(InputAction::Move(Direction::South)),
+            "ArrowLeft" => Some(InputAction::Move(Direction::West)),
+            "ArrowRight" => Some(InputAction::Move(Direction::East)),
+            _ => None,
+        };
+        let wasd = match code {
+            "KeyW" => Some(InputAction::Move(Direction::North)),
+            "KeyS" => Some(InputAction::Move(Direction::South)),
+            "KeyA" => Some(InputAction::Move(Direction::West)),
+            "KeyD" => Some(InputAction::Move(Direction::East)),
+            _ => None,
+        };
+        match self {
+            InputSource::Any => arrows.or(wasd).or(match code {
+                "ShiftLeft" => Some(InputAction::Rotate(RotateDir::CCW)),
+                "ShiftRight" => Some(InputAction::Rotate(RotateDir::CW)),
+                "Space" => Some(InputAction::Confirm),
+                "KeyR" => Some(InputAction::AcceptSuggestion),
+                "KeyG" => Some(InputAction::Hint),
+                _ => None,
+            }),
+            InputSource::Arrows => arrows.or(match code {
+                "ControlRight" => Some(InputAction::Rotate(RotateDir::CCW)),
+                "ShiftRight" => Some(InputAction::Rotate(RotateDir::CW)),
+                "Enter" => Some(InputAction::Confirm),
+                "Slash" => Some(InputAction::AcceptSuggestion),
+                "Period" => Some(InputAction::Hint),
+                _ => None,
+            }),
+            InputSource::Wasd => wasd.or(match code {
+                "KeyQ" => Some(InputAction::Rotate(RotateDir::CCW)),
+                "KeyE" => Some(InputAction::Rotate(RotateDir::CW)),
+                "Space" => Some(InputAction::Confirm),
+                "KeyR" => Some(InputAction::AcceptSuggestion),
+                "KeyG" => Some(InputAction::Hint),
+                _ => None,
+            }),
+            InputSource::Gamepad(_) => None,
+        }
+    }
+
+    /// Gets what the given button on the given gamepad does for a player using this input source
+    pub fn button_action(self, gamepad: u32, button: u32) -> Option<InputAction> {
+        match self {
+            InputSource::Any => (),
+            InputSource::Gamepad(i) if i == gamepad => (),
+            _ => return None,
+        }
+        match button {
+            BUTTON_UP => Some(InputAction::Move(Direction::North)),
+            BUTTON_DOWN => Some(InputAction::Move(Direction::South)),
+            BUTTON_LEFT => Some(InputAction::Move(Direction::West)),
+            BUTTON_RIGHT => Some(InputAction::Move(Direction::East)),
+            BUTTON_ROTATE_CCW => Some(InputAction::Rotate(RotateDir::CCW)),
+            BUTTON_ROTATE_CW => Some(InputAction::Rotate(RotateDir::CW)),
+            BUTTON_CONFIRM => Some(InputAction::Confirm),
+            BUTTON_SUGGESTION => Some(InputAction::AcceptSuggestion),
+            BUTTON_HINT => Some(InputAction::Hint),
+            _ => None,
+        }
+    }
+}
+
+/// Remembers which gamepad buttons were held down, so holding one only counts as one press
+#[derive(Default)]
+pub struct GamepadState {
+    held: BTreeMap<u32, Vec<bool>>,
+}
+
+impl GamepadState {
+    /// Checks every connected gamepad, returning (gamepad, button) for each button pressed since last time
+    pub fn poll(&mut self) -> Vec<(u32, u32)> {
+        let navigator = web_sys::window().expect("No window").navigator();
+        // browsers without gamepad support just don't get any presses
+        let gamepads = match navigator.get_gamepads() {
+            Ok(gamepads) => gamepads,
+            Err(_) => return vec![],
+        };
+        let mut pressed = vec![];
+        for gamepad in gamepads.iter() {
+            let gamepad = match gamepad.dyn_into::<web_sys::Gamepad>() {
+                Ok(gamepad) => gamepad,
+                // disconnected gamepads leave null gaps
+                Err(_) => continue,
+            };
+            let index = gamepad.index();
+            let now: Vec<bool> = gamepad
+                .buttons()
+                .iter()
+                .map(|button| match button.dyn_into::<web_sys::GamepadButton>() {
+                    Ok(button) => button.pressed(),
+                    Err(_) => false,
+                })
+                .collect();
+            let before = self.held.get(&index);
+            for (button, &down) in now.iter().enumerate() {
+                let was_down = before.and_then(|b| b.get(button)).cloned().unwrap_or(false);
+                if down && !was_down {
+                    pressed.push((index, button as u32));
+                }
+            }
+            self.held.insert(index, now);
+        }
+        pressed
+    }
+}
@@ -0,0 +1,171 @@
+//! Treasures on the target cards dealt to each player, like in the board game
+
+use std::collections::VecDeque;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Something a target card can send a player after
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    /// Ghost
+    Ghost,
+    /// Dragon
+    Dragon,
+    /// Bat
+    Bat,
+    /// Beetle
+    Beetle,
+    /// Moth
+    Moth,
+    /// Owl
+    Owl,
+    /// Mouse
+    Mouse,
+    /// Spider
+    Spider,
+    /// Lizard
+    Lizard,
+    /// Genie
+    Genie,
+    /// Troll
+    Troll,
+    /// Sorceress
+    Sorceress,
+    /// Book of spells
+    Book,
+    /// Crown
+    Crown,
+    /// Bag of gold
+    GoldBag,
+    /// Helmet
+    Helmet,
+    /// Ring of keys
+    Keys,
+    /// Treasure map
+    Map,
+    /// Ring
+    Ring,
+    /// Sword
+    Sword,
+    /// Treasure chest
+    Chest,
+    /// Candlestick
+    Candlestick,
+    /// Skull
+    Skull,
+    /// Emerald
+    Emerald,
+}
+
+impl Item {
+    /// Every item, one card each in a full deck
+    pub const ALL: [Item; 24] = [
+        Item::Ghost,
+        Item::Dragon,
+        Item::Bat,
+        Item::Beetle,
+        Item::Moth,
+        Item::Owl,
+        Item::Mouse,
+        Item::Spider,
+        Item::Lizard,
+        Item::Genie,
+        Item::Troll,
+        Item::Sorceress,
+        Item::Book,
+        Item::Crown,
+        Item::GoldBag,
+        Item::Helmet,
+        Item::Keys,
+        Item::Map,
+        Item::Ring,
+        Item::Sword,
+        Item::Chest,
+        Item::Candlestick,
+        Item::Skull,
+        Item::Emerald,
+    ];
+
+    /// Name to show on the card
+    pub fn name(self) -> &'static str {
+        match self {
+            Item::Ghost => "Ghost",
+            Item::Dragon => "Dragon",
+            Item::Bat => "Bat",
+            Item::Beetle => "Beetle",
+            Item::Moth => "Moth",
+            Item::Owl => "Owl",
+            Item::Mouse => "Mouse",
+            Item::Spider => "Spider",
+            Item::Lizard => "Lizard",
+            Item::Genie => "Genie",
+            Item::Troll => "Troll",
+            Item::Sorceress => "Sorceress",
+            Item::Book => "Book of Spells",
+            Item::Crown => "Crown",
+            Item::GoldBag => "Bag of Gold",
+            Item::Helmet => "Helmet",
+            Item::Keys => "Keys",
+            Item::Map => "Treasure Map",
+            Item::Ring => "Ring",
+            Item::Sword => "Sword",
+            Item::Chest => "Treasure Chest",
+            Item::Candlestick => "Candlestick",
+            Item::Skull => "Skull",
+            Item::Emerald => "Emerald",
+        }
+    }
+
+    /// Picture to draw on the target tile
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Item::Ghost => "👻",
+            Item::Dragon => "🐉",
+            Item::Bat => "🦇",
+            Item::Beetle => "🪲",
+            Item::Moth => "🦋",
+            Item::Owl => "🦉",
+            Item::Mouse => "🐭",
+            Item::Spider => "🕷",
+            Item::Lizard => "🦎",
+            Item::Genie => "🧞",
+            Item::Troll => "👹",
+            Item::Sorceress => "🧙",
+            Item::Book => "📖",
+            Item::Crown => "👑",
+            Item::GoldBag => "💰",
+            Item::Helmet => "⛑",
+            Item::Keys => "🗝",
+            Item::Map => "🗺",
+            Item::Ring => "💍",
+            Item::Sword => "🗡",
+            Item::Chest => "🧰",
+            Item::Candlestick => "🕯",
+            Item::Skull => "💀",
+            Item::Emerald => "💎",
+        }
+    }
+}
+
+/// Shuffles up a full deck of target cards for one player
+pub fn deal(rng: &mut impl Rng) -> VecDeque<Item> {
+    let mut deck = Item::ALL.to_vec();
+    deck.shuffle(rng);
+    deck.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decks_have_every_item_once() {
+        let mut rng = StdRng::seed_from_u64(4001);
+        let deck = deal(&mut rng);
+        assert_eq!(deck.len(), Item::ALL.len());
+        for item in &Item::ALL {
+            assert_eq!(deck.iter().filter(|card| *card == item).count(), 1, "{:?}", item);
+        }
+    }
+}
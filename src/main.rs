@@ -21,22 +21,40 @@ pub use crate::menu_view::GameView;
 pub use crate::player::{Player, PlayerID};
 pub use crate::tile::{Direction, Shape, Tile};
 
+mod ai;
 mod anim;
 mod board;
 mod board_controller;
 mod board_view;
+mod bot;
 mod colors;
+mod deep_link;
 mod demo;
+mod diagnostics;
+mod embed;
+mod input;
+mod item;
 mod menu;
 mod menu_controller;
 mod menu_view;
 mod meta_net;
 mod net;
+mod net_sim;
 mod options;
+mod party;
 mod player;
+mod replay;
+mod sandbox;
+mod schedule;
+mod seasonal;
+mod series;
 mod sound;
 mod tile;
+mod toast;
+mod trace;
+mod traffic;
 mod tutorial;
+mod world;
 
 /// Logs some text
 pub fn log(text: &str) {
@@ -44,14 +62,34 @@ pub fn log(text: &str) {
 }
 
 fn main() {
-    console_error_panic_hook::set_once();
+    std::panic::set_hook(Box::new(|info| {
+        console_error_panic_hook::hook(info);
+        diagnostics::on_panic(info);
+    }));
+    trace::start();
+}
+
+/// Mounts the game into the element picked out by the given CSS selector, so it can run on pages
+/// other than its own; the config object can give a `serverUrl` to play through, ask for the
+/// `demo`, and set `lockedSettings` (board settings by field name) that games hosted here can't change
+///
+/// The game takes over the element's contents and class, and should only be mounted once per page.
+/// Its styles key off the `dynamaze` class it gives the element, so any kind of element will do.
+/// It fires `dynamaze:gamestart`, `dynamaze:turn`, and `dynamaze:gameover` events from the element,
+/// with details of the players, whose turn it is, and the final scores.
+#[wasm_bindgen]
+pub fn mount(selector: &str, config: JsValue) {
+    match embed::EmbedConfig::from_js(&config) {
+        Ok(config) => embed::configure(config),
+        Err(err) => wasm_bindgen::throw_str(&format!("Bad DynaMaze config: {}", err)),
+    }
     let window = web_sys::window().expect("no window");
     let main = {
         let document = window.document().expect_throw("no document");
         document
-            .query_selector("main")
-            .expect_throw("failed to find main")
-            .expect_throw("failed to find main")
+            .query_selector(selector)
+            .expect_throw("bad selector")
+            .expect_throw("nothing to mount into")
     };
 
     let game_controller = GameController::new();
@@ -59,44 +97,78 @@ fn main() {
 
     {
         let game_controller = game_controller.clone();
-        let main2 = main.clone();
         let options = EventListenerOptions::enable_prevent_default();
         let click_listener =
             EventListener::new_with_options(&main, "click", options, move |event| {
                 let event = event
                     .dyn_ref::<web_sys::MouseEvent>()
                     .expect_throw("bad click event");
-                game_controller.lock().unwrap().on_click(event, &main2);
+                trace::record_mouse(event);
+                game_controller.lock().unwrap().on_click(event);
             });
         click_listener.forget();
     }
 
     {
         let game_controller = game_controller.clone();
-        let main2 = main.clone();
         let options = EventListenerOptions::enable_prevent_default();
         let contextmenu_listener =
             EventListener::new_with_options(&main, "contextmenu", options, move |event| {
                 let event = event
                     .dyn_ref::<web_sys::MouseEvent>()
                     .expect_throw("bad contextmenu event");
-                game_controller.lock().unwrap().on_click(event, &main2);
+                trace::record_mouse(event);
+                game_controller.lock().unwrap().on_click(event);
             });
         contextmenu_listener.forget();
     }
 
     {
         let game_controller = game_controller.clone();
-        let main2 = main.clone();
+        let mousedown_listener = EventListener::new(&main, "mousedown", move |event| {
+            let event = event
+                .dyn_ref::<web_sys::MouseEvent>()
+                .expect_throw("bad mousedown event");
+            trace::record_mouse(event);
+            game_controller.lock().unwrap().on_mousedown(event);
+        });
+        mousedown_listener.forget();
+    }
+
+    {
+        let game_controller = game_controller.clone();
         let mousemove_listener = EventListener::new(&main, "mousemove", move |event| {
             let event = event
                 .dyn_ref::<web_sys::MouseEvent>()
                 .expect_throw("bad mousemove event");
-            game_controller.lock().unwrap().on_mousemove(event, &main2);
+            trace::record_mouse(event);
+            game_controller.lock().unwrap().on_mousemove(event);
         });
         mousemove_listener.forget();
     }
 
+    {
+        let game_controller = game_controller.clone();
+        let options = EventListenerOptions::enable_prevent_default();
+        let wheel_listener = EventListener::new_with_options(&main, "wheel", options, move |event| {
+            let event = event
+                .dyn_ref::<web_sys::WheelEvent>()
+                .expect_throw("bad wheel event");
+            game_controller.lock().unwrap().on_wheel(event);
+        });
+        wheel_listener.forget();
+    }
+
+    // phones rotating don't always fire resize, so listen for both
+    for event_type in &["resize", "orientationchange"] {
+        let game_controller = game_controller.clone();
+        let main2 = main.clone();
+        let resize_listener = EventListener::new(&window, *event_type, move |_| {
+            game_controller.lock().unwrap().on_resize(&main2);
+        });
+        resize_listener.forget();
+    }
+
     {
         let game_controller = game_controller.clone();
         let main2 = main.clone();
@@ -104,6 +176,7 @@ fn main() {
             let event = event
                 .dyn_ref::<web_sys::KeyboardEvent>()
                 .expect_throw("bad keydown event");
+            trace::record_key(event);
             game_controller.lock().unwrap().on_keydown(event, &main2);
         });
         keydown_listener.forget();
@@ -112,14 +185,23 @@ fn main() {
     {
         use gloo::timers::callback::Interval;
         let game_controller = game_controller.clone();
-        let mut last_frame = now();
+        let main = main.clone();
+        let mut scheduler = schedule::Scheduler::new();
+        // the timer drifts and stalls in background tabs, so it only says how many fixed ticks to run
         Interval::new(1_000 / 60, move || {
-            last_frame = {
-                let this_frame = now();
-                let dt = this_frame - last_frame;
-                game_controller.lock().unwrap().on_tick(dt);
-                this_frame
-            };
+            for _ in 0..scheduler.ticks_due(now()) {
+                {
+                    let mut game_controller = game_controller.lock().unwrap();
+                    game_controller.on_tick(schedule::TICK, &main);
+                    for task in scheduler.tick_tasks() {
+                        game_controller.on_task(task);
+                    }
+                }
+                // replayed input goes through the listeners, which need the controller unlocked
+                for input in trace::tick() {
+                    trace::dispatch(&input, &main);
+                }
+            }
         }).forget();
     }
 
@@ -5,9 +5,12 @@ use std::sync::{Arc, RwLock};
 use serde::{Deserialize, Serialize};
 
 use crate::{BoardController, BoardSettings, Player, PlayerID};
+use crate::board_controller::WinReason;
 use crate::colors::Color;
 use crate::net::{GameID, Message, NetHandler};
 use crate::options::GameOptions;
+use crate::series::SeriesState;
+use crate::trace;
 
 /// Lobby information
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,12 +27,12 @@ pub struct LobbyInfo {
 
 impl LobbyInfo {
     /// Creates a new lobby
-    pub fn new(player_id: PlayerID, id: GameID) -> LobbyInfo {
+    pub fn new(player_id: PlayerID, id: GameID, settings: BoardSettings) -> LobbyInfo {
         LobbyInfo {
             host: Player::new("Host McHostface".into(), Color(0.7, 0.2, 0.7), player_id),
             guests: vec![],
             id,
-            settings: BoardSettings::default(),
+            settings,
         }
     }
 
@@ -47,6 +50,35 @@ impl LobbyInfo {
         players
     }
 
+    /// Checks if a player with the given ID is in the lobby
+    pub fn has_player(&self, id: PlayerID) -> bool {
+        self.players_ref().iter().any(|p| p.id == id)
+    }
+
+    /// Removes a guest and any local players of theirs, returns whether anyone was removed
+    pub fn remove_client(&mut self, client: PlayerID) -> bool {
+        let count = self.guests.len();
+        self.guests.retain(|p| !p.lives_with(client));
+        self.guests.len() != count
+    }
+
+    /// Removes a single guest, returns whether they were here
+    pub fn remove_player(&mut self, id: PlayerID) -> bool {
+        let count = self.guests.len();
+        self.guests.retain(|p| p.id != id);
+        self.guests.len() != count
+    }
+
+    /// Picks an ID that no player in the lobby has yet
+    pub fn fresh_player_id(&self) -> PlayerID {
+        loop {
+            let id = trace::random();
+            if !self.has_player(id) {
+                return id;
+            }
+        }
+    }
+
     /// Gets a player by ID
     pub fn player(&self, id: &PlayerID) -> &Player {
         if self.host.id == *id {
@@ -79,11 +111,61 @@ impl LobbyInfo {
 pub struct GameOverInfo {
     /// Winning player
     pub winner: Player,
+    /// How the winner beat anyone else who reached the score limit
+    pub win_reason: WinReason,
     /// Host ID
     pub host_id: PlayerID,
+    /// The game as it ended, for reviewing afterwards
+    pub final_board: Box<BoardController>,
+    /// Standings so far, if this game was one board of a series
+    #[serde(default)]
+    pub series: Option<Box<SeriesState>>,
+    /// Settings for the next board of the series, which the host can change in between
+    #[serde(default)]
+    pub next_settings: Option<BoardSettings>,
+}
+
+impl GameOverInfo {
+    /// Builds endgame information, if someone has won the given game
+    pub fn for_finished_game(board_controller: &BoardController) -> Option<GameOverInfo> {
+        board_controller.win().map(|(winner, win_reason)| {
+            let series = board_controller.series.clone().map(|mut series| {
+                series.record(board_controller, winner.id);
+                series
+            });
+            let next_settings = match series {
+                Some(ref series) if !series.is_over() => Some((*board_controller.settings).clone()),
+                _ => None,
+            };
+            GameOverInfo {
+                winner: winner.clone(),
+                win_reason,
+                host_id: board_controller.host_id,
+                final_board: Box::new(board_controller.clone()),
+                series,
+                next_settings,
+            }
+        })
+    }
+
+    /// Lists each player's name and final score, in turn order
+    pub fn scores(&self) -> Vec<(String, u8)> {
+        let board = &self.final_board;
+        board
+            .turn_order
+            .iter()
+            .filter_map(|id| {
+                let player = board.players.get(id)?;
+                let token = board.board.player_tokens.get(id)?;
+                Some((player.name.clone(), token.score))
+            })
+            .collect()
+    }
 }
 
 /// Synchronized state of a network game
+// a game's on nearly every time there's a state to look at, so boxing the board would just add a hop
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum NetGameState {
     /// Connecting
@@ -110,12 +192,65 @@ impl NetGameState {
         };
         host_id == id
     }
+
+    /// Gets the settings that can be edited right now, if any, either the lobby's or the next board's
+    pub fn settings(&self) -> Option<&BoardSettings> {
+        match self {
+            NetGameState::Lobby(ref info) => Some(&info.settings),
+            NetGameState::GameOver(ref info) => info.next_settings.as_ref(),
+            NetGameState::Connecting | NetGameState::Active(_) | NetGameState::Error(_) => None,
+        }
+    }
+
+    /// Gets the settings that can be edited right now, if any, mutably
+    pub fn settings_mut(&mut self) -> Option<&mut BoardSettings> {
+        match self {
+            NetGameState::Lobby(ref mut info) => Some(&mut info.settings),
+            NetGameState::GameOver(ref mut info) => info.next_settings.as_mut(),
+            NetGameState::Connecting | NetGameState::Active(_) | NetGameState::Error(_) => None,
+        }
+    }
+
+    /// Gets a short name for the kind of state this is
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetGameState::Connecting => "connecting",
+            NetGameState::Lobby(_) => "lobby",
+            NetGameState::Active(_) => "active",
+            NetGameState::GameOver(_) => "game-over",
+            NetGameState::Error(_) => "error",
+        }
+    }
+
+    /// Carries over the animations running on the old state's board and animates what changed
+    /// since it, if both are a game in progress
+    pub fn reconcile(&mut self, old: &mut NetGameState) {
+        match (self, old) {
+            (NetGameState::Active(new), NetGameState::Active(old)) => new.reconcile(old),
+            // whoever finished the game may have been missing the start of the log, but we might not be
+            (NetGameState::GameOver(new), NetGameState::Active(old)) => {
+                new.final_board.log.splice(&old.log);
+            }
+            _ => (),
+        }
+    }
+
+    /// Copies this state to send to everyone else, leaving out the parts of a game in progress
+    /// they've already heard
+    pub fn for_broadcast(&self) -> NetGameState {
+        match self {
+            NetGameState::Active(ref board) => NetGameState::Active(board.for_broadcast()),
+            _ => self.clone(),
+        }
+    }
 }
 
 impl NetGameState {
     /// Sends player information to the given lobby
     pub fn join_lobby(handler: &mut NetHandler, player: Player) {
-        handler.send(Message::JoinLobby(player));
+        handler.send(Message::JoinLobby(player.clone()));
+        // in case the game's already started, the host will ignore whichever doesn't apply
+        handler.send(Message::RequestJoinActive(player));
     }
 }
 
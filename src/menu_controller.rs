@@ -1,25 +1,49 @@
 //! Menu / global state controller
 
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, Receiver, Sender};
 
 use gloo::events::{EventListener, EventListenerOptions};
 use rand::prelude::*;
+use serde_json::json;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d as Context;
 
-use crate::{BoardController, GameView, Player, PlayerID};
+use crate::{BoardController, BoardSettings, BoardView, GameView, Player, PlayerID};
+use crate::ai::{self, BotPlayer};
 use crate::anim;
-use crate::colors::Color;
+use crate::board::{Scoring, ENERGY_BANK_TURNS};
+use crate::board_controller::{
+    CONTROL_HINTS, MAX_HINT_LIMIT, MAX_SPEED_BONUS, MAX_TURN_TIME_LIMIT, RULE_PRESETS, SIZE_PRESETS, SPEED_STREAK,
+};
+use crate::board_view::Cursor;
+use crate::colors::{self, Color, Theme};
+use crate::deep_link::DeepLink;
 use crate::demo;
+use crate::diagnostics;
+use crate::embed;
 use crate::menu::{ConnectedState, GameOverInfo, GameState, LobbyInfo, NetGameState};
-use crate::net::{self, Message};
+use crate::net::{self, GameID, Message, QueryResult};
+use crate::input::{GamepadState, InputAction, InputSource};
 use crate::options;
+use crate::party::{self, Draft, LocalPlanning};
 use crate::sound::{self, SoundEngine};
-use crate::tutorial;
+use crate::replay::GameEvent;
+use crate::sandbox;
+use crate::schedule::Task;
+use crate::seasonal;
+use crate::series;
+use crate::toast::Toasts;
+use crate::trace;
+use crate::tutorial::{self, TutorialStep};
+
+/// Picks out the canvas the game is drawn on, and not the lobby's board preview
+const MAIN_CANVAS: &str = "canvas:not(#board-preview)";
 
 fn get_context(main: &web_sys::Element) -> Option<Context> {
-    let canvas = main.query_selector("canvas").unwrap_throw()?;
+    let canvas = main.query_selector(MAIN_CANVAS).unwrap_throw()?;
     let canvas = canvas
         .dyn_ref::<web_sys::HtmlCanvasElement>()
         .unwrap_throw();
@@ -28,7 +52,241 @@ fn get_context(main: &web_sys::Element) -> Option<Context> {
     Some(ctx.clone())
 }
 
-type DeferredAction = Box<dyn FnOnce(&mut GameController)>;
+/// Draws a board like the given settings would make onto the lobby's preview canvas
+fn draw_board_preview(canvas: &web_sys::HtmlCanvasElement, settings: &BoardSettings, view: &BoardView) {
+    let ctx = canvas.get_context("2d").unwrap_throw().unwrap_throw();
+    let ctx = ctx.dyn_ref::<Context>().unwrap_throw();
+    view.draw_preview(&settings.preview_board(), f64::from(canvas.width()), ctx);
+}
+
+/// Labels the hint button with how many hints the local player has left
+fn hint_text(board_controller: &BoardController, local_id: PlayerID) -> String {
+    let id = board_controller.effective_local_id(local_id);
+    match board_controller.hints_left(id) {
+        0 => "No Hints Left".to_string(),
+        left => format!("Hint ({} left)", left),
+    }
+}
+
+/// Reads the `#rrggbb` value of a color input
+fn parse_color(value: &str) -> Option<Color> {
+    if value.len() != 7 || !value.starts_with('#') {
+        return None;
+    }
+    let color_r = u8::from_str_radix(&value[1..3], 16).ok()?;
+    let color_g = u8::from_str_radix(&value[3..5], 16).ok()?;
+    let color_b = u8::from_str_radix(&value[5..7], 16).ok()?;
+    Some(Color(color_r as f32 / 255.0, color_g as f32 / 255.0, color_b as f32 / 255.0))
+}
+
+/// Gets the question to ask before doing the given thing
+fn confirm_prompt(event: &UiEvent) -> &'static str {
+    match event {
+        UiEvent::MainMenu => "Leave for the main menu? There's no coming back to this game.",
+        UiEvent::RemoveLocalPlayer(_) => "Remove this player from the game?",
+        _ => "Are you sure?",
+    }
+}
+
+/// Lists the themes that can be picked in Options, as (label, name), starting with the seasonal one
+fn theme_choices() -> Vec<(&'static str, &'static str)> {
+    let mut choices = vec![("Seasonal", "")];
+    choices.extend(colors::THEMES.iter().map(|theme| (theme.name, theme.name)));
+    choices
+}
+
+/// Describes what's going on in the game being watched, for spectators, who only see the board
+fn spectate_text(state: &NetGameState) -> String {
+    match *state {
+        NetGameState::Connecting => "Connecting...".to_string(),
+        NetGameState::Lobby(_) => "Waiting for the game to start".to_string(),
+        NetGameState::Active(_) => String::new(),
+        NetGameState::GameOver(ref info) => format!("{} wins!", info.winner.name),
+        NetGameState::Error(ref text) => text.clone(),
+    }
+}
+
+/// Describes the next board of a series, for anyone but the host
+fn next_board_text(settings: &BoardSettings) -> String {
+    let points = match settings.scoring {
+        Scoring::Targets => "",
+        Scoring::Points => " points",
+    };
+    let text = format!(
+        "Next board: {}×{}, first to {}{}",
+        settings.width, settings.height, settings.score_limit, points
+    );
+    match settings.turn_time_limit {
+        0 => text,
+        limit => format!("{}, {}s per turn", text, limit),
+    }
+}
+
+/// Puts the given text on the clipboard, which only works while handling a click
+fn copy_text(document: &web_sys::Document, text: &str) {
+    let scratch = document.create_element("textarea").unwrap_throw();
+    let scratch = scratch.dyn_into::<web_sys::HtmlTextAreaElement>().unwrap_throw();
+    scratch.set_value(text);
+    let body = document.body().unwrap_throw();
+    body.append_with_node_1(&scratch).unwrap_throw();
+    scratch.select();
+    if let Some(document) = document.dyn_ref::<web_sys::HtmlDocument>() {
+        let _ = document.exec_command("copy");
+    }
+    scratch.remove();
+}
+
+/// Width and height of the lobby's board preview, in canvas pixels
+const BOARD_PREVIEW_SIZE: u32 = 240;
+
+/// Most UI events handled in one tick, so a flood of input can't stall a frame
+const MAX_EVENTS_PER_TICK: usize = 32;
+
+/// Something that happened in the DOM UI, to be handled on the next tick
+#[derive(Debug, Clone)]
+pub enum UiEvent {
+    /// Ask before doing the given thing, since it can't be undone
+    AskFirst(Box<UiEvent>),
+    /// Go ahead with the thing being asked about
+    Confirm,
+    /// Don't do the thing being asked about after all
+    CancelConfirm,
+    /// Pick back up whatever was left partway through
+    Continue,
+    /// Start the tutorial
+    Tutorial,
+    /// Start a practice sandbox
+    Sandbox,
+    /// Start a local game
+    LocalGame,
+    /// Host a network game
+    Host,
+    /// Go to the connect menu
+    Connect,
+    /// Go to the options menu
+    EnterOptions,
+    /// Connect to the given game
+    DoConnect(GameID),
+    /// Set board width
+    SetWidth(usize),
+    /// Set board height
+    SetHeight(usize),
+    /// Set board width and height at once
+    SetSize(usize, usize),
+    /// Set size, score limit, and turn time limit to the rule preset at the given index
+    SetRulePreset(usize),
+    /// Set score limit
+    SetScoreLimit(u8),
+    /// Set turn time limit
+    SetTurnTimeLimit(u16),
+    /// Set whether pushed-off tokens wrap around
+    SetWrapTokens(bool),
+    /// Set whether targets are worth points by distance
+    SetPointsScoring(bool),
+    /// Set whether landing on someone else's target locks it
+    SetTargetSteal(bool),
+    /// Set whether each player gets a double push
+    SetDoublePush(bool),
+    /// Set whether the game is played across two boards linked by portals
+    SetPortals(bool),
+    /// Set how quick a turn has to be to count toward a speed bonus
+    SetSpeedBonus(u16),
+    /// Set how much energy tokens get back each turn, or 0 for moving freely
+    SetEnergyPerTurn(u8),
+    /// Set how close new targets can be
+    SetMinTargetDistance(u8),
+    /// Set how far new targets can be
+    SetMaxTargetDistance(u8),
+    /// Set how many hints each player gets
+    SetHintLimit(u8),
+    /// Set how many boards the series has
+    SetSeriesLength(u8),
+    /// Set how many turns go by between the board collapsing
+    SetCollapseEvery(u16),
+    /// Set how far players can see through the fog of war
+    SetFogDistance(u8),
+    /// Set whether everyone plans their turn at once
+    SetParty(bool),
+    /// Set whether everyone in the game uses our theme
+    SetShareTheme(bool),
+    /// Set music level
+    SetMusicLevel(u8),
+    /// Set sound level
+    SetSoundLevel(u8),
+    /// Set ambience level
+    SetAmbienceLevel(u8),
+    /// Set whether the rotate assist is on
+    SetRotateAssist(bool),
+    /// Set whether the last few moves are drawn on the board
+    SetMoveHistory(bool),
+    /// Set whether to skip asking before leaving games and removing players
+    SetSkipConfirmations(bool),
+    /// Set the theme the board is drawn in, by name, or empty for the seasonal one
+    SetTheme(String),
+    /// Set where hosted games' events get sent
+    SetWebhookUrl(String),
+    /// Set how the tutorial speaks up as each step starts
+    SetTutorialNarration(options::Narration),
+    /// Set how far the board's turned on this screen
+    SetBoardRotation(options::BoardRotation),
+    /// Save options
+    SaveOptions,
+    /// Set the name of the given player
+    SetName(PlayerID, String),
+    /// Set the color of the given player
+    SetColor(PlayerID, Color),
+    /// Add a new local player
+    NewLocalPlayer,
+    /// Add a computer player
+    NewBotPlayer,
+    /// Switch the given computer player to the next difficulty
+    CycleBotDifficulty(PlayerID),
+    /// Take the given local player back out of the lobby
+    RemoveLocalPlayer(PlayerID),
+    /// Ask for a settings code and switch the lobby over to those settings
+    PasteSettings,
+    /// Let the player who's been waiting longest to join the game in progress in
+    AcceptJoin,
+    /// Turn away the player who's been waiting longest to join the game in progress
+    DeclineJoin,
+    /// Start the hosted game
+    StartHostedGame,
+    /// Return to the main menu
+    MainMenu,
+    /// Try joining the same lobby again
+    RetryConnect,
+    /// Join a lobby even though the server said it wasn't open
+    JoinAnyway,
+    /// Step through the finished game by the given number of turns
+    Review(isize),
+    /// Start the next board of the series
+    NextBoard,
+    /// Say we're ready for the next board of the series
+    SeriesReady,
+    /// Ask the host for a fresh copy of the game after a desync
+    Resync,
+    /// Show or hide the rules over the game
+    ToggleRules,
+    /// Ask for a hint for the current turn
+    Hint,
+    /// Say the given quick-chat phrase
+    QuickChat(usize),
+    /// Start the idle lobby countdown over
+    KeepLobbyOpen,
+    /// Switch the given local player to the next input source
+    CycleInputSource(PlayerID),
+}
+
+/// Fewest seconds between settings messages, so typing in a field doesn't send every keystroke
+const SETTINGS_SEND_INTERVAL: f64 = 0.25;
+/// Seconds to wait for the host before offering to retry
+const CONNECT_TIMEOUT: f64 = 10.0;
+/// Seconds a hosted lobby can sit with nobody else in it before it closes
+const LOBBY_IDLE_TIMEOUT: f64 = 600.0;
+/// Seconds before an idle lobby closes that the countdown shows up
+const LOBBY_IDLE_COUNTDOWN: f64 = 60.0;
+/// How much a finished game zooms for every pixel the mouse wheel scrolls
+const WHEEL_ZOOM_RATE: f64 = 0.002;
 
 /// Handles events for DynaMaze game
 pub struct GameController {
@@ -36,16 +294,62 @@ pub struct GameController {
     pub state: GameState,
     /// Current player ID
     pub player_id: PlayerID,
+    /// This page's own player ID, which the current one goes back to after playing a local game
+    /// under whoever hosted it
+    pub own_id: PlayerID,
     /// Active player ID the last time the state was checked for a notification
     pub last_player: Option<PlayerID>,
     /// View
     pub view: GameView,
     /// Sound controller
     pub sound_engine: SoundEngine,
-    /// Action queue
-    pub actions: Arc<Mutex<Vec<DeferredAction>>>,
+    /// Sender for UI events, cloned into DOM event listeners
+    pub events: Sender<UiEvent>,
+    /// Receiver for UI events
+    pub event_queue: Receiver<UiEvent>,
     /// DOM event listeners
     pub listeners: Vec<EventListener>,
+    /// Whether lobby settings have been edited since they were last sent
+    pub settings_dirty: bool,
+    /// When lobby settings were last sent, so a run of edits goes out as one message
+    pub settings_sent_at: f64,
+    /// Lobby being joined and when the attempt started, for the connecting screen
+    pub connecting_to: Option<(GameID, f64)>,
+    /// Check on a lobby that's about to be joined
+    pub lobby_query: Option<net::LobbyQuery>,
+    /// Why the last custom board size wasn't accepted, if it wasn't
+    pub size_error: Option<String>,
+    /// Whether the rules are showing over the game
+    pub show_rules: bool,
+    /// When the lobby we're hosting was last left with nobody else in it
+    pub lonely_since: Option<f64>,
+    /// Where each local player's input comes from, if they've picked
+    pub input_sources: BTreeMap<PlayerID, InputSource>,
+    /// Gamepad buttons held down as of the last tick
+    pub gamepads: GamepadState,
+    /// Turns being planned here in party mode
+    pub party: LocalPlanning,
+    /// Turn a computer player is up for and when they started thinking about it, on the host
+    pub bot_clock: Option<(usize, f64)>,
+    /// Whether we're only watching the game, from a spectator link
+    pub spectating: bool,
+    /// How many of the board's events have had their sounds played, if there's a game going
+    pub heard_events: Option<usize>,
+    /// Notices about things happening in the game
+    pub toasts: Toasts,
+    /// Something that can't be undone, waiting to be confirmed
+    pub confirming: Option<Box<UiEvent>>,
+    /// How far along the game was when the page the game's mounted in last heard about it
+    pub page_progress: embed::Progress,
+    /// Whether everyone in the finished game on screen has been added to the recent players list
+    pub remembered_game: bool,
+    /// Turn the game was on when spectators were last sent the whole state, since they only get it
+    /// once a turn
+    pub spectators_turn: Option<usize>,
+    /// Turn the host started timing for speed bonuses, and when it started (by the host's clock)
+    pub turn_clock: Option<(usize, f64)>,
+    /// Step of the tutorial that was last narrated, so each one is only narrated as it starts
+    pub narrated_step: Option<TutorialStep>,
 }
 
 impl GameController {
@@ -54,30 +358,125 @@ impl GameController {
         if demo::is_demo() {
             return demo::new_controller();
         }
-        let player_id = random();
+        let player_id = trace::player_id(random());
         let sound_engine = SoundEngine::new();
         sound_engine.play_music(sound::Music::Menu);
-        GameController {
+        let (events, event_queue) = mpsc::channel();
+        let mut controller = GameController {
             state: GameState::MainMenu,
             player_id,
+            own_id: player_id,
             last_player: None,
             view: GameView::new(),
             sound_engine,
-            actions: Default::default(),
+            events,
+            event_queue,
             listeners: vec![],
+            settings_dirty: false,
+            settings_sent_at: 0.0,
+            connecting_to: None,
+            lobby_query: None,
+            size_error: None,
+            show_rules: false,
+            lonely_since: None,
+            input_sources: BTreeMap::new(),
+            gamepads: GamepadState::default(),
+            party: LocalPlanning::default(),
+            bot_clock: None,
+            spectating: false,
+            heard_events: None,
+            toasts: Toasts::default(),
+            confirming: None,
+            page_progress: Default::default(),
+            remembered_game: false,
+            spectators_turn: None,
+            turn_clock: None,
+            narrated_step: None,
+        };
+        match DeepLink::from_query() {
+            Some(DeepLink::Host) => controller.host(),
+            Some(DeepLink::Tutorial) => controller.tutorial(),
+            Some(DeepLink::Options) => controller.enter_options(),
+            Some(DeepLink::Join(game)) => {
+                controller.state = GameState::ConnectMenu;
+                controller.do_connect(game);
+            }
+            Some(DeepLink::Spectate(game)) => controller.spectate(game),
+            None => (),
+        }
+        controller
+    }
+
+    /// Holds off on the given event until it's confirmed, unless confirmations are turned off
+    fn ask_first(&mut self, event: UiEvent) {
+        if options::HANDLE.fetch().skip_confirmations {
+            self.handle_event(event);
+        } else {
+            self.confirming = Some(Box::new(event));
+        }
+    }
+
+    fn confirm(&mut self) {
+        if let Some(event) = self.confirming.take() {
+            self.handle_event(*event);
+        }
+    }
+
+    fn continue_last(&mut self) {
+        match options::continue_target() {
+            Some(options::Continue::LocalGame) => {
+                if let Some(board) = options::saved_local_game() {
+                    // the local players all live with whoever hosted the game, until it's left
+                    self.player_id = board.host_id;
+                    let state = Arc::new(RwLock::new(NetGameState::Active(board)));
+                    let sender = net::NetHandler::run_fake(self.player_id);
+                    self.state = GameState::InGame(ConnectedState { state, sender });
+                }
+            }
+            Some(options::Continue::Tutorial(step)) => {
+                self.state = GameState::InGame(tutorial::new_conn_state(self.player_id, step));
+            }
+            Some(options::Continue::Lobby(game)) => {
+                self.state = GameState::ConnectMenu;
+                self.do_connect(game);
+            }
+            None => (),
         }
     }
 
     fn tutorial(&mut self) {
-        self.state = GameState::InGame(tutorial::new_conn_state(self.player_id));
+        options::save_continue_target(&options::Continue::Tutorial(TutorialStep::First));
+        self.state = GameState::InGame(tutorial::new_conn_state(self.player_id, TutorialStep::First));
+    }
+
+    fn sandbox(&mut self) {
+        self.state = GameState::InGame(sandbox::new_conn_state(self.player_id));
     }
 
     fn host(&mut self) {
         let game = random();
-        let state = NetGameState::Lobby(LobbyInfo::new(self.player_id, game));
+        let mut settings = options::last_lobby_settings();
+        embed::lock_settings(&mut settings);
+        let state = NetGameState::Lobby(LobbyInfo::new(self.player_id, game, settings));
         let state = Arc::new(RwLock::new(state));
         let sender = net::NetHandler::run(state.clone(), game, self.player_id);
-        anim::STATE.write().unwrap().set_send(sender.queue());
+        options::save_continue_target(&options::Continue::Lobby(game));
+        options::save_recent_lobby(game);
+        let webhook_url = options::HANDLE.fetch().webhook_url.trim().to_string();
+        if !webhook_url.is_empty() {
+            sender.set_webhook(webhook_url);
+        }
+        let conn_state = ConnectedState { state, sender };
+        self.state = GameState::InGame(conn_state);
+    }
+
+    fn local_game(&mut self) {
+        let game = random();
+        let mut settings = options::last_lobby_settings();
+        embed::lock_settings(&mut settings);
+        let state = NetGameState::Lobby(LobbyInfo::new(self.player_id, game, settings));
+        let state = Arc::new(RwLock::new(state));
+        let sender = net::NetHandler::run_fake(self.player_id);
         let conn_state = ConnectedState { state, sender };
         self.state = GameState::InGame(conn_state);
     }
@@ -90,87 +489,341 @@ impl GameController {
         self.state = GameState::Options(options::HANDLE.fetch().clone());
     }
 
-    fn do_connect(&mut self, form: web_sys::HtmlFormElement) {
+    fn do_connect(&mut self, game: GameID) {
         if let GameState::ConnectMenu = self.state {
-            let elements = form.elements();
-            let game = elements.item(0).unwrap_throw();
-            let game = game.dyn_ref::<web_sys::HtmlInputElement>().unwrap_throw();
-            let game = game.value().parse().unwrap_throw();
-            let state = NetGameState::Connecting;
-            let state = Arc::new(RwLock::new(state));
-            let mut sender = net::NetHandler::run(state.clone(), game, self.player_id);
-            anim::STATE.write().unwrap().set_send(sender.queue());
-            let player = Player::new("Guesty McGuestface".into(), random(), self.player_id);
-            NetGameState::join_lobby(&mut sender, player);
-            let conn_state = ConnectedState { sender, state };
-            self.state = GameState::InGame(conn_state);
-        }
-    }
-
-    fn set_width(&mut self, width: web_sys::HtmlInputElement) {
-        if let GameState::InGame(ref mut conn_state) = self.state {
-            let sender = &mut conn_state.sender;
-            let state = &mut conn_state.state;
-            let mut state = state.write().expect("Failed to lock state");
-            if let NetGameState::Lobby(ref mut info) = *state {
-                let settings = &mut info.settings;
-                settings.width = width.value().parse().unwrap_throw();
-                settings.version += 1;
-                width.form().unwrap_throw().dataset().set("version", &format!("{}", settings.version)).unwrap_throw();
-                let message = Message::EditSettings(settings.clone());
-                sender.send(message);
+            // make sure there's an open lobby there before actually joining
+            self.lobby_query = Some(net::LobbyQuery::run(game));
+        }
+    }
+
+    fn join_anyway(&mut self) {
+        if let GameState::ConnectMenu = self.state {
+            if let Some(query) = self.lobby_query.take() {
+                self.start_connecting(query.game);
+            }
+        }
+    }
+
+    fn check_lobby_query(&mut self) {
+        if let GameState::ConnectMenu = self.state {
+            let open = match self.lobby_query.as_ref().and_then(|query| query.result()) {
+                Some(QueryResult::Answered(info)) => info.players > 0 && !info.started,
+                Some(QueryResult::Failed) | None => false,
+            };
+            if open {
+                self.join_anyway();
             }
+        } else {
+            self.lobby_query = None;
+        }
+    }
+
+    fn retry_connect(&mut self) {
+        if let Some((game, _)) = self.connecting_to {
+            self.start_connecting(game);
         }
     }
 
-    fn set_height(&mut self, height: web_sys::HtmlInputElement) {
+    fn start_connecting(&mut self, game: GameID) {
+        // replacing the old state drops its handler, which closes any previous socket
+        self.connecting_to = Some((game, crate::now()));
+        options::save_continue_target(&options::Continue::Lobby(game));
+        options::save_recent_lobby(game);
+        let state = NetGameState::Connecting;
+        let state = Arc::new(RwLock::new(state));
+        let mut sender = net::NetHandler::run(state.clone(), game, self.player_id);
+        let player = Player::new_guest(self.player_id);
+        NetGameState::join_lobby(&mut sender, player);
+        let conn_state = ConnectedState { sender, state };
+        self.state = GameState::InGame(conn_state);
+    }
+
+    /// Watches the given game without joining it, with nothing on screen but the board
+    fn spectate(&mut self, game: GameID) {
+        self.spectating = true;
+        self.connecting_to = Some((game, crate::now()));
+        let state = Arc::new(RwLock::new(NetGameState::Connecting));
+        let sender = net::NetHandler::run(state.clone(), game, self.player_id);
+        // the host answers with the whole state, and everything after that gets sent to everyone anyway
+        sender.send(Message::RequestState);
+        let conn_state = ConnectedState { sender, state };
+        self.state = GameState::InGame(conn_state);
+    }
+
+    fn edit_settings<F: FnOnce(&mut BoardSettings)>(&mut self, edit: F) {
         if let GameState::InGame(ref mut conn_state) = self.state {
-            let sender = &mut conn_state.sender;
             let state = &mut conn_state.state;
             let mut state = state.write().expect("Failed to lock state");
-            if let NetGameState::Lobby(ref mut info) = *state {
-                let settings = &mut info.settings;
-                settings.height = height.value().parse().unwrap_throw();
-                settings.version += 1;
-                height.form().unwrap_throw().dataset().set("version", &format!("{}", settings.version)).unwrap_throw();
-                let message = Message::EditSettings(settings.clone());
-                sender.send(message);
+            // between boards of a series, only the host gets a say
+            let may_edit = match *state {
+                NetGameState::GameOver(_) => state.is_host(self.player_id),
+                _ => true,
+            };
+            if let (Some(settings), true) = (state.settings_mut(), may_edit) {
+                edit(settings);
+                embed::lock_settings(settings);
+                // a held edit has to be newer than whatever the host sends back before it goes out,
+                // and the edits after it are part of the same one
+                if !self.settings_dirty {
+                    settings.version += 1;
+                }
+                // sent once the edits stop coming so fast, with whatever the settings are by then
+                self.settings_dirty = true;
             }
         }
     }
 
-    fn set_score_limit(&mut self, score_limit: web_sys::HtmlInputElement) {
+    fn send_settings(&mut self) {
         if let GameState::InGame(ref mut conn_state) = self.state {
             let sender = &mut conn_state.sender;
-            let state = &mut conn_state.state;
-            let mut state = state.write().expect("Failed to lock state");
-            if let NetGameState::Lobby(ref mut info) = *state {
-                let settings = &mut info.settings;
-                settings.score_limit = score_limit.value().parse().unwrap_throw();
-                settings.version += 1;
-                score_limit.form().unwrap_throw().dataset().set("version", &format!("{}", settings.version)).unwrap_throw();
-                let message = Message::EditSettings(settings.clone());
+            let state = conn_state.state.read().expect("Failed to lock state");
+            let is_host = state.is_host(self.player_id);
+            if let Some(settings) = state.settings() {
+                // the host has the final say, everyone else just proposes
+                let message = if is_host {
+                    Message::Settings(settings.clone())
+                } else {
+                    Message::EditSettings(settings.clone())
+                };
                 sender.send(message);
             }
         }
+        self.settings_dirty = false;
+        self.settings_sent_at = crate::now();
+    }
+
+    fn current_settings(&self) -> Option<BoardSettings> {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let state = conn_state.state.read().expect("Failed to lock state");
+            return state.settings().cloned();
+        }
+        None
+    }
+
+    fn set_width(&mut self, width: usize) {
+        if let Some(settings) = self.current_settings() {
+            self.set_size(width, settings.height);
+        }
+    }
+
+    fn set_height(&mut self, height: usize) {
+        if let Some(settings) = self.current_settings() {
+            self.set_size(settings.width, height);
+        }
+    }
+
+    fn set_size(&mut self, width: usize, height: usize) {
+        match BoardSettings::check_size(width, height) {
+            Ok(()) => {
+                self.size_error = None;
+                self.edit_settings(|settings| {
+                    settings.width = width;
+                    settings.height = height;
+                });
+            }
+            Err(error) => self.size_error = Some(error),
+        }
+    }
+
+    fn set_rule_preset(&mut self, i: usize) {
+        if let Some(&preset) = RULE_PRESETS.get(i) {
+            self.size_error = None;
+            self.edit_settings(|settings| settings.apply_rule_preset(preset));
+        }
+    }
+
+    fn paste_settings(&mut self) {
+        let is_host = match self.state {
+            GameState::InGame(ref conn_state) => conn_state.state.read().expect("Failed to lock state").is_host(self.player_id),
+            _ => false,
+        };
+        if !is_host {
+            self.toasts.push("Only the host can paste in settings");
+            return;
+        }
+        let window = web_sys::window().unwrap_throw();
+        let code = match window.prompt_with_message("Paste a settings code from Copy Settings").ok().flatten() {
+            Some(code) => code,
+            None => return,
+        };
+        match BoardSettings::from_share_code(&code) {
+            Ok(pasted) => {
+                self.size_error = None;
+                self.edit_settings(|settings| {
+                    // this lobby's seed stays, and so does its version, which goes up once this is sent
+                    *settings = BoardSettings {
+                        seed: settings.seed,
+                        version: settings.version,
+                        ..pasted
+                    };
+                });
+            }
+            Err(err) => self.toasts.push(err),
+        }
+    }
+
+    fn answer_join_request(&mut self, accept: bool) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let player = match conn_state.sender.take_join_request() {
+                Some(player) => player,
+                None => return,
+            };
+            if !accept {
+                conn_state.sender.send(Message::JoinDeclined(player.id));
+                return;
+            }
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            if let NetGameState::Active(ref mut board) = *state {
+                if board.admit_player(player) {
+                    // they haven't seen any of the game yet, so they get all of it
+                    conn_state.sender.send(Message::State(state.clone()));
+                }
+            }
+        }
+    }
+
+    fn set_score_limit(&mut self, score_limit: u8) {
+        self.edit_settings(|settings| settings.score_limit = score_limit);
+    }
+
+    fn set_turn_time_limit(&mut self, limit: u16) {
+        self.edit_settings(|settings| settings.turn_time_limit = limit);
+    }
+
+    fn set_wrap_tokens(&mut self, wrap_tokens: bool) {
+        self.edit_settings(|settings| settings.wrap_tokens = wrap_tokens);
+    }
+
+    fn set_points_scoring(&mut self, points: bool) {
+        let scoring = if points { Scoring::Points } else { Scoring::Targets };
+        self.edit_settings(|settings| {
+            settings.scoring = scoring;
+            // a points game's limit can be far past what anyone could collect in targets
+            settings.score_limit = settings.score_limit.min(settings.max_score_limit());
+        });
+    }
+
+    fn set_target_steal(&mut self, target_steal: bool) {
+        self.edit_settings(|settings| settings.target_steal = target_steal);
+    }
+
+    fn set_double_push(&mut self, double_push: bool) {
+        self.edit_settings(|settings| settings.double_push = double_push);
+    }
+
+    fn set_portals(&mut self, portals: bool) {
+        self.edit_settings(|settings| settings.portals = portals);
+    }
+
+    fn set_speed_bonus(&mut self, speed_bonus: u16) {
+        self.edit_settings(|settings| settings.speed_bonus = speed_bonus);
+    }
+
+    fn set_energy_per_turn(&mut self, energy_per_turn: u8) {
+        self.edit_settings(|settings| settings.energy_per_turn = energy_per_turn);
+    }
+
+    fn set_min_target_distance(&mut self, distance: u8) {
+        self.edit_settings(|settings| settings.min_target_distance = distance);
+    }
+
+    fn set_max_target_distance(&mut self, distance: u8) {
+        self.edit_settings(|settings| settings.max_target_distance = distance);
+    }
+
+    fn set_hint_limit(&mut self, hint_limit: u8) {
+        self.edit_settings(|settings| settings.hint_limit = hint_limit);
+    }
+
+    fn set_series_length(&mut self, series_length: u8) {
+        let series_length = series_length.clamp(1, series::MAX_SERIES_LENGTH);
+        self.edit_settings(|settings| settings.series_length = series_length);
+    }
+
+    fn set_collapse_every(&mut self, collapse_every: u16) {
+        self.edit_settings(|settings| settings.collapse_every = collapse_every);
+    }
+
+    fn set_fog_distance(&mut self, fog_distance: u8) {
+        self.edit_settings(|settings| settings.fog_distance = fog_distance);
+    }
+
+    fn set_party(&mut self, party: bool) {
+        self.edit_settings(|settings| settings.party = party);
+    }
+
+    fn set_share_theme(&mut self, share: bool) {
+        let theme = if share {
+            Some(colors::pick_theme(&options::HANDLE.fetch().theme).name.to_string())
+        } else {
+            None
+        };
+        self.edit_settings(|settings| settings.theme = theme);
+    }
+
+    fn set_music_level(&mut self, level: u8) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.music_level = level;
+            self.sound_engine.poke_options(opts);
+        }
     }
 
-    fn set_music_level(&mut self, slider: web_sys::HtmlInputElement) {
+    fn set_sound_level(&mut self, level: u8) {
         if let GameState::Options(ref mut opts) = self.state {
-            let val = slider.value();
-            opts.music_level = val.parse().unwrap_throw();
+            opts.sound_level = level;
             self.sound_engine.poke_options(opts);
         }
     }
 
-    fn set_sound_level(&mut self, slider: web_sys::HtmlInputElement) {
+    fn set_ambience_level(&mut self, level: u8) {
         if let GameState::Options(ref mut opts) = self.state {
-            let val = slider.value();
-            opts.sound_level = val.parse().unwrap_throw();
+            opts.ambience_level = level;
             self.sound_engine.poke_options(opts);
         }
     }
 
+    fn set_rotate_assist(&mut self, rotate_assist: bool) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.rotate_assist = rotate_assist;
+        }
+    }
+
+    fn set_move_history(&mut self, move_history: bool) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.move_history = move_history;
+        }
+    }
+
+    fn set_skip_confirmations(&mut self, skip: bool) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.skip_confirmations = skip;
+        }
+    }
+
+    fn set_theme(&mut self, theme: String) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.theme = theme;
+        }
+    }
+
+    fn set_webhook_url(&mut self, webhook_url: String) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.webhook_url = webhook_url;
+        }
+    }
+
+    fn set_tutorial_narration(&mut self, narration: options::Narration) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.tutorial_narration = narration;
+        }
+    }
+
+    fn set_board_rotation(&mut self, rotation: options::BoardRotation) {
+        if let GameState::Options(ref mut opts) = self.state {
+            opts.board_rotation = rotation;
+        }
+    }
+
     fn save_options(&mut self) {
         if let GameState::Options(ref opts) = self.state {
             options::HANDLE.save(opts);
@@ -179,36 +832,27 @@ impl GameController {
         }
     }
 
-    fn set_name(&mut self, name_field: web_sys::HtmlInputElement, id: PlayerID) {
+    fn set_name(&mut self, id: PlayerID, name: String) {
         if let GameState::InGame(ref mut conn_state) = self.state {
             let sender = &mut conn_state.sender;
             let state = &mut conn_state.state;
             let mut state = state.write().expect("Failed to lock state");
             if let NetGameState::Lobby(ref mut info) = *state {
                 let player = info.player_mut(&id);
-                let new_name = name_field.value();
-                player.name = new_name;
+                player.name = name;
                 let message = Message::EditPlayer(id, player.clone());
                 sender.send(message);
             }
         }
     }
 
-    fn set_color(&mut self, color_field: web_sys::HtmlInputElement, id: PlayerID) {
+    fn set_color(&mut self, id: PlayerID, color: Color) {
         if let GameState::InGame(ref mut conn_state) = self.state {
             let sender = &mut conn_state.sender;
             let state = &mut conn_state.state;
             let mut state = state.write().expect("Failed to lock state");
             if let NetGameState::Lobby(ref mut info) = *state {
                 let player = info.player_mut(&id);
-                let color = color_field.value();
-                let color_r = u8::from_str_radix(&color[1..3], 16).unwrap_throw();
-                let color_g = u8::from_str_radix(&color[3..5], 16).unwrap_throw();
-                let color_b = u8::from_str_radix(&color[5..7], 16).unwrap_throw();
-                let color_r = color_r as f32 / 255.0;
-                let color_g = color_g as f32 / 255.0;
-                let color_b = color_b as f32 / 255.0;
-                let color = Color(color_r, color_g, color_b);
                 player.color = color;
                 let message = Message::EditPlayer(id, player.clone());
                 sender.send(message);
@@ -224,7 +868,8 @@ impl GameController {
             let is_host = state.is_host(self.player_id);
             if let NetGameState::Lobby(ref mut info) = *state {
                 let me = info.player(&self.player_id);
-                let child = Player::new_child(format!("{} - Copy", me.name), random(), random(), me.id);
+                let child_id = info.fresh_player_id();
+                let child = Player::new_child(format!("{} - Copy", me.name), trace::random(), child_id, me.id);
                 info.guests.push(child.clone());
                 if is_host {
                     drop(state);
@@ -236,6 +881,60 @@ impl GameController {
         }
     }
 
+    fn new_bot_player(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let mut state = state.write().expect("Failed to lock state");
+            // computer players take their turns on the host, so only the host can bring them in
+            if !state.is_host(self.player_id) {
+                return;
+            }
+            if let NetGameState::Lobby(ref mut info) = *state {
+                let count = info.guests.iter().filter(|p| p.bot.is_some()).count();
+                let bot_id = info.fresh_player_id();
+                let bot = Player::new_bot(
+                    format!("Computer {}", count + 1),
+                    trace::random(),
+                    bot_id,
+                    info.host.id,
+                    ai::Difficulty::default(),
+                );
+                info.guests.push(bot);
+                drop(state);
+                self.broadcast_state();
+            }
+        }
+    }
+
+    fn cycle_bot_difficulty(&mut self, id: PlayerID) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let sender = &mut conn_state.sender;
+            let state = &mut conn_state.state;
+            let mut state = state.write().expect("Failed to lock state");
+            if let NetGameState::Lobby(ref mut info) = *state {
+                let player = info.player_mut(&id);
+                if let Some(difficulty) = player.bot {
+                    player.bot = Some(difficulty.next());
+                    let message = Message::EditPlayer(id, player.clone());
+                    sender.send(message);
+                }
+            }
+        }
+    }
+
+    fn remove_local_player(&mut self, id: PlayerID) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let mut state = state.write().expect("Failed to lock state");
+            if let NetGameState::Lobby(ref mut info) = *state {
+                // only copies can go, the player the connection belongs to leaves with the main menu
+                if id != self.player_id && info.remove_player(id) {
+                    conn_state.sender.send(Message::LeaveLobby(id));
+                }
+            }
+        }
+    }
+
     fn start_hosted_game(&mut self) {
         if let GameState::InGame(ref mut conn_state) = self.state {
             let state = &mut conn_state.state;
@@ -245,24 +944,193 @@ impl GameController {
                 if is_host {
                     let players = info.players_cloned();
                     let settings = info.settings.clone();
+                    options::save_lobby_settings(&settings);
                     let board_controller = BoardController::new(settings, players, info.host.id);
                     let net_state = NetGameState::Active(board_controller);
                     *state = net_state;
                     drop(state);
+                    conn_state.sender.mark_started();
                     self.broadcast_state();
                 }
             }
         }
     }
 
+    fn next_board(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let mut state = state.write().expect("Failed to lock state");
+            let is_host = state.is_host(self.player_id);
+            if let NetGameState::GameOver(ref info) = *state {
+                let series = match info.series {
+                    Some(ref series) if is_host && !series.is_over() => series.clone(),
+                    _ => return,
+                };
+                let final_board = &info.final_board;
+                let mut settings = info.next_settings.clone().unwrap_or_else(|| (*final_board.settings).clone());
+                // the same seed every board would make for the same board every time
+                settings.seed = settings.seed.map(|seed| seed.wrapping_add(u64::from(series.boards_played)));
+                let players = final_board
+                    .turn_order
+                    .iter()
+                    .filter_map(|id| final_board.players.get(id).cloned())
+                    .collect();
+                let mut board_controller = BoardController::new(settings, players, info.host_id);
+                board_controller.series = Some(series);
+                *state = NetGameState::Active(board_controller);
+                drop(state);
+                self.view.review_turn = None;
+                self.broadcast_state();
+            }
+        }
+    }
+
+    fn series_ready(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            if let NetGameState::GameOver(ref mut info) = *state {
+                if let Some(ref mut series) = info.series {
+                    series.ready.insert(self.player_id);
+                    conn_state.sender.send(Message::SeriesReady(self.player_id));
+                }
+            }
+        }
+    }
+
     fn main_menu(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            conn_state.sender.leave();
+        }
         self.sound_engine.fetch_volume();
+        self.view.review_turn = None;
+        self.spectating = false;
+        self.confirming = None;
+        self.player_id = self.own_id;
         self.state = GameState::MainMenu;
     }
 
-    /// Handles tick
-    pub fn on_tick(&mut self, dt: f64) {
-        anim::STATE.write().unwrap().advance_by(dt);
+    fn resync(&mut self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            conn_state.sender.resync();
+        }
+    }
+
+    fn quick_chat(&mut self, phrase: usize) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            if let NetGameState::Active(ref mut board) = *state {
+                board.quick_chat(self.player_id, phrase);
+            }
+        }
+        self.send_board_outbox();
+    }
+
+    fn review(&mut self, delta: isize) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let state = conn_state.state.read().expect("Failed to lock state");
+            if let NetGameState::GameOver(ref info) = *state {
+                let turns = info.final_board.log.turn_count();
+                let turn = self.view.review_turn(&info.final_board) as isize + delta;
+                let turn = turn.max(0) as usize;
+                self.view.review_turn = Some(turn.min(turns));
+            }
+        }
+    }
+
+    /// Handles a UI event
+    pub fn handle_event(&mut self, event: UiEvent) {
+        match event {
+            UiEvent::AskFirst(event) => self.ask_first(*event),
+            UiEvent::Confirm => self.confirm(),
+            UiEvent::CancelConfirm => self.confirming = None,
+            UiEvent::Continue => self.continue_last(),
+            UiEvent::Tutorial => self.tutorial(),
+            UiEvent::Sandbox => self.sandbox(),
+            UiEvent::LocalGame => self.local_game(),
+            UiEvent::Host => self.host(),
+            UiEvent::Connect => self.connect(),
+            UiEvent::EnterOptions => self.enter_options(),
+            UiEvent::DoConnect(game) => self.do_connect(game),
+            UiEvent::SetWidth(width) => self.set_width(width),
+            UiEvent::SetHeight(height) => self.set_height(height),
+            UiEvent::SetSize(width, height) => self.set_size(width, height),
+            UiEvent::SetRulePreset(i) => self.set_rule_preset(i),
+            UiEvent::SetScoreLimit(score_limit) => self.set_score_limit(score_limit),
+            UiEvent::SetHintLimit(hint_limit) => self.set_hint_limit(hint_limit),
+            UiEvent::SetSeriesLength(series_length) => self.set_series_length(series_length),
+            UiEvent::SetCollapseEvery(collapse_every) => self.set_collapse_every(collapse_every),
+            UiEvent::SetFogDistance(fog_distance) => self.set_fog_distance(fog_distance),
+            UiEvent::SetParty(party) => self.set_party(party),
+            UiEvent::SetShareTheme(share) => self.set_share_theme(share),
+            UiEvent::SetTurnTimeLimit(limit) => self.set_turn_time_limit(limit),
+            UiEvent::SetWrapTokens(wrap_tokens) => self.set_wrap_tokens(wrap_tokens),
+            UiEvent::SetPointsScoring(points) => self.set_points_scoring(points),
+            UiEvent::SetTargetSteal(target_steal) => self.set_target_steal(target_steal),
+            UiEvent::SetDoublePush(double_push) => self.set_double_push(double_push),
+            UiEvent::SetPortals(portals) => self.set_portals(portals),
+            UiEvent::SetSpeedBonus(speed_bonus) => self.set_speed_bonus(speed_bonus),
+            UiEvent::SetEnergyPerTurn(energy_per_turn) => self.set_energy_per_turn(energy_per_turn),
+            UiEvent::SetMinTargetDistance(distance) => self.set_min_target_distance(distance),
+            UiEvent::SetMaxTargetDistance(distance) => self.set_max_target_distance(distance),
+            UiEvent::SetMusicLevel(level) => self.set_music_level(level),
+            UiEvent::SetSoundLevel(level) => self.set_sound_level(level),
+            UiEvent::SetAmbienceLevel(level) => self.set_ambience_level(level),
+            UiEvent::SetRotateAssist(rotate_assist) => self.set_rotate_assist(rotate_assist),
+            UiEvent::SetMoveHistory(move_history) => self.set_move_history(move_history),
+            UiEvent::SetSkipConfirmations(skip) => self.set_skip_confirmations(skip),
+            UiEvent::SetTheme(theme) => self.set_theme(theme),
+            UiEvent::SetTutorialNarration(narration) => self.set_tutorial_narration(narration),
+            UiEvent::SetBoardRotation(rotation) => self.set_board_rotation(rotation),
+            UiEvent::SetWebhookUrl(webhook_url) => self.set_webhook_url(webhook_url),
+            UiEvent::SaveOptions => self.save_options(),
+            UiEvent::SetName(id, name) => self.set_name(id, name),
+            UiEvent::SetColor(id, color) => self.set_color(id, color),
+            UiEvent::NewLocalPlayer => self.new_local_player(),
+            UiEvent::NewBotPlayer => self.new_bot_player(),
+            UiEvent::CycleBotDifficulty(id) => self.cycle_bot_difficulty(id),
+            UiEvent::RemoveLocalPlayer(id) => self.remove_local_player(id),
+            UiEvent::PasteSettings => self.paste_settings(),
+            UiEvent::AcceptJoin => self.answer_join_request(true),
+            UiEvent::DeclineJoin => self.answer_join_request(false),
+            UiEvent::StartHostedGame => self.start_hosted_game(),
+            UiEvent::MainMenu => self.main_menu(),
+            UiEvent::RetryConnect => self.retry_connect(),
+            UiEvent::JoinAnyway => self.join_anyway(),
+            UiEvent::Review(delta) => self.review(delta),
+            UiEvent::NextBoard => self.next_board(),
+            UiEvent::SeriesReady => self.series_ready(),
+            UiEvent::Resync => self.resync(),
+            UiEvent::ToggleRules => self.show_rules = !self.show_rules,
+            UiEvent::Hint => self.on_input(|_| Some(InputAction::Hint)),
+            UiEvent::QuickChat(phrase) => self.quick_chat(phrase),
+            UiEvent::KeepLobbyOpen => self.keep_lobby_open(),
+            UiEvent::CycleInputSource(id) => {
+                let source = self.input_sources.entry(id).or_insert(InputSource::Any);
+                *source = source.next();
+            }
+        }
+    }
+
+    /// Handles tick, telling the page the game's mounted in about anything that happened
+    pub fn on_tick(&mut self, dt: f64, main: &web_sys::Element) {
+        self.advance_anims(dt);
+
+        // the camera only moves around a finished game, and starts centered every time
+        if !self.is_game_over() {
+            self.view.board_view.camera = Default::default();
+        }
+
+        let theme = self.current_theme();
+        if self.view.board_view.settings.theme != theme.name {
+            self.view.board_view.settings.apply_theme(theme);
+        }
+        self.view.board_view.rotation = options::HANDLE.fetch().board_rotation;
+
+        if let GameState::InGame(ref conn_state) = self.state {
+            // the host may have sent us off to find a different ID
+            self.player_id = conn_state.sender.player_id();
+            self.view.board_view.latencies = conn_state.sender.latencies();
+        }
 
         let old_last_player = self.last_player;
 
@@ -278,7 +1146,12 @@ impl GameController {
                 let state = conn_state.state.read().unwrap();
                 match *state {
                     NetGameState::Active(ref board) => {
-                        self.last_player = Some(board.active_player_id());
+                        // in party mode everyone plans at once, so nobody's turn comes up
+                        self.last_player = if board.is_party() {
+                            None
+                        } else {
+                            Some(board.active_player_id())
+                        };
                         sound::Music::InGame
                     }
                     _ => {
@@ -293,24 +1166,528 @@ impl GameController {
         if old_last_player != self.last_player && self.last_player == Some(self.player_id) {
             self.sound_engine.play_sound(sound::Sound::YourTurn);
         }
+        self.narrate_tutorial();
+        let old_heard_events = self.heard_events;
+        self.play_event_sounds();
+        if self.heard_events != old_heard_events {
+            self.save_local_game();
+        }
 
-        // drain one action at a time
-        let action = {
-            let mut actions = self.actions.lock().unwrap();
-            actions.pop()
-        };
-        if let Some(action) = action {
-            action(self);
+        self.tick_turn_timer();
+        self.tick_party();
+        self.skip_away_players();
+        self.play_bots();
+        self.time_turns();
+        self.resync_spectator();
+        self.close_idle_lobby();
+        self.report_finished_game();
+        self.remember_players();
+        self.poll_gamepads();
+
+        self.handle_queued_events();
+        let edited = self.settings_dirty;
+        if self.settings_dirty && crate::now() - self.settings_sent_at >= SETTINGS_SEND_INTERVAL {
+            self.send_settings();
         }
+        self.check_lobby_query();
+        self.watch_for_toasts(edited);
+        self.notify_page(main);
 
+        self.send_board_outbox();
         if let GameState::InGame(ref state) = self.state {
             state.sender.drain_queue();
         }
     }
 
+    /// Handles everything that happened in the DOM since the last tick, within reason
+    fn handle_queued_events(&mut self) {
+        for event in self.event_queue.try_iter().take(MAX_EVENTS_PER_TICK).collect::<Vec<_>>() {
+            self.handle_event(event);
+        }
+    }
+
+    /// Moves along the animations on the board in play, and on the draft being planned if there is one
+    fn advance_anims(&mut self, dt: f64) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            if let NetGameState::Active(ref mut board) = *state {
+                board.anim.advance_by(dt);
+                if let Some(ref mut draft) = self.party.draft {
+                    draft.board.anim.advance_by(dt);
+                    draft.board.anim.follow(&board.anim);
+                }
+            }
+        }
+    }
+
+    /// Handles a periodic task coming due
+    pub fn on_task(&mut self, task: Task) {
+        match task {
+            Task::Digest => self.send_digest(),
+        }
+    }
+
+    /// Puts up toasts for anything that happened in the game since last tick
+    fn watch_for_toasts(&mut self, edited: bool) {
+        self.toasts.expire();
+        match self.state {
+            GameState::InGame(ref conn_state) => {
+                let state = conn_state.state.read().expect("Failed to lock state");
+                self.toasts.watch(Some(&state), self.player_id, edited);
+                if let Some(warning) = conn_state.sender.take_traffic_warning() {
+                    diagnostics::record("traffic", warning.clone());
+                    self.toasts.push(warning);
+                }
+            }
+            _ => self.toasts.watch(None, self.player_id, edited),
+        }
+    }
+
+    /// Gets the theme to draw the board in, which is the host's if they're making everyone use it,
+    /// or the one picked in Options (or the one being picked, to try it out)
+    fn current_theme(&self) -> &'static Theme {
+        let shared = match self.state {
+            GameState::InGame(ref conn_state) => {
+                let state = conn_state.state.read().expect("Failed to lock state");
+                match *state {
+                    NetGameState::Lobby(ref info) => info.settings.theme.clone(),
+                    NetGameState::Active(ref board) => board.settings.theme.clone(),
+                    NetGameState::GameOver(ref info) => info.final_board.settings.theme.clone(),
+                    NetGameState::Connecting | NetGameState::Error(_) => None,
+                }
+            }
+            GameState::Options(ref opts) => Some(opts.theme.clone()),
+            _ => None,
+        };
+        match shared {
+            Some(name) => colors::pick_theme(&name),
+            None => colors::pick_theme(&options::HANDLE.fetch().theme),
+        }
+    }
+
+    /// Plays the tune for a tutorial step as it starts, and reads out its caption if the options say to
+    fn narrate_tutorial(&mut self) {
+        let step = match self.state {
+            GameState::InGame(ref conn_state) => match *conn_state.state.read().expect("Failed to lock state") {
+                NetGameState::Active(ref board) => board.board.tutorial_step,
+                _ => None,
+            },
+            _ => None,
+        };
+        if step == self.narrated_step {
+            return;
+        }
+        self.narrated_step = step;
+        let step = match step {
+            Some(step) => step,
+            None => return,
+        };
+        match options::HANDLE.fetch().tutorial_narration {
+            options::Narration::Off => (),
+            options::Narration::Stingers => self.sound_engine.play_sound(sound::Sound::Tutorial(step)),
+            options::Narration::Voice => {
+                self.sound_engine.play_sound(sound::Sound::Tutorial(step));
+                self.sound_engine.speak(step.text());
+            }
+        }
+    }
+
+    /// Plays the sounds for tiles being inserted and tokens moving since the last tick
+    fn play_event_sounds(&mut self) {
+        let mut heard_events = None;
+        if let GameState::InGame(ref conn_state) = self.state {
+            let state = conn_state.state.read().expect("Failed to lock state");
+            if let NetGameState::Active(ref board) = *state {
+                // when joining or starting a game, there's nothing new to hear yet
+                let events = self.heard_events.and_then(|heard| board.log.events_since(heard)).unwrap_or(&[]);
+                for event in events {
+                    match event {
+                        GameEvent::Insert { .. } => self.sound_engine.play_sound(sound::Sound::Insert),
+                        GameEvent::Move { from, to, .. } if from != to => {
+                            self.sound_engine.play_sound(sound::Sound::Move)
+                        }
+                        _ => (),
+                    }
+                }
+                heard_events = Some(board.log.event_count());
+            }
+        }
+        self.heard_events = heard_events;
+    }
+
+    /// Saves a local game after every move, so it can be picked back up from the main menu if it's
+    /// left, and forgets it once it's over
+    fn save_local_game(&self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            // the demo's board isn't anybody's game to continue
+            if conn_state.sender.is_online() || demo::is_demo() {
+                return;
+            }
+            let state = conn_state.state.read().expect("Failed to lock state");
+            match *state {
+                NetGameState::Active(ref board) => {
+                    let practice = board.board.tutorial_step.is_some() || board.settings.sandbox;
+                    if !practice && board.winner().is_none() {
+                        options::save_local_game(board);
+                    }
+                }
+                NetGameState::GameOver(_) => {
+                    if let Some(options::Continue::LocalGame) = options::continue_target() {
+                        options::clear_continue_target();
+                    }
+                }
+                NetGameState::Connecting | NetGameState::Lobby(_) | NetGameState::Error(_) => (),
+            }
+        }
+    }
+
+    fn tick_turn_timer(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let (broadcast, new_net_state) = {
+                let mut state = state.write().expect("Failed to lock state");
+                let is_host = state.is_host(self.player_id);
+                if let NetGameState::Active(ref mut board_controller) = *state {
+                    let expired = {
+                        let timer = &mut board_controller.anim.turn_timer;
+                        match board_controller.party.as_deref() {
+                            // rounds of party mode are timed as a whole, while everyone plans
+                            Some(round) if round.is_resolving() => timer.reset(),
+                            Some(round) => timer.start(
+                                round.round as usize,
+                                party::planning_time(&board_controller.settings),
+                            ),
+                            None => timer.start(
+                                board_controller.log.turn_count(),
+                                board_controller.settings.turn_time_limit,
+                            ),
+                        }
+                        if timer.tick_due() {
+                            self.sound_engine.play_sound(sound::Sound::TimerTick);
+                        }
+                        let expired = timer.expired();
+                        if expired {
+                            timer.stop();
+                        }
+                        expired
+                    };
+                    if expired {
+                        self.sound_engine.play_sound(sound::Sound::TimerBuzzer);
+                    }
+                    if expired && board_controller.is_party() {
+                        // anyone who hasn't planned by now just doesn't get a turn this round
+                        let started = is_host && board_controller.start_resolving();
+                        (started, None)
+                    } else if expired && board_controller.local_turn(self.player_id) {
+                        // only the client whose turn ran out finishes it, so it only happens once
+                        board_controller.auto_complete_turn();
+                        let info = GameOverInfo::for_finished_game(board_controller);
+                        (true, info.map(NetGameState::GameOver))
+                    } else {
+                        (false, None)
+                    }
+                } else {
+                    (false, None)
+                }
+            };
+            if let Some(ns) = new_net_state {
+                let mut state = state.write().expect("Failed to lock state");
+                *state = ns;
+            }
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
+    /// Keeps a draft going for whichever local player still has to plan their party mode turn,
+    /// hands in finished plans, and has the host play the plans out once they're in
+    fn tick_party(&mut self) {
+        let conn_state = match self.state {
+            GameState::InGame(ref conn_state) => conn_state,
+            _ => {
+                self.party = LocalPlanning::default();
+                return;
+            }
+        };
+        let (broadcast, new_net_state) = {
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            let is_host = state.is_host(self.player_id);
+            let board = match *state {
+                NetGameState::Active(ref mut board) if board.is_party() => board,
+                _ => {
+                    self.party = LocalPlanning::default();
+                    return;
+                }
+            };
+            let (round, resolving) = match board.party.as_deref() {
+                Some(party) => (party.round, party.is_resolving()),
+                None => return,
+            };
+            let planning = &mut self.party;
+            if resolving {
+                planning.draft = None;
+                let now = crate::now();
+                if is_host && now - planning.step_at >= party::RESOLVE_STEP {
+                    planning.step_at = now;
+                    let stepped = board.resolve_step();
+                    let info = GameOverInfo::for_finished_game(board);
+                    (stepped, info.map(NetGameState::GameOver))
+                } else {
+                    (false, None)
+                }
+            } else {
+                let mut broadcast = false;
+                let finished = match planning.draft {
+                    Some(ref draft) if draft.round == round => {
+                        draft.finished_plan().map(|plan| (draft.planner(), plan))
+                    }
+                    _ => None,
+                };
+                if let Some((id, plan)) = finished {
+                    planning.draft = None;
+                    planning.sent.insert(id, round);
+                    board.submit_plan(id, round, plan.clone());
+                    // the host hands out the whole state anyway, so only guests need to send the plan
+                    if is_host {
+                        broadcast = true;
+                    } else {
+                        conn_state.sender.send(Message::Plan(id, round, plan));
+                    }
+                }
+                // local players plan one at a time, in turn order
+                let player_id = self.player_id;
+                let planner = board.turn_order.iter().cloned().find(|id| {
+                    let sent = planning.sent.get(id) == Some(&round);
+                    // someone who just left can still be in the turn order for a moment
+                    let here = matches!(board.players.get(id), Some(player) if player.played_from(player_id));
+                    here && !sent
+                });
+                let current = planning.draft.as_ref().map(|draft| (draft.round, draft.planner()));
+                match planner {
+                    Some(id) if current != Some((round, id)) => {
+                        planning.draft = Some(Draft::new(board, round, id));
+                    }
+                    Some(_) => (),
+                    None => planning.draft = None,
+                }
+                if is_host && board.ready_to_resolve() {
+                    planning.step_at = crate::now();
+                    broadcast |= board.start_resolving();
+                }
+                (broadcast, None)
+            }
+        };
+        if let Some(ns) = new_net_state {
+            let mut state = conn_state.state.write().expect("Failed to lock state");
+            *state = ns;
+        }
+        if broadcast {
+            self.broadcast_state();
+        }
+    }
+
+    fn send_digest(&self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let state = conn_state.state.read().expect("Failed to lock state");
+            if let NetGameState::Active(ref board_controller) = *state {
+                if state.is_host(self.player_id) {
+                    // spectators only get the state once a turn, so they'd look out of sync in between
+                    let players = board_controller.peer_ids();
+                    conn_state.sender.send_to(players, Message::StateDigest(board_controller.digest()));
+                }
+            }
+        }
+    }
+
+    /// Times turns on the host, so quick ones count toward a speed bonus
+    fn time_turns(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let broadcast = {
+                let mut state = conn_state.state.write().expect("Failed to lock state");
+                let is_host = state.is_host(self.player_id);
+                let (changed, new_net_state) = match *state {
+                    NetGameState::Active(ref mut board_controller) if is_host => {
+                        let turn = board_controller.log.turn_count();
+                        let now = crate::now();
+                        let changed = match self.turn_clock {
+                            // if several turns went by since the last look, there's no telling how long each took
+                            Some((timed, started)) if timed + 1 == turn => {
+                                board_controller.finish_timed_turn(now - started)
+                            }
+                            _ => false,
+                        };
+                        if !matches!(self.turn_clock, Some((timed, _)) if timed == turn) {
+                            self.turn_clock = Some((turn, now));
+                        }
+                        if changed {
+                            let info = GameOverInfo::for_finished_game(board_controller);
+                            (true, info.map(NetGameState::GameOver))
+                        } else {
+                            (false, None)
+                        }
+                    }
+                    _ => {
+                        self.turn_clock = None;
+                        (false, None)
+                    }
+                };
+                if let Some(ns) = new_net_state {
+                    *state = ns;
+                }
+                changed
+            };
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
+    /// Asks the host for the whole state if we're spectating and fall out of sync,
+    /// since there's no resync button on screen to click
+    fn resync_spectator(&self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            if self.spectating && conn_state.sender.desynced() {
+                conn_state.sender.resync();
+            }
+        }
+    }
+
+    fn keep_lobby_open(&mut self) {
+        self.lonely_since = self.lonely_since.map(|_| crate::now());
+        // the server has its own idea of how long the lobby's been idle
+        if let GameState::InGame(ref conn_state) = self.state {
+            conn_state.sender.keep_alive();
+        }
+    }
+
+    fn close_idle_lobby(&mut self) {
+        let alone = match self.state {
+            // a lobby only on this screen isn't taking up room anywhere else
+            GameState::InGame(ref conn_state) if !conn_state.sender.is_online() => false,
+            GameState::InGame(ref conn_state) => {
+                let state = conn_state.state.read().expect("Failed to lock state");
+                match *state {
+                    // local players don't count, since they're at the same screen as the host
+                    NetGameState::Lobby(ref info) => {
+                        info.host.id == self.player_id
+                            && info.guests.iter().all(|p| p.lives_with(self.player_id))
+                    }
+                    _ => false,
+                }
+            }
+            _ => false,
+        };
+        if !alone {
+            self.lonely_since = None;
+            return;
+        }
+        let now = crate::now();
+        let since = *self.lonely_since.get_or_insert(now);
+        if now - since >= LOBBY_IDLE_TIMEOUT {
+            // going back to the main menu leaves, and the server forgets games nobody's in
+            self.lonely_since = None;
+            self.main_menu();
+        }
+    }
+
+    /// Gets how many seconds are left before the idle lobby we're hosting closes, once it's close
+    fn idle_countdown(&self) -> Option<f64> {
+        let since = self.lonely_since?;
+        let left = LOBBY_IDLE_TIMEOUT - (crate::now() - since);
+        if left <= LOBBY_IDLE_COUNTDOWN {
+            Some(left.max(0.0))
+        } else {
+            None
+        }
+    }
+
+    fn skip_away_players(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let broadcast = {
+                let mut state = state.write().expect("Failed to lock state");
+                // only the host hands off turns, so a dropout only gets skipped once
+                let is_host = state.is_host(self.player_id);
+                let (skipped, new_net_state) = match *state {
+                    NetGameState::Active(ref mut board_controller) if is_host => {
+                        // not short-circuiting, so a skip can happen on the same tick as a retirement
+                        if board_controller.retire_away_players() | board_controller.skip_away_player() {
+                            let info = GameOverInfo::for_finished_game(board_controller);
+                            (true, info.map(NetGameState::GameOver))
+                        } else {
+                            (false, None)
+                        }
+                    }
+                    _ => (false, None),
+                };
+                if let Some(ns) = new_net_state {
+                    *state = ns;
+                }
+                skipped
+            };
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
+    /// Has computer players take their turns on the host, once they've had a moment to think
+    fn play_bots(&mut self) {
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let broadcast = {
+                let mut state = state.write().expect("Failed to lock state");
+                let is_host = state.is_host(self.player_id);
+                let (played, new_net_state) = match *state {
+                    NetGameState::Active(ref mut board_controller) if is_host => {
+                        // party mode plans are kept secret until the round plays out, so there's no show to put on
+                        if board_controller.plan_for_bots() {
+                            (true, None)
+                        } else if let Some(bot) = BotPlayer::up_next(board_controller) {
+                            let turn = board_controller.log.turn_count();
+                            let now = crate::now();
+                            let since = match self.bot_clock {
+                                Some((clock_turn, since)) if clock_turn == turn => since,
+                                _ => {
+                                    self.bot_clock = Some((turn, now));
+                                    now
+                                }
+                            };
+                            if now - since >= ai::THINK_TIME && board_controller.play_bot_turn(bot) {
+                                self.bot_clock = None;
+                                let info = GameOverInfo::for_finished_game(board_controller);
+                                (true, info.map(NetGameState::GameOver))
+                            } else {
+                                (false, None)
+                            }
+                        } else {
+                            (false, None)
+                        }
+                    }
+                    _ => (false, None),
+                };
+                if let Some(ns) = new_net_state {
+                    *state = ns;
+                }
+                played
+            };
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
     /// Handles click event
-    pub fn on_click(&mut self, event: &web_sys::MouseEvent, main: &web_sys::Element) {
+    pub fn on_click(&mut self, event: &web_sys::MouseEvent) {
         self.sound_engine.unpause();
+        // a party mode turn being planned stays on this screen until it's done
+        if let Some(ref mut draft) = self.party.draft {
+            if draft.board.on_click(event, self.player_id, &self.view.board_view) {
+                event.prevent_default();
+            }
+            return;
+        }
         if let GameState::InGame(ref mut conn_state) = self.state {
             let state = &mut conn_state.state;
             let (broadcast, new_state, new_net_state) = {
@@ -320,19 +1697,50 @@ impl GameController {
                         event,
                         self.player_id,
                         &self.view.board_view,
-                        &get_context(main).unwrap_throw(),
                     );
                     if state_dirty {
                         event.prevent_default();
-                        if let Some(winner) = board_controller.winner() {
-                            let info = GameOverInfo {
-                                winner: winner.clone(),
-                                host_id: board_controller.host_id,
-                            };
-                            (true, None, Some(NetGameState::GameOver(info)))
-                        } else {
-                            (true, None, None)
-                        }
+                        let info = GameOverInfo::for_finished_game(board_controller);
+                        (true, None, info.map(NetGameState::GameOver))
+                    } else {
+                        (false, None, None)
+                    }
+                } else {
+                    (false, None, None)
+                }
+            };
+            if let Some(ns) = new_net_state {
+                let mut state = state.write().expect("Failed to lock state");
+                *state = ns;
+            }
+            if let Some(s) = new_state {
+                self.state = s;
+            }
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
+    /// Handles mousedown event
+    pub fn on_mousedown(&mut self, event: &web_sys::MouseEvent) {
+        if let Some(ref mut draft) = self.party.draft {
+            draft.board.on_mousedown(event, self.player_id, &self.view.board_view);
+            return;
+        }
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let state = &mut conn_state.state;
+            let (broadcast, new_state, new_net_state) = {
+                let mut state = state.write().expect("Failed to lock state");
+                if let NetGameState::Active(ref mut board_controller) = *state {
+                    let state_dirty = board_controller.on_mousedown(
+                        event,
+                        self.player_id,
+                        &self.view.board_view,
+                    );
+                    if state_dirty {
+                        let info = GameOverInfo::for_finished_game(board_controller);
+                        (true, None, info.map(NetGameState::GameOver))
                     } else {
                         (false, None, None)
                     }
@@ -353,8 +1761,61 @@ impl GameController {
         }
     }
 
+    /// Handles wheel event
+    pub fn on_wheel(&mut self, event: &web_sys::WheelEvent) {
+        // only a finished game can be zoomed around, everything else scrolls like normal
+        if !self.is_game_over() {
+            return;
+        }
+        event.prevent_default();
+        let factor = (-event.delta_y() * WHEEL_ZOOM_RATE).exp();
+        let point = [f64::from(event.offset_x()), f64::from(event.offset_y())];
+        let size = self.view.board_view.size;
+        self.view.board_view.camera.zoom_at(factor, point, size);
+    }
+
+    fn is_game_over(&self) -> bool {
+        if let GameState::InGame(ref conn_state) = self.state {
+            if let NetGameState::GameOver(_) = *conn_state.state.read().expect("Failed to lock state") {
+                return true;
+            }
+        }
+        false
+    }
+
     /// Handles mousemove event
-    pub fn on_mousemove(&mut self, event: &web_sys::MouseEvent, main: &web_sys::Element) {
+    pub fn on_mousemove(&mut self, event: &web_sys::MouseEvent) {
+        self.on_mousemove_board(event);
+        // the cursor only means anything over the board, and the badge beside it follows it around there
+        let canvas = event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+            .filter(|canvas| canvas.id() != "board-preview");
+        let board_view = &mut self.view.board_view;
+        match canvas {
+            Some(canvas) => {
+                board_view.pointer = Some([event.offset_x() as f64, event.offset_y() as f64]);
+                canvas.style().set_property("cursor", board_view.cursor.css()).unwrap_throw();
+            }
+            None => {
+                board_view.pointer = None;
+                board_view.cursor = Cursor::Idle;
+            }
+        }
+    }
+
+    fn on_mousemove_board(&mut self, event: &web_sys::MouseEvent) {
+        self.view.board_view.cursor = Cursor::Idle;
+        if let Some(ref mut draft) = self.party.draft {
+            draft.board.on_mousemove(event, self.player_id, &mut self.view.board_view);
+            return;
+        }
+        // dragging with the left button held pans around a finished game
+        if self.is_game_over() && event.buttons() & 1 != 0 {
+            let movement = [f64::from(event.movement_x()), f64::from(event.movement_y())];
+            self.view.board_view.camera.drag(movement);
+            return;
+        }
         if let GameState::InGame(ref mut conn_state) = self.state {
             let state = &mut conn_state.state;
             let (broadcast, new_state, new_net_state) = {
@@ -363,19 +1824,11 @@ impl GameController {
                     let state_dirty = board_controller.on_mousemove(
                         event,
                         self.player_id,
-                        &self.view.board_view,
-                        &get_context(main).unwrap_throw(),
+                        &mut self.view.board_view,
                     );
                     if state_dirty {
-                        if let Some(winner) = board_controller.winner() {
-                            let info = GameOverInfo {
-                                winner: winner.clone(),
-                                host_id: board_controller.host_id,
-                            };
-                            (true, None, Some(NetGameState::GameOver(info)))
-                        } else {
-                            (true, None, None)
-                        }
+                        let info = GameOverInfo::for_finished_game(board_controller);
+                        (true, None, info.map(NetGameState::GameOver))
                     } else {
                         (false, None, None)
                     }
@@ -398,22 +1851,127 @@ impl GameController {
 
     /// Handles keydown event
     pub fn on_keydown(&mut self, event: &web_sys::KeyboardEvent, _main: &web_sys::Element) {
+        // spectators can't do anything but watch
+        if self.spectating {
+            return;
+        }
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            if event.code() == "KeyC" {
+                // purely local, so it works whether or not it's our turn
+                let board_view = &mut self.view.board_view;
+                board_view.coach_view = !board_view.coach_view;
+                return;
+            }
+            if event.code() == "KeyL" {
+                let board_view = &mut self.view.board_view;
+                board_view.legend = !board_view.legend;
+                return;
+            }
+            if event.code() == "KeyH" {
+                self.show_rules = !self.show_rules;
+                return;
+            }
+            // number keys pick quick-chat phrases, counting from 1
+            let phrase = event
+                .code()
+                .trim_start_matches("Digit")
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=anim::QUICK_CHAT_COUNT).contains(n));
+            if let Some(n) = phrase {
+                self.quick_chat(n - 1);
+                return;
+            }
+            if let NetGameState::GameOver(_) = *conn_state.state.read().expect("Failed to lock state") {
+                match event.code().as_str() {
+                    "ArrowLeft" | "KeyA" => self.events.send(UiEvent::Review(-1)).unwrap_throw(),
+                    "ArrowRight" | "KeyD" => self.events.send(UiEvent::Review(1)).unwrap_throw(),
+                    _ => {}
+                }
+                return;
+            }
+        }
+        let code = event.code();
+        self.on_input(|source| source.key_action(&code));
+    }
+
+    /// Lets the server know how a game we hosted ended, for its webhook and its replay archive
+    fn report_finished_game(&self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let state = conn_state.state.read().unwrap();
+            if let NetGameState::GameOver(ref info) = *state {
+                if info.host_id == self.player_id {
+                    let replay = serde_json::to_string(&info.final_board.log).unwrap_or_default();
+                    conn_state.sender.mark_finished(info.winner.name.clone(), info.scores(), replay);
+                }
+            }
+        }
+    }
+
+    /// Adds everyone from somewhere else in a finished game to the recent players list, once a game
+    fn remember_players(&mut self) {
+        let names: Vec<String> = match self.state {
+            GameState::InGame(ref conn_state) if !self.spectating => {
+                match *conn_state.state.read().unwrap() {
+                    NetGameState::GameOver(_) if self.remembered_game => return,
+                    NetGameState::GameOver(ref info) => info
+                        .final_board
+                        .players
+                        .values()
+                        .filter(|player| !player.lives_with(self.player_id) && player.bot.is_none())
+                        .map(|player| player.name.clone())
+                        .collect(),
+                    _ => {
+                        self.remembered_game = false;
+                        return;
+                    }
+                }
+            }
+            _ => {
+                self.remembered_game = false;
+                return;
+            }
+        };
+        options::remember_players(&names);
+        self.remembered_game = true;
+    }
+
+    /// Checks the gamepads for anything newly pressed
+    fn poll_gamepads(&mut self) {
+        if let GameState::InGame(_) = self.state {
+            for (gamepad, button) in self.gamepads.poll() {
+                self.on_input(|source| source.button_action(gamepad, button));
+            }
+        }
+    }
+
+    /// Handles a key or button press, which the given function turns into an action based on
+    /// the active player's input source
+    fn on_input<F: Fn(InputSource) -> Option<InputAction>>(&mut self, action_for: F) {
+        if let Some(ref mut draft) = self.party.draft {
+            let source = self.input_sources.get(&draft.planner());
+            if let Some(action) = action_for(source.cloned().unwrap_or(InputSource::Any)) {
+                draft.board.on_input(action, self.player_id);
+            }
+            return;
+        }
         if let GameState::InGame(ref mut conn_state) = self.state {
             let state = &mut conn_state.state;
             let (broadcast, new_state, new_net_state) = {
                 let mut state = state.write().expect("Failed to lock state");
-                if let NetGameState::Active(ref mut board_controller) = *state {
-                    let state_dirty = board_controller.on_keydown(event, self.player_id);
+                let input_sources = &self.input_sources;
+                let action = match *state {
+                    NetGameState::Active(ref board_controller) => {
+                        let source = input_sources.get(&board_controller.active_player_id());
+                        action_for(source.cloned().unwrap_or(InputSource::Any))
+                    }
+                    _ => None,
+                };
+                if let (NetGameState::Active(ref mut board_controller), Some(action)) = (&mut *state, action) {
+                    let state_dirty = board_controller.on_input(action, self.player_id);
                     if state_dirty {
-                        if let Some(winner) = board_controller.winner() {
-                            let info = GameOverInfo {
-                                winner: winner.clone(),
-                                host_id: board_controller.host_id,
-                            };
-                            (true, None, Some(NetGameState::GameOver(info)))
-                        } else {
-                            (true, None, None)
-                        }
+                        let info = GameOverInfo::for_finished_game(board_controller);
+                        (true, None, info.map(NetGameState::GameOver))
                     } else {
                         (false, None, None)
                     }
@@ -428,27 +1986,262 @@ impl GameController {
             if let Some(s) = new_state {
                 self.state = s;
             }
-            if broadcast {
-                self.broadcast_state();
+            if broadcast {
+                self.broadcast_state();
+            }
+        }
+    }
+
+    /// Fits the canvas, if there is one, to the window, with a canvas pixel for every device pixel
+    pub fn on_resize(&mut self, main: &web_sys::Element) {
+        let canvas = match main.query_selector(MAIN_CANVAS).unwrap_throw() {
+            Some(canvas) => canvas.dyn_into::<web_sys::HtmlCanvasElement>().unwrap_throw(),
+            None => return,
+        };
+        let window = web_sys::window().unwrap_throw();
+        let width = window.inner_width().unwrap_throw().as_f64().unwrap_throw();
+        let inner_height = window.inner_height().unwrap_throw().as_f64().unwrap_throw();
+        // anything above the canvas, like the review controls, takes space away from it
+        let height = (inner_height - canvas.offset_top() as f64).max(0.0);
+        let scale = window.device_pixel_ratio();
+        let pixel_width = (width * scale).round() as u32;
+        let pixel_height = (height * scale).round() as u32;
+        // resizing clears the canvas, so only do it when something actually changed
+        if canvas.width() != pixel_width {
+            canvas.set_width(pixel_width);
+        }
+        if canvas.height() != pixel_height {
+            canvas.set_height(pixel_height);
+        }
+        let style = canvas.style();
+        style.set_property("width", &format!("{}px", width)).unwrap_throw();
+        style.set_property("height", &format!("{}px", height)).unwrap_throw();
+        self.view.board_view.resize(width, height, scale);
+    }
+
+    /// Draw to the given element
+    pub fn draw(&mut self, main: &web_sys::Element) {
+        self.build_dom(main);
+        self.update_net_warning(main);
+        self.update_debug_overlay(main);
+        self.update_toasts(main);
+        self.update_join_request(main);
+        if let Some(ctx) = get_context(main) {
+            self.view.draw(self, &ctx);
+        }
+    }
+
+    /// Fires events from the element the game's mounted in when a game starts, a turn changes hands, or
+    /// a game ends, so the page can react to them
+    fn notify_page(&mut self, main: &web_sys::Element) {
+        let conn_state = match self.state {
+            GameState::InGame(ref conn_state) => conn_state,
+            _ => {
+                self.page_progress = embed::Progress::Idle;
+                return;
+            }
+        };
+        let state = conn_state.state.read().expect("Failed to lock state");
+        let progress = match *state {
+            NetGameState::Active(ref board) => {
+                let player = if board.is_party() { None } else { Some(board.active_player_id()) };
+                embed::Progress::Playing(board.log.turn_count(), player)
+            }
+            NetGameState::GameOver(_) => embed::Progress::Over,
+            _ => embed::Progress::Idle,
+        };
+        if progress == self.page_progress {
+            return;
+        }
+        let was_playing = matches!(self.page_progress, embed::Progress::Playing(..));
+        match (&*state, &progress) {
+            (NetGameState::Active(ref board), embed::Progress::Playing(turn, player)) => {
+                if !was_playing {
+                    let players: Vec<&str> = board.turn_order.iter().map(|id| board.players[id].name.as_str()).collect();
+                    embed::emit(main, "gamestart", &json!({ "players": players, "spectating": self.spectating }));
+                }
+                let up = player.and_then(|id| board.players.get(&id));
+                let name = up.map(|up| up.name.as_str());
+                let local = matches!(up, Some(up) if up.played_from(self.player_id));
+                embed::emit(main, "turn", &json!({ "turn": turn, "player": name, "local": local }));
+            }
+            (NetGameState::GameOver(ref info), _) if was_playing => {
+                embed::emit(main, "gameover", &json!({ "winner": info.winner.name, "scores": info.scores() }));
+            }
+            _ => (),
+        }
+        self.page_progress = progress;
+    }
+
+    fn broadcast_state(&mut self) {
+        self.send_board_outbox();
+        if let GameState::InGame(ref mut conn_state) = self.state {
+            let sender = &mut conn_state.sender;
+            let state = &mut conn_state.state;
+            let state = state.read().expect("Failed to lock state");
+            let message = Message::State(state.for_broadcast());
+            // spectators don't need every move, so mid-turn updates only go to the players
+            if let NetGameState::Active(ref board) = *state {
+                let turn = board.log.turn_count();
+                if self.spectators_turn == Some(turn) {
+                    sender.send_to(board.peer_ids(), message);
+                    return;
+                }
+                self.spectators_turn = Some(turn);
+            } else {
+                self.spectators_turn = None;
+            }
+            sender.send(message);
+        }
+    }
+
+    /// Sends whatever the board has for everyone else, ahead of anything sent after it
+    fn send_board_outbox(&mut self) {
+        if let GameState::InGame(ref conn_state) = self.state {
+            let messages = match *conn_state.state.write().expect("Failed to lock state") {
+                NetGameState::Active(ref mut board) => board.take_outbox(),
+                _ => return,
+            };
+            for message in messages {
+                conn_state.sender.send(message);
+            }
+        }
+    }
+
+    fn update_net_warning(&self, main: &web_sys::Element) {
+        let mut warnings = vec![];
+        if let GameState::InGame(ref conn_state) = self.state {
+            let bad_messages = conn_state.sender.bad_message_count();
+            if bad_messages > 0 {
+                warnings.push(format!("Ignored {} malformed messages from other players", bad_messages));
+            }
+            if let Some(notice) = conn_state.sender.notice() {
+                warnings.push(notice);
+            }
+            if let Some(sim) = conn_state.sender.net_sim() {
+                warnings.push(sim.describe());
+            }
+        }
+        if warnings.is_empty() {
+            return;
+        }
+        let text = warnings.join(". ");
+        let banner = main.query_selector("#net-warning").unwrap_throw();
+        let banner = match banner {
+            Some(banner) => banner,
+            None => {
+                let document = main.owner_document().unwrap_throw();
+                let banner = document.create_element("p").unwrap_throw();
+                banner.set_id("net-warning");
+                banner.set_class_name("warning");
+                main.append_with_node_1(&banner).unwrap_throw();
+                banner
+            }
+        };
+        if banner.text_content().as_ref() != Some(&text) {
+            banner.set_text_content(Some(&text));
+        }
+    }
+
+    /// Shows how much is going over the connection, if the page asked for it with `?debug`
+    fn update_debug_overlay(&self, main: &web_sys::Element) {
+        let conn_state = match self.state {
+            GameState::InGame(ref conn_state) if net::debug_overlay() && conn_state.sender.is_online() => conn_state,
+            _ => return,
+        };
+        let text = conn_state.sender.traffic().describe();
+        let overlay = match main.query_selector("#debug-overlay").unwrap_throw() {
+            Some(overlay) => overlay,
+            None => {
+                let document = main.owner_document().unwrap_throw();
+                let overlay = document.create_element("p").unwrap_throw();
+                overlay.set_id("debug-overlay");
+                main.append_with_node_1(&overlay).unwrap_throw();
+                overlay
             }
+        };
+        if overlay.text_content().as_ref() != Some(&text) {
+            overlay.set_text_content(Some(&text));
         }
     }
 
-    /// Draw to the given element
-    pub fn draw(&mut self, main: &web_sys::Element) {
-        self.build_dom(main);
-        if let Some(ctx) = get_context(main) {
-            self.view.draw(self, &ctx);
+    fn update_toasts(&self, main: &web_sys::Element) {
+        let texts = self.toasts.texts();
+        let toasts = main.query_selector("#toasts").unwrap_throw();
+        let toasts = match toasts {
+            Some(toasts) => toasts,
+            None if texts.is_empty() => return,
+            None => {
+                let document = main.owner_document().unwrap_throw();
+                let toasts = document.create_element("div").unwrap_throw();
+                toasts.set_id("toasts");
+                main.append_with_node_1(&toasts).unwrap_throw();
+                toasts
+            }
+        };
+        let shown = toasts.children();
+        let unchanged = shown.length() as usize == texts.len()
+            && texts.iter().enumerate().all(|(i, text)| {
+                let toast = shown.item(i as u32).unwrap_throw();
+                toast.text_content().as_deref() == Some(*text)
+            });
+        if unchanged {
+            return;
+        }
+        let document = main.owner_document().unwrap_throw();
+        toasts.set_text_content(None);
+        for text in texts {
+            let toast = document.create_element("p").unwrap_throw();
+            toast.set_text_content(Some(text));
+            toasts.append_with_node_1(&toast).unwrap_throw();
         }
     }
 
-    fn broadcast_state(&mut self) {
-        if let GameState::InGame(ref mut conn_state) = self.state {
-            let sender = &mut conn_state.sender;
-            let state = &mut conn_state.state;
-            let state = state.read().expect("Failed to lock state");
-            let message = Message::State(state.clone());
-            sender.send(message);
+    /// Shows the host who's waiting to join the game in progress, one at a time, so they can be let
+    /// in or turned away
+    fn update_join_request(&mut self, main: &web_sys::Element) {
+        let waiting = match self.state {
+            GameState::InGame(ref conn_state) => match *conn_state.state.read().expect("Failed to lock state") {
+                NetGameState::Active(_) => conn_state.sender.join_requests().into_iter().next(),
+                _ => None,
+            },
+            _ => None,
+        };
+        let panel = main.query_selector("#join-request").unwrap_throw();
+        let panel = match panel {
+            Some(panel) => panel,
+            None if waiting.is_none() => return,
+            None => {
+                let document = main.owner_document().unwrap_throw();
+                let panel = document.create_element("div").unwrap_throw();
+                panel.set_id("join-request");
+                let prompt = document.create_element("p").unwrap_throw();
+                panel.append_with_node_1(&prompt).unwrap_throw();
+                let choices = [("Accept", UiEvent::AcceptJoin), ("Decline", UiEvent::DeclineJoin)];
+                for (label, event) in choices.iter().cloned() {
+                    let button = document.create_element("button").unwrap_throw();
+                    button.set_text_content(Some(label));
+                    panel.append_with_node_1(&button).unwrap_throw();
+                    let events = self.events.clone();
+                    let listener = EventListener::new(&button, "click", move |_| {
+                        events.send(event.clone()).unwrap_throw();
+                    });
+                    self.listeners.push(listener);
+                }
+                main.append_with_node_1(&panel).unwrap_throw();
+                panel
+            }
+        };
+        let panel: web_sys::HtmlElement = panel.dyn_into().unwrap_throw();
+        if panel.hidden() != waiting.is_none() {
+            panel.set_hidden(waiting.is_none());
+        }
+        if let Some(player) = waiting {
+            let text = format!("{} wants to join the game", player.name);
+            let prompt = panel.first_element_child().unwrap_throw();
+            if prompt.text_content().as_ref() != Some(&text) {
+                prompt.set_text_content(Some(&text));
+            }
         }
     }
 
@@ -456,16 +2249,11 @@ impl GameController {
         match self.state {
             GameState::MainMenu => "main-menu",
             GameState::ConnectMenu => "connect-menu",
+            GameState::InGame(_) if self.spectating => "spectating",
             GameState::InGame(ref conn_state) => {
                 let state = &conn_state.state;
                 let state = state.read().expect("Failed to lock state");
-                match *state {
-                    NetGameState::Connecting => "connecting",
-                    NetGameState::Lobby(_) => "lobby",
-                    NetGameState::Active(_) => "active",
-                    NetGameState::GameOver(_) => "game-over",
-                    NetGameState::Error(_) => "error",
-                }
+                state.name()
             }
             GameState::HardError(_) => "hard-error",
             GameState::Options(_) => "options",
@@ -498,27 +2286,44 @@ impl GameController {
 
         let old_class = main.class_name();
         let curr_class = self.curr_class();
+        let root_class = format!("{} {}", embed::ROOT_CLASS, curr_class);
 
         // deferring is complicated, preventing default is complicated
         macro_rules! listen {
-            ($target:expr, $evt:expr, self.$e:ident($( $a:ident ),*)) => {{
+            ($target:expr, $evt:expr, $( $a:ident ),+ => $event:expr) => {{
                 let target = $target;
                 $(let $a = $a.clone();)*
                 let options = EventListenerOptions::enable_prevent_default();
-                let actions = self.actions.clone();
+                let events = self.events.clone();
                 let listener = EventListener::new_with_options(
                     target,
                     $evt,
                     options,
                     move |event| {
-                        $(let $a = $a.clone();)*
                         event.prevent_default();
-                        let mut actions = actions.lock().unwrap_throw();
-                        actions.push(Box::new(move |x: &mut Self| x.$e($($a),*)));
+                        let ui_event: Option<UiEvent> = $event;
+                        if let Some(ui_event) = ui_event {
+                            events.send(ui_event).unwrap_throw();
+                        }
+                    }
+                );
+                self.listeners.push(listener);
+            }};
+            ($target:expr, $evt:expr, $event:expr) => {{
+                let target = $target;
+                let options = EventListenerOptions::enable_prevent_default();
+                let events = self.events.clone();
+                let listener = EventListener::new_with_options(
+                    target,
+                    $evt,
+                    options,
+                    move |event| {
+                        event.prevent_default();
+                        events.send($event).unwrap_throw();
                     }
                 );
                 self.listeners.push(listener);
-            }}
+            }};
         }
 
         // get ready to make some elements
@@ -535,15 +2340,40 @@ impl GameController {
                     let name_box: web_sys::HtmlInputElement = create_element(&document, "input");
                     name_box.set_value(&player_info.name);
                     let id = player_info.id;
-                    listen!(&name_box, "input", self.set_name(name_box, id));
+                    listen!(&name_box, "input", name_box => Some(UiEvent::SetName(id, name_box.value())));
                     player.append_with_node_1(&name_box).unwrap_throw();
                     let color: web_sys::HtmlInputElement = create_element(&document, "input");
                     color.set_type("color");
                     color.set_value(&player_info.color.hex());
-                    listen!(&color, "input", self.set_color(color, id));
+                    listen!(&color, "input", color => parse_color(&color.value()).map(|color| UiEvent::SetColor(id, color)));
                     player.append_with_node_1(&color).unwrap_throw();
+                    match player_info.bot {
+                        Some(difficulty) => {
+                            let button: web_sys::HtmlElement = create_element_with_text(&document, "button", difficulty.name());
+                            button.set_class_name("bot-difficulty");
+                            button.set_title("How well the computer plays for this player");
+                            listen!(&button, "click", UiEvent::CycleBotDifficulty(id));
+                            player.append_with_node_1(&button).unwrap_throw();
+                        }
+                        None => {
+                            let source = self.input_sources.get(&id).cloned().unwrap_or(InputSource::Any);
+                            let input: web_sys::HtmlElement = create_element_with_text(&document, "button", &source.name());
+                            input.set_class_name("input-source");
+                            input.set_title("Where this player's keys or buttons come from");
+                            listen!(&input, "click", UiEvent::CycleInputSource(id));
+                            player.append_with_node_1(&input).unwrap_throw();
+                        }
+                    }
+                    if player_info.parent.is_some() {
+                        let remove: web_sys::HtmlElement = create_element_with_text(&document, "button", "Remove");
+                        listen!(&remove, "click", UiEvent::AskFirst(Box::new(UiEvent::RemoveLocalPlayer(id))));
+                        player.append_with_node_1(&remove).unwrap_throw();
+                    }
                 } else {
                     let name: web_sys::HtmlElement = create_element_with_text(&document, "span", &player_info.name);
+                    if player_info.bot.is_some() {
+                        name.set_title("The host's computer plays for this player");
+                    }
                     player.append_with_node_1(&name).unwrap_throw();
                     let color: web_sys::HtmlElement = create_element(&document, "span");
                     color.set_inner_html("&nbsp;");
@@ -554,14 +2384,113 @@ impl GameController {
             }};
         }
 
+        // same deal as create_player, the listener needs self
+        macro_rules! create_diagnostics {
+            () => {{
+                let report_box: web_sys::HtmlTextAreaElement = create_element(&document, "textarea");
+                report_box.set_read_only(true);
+                report_box.set_value(&diagnostics::report());
+                main.append_with_node_1(&report_box).unwrap_throw();
+
+                let copy: web_sys::HtmlElement = create_element_with_text(&document, "button", "Copy diagnostic report");
+                main.append_with_node_1(&copy).unwrap_throw();
+                // copying has to happen inside the click handler, so this one can't wait for a tick
+                let listener = EventListener::new(&copy, "click", move |_| diagnostics::copy_report(&report_box));
+                self.listeners.push(listener);
+            }};
+        }
+
+        // network buttons only make sense while online
+        let offline = net::is_offline();
+        let offline_title = if offline {
+            "You're offline, so only the tutorial and local games are available"
+        } else {
+            ""
+        };
+
         // if the UI doesn't need to be rebuilt from scratch...
-        if old_class == curr_class {
+        if old_class == root_class {
             // apply updates incrementally
-            if let GameState::InGame(ref conn_state) = self.state {
+            let mut resize = false;
+            if let GameState::MainMenu = self.state {
+                for id in &["#host", "#connect"] {
+                    let button: web_sys::HtmlButtonElement = query_selector(main, id);
+                    if button.disabled() != offline {
+                        button.set_disabled(offline);
+                        button.set_title(offline_title);
+                    }
+                }
+            }
+            if let GameState::ConnectMenu = self.state {
+                let (text, warning) = match self.lobby_query {
+                    None => (String::new(), false),
+                    Some(ref query) => match query.result() {
+                        None => (format!("Checking lobby {}...", query.game), false),
+                        Some(QueryResult::Failed) => (format!("Couldn't check on lobby {}", query.game), true),
+                        Some(QueryResult::Answered(info)) if info.players == 0 => (format!("Lobby {} not found", query.game), true),
+                        Some(QueryResult::Answered(info)) if info.started => (format!("Lobby {} is already in game, but the host can let you in partway through", query.game), true),
+                        Some(QueryResult::Answered(_)) => (format!("Joining lobby {}...", query.game), false),
+                    },
+                };
+                let status: web_sys::HtmlElement = query_selector(main, "#lobby-status");
+                if status.inner_text() != text {
+                    status.set_inner_text(&text);
+                }
+                let join_anyway: web_sys::HtmlElement = query_selector(main, "#join-anyway");
+                if join_anyway.hidden() == warning {
+                    join_anyway.set_hidden(!warning);
+                }
+            }
+            if let (true, GameState::InGame(ref conn_state)) = (self.spectating, &self.state) {
+                let state = conn_state.state.read().expect("Failed to lock state");
+                let text = spectate_text(&state);
+                let status: web_sys::HtmlElement = query_selector(main, "#spectate-status");
+                if status.inner_text() != text {
+                    status.set_inner_text(&text);
+                    status.set_hidden(text.is_empty());
+                    // the status sits above the board, so the board's room changes with it
+                    resize = true;
+                }
+            } else if let GameState::InGame(ref conn_state) = self.state {
                 let state = &conn_state.state;
                 let state = state.read().expect("Failed to lock state");
+                let is_host = state.is_host(self.player_id);
                 match *state {
+                    NetGameState::Connecting => {
+                        if let Some((_, started)) = self.connecting_to {
+                            let elapsed = crate::now() - started;
+                            let text = format!("Waiting for the host for {} seconds", elapsed.floor());
+                            let elapsed_text: web_sys::HtmlElement = query_selector(main, "#connecting-elapsed");
+                            if elapsed_text.inner_text() != text {
+                                elapsed_text.set_inner_text(&text);
+                            }
+
+                            let timeout: web_sys::HtmlElement = query_selector(main, "#connecting-timeout");
+                            let timed_out = elapsed >= CONNECT_TIMEOUT;
+                            if timeout.hidden() == timed_out {
+                                timeout.set_hidden(!timed_out);
+                            }
+                        }
+                    }
                     NetGameState::Lobby(ref info) => {
+                        let idle: web_sys::HtmlElement = query_selector(main, "#idle-warning");
+                        match self.idle_countdown() {
+                            Some(left) => {
+                                let text = format!("Nobody's joined, so this lobby closes in {}s. Click to keep it open", left.ceil());
+                                if idle.inner_text() != text {
+                                    idle.set_inner_text(&text);
+                                }
+                                if idle.hidden() {
+                                    idle.set_hidden(false);
+                                }
+                            }
+                            None => {
+                                if !idle.hidden() {
+                                    idle.set_hidden(true);
+                                }
+                            }
+                        }
+
                         // update players
                         let players: web_sys::HtmlElement = query_selector(main, "ul");
                         for player_info in info.players_ref() {
@@ -570,7 +2499,18 @@ impl GameController {
                                 .map_err(|e| web_sys::console::error_1(&e)).ok().flatten();
                             match existing_player {
                                 Some(player) => {
-                                    if !is_local {
+                                    if let (true, Some(difficulty)) = (is_local, player_info.bot) {
+                                        let button: web_sys::HtmlElement = query_selector(&player, ".bot-difficulty");
+                                        if button.inner_text() != difficulty.name() {
+                                            button.set_inner_text(difficulty.name());
+                                        }
+                                    } else if is_local {
+                                        let input: web_sys::HtmlElement = query_selector(&player, ".input-source");
+                                        let source = self.input_sources.get(&player_info.id).cloned().unwrap_or(InputSource::Any);
+                                        if input.inner_text() != source.name() {
+                                            input.set_inner_text(&source.name());
+                                        }
+                                    } else {
                                         let name: web_sys::HtmlElement = query_selector(&player, "span:first-child");
                                         if name.inner_text() != player_info.name {
                                             name.set_inner_text(&player_info.name);
@@ -587,8 +2527,44 @@ impl GameController {
                                 }
                             }
                         }
+                        let children = players.children();
+                        let gone: Vec<web_sys::Element> = (0..children.length())
+                            .filter_map(|i| children.item(i))
+                            .filter(|player| !info.players_ref().iter().any(|p| player.id() == format!("player-{}", p.id)))
+                            .collect();
+                        for player in gone {
+                            player.remove();
+                        }
 
                         // update settings
+                        for (i, &preset) in RULE_PRESETS.iter().enumerate() {
+                            let id = format!("#rule-preset-{}", i);
+                            let button: web_sys::HtmlButtonElement = query_selector(main, &id);
+                            let current = info.settings.matches_rule_preset(preset);
+                            if button.disabled() != current {
+                                button.set_disabled(current);
+                            }
+                        }
+                        for &(_, size) in SIZE_PRESETS.iter() {
+                            let id = format!("#size-preset-{}", size);
+                            let preset: web_sys::HtmlButtonElement = query_selector(main, &id);
+                            let current = info.settings.width == size && info.settings.height == size;
+                            if preset.disabled() != current {
+                                preset.set_disabled(current);
+                            }
+                        }
+
+                        let size_error: web_sys::HtmlElement = query_selector(main, "#size-error");
+                        let text = self.size_error.as_ref().map_or("", String::as_str);
+                        if size_error.inner_text() != text {
+                            size_error.set_inner_text(text);
+                        }
+
+                        let wrap_rule: web_sys::HtmlElement = query_selector(main, "#wrap-rule");
+                        if wrap_rule.inner_text() != info.settings.wrap_rule() {
+                            wrap_rule.set_inner_text(info.settings.wrap_rule());
+                        }
+
                         let settings_form: web_sys::HtmlFormElement = query_selector(main, "form");
                         let current_version: usize = settings_form.dataset().get("version").unwrap_throw().parse().unwrap_throw();
                         if current_version < info.settings.version {
@@ -611,21 +2587,226 @@ impl GameController {
                             if score_limit_field.value() != score_limit {
                                 score_limit_field.set_value(&score_limit);
                             }
+                            score_limit_field.set_max(&format!("{}", info.settings.max_score_limit()));
+
+                            let turn_time_limit_field: web_sys::HtmlInputElement = named_item(&elements, "turn_time_limit");
+                            let turn_time_limit = format!("{}", info.settings.turn_time_limit);
+                            if turn_time_limit_field.value() != turn_time_limit {
+                                turn_time_limit_field.set_value(&turn_time_limit);
+                            }
+
+                            let wrap_tokens_field: web_sys::HtmlInputElement = named_item(&elements, "wrap_tokens");
+                            if wrap_tokens_field.checked() != info.settings.wrap_tokens {
+                                wrap_tokens_field.set_checked(info.settings.wrap_tokens);
+                            }
+
+                            let points_scoring_field: web_sys::HtmlInputElement = named_item(&elements, "points_scoring");
+                            let points_scoring = info.settings.scoring == Scoring::Points;
+                            if points_scoring_field.checked() != points_scoring {
+                                points_scoring_field.set_checked(points_scoring);
+                            }
+
+                            let target_steal_field: web_sys::HtmlInputElement = named_item(&elements, "target_steal");
+                            if target_steal_field.checked() != info.settings.target_steal {
+                                target_steal_field.set_checked(info.settings.target_steal);
+                            }
+
+                            let double_push_field: web_sys::HtmlInputElement = named_item(&elements, "double_push");
+                            if double_push_field.checked() != info.settings.double_push {
+                                double_push_field.set_checked(info.settings.double_push);
+                            }
+
+                            let portals_field: web_sys::HtmlInputElement = named_item(&elements, "portals");
+                            if portals_field.checked() != info.settings.portals {
+                                portals_field.set_checked(info.settings.portals);
+                            }
+
+                            let speed_bonus_field: web_sys::HtmlInputElement = named_item(&elements, "speed_bonus");
+                            let speed_bonus = format!("{}", info.settings.speed_bonus);
+                            if speed_bonus_field.value() != speed_bonus {
+                                speed_bonus_field.set_value(&speed_bonus);
+                            }
+
+                            let energy_per_turn_field: web_sys::HtmlInputElement = named_item(&elements, "energy_per_turn");
+                            let energy_per_turn = format!("{}", info.settings.energy_per_turn);
+                            if energy_per_turn_field.value() != energy_per_turn {
+                                energy_per_turn_field.set_value(&energy_per_turn);
+                            }
+
+                            let min_target_distance_field: web_sys::HtmlInputElement = named_item(&elements, "min_target_distance");
+                            let min_target_distance = format!("{}", info.settings.min_target_distance);
+                            if min_target_distance_field.value() != min_target_distance {
+                                min_target_distance_field.set_value(&min_target_distance);
+                            }
+
+                            let max_target_distance_field: web_sys::HtmlInputElement = named_item(&elements, "max_target_distance");
+                            let max_target_distance = format!("{}", info.settings.max_target_distance);
+                            if max_target_distance_field.value() != max_target_distance {
+                                max_target_distance_field.set_value(&max_target_distance);
+                            }
+
+                            let hint_limit_field: web_sys::HtmlInputElement = named_item(&elements, "hint_limit");
+                            let hint_limit = format!("{}", info.settings.hint_limit);
+                            if hint_limit_field.value() != hint_limit {
+                                hint_limit_field.set_value(&hint_limit);
+                            }
+
+                            let series_length_field: web_sys::HtmlInputElement = named_item(&elements, "series_length");
+                            let series_length = format!("{}", info.settings.series_length);
+                            if series_length_field.value() != series_length {
+                                series_length_field.set_value(&series_length);
+                            }
+
+                            let collapse_every_field: web_sys::HtmlInputElement = named_item(&elements, "collapse_every");
+                            let collapse_every = format!("{}", info.settings.collapse_every);
+                            if collapse_every_field.value() != collapse_every {
+                                collapse_every_field.set_value(&collapse_every);
+                            }
+
+                            let fog_distance_field: web_sys::HtmlInputElement = named_item(&elements, "fog_distance");
+                            let fog_distance = format!("{}", info.settings.fog_distance);
+                            if fog_distance_field.value() != fog_distance {
+                                fog_distance_field.set_value(&fog_distance);
+                            }
+
+                            let party_field: web_sys::HtmlInputElement = named_item(&elements, "party");
+                            if party_field.checked() != info.settings.party {
+                                party_field.set_checked(info.settings.party);
+                            }
+
+                            let share_theme_field: web_sys::HtmlInputElement = named_item(&elements, "share_theme");
+                            let share_theme = info.settings.theme.is_some();
+                            if share_theme_field.checked() != share_theme {
+                                share_theme_field.set_checked(share_theme);
+                            }
+
+                            let preview: web_sys::HtmlCanvasElement = query_selector(main, "#board-preview");
+                            draw_board_preview(&preview, &info.settings, &self.view.board_view);
+
+                            settings_form.dataset().set("version", &format!("{}", info.settings.version)).unwrap_throw();
+                        }
+                    }
+                    NetGameState::Active(ref board_controller) => {
+                        let hint: web_sys::HtmlButtonElement = query_selector(main, "#hint");
+                        let text = hint_text(board_controller, self.player_id);
+                        if hint.inner_text() != text {
+                            hint.set_inner_text(&text);
+                        }
+                        let can_take_hint = board_controller.can_take_hint(self.player_id);
+                        if hint.disabled() == can_take_hint {
+                            hint.set_disabled(!can_take_hint);
+                        }
+                        let desync: web_sys::HtmlElement = query_selector(main, "#desync-warning");
+                        let desynced = conn_state.sender.desynced();
+                        if desync.hidden() == desynced {
+                            desync.set_hidden(!desynced);
+                        }
+                        let rules: web_sys::HtmlElement = query_selector(main, "#rules");
+                        if rules.hidden() == self.show_rules {
+                            rules.set_hidden(!self.show_rules);
                         }
                     }
-                    NetGameState::Active(_) => {
-                        let canvas: web_sys::HtmlCanvasElement = query_selector(main, "canvas");
-                        let window = web_sys::window().unwrap_throw();
-                        let inner_width = window.inner_width().unwrap_throw().as_f64().unwrap_throw() as u32;
-                        let inner_height = window.inner_height().unwrap_throw().as_f64().unwrap_throw() as u32;
-                        canvas.set_width(inner_width);
-                        canvas.set_height(inner_height);
+                    NetGameState::GameOver(ref info) => {
+                        let turn = self.view.review_turn(&info.final_board);
+                        let board = &info.final_board;
+                        let text = board.log.describe_turn(turn, &board.players, board.settings.wrap_tokens);
+                        let annotation: web_sys::HtmlElement = query_selector(main, "#review-annotation");
+                        if annotation.inner_text() != text {
+                            annotation.set_inner_text(&text);
+                            // a longer annotation can wrap and push the board down
+                            resize = true;
+                        }
+                        if let Some(link) = conn_state.sender.replay_link() {
+                            let download: web_sys::HtmlElement = query_selector(main, "#replay-download");
+                            if download.hidden() {
+                                download.set_attribute("href", &link).unwrap_throw();
+                                download.set_hidden(false);
+                            }
+                        }
+                        if let Some(ref series) = info.series {
+                            for (player, _, _) in series.standings() {
+                                let id = format!("#standing-{} td:last-child", player.id);
+                                let cell: web_sys::HtmlElement = query_selector(main, &id);
+                                let ready = if series.ready.contains(&player.id) { "Ready" } else { "" };
+                                if cell.inner_text() != ready {
+                                    cell.set_inner_text(ready);
+                                }
+                            }
+                            match info.next_settings {
+                                Some(ref settings) if is_host => {
+                                    for &(_, size) in SIZE_PRESETS.iter() {
+                                        let id = format!("#size-preset-{}", size);
+                                        let preset: web_sys::HtmlButtonElement = query_selector(main, &id);
+                                        let current = settings.width == size && settings.height == size;
+                                        if preset.disabled() != current {
+                                            preset.set_disabled(current);
+                                        }
+                                    }
+                                    let size_error: web_sys::HtmlElement = query_selector(main, "#size-error");
+                                    let text = self.size_error.as_ref().map_or("", String::as_str);
+                                    if size_error.inner_text() != text {
+                                        size_error.set_inner_text(text);
+                                    }
+                                }
+                                Some(ref settings) => {
+                                    let next: web_sys::HtmlElement = query_selector(main, "#next-settings");
+                                    let text = next_board_text(settings);
+                                    if next.inner_text() != text {
+                                        next.set_inner_text(&text);
+                                    }
+                                }
+                                None => {}
+                            }
+                            if let Some(ready) = main.query_selector("#series-ready").unwrap_throw() {
+                                let ready: web_sys::HtmlButtonElement = ready.dyn_into().unwrap_throw();
+                                let is_ready = series.ready.contains(&self.player_id);
+                                if ready.disabled() != is_ready {
+                                    ready.set_disabled(is_ready);
+                                }
+                            }
+                        }
                     }
                     _ => {}
                 }
             }
+            if let GameState::Options(ref opts) = self.state {
+                for (i, (_, name)) in theme_choices().into_iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = query_selector(main, &format!("#theme-{}", i));
+                    let picked = opts.theme == name;
+                    if choice.disabled() != picked {
+                        choice.set_disabled(picked);
+                    }
+                }
+                for (i, &(narration, _)) in options::Narration::ALL.iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = query_selector(main, &format!("#narration-{}", i));
+                    let picked = opts.tutorial_narration == narration;
+                    if choice.disabled() != picked {
+                        choice.set_disabled(picked);
+                    }
+                }
+                for (i, &(rotation, _)) in options::BoardRotation::ALL.iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = query_selector(main, &format!("#rotation-{}", i));
+                    let picked = opts.board_rotation == rotation;
+                    if choice.disabled() != picked {
+                        choice.set_disabled(picked);
+                    }
+                }
+            }
+            let confirm: web_sys::HtmlElement = query_selector(main, "#confirm");
+            let prompt = self.confirming.as_ref().map_or("", |event| confirm_prompt(event));
+            let prompt_text: web_sys::HtmlElement = query_selector(main, "#confirm-prompt");
+            if prompt_text.inner_text() != prompt {
+                prompt_text.set_inner_text(prompt);
+            }
+            if confirm.hidden() != self.confirming.is_none() {
+                confirm.set_hidden(self.confirming.is_none());
+            }
+            if resize {
+                self.on_resize(main);
+            }
             return;
         }
+        diagnostics::record("transition", format!("{} -> {}", old_class, root_class));
         // if there's a wrong UI already...
         if old_class != "" {
             // nuke everything from orbit
@@ -633,28 +2814,82 @@ impl GameController {
             self.listeners = vec![];
         }
         // give it the right class
-        main.set_class_name(curr_class);
+        main.set_class_name(&root_class);
 
         match self.state {
             GameState::MainMenu => {
                 let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", "DynaMaze");
                 main.append_with_node_1(&header).unwrap_throw();
 
+                if let Some(target) = options::continue_target() {
+                    let (label, title) = match target {
+                        options::Continue::LocalGame => ("Continue Local Game".to_string(), "Pick the local game back up where it was left"),
+                        options::Continue::Tutorial(_) => ("Continue Tutorial".to_string(), "Pick the tutorial back up at the next step"),
+                        options::Continue::Lobby(game) => (format!("Rejoin Lobby {}", game), "Join the lobby most recently hosted or joined here again"),
+                    };
+                    let resume: web_sys::HtmlElement = create_element_with_text(&document, "button", &label);
+                    resume.set_id("continue");
+                    resume.set_title(title);
+                    main.append_with_node_1(&resume).unwrap_throw();
+                    listen!(&resume, "click", UiEvent::Continue);
+                }
+
                 let tutorial: web_sys::HtmlElement = create_element_with_text(&document, "button", "Tutorial");
                 main.append_with_node_1(&tutorial).unwrap_throw();
-                listen!(&tutorial, "click", self.tutorial());
+                listen!(&tutorial, "click", UiEvent::Tutorial);
+
+                let sandbox: web_sys::HtmlElement = create_element_with_text(&document, "button", "Sandbox");
+                sandbox.set_title("Practice with no turns, moving tiles and your token around freely");
+                main.append_with_node_1(&sandbox).unwrap_throw();
+                listen!(&sandbox, "click", UiEvent::Sandbox);
 
-                let host: web_sys::HtmlElement = create_element_with_text(&document, "button", "Host Game");
+                let local: web_sys::HtmlElement = create_element_with_text(&document, "button", "Local Game");
+                main.append_with_node_1(&local).unwrap_throw();
+                listen!(&local, "click", UiEvent::LocalGame);
+
+                let host: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", "Host Game");
+                host.set_id("host");
+                host.set_disabled(offline);
+                host.set_title(offline_title);
                 main.append_with_node_1(&host).unwrap_throw();
-                listen!(&host, "click", self.host());
+                listen!(&host, "click", UiEvent::Host);
 
-                let connect: web_sys::HtmlElement = create_element_with_text(&document, "button", "Join Game");
+                let connect: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", "Join Game");
+                connect.set_id("connect");
+                connect.set_disabled(offline);
+                connect.set_title(offline_title);
                 main.append_with_node_1(&connect).unwrap_throw();
-                listen!(&connect, "click", self.connect());
+                listen!(&connect, "click", UiEvent::Connect);
 
                 let options: web_sys::HtmlElement = create_element_with_text(&document, "button", "Options");
                 main.append_with_node_1(&options).unwrap_throw();
-                listen!(&options, "click", self.enter_options());
+                listen!(&options, "click", UiEvent::EnterOptions);
+
+                let recent = options::recent_players();
+                if !recent.is_empty() {
+                    let recent_list: web_sys::HtmlElement = create_element(&document, "section");
+                    recent_list.set_id("recent-players");
+                    main.append_with_node_1(&recent_list).unwrap_throw();
+                    let header: web_sys::HtmlElement = create_element_with_text(&document, "h2", "Recent Players");
+                    recent_list.append_with_node_1(&header).unwrap_throw();
+                    let players: web_sys::HtmlElement = create_element(&document, "ul");
+                    recent_list.append_with_node_1(&players).unwrap_throw();
+                    for recent_player in recent {
+                        let player: web_sys::HtmlElement = create_element_with_text(&document, "li", &recent_player.name);
+                        players.append_with_node_1(&player).unwrap_throw();
+                        let star_text = |favorite| if favorite { "★" } else { "☆" };
+                        let star: web_sys::HtmlElement = create_element_with_text(&document, "button", star_text(recent_player.favorite));
+                        star.set_title("Favorites stay at the top of the list, and on it");
+                        player.append_with_node_1(&star).unwrap_throw();
+                        // nothing but the saved list changes, so the star can flip right away
+                        let name = recent_player.name;
+                        let star2 = star.clone();
+                        let listener = EventListener::new(&star, "click", move |_| {
+                            star2.set_inner_text(star_text(options::toggle_favorite(&name)));
+                        });
+                        self.listeners.push(listener);
+                    }
+                }
             }
             GameState::ConnectMenu => {
                 let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", "Connect to Game");
@@ -662,7 +2897,7 @@ impl GameController {
 
                 let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                 main.append_with_node_1(&main_menu).unwrap_throw();
-                listen!(&main_menu, "click", self.main_menu());
+                listen!(&main_menu, "click", UiEvent::MainMenu);
 
                 let connect_form: web_sys::HtmlFormElement = create_element(&document, "form");
                 main.append_with_node_1(&connect_form).unwrap_throw();
@@ -670,7 +2905,7 @@ impl GameController {
                 let connect_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Lobby ID");
                 connect_form.append_with_node_1(&connect_label).unwrap_throw();
 
-                let connect_text: web_sys::HtmlElement = create_element(&document, "input");
+                let connect_text: web_sys::HtmlInputElement = create_element(&document, "input");
                 connect_label
                     .append_with_node_1(&connect_text)
                     .unwrap_throw();
@@ -678,7 +2913,39 @@ impl GameController {
                 let connect: web_sys::HtmlElement = create_element_with_text(&document, "button", "Connect");
                 connect_form.append_with_node_1(&connect).unwrap_throw();
 
-                listen!(&connect_form, "submit", self.do_connect(connect_form));
+                listen!(&connect_form, "submit", connect_text => connect_text.value().parse().ok().map(UiEvent::DoConnect));
+
+                // refreshing loses the lobby ID, so the last one stays handy
+                if let Some(game) = options::recent_lobby() {
+                    let recent: web_sys::HtmlElement = create_element_with_text(&document, "p", &format!("Recent lobby: {} ", game));
+                    recent.set_id("recent-lobby");
+                    main.append_with_node_1(&recent).unwrap_throw();
+                    let rejoin: web_sys::HtmlElement = create_element_with_text(&document, "button", "Rejoin");
+                    recent.append_with_node_1(&rejoin).unwrap_throw();
+                    listen!(&rejoin, "click", UiEvent::DoConnect(game));
+                }
+
+                let status: web_sys::HtmlElement = create_element(&document, "p");
+                status.set_id("lobby-status");
+                main.append_with_node_1(&status).unwrap_throw();
+
+                let join_anyway: web_sys::HtmlElement = create_element_with_text(&document, "button", "Join Anyway");
+                join_anyway.set_id("join-anyway");
+                join_anyway.set_hidden(true);
+                main.append_with_node_1(&join_anyway).unwrap_throw();
+                listen!(&join_anyway, "click", UiEvent::JoinAnyway);
+            }
+            GameState::InGame(ref conn_state) if self.spectating => {
+                // nothing but the board, for streaming or projecting
+                let state = conn_state.state.read().expect("Failed to lock state");
+                let text = spectate_text(&state);
+                let status: web_sys::HtmlElement = create_element_with_text(&document, "p", &text);
+                status.set_id("spectate-status");
+                status.set_hidden(text.is_empty());
+                main.append_with_node_1(&status).unwrap_throw();
+
+                let canvas: web_sys::HtmlCanvasElement = create_element(&document, "canvas");
+                main.append_with_node_1(&canvas).unwrap_throw();
             }
             GameState::InGame(ref conn_state) => {
                 let state = &conn_state.state;
@@ -688,6 +2955,33 @@ impl GameController {
                     NetGameState::Connecting => {
                         let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", "Connecting...");
                         main.append_with_node_1(&header).unwrap_throw();
+
+                        if let Some((game, _)) = self.connecting_to {
+                            let id = format!("Lobby ID: {}", game);
+                            let header: web_sys::HtmlElement = create_element_with_text(&document, "h2", &id);
+                            main.append_with_node_1(&header).unwrap_throw();
+                        }
+
+                        let elapsed: web_sys::HtmlElement = create_element(&document, "p");
+                        elapsed.set_id("connecting-elapsed");
+                        main.append_with_node_1(&elapsed).unwrap_throw();
+
+                        let timeout: web_sys::HtmlElement = create_element(&document, "div");
+                        timeout.set_id("connecting-timeout");
+                        timeout.set_hidden(true);
+                        main.append_with_node_1(&timeout).unwrap_throw();
+
+                        let text = "The host hasn't answered. Check the lobby ID, or ask the host if their game is still open.";
+                        let message: web_sys::HtmlElement = create_element_with_text(&document, "p", text);
+                        timeout.append_with_node_1(&message).unwrap_throw();
+
+                        let retry: web_sys::HtmlElement = create_element_with_text(&document, "button", "Retry");
+                        timeout.append_with_node_1(&retry).unwrap_throw();
+                        listen!(&retry, "click", UiEvent::RetryConnect);
+
+                        let cancel: web_sys::HtmlElement = create_element_with_text(&document, "button", "Cancel");
+                        main.append_with_node_1(&cancel).unwrap_throw();
+                        listen!(&cancel, "click", UiEvent::MainMenu);
                     }
                     NetGameState::Lobby(ref info) => {
                         let status = if is_host {
@@ -698,13 +2992,62 @@ impl GameController {
                         let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", status);
                         main.append_with_node_1(&header).unwrap_throw();
 
+                        let idle: web_sys::HtmlElement = create_element(&document, "button");
+                        idle.set_id("idle-warning");
+                        idle.set_class_name("warning");
+                        idle.set_hidden(true);
+                        main.append_with_node_1(&idle).unwrap_throw();
+                        listen!(&idle, "click", UiEvent::KeepLobbyOpen);
+
                         let id = format!("Lobby ID: {}", info.id);
                         let header: web_sys::HtmlElement = create_element_with_text(&document, "h2", &id);
                         main.append_with_node_1(&header).unwrap_throw();
 
+                        let events = seasonal::active_events();
+                        if !events.is_empty() {
+                            let badges: web_sys::HtmlElement = create_element(&document, "section");
+                            badges.set_id("event-badges");
+                            main.append_with_node_1(&badges).unwrap_throw();
+                            for event in &events {
+                                let badge: web_sys::HtmlElement = create_element_with_text(&document, "span", &event.badge());
+                                badge.set_class_name("badge");
+                                badge.set_title("On for a limited time, in any game started while it lasts");
+                                badges.append_with_node_1(&badge).unwrap_throw();
+                            }
+                        }
+
+                        if conn_state.sender.is_online() {
+                            let watch_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Spectator Link");
+                            watch_label.set_title("Opens the game read-only with nothing on screen but the board, for streaming or projecting");
+                            main.append_with_node_1(&watch_label).unwrap_throw();
+                            let watch_link: web_sys::HtmlInputElement = create_element(&document, "input");
+                            watch_link.set_id("watch-link");
+                            watch_link.set_read_only(true);
+                            watch_link.set_value(&net::watch_link(info.id));
+                            watch_label.append_with_node_1(&watch_link).unwrap_throw();
+                        }
+
+                        if is_host && conn_state.sender.is_online() {
+                            let invites: web_sys::HtmlElement = create_element(&document, "section");
+                            invites.set_id("invites");
+                            main.append_with_node_1(&invites).unwrap_throw();
+                            let link = net::join_link(info.id);
+                            for recent_player in options::recent_players() {
+                                let label = format!("Copy Invite for {}", recent_player.name);
+                                let invite: web_sys::HtmlElement = create_element_with_text(&document, "button", &label);
+                                invite.set_title("Copies a message with a link that joins this lobby");
+                                invites.append_with_node_1(&invite).unwrap_throw();
+                                let text = format!("{}, come play DynaMaze with me! {}", recent_player.name, link);
+                                let document = document.clone();
+                                // copying has to happen inside the click handler, so this one can't wait for a tick
+                                let listener = EventListener::new(&invite, "click", move |_| copy_text(&document, &text));
+                                self.listeners.push(listener);
+                            }
+                        }
+
                         let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                         main.append_with_node_1(&main_menu).unwrap_throw();
-                        listen!(&main_menu, "click", self.main_menu());
+                        listen!(&main_menu, "click", UiEvent::AskFirst(Box::new(UiEvent::MainMenu)));
 
                         let players: web_sys::Element = create_element(&document, "ul");
                         main.append_with_node_1(&players).unwrap_throw();
@@ -717,14 +3060,58 @@ impl GameController {
 
                         let new_local: web_sys::HtmlElement = create_element_with_text(&document, "button", "New Local Player");
                         main.append_with_node_1(&new_local).unwrap_throw();
-                        listen!(&new_local, "click", self.new_local_player());
+                        listen!(&new_local, "click", UiEvent::NewLocalPlayer);
+
+                        if info.host.id == self.player_id {
+                            let new_bot: web_sys::HtmlElement = create_element_with_text(&document, "button", "Add Computer Player");
+                            new_bot.set_title("Fill a seat with a player the computer plays for, on this screen");
+                            main.append_with_node_1(&new_bot).unwrap_throw();
+                            listen!(&new_bot, "click", UiEvent::NewBotPlayer);
+                        }
 
                         let settings_form: web_sys::HtmlElement = create_element(&document, "form");
                         settings_form.dataset().set("version", &format!("{}", info.settings.version)).unwrap_throw();
                         main.append_with_node_1(&settings_form).unwrap_throw();
 
+                        // a label would pass its clicks on to the first preset, so these get a fieldset
+                        let rule_presets: web_sys::HtmlElement = create_element(&document, "fieldset");
+                        settings_form.append_with_node_1(&rule_presets).unwrap_throw();
+                        let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Presets");
+                        rule_presets.append_with_node_1(&legend).unwrap_throw();
+                        for (i, &preset) in RULE_PRESETS.iter().enumerate() {
+                            let (name, size, score_limit, _) = preset;
+                            let text = format!("{} ({}×{}, first to {})", name, size, size, score_limit);
+                            let button: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", &text);
+                            button.set_id(&format!("rule-preset-{}", i));
+                            button.set_disabled(info.settings.matches_rule_preset(preset));
+                            rule_presets.append_with_node_1(&button).unwrap_throw();
+                            listen!(&button, "click", UiEvent::SetRulePreset(i));
+                        }
+
+                        let size_presets: web_sys::HtmlElement = create_element(&document, "fieldset");
+                        settings_form.append_with_node_1(&size_presets).unwrap_throw();
+                        let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Board Size");
+                        size_presets.append_with_node_1(&legend).unwrap_throw();
+                        for &(name, size) in SIZE_PRESETS.iter() {
+                            let text = format!("{} ({}×{})", name, size, size);
+                            let preset: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", &text);
+                            preset.set_id(&format!("size-preset-{}", size));
+                            preset.set_disabled(info.settings.width == size && info.settings.height == size);
+                            size_presets.append_with_node_1(&preset).unwrap_throw();
+                            listen!(&preset, "click", UiEvent::SetSize(size, size));
+                        }
+
+                        // anything else goes in here, open from the start if it's already in use
+                        let advanced: web_sys::HtmlElement = create_element(&document, "details");
+                        if info.settings.size_preset().is_none() {
+                            advanced.set_attribute("open", "").unwrap_throw();
+                        }
+                        settings_form.append_with_node_1(&advanced).unwrap_throw();
+                        let summary: web_sys::HtmlElement = create_element_with_text(&document, "summary", "Advanced");
+                        advanced.append_with_node_1(&summary).unwrap_throw();
+
                         let width_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Board Width");
-                        settings_form.append_with_node_1(&width_label).unwrap_throw();
+                        advanced.append_with_node_1(&width_label).unwrap_throw();
                         let width: web_sys::HtmlInputElement = create_element(&document, "input");
                         width.set_name("width");
                         width.set_type("number");
@@ -732,11 +3119,11 @@ impl GameController {
                         width.set_max("21");
                         width.set_step("2");
                         width.set_value(&format!("{}", info.settings.width));
-                        listen!(&width, "input", self.set_width(width));
+                        listen!(&width, "input", width => width.value().parse().ok().map(UiEvent::SetWidth));
                         width_label.append_with_node_1(&width).unwrap_throw();
 
                         let height_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Board Height");
-                        settings_form.append_with_node_1(&height_label).unwrap_throw();
+                        advanced.append_with_node_1(&height_label).unwrap_throw();
                         let height: web_sys::HtmlInputElement = create_element(&document, "input");
                         height.set_name("height");
                         height.set_type("number");
@@ -744,39 +3131,470 @@ impl GameController {
                         height.set_max("21");
                         height.set_step("2");
                         height.set_value(&format!("{}", info.settings.height));
-                        listen!(&height, "input", self.set_height(height));
+                        listen!(&height, "input", height => height.value().parse().ok().map(UiEvent::SetHeight));
                         height_label.append_with_node_1(&height).unwrap_throw();
 
+                        let text = self.size_error.as_ref().map_or("", String::as_str);
+                        let size_error: web_sys::HtmlElement = create_element_with_text(&document, "p", text);
+                        size_error.set_id("size-error");
+                        advanced.append_with_node_1(&size_error).unwrap_throw();
+
                         let score_limit_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Score Limit");
                         settings_form.append_with_node_1(&score_limit_label).unwrap_throw();
                         let score_limit: web_sys::HtmlInputElement = create_element(&document, "input");
                         score_limit.set_name("score_limit");
                         score_limit.set_type("number");
                         score_limit.set_min("1");
-                        score_limit.set_max("20");
+                        score_limit.set_max(&format!("{}", info.settings.max_score_limit()));
                         score_limit.set_step("1");
                         score_limit.set_value(&format!("{}", info.settings.score_limit));
-                        listen!(&score_limit, "input", self.set_score_limit(score_limit));
+                        listen!(&score_limit, "input", score_limit => score_limit.value().parse().ok().map(UiEvent::SetScoreLimit));
                         score_limit_label.append_with_node_1(&score_limit).unwrap_throw();
 
+                        let turn_time_limit_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Seconds Per Turn (0 for unlimited)");
+                        settings_form.append_with_node_1(&turn_time_limit_label).unwrap_throw();
+                        let turn_time_limit: web_sys::HtmlInputElement = create_element(&document, "input");
+                        turn_time_limit.set_name("turn_time_limit");
+                        turn_time_limit.set_type("number");
+                        turn_time_limit.set_min("0");
+                        turn_time_limit.set_max(&format!("{}", MAX_TURN_TIME_LIMIT));
+                        turn_time_limit.set_step("5");
+                        turn_time_limit.set_value(&format!("{}", info.settings.turn_time_limit));
+                        listen!(&turn_time_limit, "input", turn_time_limit => turn_time_limit.value().parse().ok().map(UiEvent::SetTurnTimeLimit));
+                        turn_time_limit_label.append_with_node_1(&turn_time_limit).unwrap_throw();
+
+                        let wrap_tokens_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Wrap Pushed-Off Tokens");
+                        settings_form.append_with_node_1(&wrap_tokens_label).unwrap_throw();
+                        let wrap_tokens: web_sys::HtmlInputElement = create_element(&document, "input");
+                        wrap_tokens.set_name("wrap_tokens");
+                        wrap_tokens.set_type("checkbox");
+                        wrap_tokens.set_checked(info.settings.wrap_tokens);
+                        listen!(&wrap_tokens, "change", wrap_tokens => Some(UiEvent::SetWrapTokens(wrap_tokens.checked())));
+                        wrap_tokens_label.append_with_node_1(&wrap_tokens).unwrap_throw();
+
+                        let points_scoring_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Farther Targets Score More Points");
+                        points_scoring_label.set_title("Targets are worth 1 to 5 points depending on how far away they are, and the score limit counts points");
+                        settings_form.append_with_node_1(&points_scoring_label).unwrap_throw();
+                        let points_scoring: web_sys::HtmlInputElement = create_element(&document, "input");
+                        points_scoring.set_name("points_scoring");
+                        points_scoring.set_type("checkbox");
+                        points_scoring.set_checked(info.settings.scoring == Scoring::Points);
+                        listen!(&points_scoring, "change", points_scoring => Some(UiEvent::SetPointsScoring(points_scoring.checked())));
+                        points_scoring_label.append_with_node_1(&points_scoring).unwrap_throw();
+
+                        let target_steal_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Lock Targets Landed On");
+                        target_steal_label.set_title("Ending a move on someone else's target locks it, so they can't reach it on their next turn");
+                        settings_form.append_with_node_1(&target_steal_label).unwrap_throw();
+                        let target_steal: web_sys::HtmlInputElement = create_element(&document, "input");
+                        target_steal.set_name("target_steal");
+                        target_steal.set_type("checkbox");
+                        target_steal.set_checked(info.settings.target_steal);
+                        listen!(&target_steal, "change", target_steal => Some(UiEvent::SetTargetSteal(target_steal.checked())));
+                        target_steal_label.append_with_node_1(&target_steal).unwrap_throw();
+
+                        let double_push_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Double Push");
+                        double_push_label.set_title("Once a game, each player can insert the loose tile twice before moving");
+                        settings_form.append_with_node_1(&double_push_label).unwrap_throw();
+                        let double_push: web_sys::HtmlInputElement = create_element(&document, "input");
+                        double_push.set_name("double_push");
+                        double_push.set_type("checkbox");
+                        double_push.set_checked(info.settings.double_push);
+                        listen!(&double_push, "change", double_push => Some(UiEvent::SetDoublePush(double_push.checked())));
+                        double_push_label.append_with_node_1(&double_push).unwrap_throw();
+
+                        let portals_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Portal Mode");
+                        portals_label.set_title("Play across two boards, where ending a move on a portal tile takes your token through to the other one");
+                        settings_form.append_with_node_1(&portals_label).unwrap_throw();
+                        let portals: web_sys::HtmlInputElement = create_element(&document, "input");
+                        portals.set_name("portals");
+                        portals.set_type("checkbox");
+                        portals.set_checked(info.settings.portals);
+                        listen!(&portals, "change", portals => Some(UiEvent::SetPortals(portals.checked())));
+                        portals_label.append_with_node_1(&portals).unwrap_throw();
+
+                        let speed_bonus_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Speed Bonus Seconds (0 for off)");
+                        speed_bonus_label.set_title(&format!(
+                            "Finishing {} turns in a row within this many seconds each earns a bonus point",
+                            SPEED_STREAK
+                        ));
+                        settings_form.append_with_node_1(&speed_bonus_label).unwrap_throw();
+                        let speed_bonus: web_sys::HtmlInputElement = create_element(&document, "input");
+                        speed_bonus.set_name("speed_bonus");
+                        speed_bonus.set_type("number");
+                        speed_bonus.set_min("0");
+                        speed_bonus.set_max(&format!("{}", MAX_SPEED_BONUS));
+                        speed_bonus.set_step("5");
+                        speed_bonus.set_value(&format!("{}", info.settings.speed_bonus));
+                        listen!(&speed_bonus, "input", speed_bonus => speed_bonus.value().parse().ok().map(UiEvent::SetSpeedBonus));
+                        speed_bonus_label.append_with_node_1(&speed_bonus).unwrap_throw();
+
+                        let energy_per_turn_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Energy per Turn (0 for off)");
+                        energy_per_turn_label.set_title(&format!(
+                            "Each step costs one energy, and unused energy banks up to {} turns' worth",
+                            ENERGY_BANK_TURNS
+                        ));
+                        settings_form.append_with_node_1(&energy_per_turn_label).unwrap_throw();
+                        let energy_per_turn: web_sys::HtmlInputElement = create_element(&document, "input");
+                        energy_per_turn.set_name("energy_per_turn");
+                        energy_per_turn.set_type("number");
+                        energy_per_turn.set_min("0");
+                        energy_per_turn.set_max("20");
+                        energy_per_turn.set_value(&format!("{}", info.settings.energy_per_turn));
+                        listen!(&energy_per_turn, "input", energy_per_turn => energy_per_turn.value().parse().ok().map(UiEvent::SetEnergyPerTurn));
+                        energy_per_turn_label.append_with_node_1(&energy_per_turn).unwrap_throw();
+
+                        let min_target_distance_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Closest Target (tiles away)");
+                        settings_form.append_with_node_1(&min_target_distance_label).unwrap_throw();
+                        let min_target_distance: web_sys::HtmlInputElement = create_element(&document, "input");
+                        min_target_distance.set_name("min_target_distance");
+                        min_target_distance.set_type("number");
+                        min_target_distance.set_min("0");
+                        min_target_distance.set_max("40");
+                        min_target_distance.set_step("1");
+                        min_target_distance.set_value(&format!("{}", info.settings.min_target_distance));
+                        listen!(&min_target_distance, "input", min_target_distance => min_target_distance.value().parse().ok().map(UiEvent::SetMinTargetDistance));
+                        min_target_distance_label.append_with_node_1(&min_target_distance).unwrap_throw();
+
+                        let max_target_distance_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Farthest Target (0 for anywhere)");
+                        settings_form.append_with_node_1(&max_target_distance_label).unwrap_throw();
+                        let max_target_distance: web_sys::HtmlInputElement = create_element(&document, "input");
+                        max_target_distance.set_name("max_target_distance");
+                        max_target_distance.set_type("number");
+                        max_target_distance.set_min("0");
+                        max_target_distance.set_max("40");
+                        max_target_distance.set_step("1");
+                        max_target_distance.set_value(&format!("{}", info.settings.max_target_distance));
+                        listen!(&max_target_distance, "input", max_target_distance => max_target_distance.value().parse().ok().map(UiEvent::SetMaxTargetDistance));
+                        max_target_distance_label.append_with_node_1(&max_target_distance).unwrap_throw();
+
+                        let hint_limit_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Hints per Player");
+                        settings_form.append_with_node_1(&hint_limit_label).unwrap_throw();
+                        let hint_limit: web_sys::HtmlInputElement = create_element(&document, "input");
+                        hint_limit.set_name("hint_limit");
+                        hint_limit.set_type("number");
+                        hint_limit.set_min("0");
+                        hint_limit.set_max(&format!("{}", MAX_HINT_LIMIT));
+                        hint_limit.set_step("1");
+                        hint_limit.set_value(&format!("{}", info.settings.hint_limit));
+                        listen!(&hint_limit, "input", hint_limit => hint_limit.value().parse().ok().map(UiEvent::SetHintLimit));
+                        hint_limit_label.append_with_node_1(&hint_limit).unwrap_throw();
+
+                        let series_length_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Boards in Series");
+                        series_length_label.set_title("Play several boards in a row, adding up everyone's targets");
+                        settings_form.append_with_node_1(&series_length_label).unwrap_throw();
+                        let series_length: web_sys::HtmlInputElement = create_element(&document, "input");
+                        series_length.set_name("series_length");
+                        series_length.set_type("number");
+                        series_length.set_min("1");
+                        series_length.set_max(&format!("{}", series::MAX_SERIES_LENGTH));
+                        series_length.set_step("1");
+                        series_length.set_value(&format!("{}", info.settings.series_length));
+                        listen!(&series_length, "input", series_length => series_length.value().parse().ok().map(UiEvent::SetSeriesLength));
+                        series_length_label.append_with_node_1(&series_length).unwrap_throw();
+
+                        let collapse_every_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Collapse Edge Every N Turns (0 for never)");
+                        collapse_every_label.set_title("The outer ring of tiles falls away, shrinking the board to speed up long games");
+                        settings_form.append_with_node_1(&collapse_every_label).unwrap_throw();
+                        let collapse_every: web_sys::HtmlInputElement = create_element(&document, "input");
+                        collapse_every.set_name("collapse_every");
+                        collapse_every.set_type("number");
+                        collapse_every.set_min("0");
+                        collapse_every.set_max("200");
+                        collapse_every.set_step("1");
+                        collapse_every.set_value(&format!("{}", info.settings.collapse_every));
+                        listen!(&collapse_every, "input", collapse_every => collapse_every.value().parse().ok().map(UiEvent::SetCollapseEvery));
+                        collapse_every_label.append_with_node_1(&collapse_every).unwrap_throw();
+
+                        let fog_distance_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Fog of War Sight (0 for off)");
+                        fog_distance_label.set_title("Players only see tiles this many steps along the paths from their token, until the game's over");
+                        settings_form.append_with_node_1(&fog_distance_label).unwrap_throw();
+                        let fog_distance: web_sys::HtmlInputElement = create_element(&document, "input");
+                        fog_distance.set_name("fog_distance");
+                        fog_distance.set_type("number");
+                        fog_distance.set_min("0");
+                        fog_distance.set_max("20");
+                        fog_distance.set_step("1");
+                        fog_distance.set_value(&format!("{}", info.settings.fog_distance));
+                        listen!(&fog_distance, "input", fog_distance => fog_distance.value().parse().ok().map(UiEvent::SetFogDistance));
+                        fog_distance_label.append_with_node_1(&fog_distance).unwrap_throw();
+
+                        let party_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Party Mode (Experimental)");
+                        party_label.set_title("Everyone plans their turn at once, then the plans play out in turn order");
+                        settings_form.append_with_node_1(&party_label).unwrap_throw();
+                        let party: web_sys::HtmlInputElement = create_element(&document, "input");
+                        party.set_name("party");
+                        party.set_type("checkbox");
+                        party.set_checked(info.settings.party);
+                        listen!(&party, "change", party => Some(UiEvent::SetParty(party.checked())));
+                        party_label.append_with_node_1(&party).unwrap_throw();
+
+                        let share_theme_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Make Everyone Use My Theme");
+                        share_theme_label.set_title("Everyone sees the board in the theme picked in your Options, instead of their own");
+                        settings_form.append_with_node_1(&share_theme_label).unwrap_throw();
+                        let share_theme: web_sys::HtmlInputElement = create_element(&document, "input");
+                        share_theme.set_name("share_theme");
+                        share_theme.set_type("checkbox");
+                        share_theme.set_checked(info.settings.theme.is_some());
+                        listen!(&share_theme, "change", share_theme => Some(UiEvent::SetShareTheme(share_theme.checked())));
+                        share_theme_label.append_with_node_1(&share_theme).unwrap_throw();
+
+                        let wrap_rule: web_sys::HtmlElement = create_element_with_text(&document, "p", info.settings.wrap_rule());
+                        wrap_rule.set_id("wrap-rule");
+                        settings_form.append_with_node_1(&wrap_rule).unwrap_throw();
+
+                        // whatever page the game's mounted in may have settled some of these already
+                        for name in embed::locked_settings() {
+                            let selector = format!("[name=\"{}\"]", name);
+                            if let Some(input) = settings_form.query_selector(&selector).unwrap_throw() {
+                                input.set_attribute("disabled", "").unwrap_throw();
+                            }
+                        }
+
+                        let preview: web_sys::HtmlCanvasElement = create_element(&document, "canvas");
+                        preview.set_id("board-preview");
+                        preview.set_title("A board like these settings will make");
+                        preview.set_width(BOARD_PREVIEW_SIZE);
+                        preview.set_height(BOARD_PREVIEW_SIZE);
+                        main.append_with_node_1(&preview).unwrap_throw();
+                        draw_board_preview(&preview, &info.settings, &self.view.board_view);
+
+                        {
+                            let copy: web_sys::HtmlElement = create_element_with_text(&document, "button", "Copy Settings");
+                            copy.set_title("Copies a code for these settings, to share in chat and paste into another lobby");
+                            main.append_with_node_1(&copy).unwrap_throw();
+                            let document = document.clone();
+                            let state = conn_state.state.clone();
+                            // copying has to happen inside the click handler, so this one can't wait for a tick
+                            let listener = EventListener::new(&copy, "click", move |_| {
+                                let state = state.read().expect("Failed to lock state");
+                                if let Some(settings) = state.settings() {
+                                    copy_text(&document, &settings.share_code());
+                                }
+                            });
+                            self.listeners.push(listener);
+                        }
+                        // the host has the final say on settings, so only they get to swap them all out at once
+                        if is_host {
+                            let paste: web_sys::HtmlElement = create_element_with_text(&document, "button", "Paste Settings");
+                            paste.set_title("Switches this lobby to the settings from a code made with Copy Settings");
+                            main.append_with_node_1(&paste).unwrap_throw();
+                            listen!(&paste, "click", UiEvent::PasteSettings);
+                        }
+
                         if is_host {
                             let start: web_sys::HtmlElement = create_element_with_text(&document, "button", "Begin Game");
                             main.append_with_node_1(&start).unwrap_throw();
-                            listen!(&start, "click", self.start_hosted_game());
+                            listen!(&start, "click", UiEvent::StartHostedGame);
                         }
                     }
-                    NetGameState::Active(_) => {
+                    NetGameState::Active(ref board_controller) => {
+                        let desync: web_sys::HtmlElement = create_element_with_text(&document, "button", "Desynchronized from the host, click to resync");
+                        desync.set_id("desync-warning");
+                        desync.set_class_name("warning");
+                        desync.set_hidden(!conn_state.sender.desynced());
+                        main.append_with_node_1(&desync).unwrap_throw();
+                        listen!(&desync, "click", UiEvent::Resync);
+
+                        let rules_toggle: web_sys::HtmlElement = create_element_with_text(&document, "button", "Rules");
+                        rules_toggle.set_id("rules-toggle");
+                        main.append_with_node_1(&rules_toggle).unwrap_throw();
+                        listen!(&rules_toggle, "click", UiEvent::ToggleRules);
+
+                        let hint: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", &hint_text(board_controller, self.player_id));
+                        hint.set_id("hint");
+                        hint.set_hidden(board_controller.settings.hint_limit == 0);
+                        hint.set_disabled(!board_controller.can_take_hint(self.player_id));
+                        main.append_with_node_1(&hint).unwrap_throw();
+                        listen!(&hint, "click", UiEvent::Hint);
+
+                        let leave: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
+                        leave.set_id("leave-game");
+                        main.append_with_node_1(&leave).unwrap_throw();
+                        if board_controller.settings.sandbox {
+                            listen!(&leave, "click", UiEvent::MainMenu);
+                        } else {
+                            listen!(&leave, "click", UiEvent::AskFirst(Box::new(UiEvent::MainMenu)));
+                        }
+
+                        let rules: web_sys::HtmlElement = create_element(&document, "div");
+                        rules.set_id("rules");
+                        rules.set_hidden(!self.show_rules);
+                        main.append_with_node_1(&rules).unwrap_throw();
+                        let mut sections = vec![
+                            ("Rules", board_controller.settings.rules()),
+                            ("Controls", CONTROL_HINTS.iter().map(|hint| hint.to_string()).collect()),
+                        ];
+                        if !board_controller.events.is_empty() {
+                            sections.push(("Events", board_controller.events.iter().map(|event| event.badge()).collect()));
+                        }
+                        for (title, lines) in &sections {
+                            let header: web_sys::HtmlElement = create_element_with_text(&document, "h2", title);
+                            rules.append_with_node_1(&header).unwrap_throw();
+                            let list: web_sys::HtmlElement = create_element(&document, "ul");
+                            rules.append_with_node_1(&list).unwrap_throw();
+                            for line in lines {
+                                let item: web_sys::HtmlElement = create_element_with_text(&document, "li", line);
+                                list.append_with_node_1(&item).unwrap_throw();
+                            }
+                        }
+                        let close: web_sys::HtmlElement = create_element_with_text(&document, "button", "Close");
+                        rules.append_with_node_1(&close).unwrap_throw();
+                        listen!(&close, "click", UiEvent::ToggleRules);
+
+                        let quick_chat: web_sys::HtmlElement = create_element(&document, "div");
+                        quick_chat.set_id("quick-chat");
+                        main.append_with_node_1(&quick_chat).unwrap_throw();
+                        for (i, phrase) in anim::quick_chats().iter().enumerate() {
+                            let text = format!("{}: {}", i + 1, phrase);
+                            let button: web_sys::HtmlElement = create_element_with_text(&document, "button", &text);
+                            quick_chat.append_with_node_1(&button).unwrap_throw();
+                            listen!(&button, "click", UiEvent::QuickChat(i));
+                        }
+
                         let canvas: web_sys::HtmlCanvasElement = create_element(&document, "canvas");
                         main.append_with_node_1(&canvas).unwrap_throw();
                     }
                     NetGameState::GameOver(ref info) => {
-                        let text = format!("{} wins!", info.winner.name);
+                        let text = match info.series {
+                            Some(ref series) => match series.champion() {
+                                Some(champion) => format!("{} is the champion!", champion.name),
+                                None => format!(
+                                    "{} wins board {} of {}!",
+                                    info.winner.name, series.boards_played, series.length
+                                ),
+                            },
+                            None => format!("{} wins!", info.winner.name),
+                        };
                         let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", &text);
                         main.append_with_node_1(&header).unwrap_throw();
 
+                        if let Some(reason) = info.win_reason.describe() {
+                            let reason: web_sys::HtmlElement = create_element_with_text(&document, "p", reason);
+                            main.append_with_node_1(&reason).unwrap_throw();
+                        }
+
+                        if let Some(ref series) = info.series {
+                            let standings: web_sys::HtmlElement = create_element(&document, "table");
+                            standings.set_id("standings");
+                            main.append_with_node_1(&standings).unwrap_throw();
+                            let header: web_sys::HtmlElement = create_element(&document, "tr");
+                            standings.append_with_node_1(&header).unwrap_throw();
+                            for title in &["Player", "Targets", "Boards Won", ""] {
+                                let cell: web_sys::HtmlElement = create_element_with_text(&document, "th", title);
+                                header.append_with_node_1(&cell).unwrap_throw();
+                            }
+                            for (player, total, wins) in series.standings() {
+                                let row: web_sys::HtmlElement = create_element(&document, "tr");
+                                row.set_id(&format!("standing-{}", player.id));
+                                standings.append_with_node_1(&row).unwrap_throw();
+                                let ready = if series.ready.contains(&player.id) { "Ready" } else { "" };
+                                let cells = [player.name.clone(), total.to_string(), wins.to_string(), ready.to_string()];
+                                for text in &cells {
+                                    let cell: web_sys::HtmlElement = create_element_with_text(&document, "td", text);
+                                    row.append_with_node_1(&cell).unwrap_throw();
+                                }
+                            }
+
+                            match info.next_settings {
+                                Some(ref settings) if is_host => {
+                                    let settings_form: web_sys::HtmlElement = create_element(&document, "form");
+                                    settings_form.set_id("next-settings");
+                                    main.append_with_node_1(&settings_form).unwrap_throw();
+
+                                    let size_presets: web_sys::HtmlElement = create_element(&document, "fieldset");
+                                    settings_form.append_with_node_1(&size_presets).unwrap_throw();
+                                    let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Next Board Size");
+                                    size_presets.append_with_node_1(&legend).unwrap_throw();
+                                    for &(name, size) in SIZE_PRESETS.iter() {
+                                        let text = format!("{} ({}×{})", name, size, size);
+                                        let preset: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", &text);
+                                        preset.set_id(&format!("size-preset-{}", size));
+                                        preset.set_disabled(settings.width == size && settings.height == size);
+                                        size_presets.append_with_node_1(&preset).unwrap_throw();
+                                        listen!(&preset, "click", UiEvent::SetSize(size, size));
+                                    }
+
+                                    let text = self.size_error.as_ref().map_or("", String::as_str);
+                                    let size_error: web_sys::HtmlElement = create_element_with_text(&document, "p", text);
+                                    size_error.set_id("size-error");
+                                    settings_form.append_with_node_1(&size_error).unwrap_throw();
+
+                                    let score_limit_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Score Limit");
+                                    settings_form.append_with_node_1(&score_limit_label).unwrap_throw();
+                                    let score_limit: web_sys::HtmlInputElement = create_element(&document, "input");
+                                    score_limit.set_name("score_limit");
+                                    score_limit.set_type("number");
+                                    score_limit.set_min("1");
+                                    score_limit.set_max(&format!("{}", settings.max_score_limit()));
+                                    score_limit.set_step("1");
+                                    score_limit.set_value(&format!("{}", settings.score_limit));
+                                    listen!(&score_limit, "input", score_limit => score_limit.value().parse().ok().map(UiEvent::SetScoreLimit));
+                                    score_limit_label.append_with_node_1(&score_limit).unwrap_throw();
+
+                                    let turn_time_limit_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Seconds Per Turn (0 for unlimited)");
+                                    settings_form.append_with_node_1(&turn_time_limit_label).unwrap_throw();
+                                    let turn_time_limit: web_sys::HtmlInputElement = create_element(&document, "input");
+                                    turn_time_limit.set_name("turn_time_limit");
+                                    turn_time_limit.set_type("number");
+                                    turn_time_limit.set_min("0");
+                                    turn_time_limit.set_max(&format!("{}", MAX_TURN_TIME_LIMIT));
+                                    turn_time_limit.set_step("5");
+                                    turn_time_limit.set_value(&format!("{}", settings.turn_time_limit));
+                                    listen!(&turn_time_limit, "input", turn_time_limit => turn_time_limit.value().parse().ok().map(UiEvent::SetTurnTimeLimit));
+                                    turn_time_limit_label.append_with_node_1(&turn_time_limit).unwrap_throw();
+                                }
+                                Some(ref settings) => {
+                                    let next: web_sys::HtmlElement = create_element_with_text(&document, "p", &next_board_text(settings));
+                                    next.set_id("next-settings");
+                                    main.append_with_node_1(&next).unwrap_throw();
+                                }
+                                None => {}
+                            }
+
+                            if !series.is_over() {
+                                if is_host {
+                                    let next_board: web_sys::HtmlElement = create_element_with_text(&document, "button", "Next Board");
+                                    main.append_with_node_1(&next_board).unwrap_throw();
+                                    listen!(&next_board, "click", UiEvent::NextBoard);
+                                } else {
+                                    let ready: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", "Ready");
+                                    ready.set_id("series-ready");
+                                    ready.set_disabled(series.ready.contains(&self.player_id));
+                                    main.append_with_node_1(&ready).unwrap_throw();
+                                    listen!(&ready, "click", UiEvent::SeriesReady);
+                                }
+                            }
+                        }
+
+                        let previous: web_sys::HtmlElement = create_element_with_text(&document, "button", "Previous Turn");
+                        main.append_with_node_1(&previous).unwrap_throw();
+                        listen!(&previous, "click", UiEvent::Review(-1));
+
+                        let next: web_sys::HtmlElement = create_element_with_text(&document, "button", "Next Turn");
+                        main.append_with_node_1(&next).unwrap_throw();
+                        listen!(&next, "click", UiEvent::Review(1));
+
                         let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                         main.append_with_node_1(&main_menu).unwrap_throw();
-                        listen!(&main_menu, "click", self.main_menu());
+                        listen!(&main_menu, "click", UiEvent::MainMenu);
+
+                        // the server only hands out the link once it's kept the replay, if it keeps them at all
+                        let download: web_sys::HtmlElement = create_element_with_text(&document, "a", "Download Replay");
+                        download.set_id("replay-download");
+                        let link = conn_state.sender.replay_link();
+                        download.set_attribute("href", link.as_deref().unwrap_or("")).unwrap_throw();
+                        download.set_hidden(link.is_none());
+                        main.append_with_node_1(&download).unwrap_throw();
+
+                        let turn = self.view.review_turn(&info.final_board);
+                        let board = &info.final_board;
+                        let text = board.log.describe_turn(turn, &board.players, board.settings.wrap_tokens);
+                        let annotation: web_sys::HtmlElement = create_element_with_text(&document, "p", &text);
+                        annotation.set_id("review-annotation");
+                        main.append_with_node_1(&annotation).unwrap_throw();
+
+                        let canvas: web_sys::HtmlCanvasElement = create_element(&document, "canvas");
+                        main.append_with_node_1(&canvas).unwrap_throw();
                     }
                     NetGameState::Error(ref text) => {
                         let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", "Error");
@@ -785,9 +3603,11 @@ impl GameController {
                         let body: web_sys::HtmlElement = create_element_with_text(&document, "p", text);
                         main.append_with_node_1(&body).unwrap_throw();
 
+                        create_diagnostics!();
+
                         let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                         main.append_with_node_1(&main_menu).unwrap_throw();
-                        listen!(&main_menu, "click", self.main_menu());
+                        listen!(&main_menu, "click", UiEvent::MainMenu);
                     }
                 }
             }
@@ -798,9 +3618,11 @@ impl GameController {
                 let body: web_sys::HtmlElement = create_element_with_text(&document, "p", text);
                 main.append_with_node_1(&body).unwrap_throw();
 
+                create_diagnostics!();
+
                 let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                 main.append_with_node_1(&main_menu).unwrap_throw();
-                listen!(&main_menu, "click", self.main_menu());
+                listen!(&main_menu, "click", UiEvent::MainMenu);
             }
             GameState::Options(ref curr_options) => {
                 let header: web_sys::HtmlElement = create_element_with_text(&document, "h1", "Options");
@@ -812,7 +3634,7 @@ impl GameController {
                 let music_slider: web_sys::HtmlInputElement = create_element(&document, "input");
                 music_slider.set_type("range");
                 music_slider.set_value(&format!("{}", curr_options.music_level));
-                listen!(&music_slider, "input", self.set_music_level(music_slider));
+                listen!(&music_slider, "input", music_slider => music_slider.value().parse().ok().map(UiEvent::SetMusicLevel));
                 music.append_with_node_1(&music_slider).unwrap_throw();
                 main.append_with_node_1(&music).unwrap_throw();
 
@@ -822,19 +3644,124 @@ impl GameController {
                 let sound_slider: web_sys::HtmlInputElement = create_element(&document, "input");
                 sound_slider.set_type("range");
                 sound_slider.set_value(&format!("{}", curr_options.sound_level));
-                listen!(&sound_slider, "input", self.set_sound_level(sound_slider));
+                listen!(&sound_slider, "input", sound_slider => sound_slider.value().parse().ok().map(UiEvent::SetSoundLevel));
                 sound.append_with_node_1(&sound_slider).unwrap_throw();
                 main.append_with_node_1(&sound).unwrap_throw();
 
+                let ambience: web_sys::Element = create_element(&document, "label");
+                let ambience_label = document.create_text_node("Ambience Level");
+                ambience.append_with_node_1(&ambience_label).unwrap_throw();
+                let ambience_slider: web_sys::HtmlInputElement = create_element(&document, "input");
+                ambience_slider.set_type("range");
+                ambience_slider.set_value(&format!("{}", curr_options.ambience_level));
+                listen!(&ambience_slider, "input", ambience_slider => ambience_slider.value().parse().ok().map(UiEvent::SetAmbienceLevel));
+                ambience.append_with_node_1(&ambience_slider).unwrap_throw();
+                main.append_with_node_1(&ambience).unwrap_throw();
+
+                let rotate_assist_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Suggest Tile Rotation");
+                rotate_assist_label.set_title("Points out which way to turn the loose tile to reach the most, and R takes the suggestion");
+                let rotate_assist: web_sys::HtmlInputElement = create_element(&document, "input");
+                rotate_assist.set_type("checkbox");
+                rotate_assist.set_checked(curr_options.rotate_assist);
+                listen!(&rotate_assist, "change", rotate_assist => Some(UiEvent::SetRotateAssist(rotate_assist.checked())));
+                rotate_assist_label.append_with_node_1(&rotate_assist).unwrap_throw();
+                main.append_with_node_1(&rotate_assist_label).unwrap_throw();
+
+                let move_history_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Show Recent Moves");
+                move_history_label.set_title("Draws fading arrows on the board for the last few pushes and moves");
+                let move_history: web_sys::HtmlInputElement = create_element(&document, "input");
+                move_history.set_type("checkbox");
+                move_history.set_checked(curr_options.move_history);
+                listen!(&move_history, "change", move_history => Some(UiEvent::SetMoveHistory(move_history.checked())));
+                move_history_label.append_with_node_1(&move_history).unwrap_throw();
+                main.append_with_node_1(&move_history_label).unwrap_throw();
+
+                let skip_confirmations_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Skip Confirmations");
+                skip_confirmations_label.set_title("Leaves games and removes players right away, without asking first");
+                let skip_confirmations: web_sys::HtmlInputElement = create_element(&document, "input");
+                skip_confirmations.set_type("checkbox");
+                skip_confirmations.set_checked(curr_options.skip_confirmations);
+                listen!(&skip_confirmations, "change", skip_confirmations => Some(UiEvent::SetSkipConfirmations(skip_confirmations.checked())));
+                skip_confirmations_label.append_with_node_1(&skip_confirmations).unwrap_throw();
+                main.append_with_node_1(&skip_confirmations_label).unwrap_throw();
+
+                let themes: web_sys::HtmlElement = create_element(&document, "fieldset");
+                main.append_with_node_1(&themes).unwrap_throw();
+                let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Theme");
+                themes.append_with_node_1(&legend).unwrap_throw();
+                for (i, (label, name)) in theme_choices().into_iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", label);
+                    choice.set_id(&format!("theme-{}", i));
+                    choice.set_disabled(curr_options.theme == name);
+                    themes.append_with_node_1(&choice).unwrap_throw();
+                    listen!(&choice, "click", UiEvent::SetTheme(name.to_string()));
+                }
+
+                let narrations: web_sys::HtmlElement = create_element(&document, "fieldset");
+                narrations.set_title("Captions always show in the tutorial, and this is what plays as each step starts");
+                main.append_with_node_1(&narrations).unwrap_throw();
+                let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Tutorial Narration");
+                narrations.append_with_node_1(&legend).unwrap_throw();
+                for (i, &(narration, label)) in options::Narration::ALL.iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", label);
+                    choice.set_id(&format!("narration-{}", i));
+                    choice.set_disabled(curr_options.tutorial_narration == narration);
+                    narrations.append_with_node_1(&choice).unwrap_throw();
+                    listen!(&choice, "click", UiEvent::SetTutorialNarration(narration));
+                }
+
+                let rotations: web_sys::HtmlElement = create_element(&document, "fieldset");
+                rotations.set_title("Turns the board on this screen clockwise, so its bottom can be nearest the corner you start in");
+                main.append_with_node_1(&rotations).unwrap_throw();
+                let legend: web_sys::HtmlElement = create_element_with_text(&document, "legend", "Board Rotation");
+                rotations.append_with_node_1(&legend).unwrap_throw();
+                for (i, &(rotation, label)) in options::BoardRotation::ALL.iter().enumerate() {
+                    let choice: web_sys::HtmlButtonElement = create_element_with_text(&document, "button", label);
+                    choice.set_id(&format!("rotation-{}", i));
+                    choice.set_disabled(curr_options.board_rotation == rotation);
+                    rotations.append_with_node_1(&choice).unwrap_throw();
+                    listen!(&choice, "click", UiEvent::SetBoardRotation(rotation));
+                }
+
+                let webhook_label: web_sys::HtmlElement = create_element_with_text(&document, "label", "Webhook URL");
+                webhook_label.set_title("The server tells this HTTPS URL when games you host are created, start, and end, if it allows that host");
+                let webhook: web_sys::HtmlInputElement = create_element(&document, "input");
+                webhook.set_type("url");
+                webhook.set_placeholder("https://example.com/dynamaze");
+                webhook.set_value(&curr_options.webhook_url);
+                listen!(&webhook, "input", webhook => Some(UiEvent::SetWebhookUrl(webhook.value())));
+                webhook_label.append_with_node_1(&webhook).unwrap_throw();
+                main.append_with_node_1(&webhook_label).unwrap_throw();
+
                 let save_button: web_sys::HtmlElement = create_element_with_text(&document, "button", "Save");
                 main.append_with_node_1(&save_button).unwrap_throw();
-                listen!(&save_button, "click", self.save_options());
+                listen!(&save_button, "click", UiEvent::SaveOptions);
 
                 let main_menu: web_sys::HtmlElement = create_element_with_text(&document, "button", "Main Menu");
                 main.append_with_node_1(&main_menu).unwrap_throw();
-                listen!(&main_menu, "click", self.main_menu());
+                listen!(&main_menu, "click", UiEvent::MainMenu);
             }
         }
+
+        let confirm: web_sys::HtmlElement = create_element(&document, "div");
+        confirm.set_id("confirm");
+        confirm.set_hidden(self.confirming.is_none());
+        main.append_with_node_1(&confirm).unwrap_throw();
+        let confirm_box: web_sys::HtmlElement = create_element(&document, "div");
+        confirm.append_with_node_1(&confirm_box).unwrap_throw();
+        let prompt = self.confirming.as_ref().map_or("", |event| confirm_prompt(event));
+        let prompt_text: web_sys::HtmlElement = create_element_with_text(&document, "p", prompt);
+        prompt_text.set_id("confirm-prompt");
+        confirm_box.append_with_node_1(&prompt_text).unwrap_throw();
+        let yes: web_sys::HtmlElement = create_element_with_text(&document, "button", "Yes");
+        confirm_box.append_with_node_1(&yes).unwrap_throw();
+        listen!(&yes, "click", UiEvent::Confirm);
+        let no: web_sys::HtmlElement = create_element_with_text(&document, "button", "No");
+        confirm_box.append_with_node_1(&no).unwrap_throw();
+        listen!(&no, "click", UiEvent::CancelConfirm);
+
+        // a fresh canvas needs sizing before anything gets drawn on it
+        self.on_resize(main);
     }
 }
 
@@ -843,3 +3770,80 @@ impl Default for GameController {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu_controller() -> GameController {
+        let (events, event_queue) = mpsc::channel();
+        GameController {
+            state: GameState::MainMenu,
+            player_id: 1,
+            own_id: 1,
+            last_player: None,
+            view: GameView::new(),
+            sound_engine: SoundEngine::silent(),
+            events,
+            event_queue,
+            listeners: vec![],
+            settings_dirty: false,
+            settings_sent_at: 0.0,
+            connecting_to: None,
+            lobby_query: None,
+            size_error: None,
+            show_rules: false,
+            lonely_since: None,
+            input_sources: BTreeMap::new(),
+            gamepads: GamepadState::default(),
+            party: LocalPlanning::default(),
+            bot_clock: None,
+            spectating: false,
+            heard_events: None,
+            toasts: Toasts::default(),
+            confirming: None,
+            page_progress: Default::default(),
+            remembered_game: false,
+            spectators_turn: None,
+            turn_clock: None,
+            narrated_step: None,
+        }
+    }
+
+    #[test]
+    fn queued_events_are_all_handled() {
+        let mut controller = menu_controller();
+        controller.events.send(UiEvent::ToggleRules).unwrap();
+        controller.events.send(UiEvent::CycleInputSource(2)).unwrap();
+        controller.events.send(UiEvent::CycleInputSource(2)).unwrap();
+        controller.handle_queued_events();
+        assert!(controller.show_rules);
+        assert_eq!(controller.input_sources.get(&2), Some(&InputSource::Any.next().next()));
+    }
+
+    #[test]
+    fn events_past_the_limit_wait_a_tick() {
+        let mut controller = menu_controller();
+        for id in 0..=MAX_EVENTS_PER_TICK {
+            controller.events.send(UiEvent::CycleInputSource(id as PlayerID)).unwrap();
+        }
+        controller.handle_queued_events();
+        assert_eq!(controller.input_sources.len(), MAX_EVENTS_PER_TICK);
+        controller.handle_queued_events();
+        assert_eq!(controller.input_sources.len(), MAX_EVENTS_PER_TICK + 1);
+    }
+
+    #[test]
+    fn confirming_waits_for_an_answer() {
+        let mut controller = menu_controller();
+        controller.confirming = Some(Box::new(UiEvent::ToggleRules));
+        controller.handle_event(UiEvent::CancelConfirm);
+        assert!(controller.confirming.is_none());
+        assert!(!controller.show_rules);
+
+        controller.confirming = Some(Box::new(UiEvent::ToggleRules));
+        controller.handle_event(UiEvent::Confirm);
+        assert!(controller.confirming.is_none());
+        assert!(controller.show_rules);
+    }
+}
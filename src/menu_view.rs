@@ -1,24 +1,43 @@
 //! Menu / Game view
 
+use std::cell::RefCell;
+
 use web_sys::CanvasRenderingContext2d as Context;
 
-use crate::{BoardView, BoardViewSettings, GameController};
+use crate::{BoardController, BoardView, BoardViewSettings, GameController, PlayerID};
 use crate::menu::{GameState, NetGameState};
 
 /// Stores visual information about the game
 pub struct GameView {
     /// Stores board view settings
     pub board_view: BoardView,
+    /// Turn being shown in post-game review, or None for the end of the game
+    pub review_turn: Option<usize>,
+    /// Board rebuilt for post-game review, and what it was rebuilt for, so it only gets rebuilt when
+    /// the turn being shown changes
+    pub replayed: RefCell<Option<(ReplayKey, BoardController)>>,
 }
 
+/// Which game and which turn a board was rebuilt for: the turn, the events in the game, and the
+/// turn order it started with
+type ReplayKey = (usize, usize, Vec<PlayerID>);
+
 impl GameView {
     /// Create a new GameView
     pub fn new() -> GameView {
         GameView {
             board_view: BoardView::new(BoardViewSettings::new()),
+            review_turn: None,
+            replayed: Default::default(),
         }
     }
 
+    /// Gets the turn being shown in post-game review
+    pub fn review_turn(&self, board: &BoardController) -> usize {
+        let turns = board.log.turn_count();
+        self.review_turn.map_or(turns, |turn| turn.min(turns))
+    }
+
     /// Draw game
     pub fn draw(&self, controller: &GameController, ctx: &Context) {
         ctx.save();
@@ -34,10 +53,38 @@ impl GameView {
                     NetGameState::Connecting => {}
                     NetGameState::Lobby(_) => {}
                     NetGameState::Active(ref board_controller) => {
+                        // a party mode turn being planned gets drawn instead of the real board
+                        let board_controller = match controller.party.draft {
+                            Some(ref draft) => &draft.board,
+                            None => board_controller,
+                        };
+                        self.board_view
+                            .draw(board_controller, controller.player_id, ctx);
+                    }
+                    NetGameState::GameOver(ref info) => {
+                        let turn = self.review_turn(&info.final_board);
+                        let log = &info.final_board.log;
+                        let key = (turn, log.event_count(), log.initial_turn_order.clone());
+                        let mut replayed = self.replayed.borrow_mut();
+                        let board_controller = match *replayed {
+                            Some((ref cached, ref board)) if *cached == key => board,
+                            _ => {
+                                let mut board = info.final_board.replay_at(turn);
+                                // the whole board's revealed once the game's over
+                                board.settings.fog_distance = 0;
+                                &replayed.insert((key, board)).1
+                            }
+                        };
                         self.board_view
                             .draw(board_controller, controller.player_id, ctx);
+                        // at the very end, show how the game was won
+                        if turn == info.final_board.log.turn_count() {
+                            if let Some(path) = info.final_board.winning_path() {
+                                self.board_view
+                                    .draw_path(board_controller, &path, info.winner.color, ctx);
+                            }
+                        }
                     }
-                    NetGameState::GameOver(_) => {}
                     NetGameState::Error(_) => {}
                 }
             }
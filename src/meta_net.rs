@@ -3,10 +3,60 @@ use serde::{Deserialize, Serialize};
 
 pub type GameID = u16;
 
+/// The player ID a client goes by, as far as the server cares
+pub type PeerID = u64;
+
 /// A network control message
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MetaMessage {
     Join(GameID),
     Leave,
+    /// Server's answer to `Leave`, once the client is out of the game
+    LeftAck,
     Message(Vec<u8>),
+    /// Same as `Message`, but JSON-encoded, for debugging
+    Text(String),
+    /// Ask the server about a game without joining it
+    QueryGame(GameID),
+    /// Tell the server the current game has started
+    Started,
+    /// Server's answer to `QueryGame`
+    GameInfo {
+        game: GameID,
+        /// Number of clients connected to the game
+        players: usize,
+        started: bool,
+    },
+    /// Tell the server which player this client is
+    Identify(PeerID),
+    /// Someone else in the game connected, sent by the server
+    PeerJoined(PeerID),
+    /// Someone else in the game disconnected, sent by the server
+    PeerLeft(PeerID),
+    /// The server shut down the current game, for the given reason
+    GameClosed(String),
+    /// Announcement from the server to everyone, like planned maintenance
+    Notice(String),
+    /// Round trip times between the server and everyone in the game it's heard from, in milliseconds
+    Latency(Vec<(PeerID, u32)>),
+    /// Ask the server to POST the current game's events to the given URL, sent by the host
+    SetWebhook(String),
+    /// Tell the server how the current game ended, sent by the host
+    Finished {
+        winner: String,
+        /// Each player's name and score, in turn order
+        scores: Vec<(String, u8)>,
+    },
+    /// The finished game's log, JSON-encoded, sent by the host after `Finished` so the server can
+    /// keep it for players to download
+    Replay(String),
+    /// Key the server kept the finished game's log under, for downloading it from `/replays/{key}`,
+    /// sent by the server to everyone in the game
+    Archived(String),
+    /// Same as the wrapped `Message` or `Text`, but only passed along to the given peers in the game
+    To(Vec<PeerID>, Box<MetaMessage>),
+    /// Same as `Message`, but deflated, from clients that asked to shrink what they send
+    Deflated(Vec<u8>),
+    /// Tell the server the current game is still wanted, so it doesn't get closed for sitting idle
+    KeepAlive,
 }
@@ -1,19 +1,33 @@
 //! Networking logic
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Cursor;
 use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use bincode::{deserialize, serialize};
+use rand::random;
 use gloo::events::EventListener;
+use gloo::timers::callback::Timeout;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 
 use crate::{BoardSettings, Player, PlayerID};
 use crate::anim;
+use crate::bot;
+use crate::diagnostics;
+use crate::embed;
 use crate::menu::NetGameState;
+use crate::net_sim::NetSim;
+use crate::series::SeriesState;
+use crate::traffic::{TrafficMeter, TrafficRates};
 pub use crate::meta_net::{GameID, MetaMessage};
 
 /// A message that can be sent over the network
+// whole states are rare and get serialized right away, so they aren't worth boxing
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     /// Join a lobby
@@ -22,29 +36,568 @@ pub enum Message {
     State(NetGameState),
     /// Edit player info
     EditPlayer(PlayerID, Player),
-    /// Edit game settings
+    /// Propose an edit to game settings
     EditSettings(BoardSettings),
+    /// Authoritative game settings, only sent by the host
+    Settings(BoardSettings),
     /// Synchronize animation state
     Anim(anim::AnimSync),
+    /// Someone tried to join with an ID that's already in the lobby, only sent by the host
+    IdConflict(PlayerID),
+    /// Ask to be let into a game that's already started
+    RequestJoinActive(Player),
+    /// The host turned down the given player's request to join a game that's already started,
+    /// only sent by the host
+    JoinDeclined(PlayerID),
+    /// The given client is leaving the game, along with any local players of theirs
+    PlayerLeft(PlayerID),
+    /// Take a single player out of the lobby, sent by whoever controls them
+    LeaveLobby(PlayerID),
+    /// Hash of the host's board, only sent by the host
+    StateDigest(u64),
+    /// Ask the host for the entire game state
+    RequestState,
+    /// The given player is ready for the next board of the series
+    SeriesReady(PlayerID),
+    /// Authoritative series standings between boards, only sent by the host
+    Standings(Box<SeriesState>),
+    /// The given player's plan for the given round of party mode
+    Plan(PlayerID, u32, bot::Move),
 }
 
-impl Into<MetaMessage> for Message {
+impl Message {
+    /// Summarizes this message for diagnostics, leaving out player names
+    pub fn summary(&self) -> String {
+        match self {
+            Message::JoinLobby(player) => format!("JoinLobby({})", player.id),
+            Message::State(state) => format!("State({})", state.name()),
+            Message::EditPlayer(id, _) => format!("EditPlayer({})", id),
+            Message::EditSettings(settings) => format!("EditSettings(v{})", settings.version),
+            Message::Settings(settings) => format!("Settings(v{})", settings.version),
+            Message::Anim(sync) => format!("Anim({:?})", sync),
+            Message::IdConflict(id) => format!("IdConflict({})", id),
+            Message::RequestJoinActive(player) => format!("RequestJoinActive({})", player.id),
+            Message::JoinDeclined(id) => format!("JoinDeclined({})", id),
+            Message::PlayerLeft(id) => format!("PlayerLeft({})", id),
+            Message::LeaveLobby(id) => format!("LeaveLobby({})", id),
+            Message::StateDigest(digest) => format!("StateDigest({:x})", digest),
+            Message::RequestState => "RequestState".to_string(),
+            Message::SeriesReady(id) => format!("SeriesReady({})", id),
+            Message::Standings(series) => {
+                format!("Standings({} of {})", series.boards_played, series.length)
+            }
+            Message::Plan(id, round, _) => format!("Plan({}, round {})", id, round),
+        }
+    }
+}
+
+impl MetaMessage {
+    /// Gets the size of the game message this carries, in bytes, or 0 if it doesn't carry one
+    fn size(&self) -> usize {
+        match self {
+            MetaMessage::Message(data) | MetaMessage::Deflated(data) => data.len(),
+            MetaMessage::Text(text) => text.len(),
+            MetaMessage::To(_, message) => message.size(),
+            _ => 0,
+        }
+    }
+
+    /// Summarizes this message for diagnostics
+    pub fn summary(&self) -> String {
+        match self {
+            MetaMessage::Join(game) => format!("Join({})", game),
+            MetaMessage::Leave => "Leave".to_string(),
+            MetaMessage::LeftAck => "LeftAck".to_string(),
+            MetaMessage::Message(data) => format!("Message({} bytes)", data.len()),
+            MetaMessage::Text(text) => format!("Text({} bytes)", text.len()),
+            MetaMessage::QueryGame(game) => format!("QueryGame({})", game),
+            MetaMessage::Started => "Started".to_string(),
+            MetaMessage::GameInfo { game, players, started } => {
+                format!("GameInfo({}, {} players, started: {})", game, players, started)
+            }
+            MetaMessage::Identify(peer) => format!("Identify({})", peer),
+            MetaMessage::PeerJoined(peer) => format!("PeerJoined({})", peer),
+            MetaMessage::PeerLeft(peer) => format!("PeerLeft({})", peer),
+            MetaMessage::GameClosed(_) => "GameClosed".to_string(),
+            MetaMessage::Notice(_) => "Notice".to_string(),
+            MetaMessage::Latency(times) => format!("Latency({} clients)", times.len()),
+            MetaMessage::SetWebhook(_) => "SetWebhook".to_string(),
+            MetaMessage::Finished { scores, .. } => format!("Finished({} players)", scores.len()),
+            MetaMessage::Replay(replay) => format!("Replay({} bytes)", replay.len()),
+            MetaMessage::Archived(_) => "Archived".to_string(),
+            MetaMessage::To(peers, message) => format!("To({} peers, {})", peers.len(), message.summary()),
+            MetaMessage::Deflated(data) => format!("Deflated({} bytes)", data.len()),
+            MetaMessage::KeepAlive => "KeepAlive".to_string(),
+        }
+    }
+}
+
+/// A message along with the player who sent it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Envelope {
+    /// ID of the player whose client sent the message
+    pub sender: PlayerID,
+    /// Sequence number, increasing with each message from the same sender
+    pub seq: u64,
+    /// The message itself
+    pub payload: Message,
+}
+
+impl Into<MetaMessage> for Envelope {
     fn into(self) -> MetaMessage {
         let data = serialize(&self).unwrap_throw();
         MetaMessage::Message(data)
     }
 }
 
+impl Envelope {
+    /// Wraps this up to be sent, deflated if that makes it smaller
+    fn deflated(&self) -> MetaMessage {
+        let data = serialize(self).unwrap_throw();
+        if data.len() >= DEFLATE_MIN_SIZE {
+            let deflated = miniz_oxide::deflate::compress_to_vec(&data, DEFLATE_LEVEL);
+            if deflated.len() < data.len() {
+                return MetaMessage::Deflated(deflated);
+            }
+        }
+        MetaMessage::Message(data)
+    }
+}
+
+/// Messages for everyone else in the game, held by whatever made them until the connection's owner
+/// hands them to the `MessageQueue`
+#[derive(Debug, Clone, Default)]
+pub struct Outbox {
+    messages: Vec<Message>,
+}
+
+impl Outbox {
+    /// Holds a message to be sent
+    pub fn push(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    /// Takes every message held so far, oldest first
+    pub fn take(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.messages)
+    }
+}
+
+struct Outgoing {
+    messages: VecDeque<MetaMessage>,
+    next_seq: u64,
+    sender: PlayerID,
+    json: bool,
+    /// Whether big messages get deflated before they're sent
+    deflate: bool,
+}
+
+/// Queue of outgoing messages, which get tagged with the local player's ID and a sequence number
+#[derive(Clone)]
+pub struct MessageQueue {
+    outgoing: Arc<Mutex<Outgoing>>,
+    /// What's gone over the connection lately, both ways
+    traffic: Arc<Mutex<TrafficMeter>>,
+}
+
+impl MessageQueue {
+    fn new(sender: PlayerID, json: bool, deflate: bool) -> MessageQueue {
+        // start from the clock so a reconnect from the same player doesn't look like old news
+        let next_seq = (js_sys::Date::now() as u64) << 10;
+        let outgoing = Outgoing {
+            messages: VecDeque::new(),
+            next_seq,
+            sender,
+            json,
+            deflate,
+        };
+        MessageQueue {
+            outgoing: Arc::new(Mutex::new(outgoing)),
+            traffic: Default::default(),
+        }
+    }
+
+    /// Gets the ID outgoing messages are tagged with
+    pub fn sender(&self) -> PlayerID {
+        self.outgoing.lock().unwrap().sender
+    }
+
+    fn set_sender(&self, sender: PlayerID) {
+        self.outgoing.lock().unwrap().sender = sender;
+    }
+
+    fn push(&self, message: MetaMessage) {
+        self.outgoing.lock().unwrap().messages.push_back(message);
+    }
+
+    fn pop(&self) -> Option<MetaMessage> {
+        self.outgoing.lock().unwrap().messages.pop_front()
+    }
+
+    /// Queues a message to be sent
+    pub fn send(&self, message: Message) {
+        self.send_wrapped(message, |message| message);
+    }
+
+    /// Queues a message to be sent only to the given clients
+    pub fn send_to(&self, peers: Vec<PlayerID>, message: Message) {
+        self.send_wrapped(message, |message| MetaMessage::To(peers, Box::new(message)));
+    }
+
+    fn send_wrapped(&self, message: Message, wrap: impl FnOnce(MetaMessage) -> MetaMessage) {
+        let mut outgoing = self.outgoing.lock().unwrap();
+        let envelope = Envelope {
+            sender: outgoing.sender,
+            seq: outgoing.next_seq,
+            payload: message,
+        };
+        outgoing.next_seq += 1;
+        let is_state = matches!(envelope.payload, Message::State(_));
+        let message = if outgoing.json {
+            MetaMessage::Text(serde_json::to_string(&envelope).unwrap_throw())
+        } else if outgoing.deflate {
+            envelope.deflated()
+        } else {
+            envelope.into()
+        };
+        if is_state {
+            self.traffic.lock().unwrap().record_state(message.size());
+        }
+        outgoing.messages.push_back(wrap(message));
+    }
+
+    fn record_sent(&self, bytes: usize) {
+        self.traffic.lock().unwrap().record_sent(bytes);
+    }
+
+    fn record_received(&self, bytes: usize) {
+        self.traffic.lock().unwrap().record_received(bytes);
+    }
+
+    fn record_state(&self, bytes: usize) {
+        self.traffic.lock().unwrap().record_state(bytes);
+    }
+}
+
+/// Checks if the browser thinks there is no network connection
+pub fn is_offline() -> bool {
+    let window = web_sys::window().unwrap_throw();
+    !window.navigator().on_line()
+}
+
+/// Checks if a state sent by the given player should replace the current state
+fn may_replace_state(state: &NetGameState, sender: PlayerID, new_state: &NetGameState) -> bool {
+    match (state, new_state) {
+        // we don't know who the host is yet, so take whatever shows up first
+        (NetGameState::Connecting, _) => true,
+        // the host is in charge of everything
+        _ if state.is_host(sender) => true,
+        // in party mode only the host plays turns out, so nobody else has anything to report
+        (NetGameState::Active(ref board), _) if board.is_party() => false,
+        // otherwise, only the player taking their turn gets to report how it went
+        (NetGameState::Active(ref board), NetGameState::Active(_))
+        | (NetGameState::Active(ref board), NetGameState::GameOver(_)) => {
+            board.active_player().lives_with(sender)
+        }
+        _ => false,
+    }
+}
+
+/// Smallest serialized game message worth deflating, since small ones only get bigger
+const DEFLATE_MIN_SIZE: usize = 256;
+/// How hard to try when deflating game messages, from 0 to 10
+const DEFLATE_LEVEL: u8 = 6;
+/// Most a deflated game message may inflate to, so a small message can't eat all the memory there is
+const MAX_INFLATED_SIZE: usize = 16 << 20;
+
+/// How long to wait for the server to confirm we've left before closing the socket anyway, in milliseconds
+const LEAVE_ACK_TIMEOUT: u32 = 1000;
+/// Number of malformed messages tolerated before giving up on the connection
+const MAX_BAD_MESSAGES: usize = 20;
+/// Number of digests in a row that have to disagree with our board before we call it a desync,
+/// since one can cross paths with a move the host hasn't heard about yet
+const DESYNC_STRIKES: usize = 2;
+
+/// Gets how many bytes a message event brought in, whether it's text or binary
+fn frame_size(event: &web_sys::Event) -> usize {
+    let data = match event.dyn_ref::<web_sys::MessageEvent>() {
+        Some(event) => event.data(),
+        None => return 0,
+    };
+    if let Some(text) = data.as_string() {
+        return text.len();
+    }
+    data.dyn_ref::<js_sys::ArrayBuffer>()
+        .map_or(0, |data| data.byte_length() as usize)
+}
+
+fn decode_meta(event: &web_sys::Event) -> Result<MetaMessage, String> {
+    let event = event
+        .dyn_ref::<web_sys::MessageEvent>()
+        .ok_or("not a message event")?;
+    let data = event.data();
+    if let Some(text) = data.as_string() {
+        return serde_json::from_str(&text).map_err(|e| e.to_string());
+    }
+    let data = data
+        .dyn_ref::<js_sys::ArrayBuffer>()
+        .ok_or("not binary data")?;
+    let data = js_sys::Uint8Array::new(data).to_vec();
+    deserialize(&data).map_err(|e| e.to_string())
+}
+
+/// Something that came in over a game connection
+enum Incoming {
+    /// A message from another client
+    Envelope(Box<Envelope>),
+    /// The server saying another client connected (true) or disconnected (false)
+    Presence(PlayerID, bool),
+    /// The server shutting the game down, and why
+    Closed(String),
+    /// An announcement from the server
+    Notice(String),
+    /// How long clients' messages take to get to the server and back
+    Latency(Vec<(PlayerID, u32)>),
+    /// The server keeping the finished game's replay, under the given key
+    Archived(String),
+    /// The server confirming we're out of the game
+    LeftAck,
+}
+
+/// Inflates a deflated game message, as long as it isn't too big
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    let mut inflated = vec![0; (data.len() * 4).clamp(1024, MAX_INFLATED_SIZE)];
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        let mut cursor = Cursor::new(inflated.as_mut_slice());
+        cursor.set_position(out_pos as u64);
+        let (status, in_used, out_used) = decompress(&mut decompressor, &data[in_pos..], &mut cursor, flags);
+        in_pos += in_used;
+        out_pos += out_used;
+        match status {
+            TINFLStatus::Done => {
+                inflated.truncate(out_pos);
+                return Ok(inflated);
+            }
+            TINFLStatus::HasMoreOutput if inflated.len() < MAX_INFLATED_SIZE => {
+                let len = (inflated.len() * 2).min(MAX_INFLATED_SIZE);
+                inflated.resize(len, 0);
+            }
+            TINFLStatus::HasMoreOutput => return Err("deflated message too big".to_string()),
+            status => return Err(format!("bad deflated message: {:?}", status)),
+        }
+    }
+}
+
+fn decode(event: &web_sys::Event) -> Result<Incoming, String> {
+    match decode_meta(event)? {
+        MetaMessage::Message(data) => deserialize(&data)
+            .map(|envelope| Incoming::Envelope(Box::new(envelope)))
+            .map_err(|e| e.to_string()),
+        MetaMessage::Deflated(data) => deserialize(&inflate(&data)?)
+            .map(|envelope| Incoming::Envelope(Box::new(envelope)))
+            .map_err(|e| e.to_string()),
+        MetaMessage::Text(text) => serde_json::from_str(&text)
+            .map(|envelope| Incoming::Envelope(Box::new(envelope)))
+            .map_err(|e| e.to_string()),
+        MetaMessage::PeerJoined(peer) => Ok(Incoming::Presence(peer, true)),
+        MetaMessage::PeerLeft(peer) => Ok(Incoming::Presence(peer, false)),
+        MetaMessage::GameClosed(reason) => Ok(Incoming::Closed(reason)),
+        MetaMessage::Notice(text) => Ok(Incoming::Notice(text)),
+        MetaMessage::Latency(times) => Ok(Incoming::Latency(times)),
+        MetaMessage::Archived(key) => Ok(Incoming::Archived(key)),
+        MetaMessage::LeftAck => Ok(Incoming::LeftAck),
+        other => Err(format!("unexpected {}", other.summary())),
+    }
+}
+
+/// Checks if the page asked for JSON text frames with `?json`, to make the protocol readable in devtools
+fn json_debug() -> bool {
+    let window = web_sys::window().unwrap_throw();
+    let search = window.location().search().unwrap_throw();
+    search.trim_start_matches('?').split('&').any(|param| param == "json")
+}
+
+/// Checks if the page asked for big game messages to be deflated with `?deflate`, which any client
+/// can read, to see what it does to the traffic
+fn deflate_requested() -> bool {
+    let window = web_sys::window().unwrap_throw();
+    let search = window.location().search().unwrap_throw();
+    search.trim_start_matches('?').split('&').any(|param| param == "deflate")
+}
+
+/// Checks if the page asked for the debug overlay with `?debug`, to keep an eye on traffic
+pub fn debug_overlay() -> bool {
+    let window = web_sys::window().unwrap_throw();
+    let search = window.location().search().unwrap_throw();
+    search.trim_start_matches('?').split('&').any(|param| param == "debug")
+}
+
+/// Builds a spectator link for the given lobby, which opens it read-only with nothing but the board on screen
+pub fn watch_link(game: GameID) -> String {
+    let window = web_sys::window().unwrap_throw();
+    let location = window.location();
+    let origin = location.origin().unwrap_throw();
+    let path = location.pathname().unwrap_throw();
+    format!("{}{}?watch={}", origin, path, game)
+}
+
+/// Builds an invite link for the given lobby, which opens the game and joins it
+pub fn join_link(game: GameID) -> String {
+    let window = web_sys::window().unwrap_throw();
+    let location = window.location();
+    let origin = location.origin().unwrap_throw();
+    let path = location.pathname().unwrap_throw();
+    format!("{}{}?join={}", origin, path, game)
+}
+
+/// Gets the WebSocket URL of the server games are played through
+fn server_addr() -> String {
+    let is_localhost = {
+        let window = web_sys::window().unwrap_throw();
+        let location = window.location();
+        let hostname = location.hostname().unwrap_throw();
+        hostname == "127.0.0.1" || hostname == "localhost"
+    };
+    match embed::server_url() {
+        Some(url) => url,
+        None if is_localhost => "ws://127.0.0.1:8080/ws/".to_string(),
+        None => "wss://dynamaze-primary-server.herokuapp.com/ws/".to_string(),
+    }
+}
+
+/// Builds the link to download the replay the server kept under the given key
+fn replay_link(key: &str) -> String {
+    let addr = server_addr();
+    // the server answers plain HTTP alongside the socket, so ws:// becomes http:// and wss:// https://
+    let base = addr.trim_end_matches('/').trim_end_matches("/ws").replacen("ws", "http", 1);
+    format!("{}/replays/{}", base, key)
+}
+
+fn open_socket(json: bool) -> web_sys::WebSocket {
+    let addr = server_addr();
+    let socket = if json {
+        web_sys::WebSocket::new_with_str(&addr, "json").unwrap_throw()
+    } else {
+        web_sys::WebSocket::new(&addr).unwrap_throw()
+    };
+    socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    socket
+}
+
+/// What the server said about a game, before joining it
+#[derive(Clone, Copy, Debug)]
+pub struct GameInfo {
+    /// Number of clients connected to the game
+    pub players: usize,
+    /// Whether the game has already started
+    pub started: bool,
+}
+
+/// How long the server gets to answer a lobby query before we stop waiting, in seconds
+const LOBBY_QUERY_TIMEOUT: f64 = 10.0;
+
+/// What came of asking the server about a game
+#[derive(Clone, Copy, Debug)]
+pub enum QueryResult {
+    /// The server answered
+    Answered(GameInfo),
+    /// The connection failed or closed, or the server took too long, so nobody knows
+    Failed,
+}
+
+/// Asks the server about a game without joining it
+pub struct LobbyQuery {
+    /// Game being asked about
+    pub game: GameID,
+    socket: web_sys::WebSocket,
+    _listeners: Vec<EventListener>,
+    result: Arc<Mutex<Option<QueryResult>>>,
+    /// When the query was sent, so it can be given up on
+    started: f64,
+}
+
+impl Drop for LobbyQuery {
+    fn drop(&mut self) {
+        let _ = self.socket.close();
+    }
+}
+
+impl LobbyQuery {
+    /// Sends the query
+    pub fn run(game: GameID) -> LobbyQuery {
+        let socket = open_socket(false);
+        let open_socket = socket.clone();
+        let open_listener = EventListener::once(&socket, "open", move |_| {
+            let mut data = serialize(&MetaMessage::QueryGame(game)).expect_throw("Bad query");
+            if let Err(e) = open_socket.send_with_u8_array(&mut data) {
+                web_sys::console::error_1(&e);
+            }
+        });
+        let result = Arc::new(Mutex::new(None));
+        let listener_result = result.clone();
+        let message_listener = EventListener::new(&socket, "message", move |event| {
+            match decode_meta(event) {
+                Ok(MetaMessage::GameInfo { game: info_game, players, started }) if info_game == game => {
+                    *listener_result.lock().unwrap() = Some(QueryResult::Answered(GameInfo { players, started }));
+                }
+                Ok(other) => diagnostics::record("malformed", format!("unexpected {}", other.summary())),
+                Err(e) => diagnostics::record("malformed", e),
+            }
+        });
+        let mut listeners = vec![open_listener, message_listener];
+        for event in &["error", "close"] {
+            let listener_result = result.clone();
+            // an answer that already came in still counts once the socket goes away
+            listeners.push(EventListener::once(&socket, *event, move |_| {
+                listener_result.lock().unwrap().get_or_insert(QueryResult::Failed);
+            }));
+        }
+        LobbyQuery {
+            game,
+            socket,
+            _listeners: listeners,
+            result,
+            started: crate::now(),
+        }
+    }
+
+    /// Gets the server's answer, or that there won't be one, if either is known yet
+    pub fn result(&self) -> Option<QueryResult> {
+        let mut result = self.result.lock().unwrap();
+        if result.is_none() && crate::now() - self.started > LOBBY_QUERY_TIMEOUT {
+            *result = Some(QueryResult::Failed);
+        }
+        *result
+    }
+}
+
 fn handle_incoming(
-    message: Message,
+    envelope: Envelope,
     state: Arc<RwLock<NetGameState>>,
-    player_id: PlayerID,
+    queue: &MessageQueue,
+    desync: &AtomicUsize,
+    join_requests: &Mutex<VecDeque<Player>>,
 ) -> Option<Message> {
+    let Envelope { sender, payload: message, .. } = envelope;
+    let player_id = queue.sender();
+    diagnostics::record("received", message.summary());
     let mut state = state.write().expect("Failed to acquire state");
     let is_host = state.is_host(player_id);
+    let from_host = state.is_host(sender);
     match message {
         Message::JoinLobby(player) => {
             if let NetGameState::Lobby(ref mut lobby_info) = *state {
+                if lobby_info.has_player(player.id) {
+                    // letting them in would leave two players fighting over one ID
+                    if is_host {
+                        return Some(Message::IdConflict(player.id));
+                    }
+                    return None;
+                }
                 lobby_info.guests.push(player);
                 if is_host {
                     return Some(Message::State(state.clone()));
@@ -58,16 +611,202 @@ fn handle_incoming(
             }
         }
         Message::EditSettings(settings) => {
-            if let NetGameState::Lobby(ref mut lobby_info) = *state {
-                lobby_info.settings = settings;
+            if let Some(current) = state.settings_mut() {
+                if is_host {
+                    // the host settles every edit, so nobody ends up with a different idea of the settings
+                    let size_ok = BoardSettings::check_size(settings.width, settings.height).is_ok();
+                    if settings.version > current.version && size_ok {
+                        *current = settings;
+                        embed::lock_settings(current);
+                    } else {
+                        // stale or conflicting edit, so keep ours but move past theirs
+                        current.version = current.version.max(settings.version) + 1;
+                    }
+                    return Some(Message::Settings(current.clone()));
+                }
+            }
+        }
+        Message::Settings(settings) => {
+            if !from_host {
+                return None;
+            }
+            if let Some(current) = state.settings_mut() {
+                // anything older than this is an edit of ours the host hasn't seen yet
+                if settings.version >= current.version {
+                    *current = settings;
+                }
             }
         }
-        Message::State(new_state) => {
-            *state = new_state;
+        Message::State(mut new_state) => {
+            if may_replace_state(&state, sender, &new_state) {
+                new_state.reconcile(&mut state);
+                *state = new_state;
+            } else if is_host {
+                // whoever sent that is out of sync, so set them straight
+                return Some(Message::State(state.clone()));
+            }
         }
         Message::Anim(sync) => {
-            anim::STATE.write().unwrap().apply(sync);
+            // nobody gets to talk on someone else's behalf
+            if let anim::AnimSync::QuickChat(id, _) = sync {
+                if id != sender {
+                    return None;
+                }
+            }
+            if let NetGameState::Active(ref mut board) = *state {
+                board.anim.apply(sync);
+            }
+        }
+        Message::RequestJoinActive(player) => {
+            if let NetGameState::Active(ref board) = *state {
+                // nobody gets in partway through until the host says so
+                let allowed = player.lives_with(sender) && !board.players.contains_key(&player.id);
+                if is_host && allowed {
+                    let mut requests = join_requests.lock().unwrap();
+                    if requests.iter().all(|p| p.id != player.id) {
+                        requests.push_back(player);
+                    }
+                }
+            }
+        }
+        Message::JoinDeclined(id) => {
+            // same as with an ID conflict, we don't know who the host is until we're let in
+            if let NetGameState::Connecting = *state {
+                if id == player_id {
+                    *state = NetGameState::Error("The host didn't let you into the game".to_string());
+                }
+            }
+        }
+        Message::PlayerLeft(id) => {
+            // nobody gets to leave on someone else's behalf
+            if id != sender {
+                return None;
+            }
+            match *state {
+                NetGameState::Lobby(ref lobby_info) if lobby_info.host.id == id => {
+                    *state = NetGameState::Error("The host left the game".to_string());
+                }
+                NetGameState::Lobby(ref mut lobby_info) => {
+                    lobby_info.remove_client(id);
+                }
+                NetGameState::Active(ref mut board) => {
+                    // they're not coming back, so their token and target would only get in the way
+                    let changed = is_host && board.retire_client(id);
+                    if changed {
+                        return Some(Message::State(state.for_broadcast()));
+                    }
+                }
+                _ => (),
+            }
+        }
+        Message::LeaveLobby(id) => {
+            if let NetGameState::Lobby(ref mut lobby_info) = *state {
+                let allowed = lobby_info
+                    .guests
+                    .iter()
+                    .any(|p| p.id == id && (p.lives_with(sender) || from_host));
+                if allowed {
+                    lobby_info.remove_player(id);
+                }
+            }
+        }
+        Message::StateDigest(digest) => {
+            if !from_host || is_host {
+                return None;
+            }
+            if let NetGameState::Active(ref board) = *state {
+                if board.digest() == digest {
+                    desync.store(0, Ordering::SeqCst);
+                } else {
+                    desync.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        Message::RequestState => {
+            if is_host {
+                return Some(Message::State(state.clone()));
+            }
+        }
+        Message::SeriesReady(id) => {
+            if let NetGameState::GameOver(ref mut info) = *state {
+                // nobody gets to be ready on someone else's behalf
+                let allowed = match info.final_board.players.get(&id) {
+                    Some(player) => player.lives_with(sender),
+                    None => false,
+                };
+                if let (Some(series), true) = (&mut info.series, allowed && is_host) {
+                    series.ready.insert(id);
+                    return Some(Message::Standings(series.clone()));
+                }
+            }
+        }
+        Message::Standings(standings) => {
+            if !from_host {
+                return None;
+            }
+            if let NetGameState::GameOver(ref mut info) = *state {
+                // a late one from the last board shouldn't undo this board's results
+                let current = info.series.as_ref().map(|s| s.boards_played);
+                if current == Some(standings.boards_played) {
+                    info.series = Some(standings);
+                }
+            }
+        }
+        Message::Plan(id, round, plan) => {
+            if let NetGameState::Active(ref mut board) = *state {
+                // nobody gets to plan on someone else's behalf
+                let allowed = match board.players.get(&id) {
+                    Some(player) => player.lives_with(sender),
+                    None => false,
+                };
+                if is_host && allowed && board.submit_plan(id, round, plan) {
+                    return Some(Message::State(state.for_broadcast()));
+                }
+            }
         }
+        Message::IdConflict(id) => {
+            // only a player who hasn't made it into the lobby yet should give up their ID,
+            // and at that point we don't know who the host is, same as with the first state
+            if let NetGameState::Connecting = *state {
+                if id != player_id {
+                    return None;
+                }
+                let new_id = random();
+                queue.set_sender(new_id);
+                queue.push(MetaMessage::Identify(new_id));
+                return Some(Message::JoinLobby(Player::new_guest(new_id)));
+            }
+        }
+    }
+    None
+}
+
+fn handle_presence(
+    client: PlayerID,
+    present: bool,
+    state: Arc<RwLock<NetGameState>>,
+    queue: &MessageQueue,
+) -> Option<Message> {
+    let summary = if present { "joined" } else { "left" };
+    diagnostics::record("presence", format!("{} {}", client, summary));
+    let mut state = state.write().expect("Failed to acquire state");
+    let is_host = state.is_host(queue.sender());
+    match *state {
+        // anyone who drops out of the lobby would otherwise get dealt into the game as a ghost
+        NetGameState::Lobby(ref mut lobby_info) if !present => {
+            let changed = is_host && lobby_info.remove_client(client);
+            if changed {
+                return Some(Message::State(state.clone()));
+            }
+        }
+        NetGameState::Active(ref mut board) => {
+            // the host decides when to skip, so everyone else just waits to hear about it
+            let changed = is_host && board.set_away(client, !present);
+            if changed {
+                return Some(Message::State(state.for_broadcast()));
+            }
+        }
+        _ => (),
     }
     None
 }
@@ -76,56 +815,160 @@ pub struct NetHandler {
     socket: Option<web_sys::WebSocket>,
     message_listener: Option<EventListener>,
     error_listener: Option<EventListener>,
-    queue: Arc<Mutex<VecDeque<MetaMessage>>>,
+    queue: MessageQueue,
+    bad_messages: Arc<AtomicUsize>,
+    sim: Option<NetSim>,
+    json: bool,
+    notice: Arc<Mutex<Option<String>>>,
+    latencies: Arc<Mutex<BTreeMap<PlayerID, u32>>>,
+    desync: Arc<AtomicUsize>,
+    join_requests: Arc<Mutex<VecDeque<Player>>>,
+    /// Key the server kept the finished game's replay under, once it has
+    replay_key: Arc<Mutex<Option<String>>>,
+    left: bool,
+    /// Whether the server has confirmed we're out of the game
+    left_acked: Arc<AtomicBool>,
+    /// Whether the server has heard how the game ended
+    finished: AtomicBool,
 }
 
 impl Drop for NetHandler {
     fn drop(&mut self) {
         drop(self.message_listener.take());
         drop(self.error_listener.take());
-        if let Some(socket) = &self.socket {
-            socket.close().unwrap_throw();
+        if let Some(socket) = self.socket.take() {
+            if socket.ready_state() != web_sys::WebSocket::OPEN {
+                socket.close().unwrap_throw();
+                return;
+            }
+            // let everyone else know now, instead of when the server notices the socket's gone
+            if !self.left {
+                self.send_now(&socket, MetaMessage::Leave);
+            }
+            if self.left_acked.load(Ordering::SeqCst) {
+                socket.close().unwrap_throw();
+            } else {
+                close_after_leaving(socket);
+            }
         }
     }
 }
 
+/// Closes the socket once the server confirms we're out of the game, or after a moment if it doesn't,
+/// since closing right away can cut off the `Leave`
+fn close_after_leaving(socket: web_sys::WebSocket) {
+    let ack_socket = socket.clone();
+    let ack_listener = EventListener::new(&socket, "message", move |event| {
+        if let Ok(Incoming::LeftAck) = decode(event) {
+            diagnostics::record("left", String::new());
+            let _ = ack_socket.close();
+        }
+    });
+    Timeout::new(LEAVE_ACK_TIMEOUT, move || {
+        drop(ack_listener);
+        let _ = socket.close();
+    })
+        .forget();
+}
+
 impl NetHandler {
     pub fn run(state: Arc<RwLock<NetGameState>>, game: GameID, player: PlayerID) -> NetHandler {
-        let is_localhost = {
-            let window = web_sys::window().unwrap_throw();
-            let location = window.location();
-            let hostname = location.hostname().unwrap_throw();
-            hostname == "127.0.0.1" || hostname == "localhost"
-        };
-        let addr = if is_localhost {
-            "ws://127.0.0.1:8080/ws/"
-        } else {
-            "wss://dynamaze-primary-server.herokuapp.com/ws/"
-        };
-        let socket = web_sys::WebSocket::new(addr).unwrap_throw();
-        socket.set_binary_type(web_sys::BinaryType::Arraybuffer);
-        let queue = {
-            let join = MetaMessage::Join(game);
-            let mut queue = VecDeque::new();
-            queue.push_back(join);
-            Arc::new(Mutex::new(queue))
-        };
+        let json = json_debug();
+        let socket = open_socket(json);
+        let queue = MessageQueue::new(player, json, !json && deflate_requested());
+        queue.push(MetaMessage::Join(game));
+        queue.push(MetaMessage::Identify(player));
         let reply_queue = queue.clone();
         let message_state = state.clone();
+        let mut last_seq = HashMap::<PlayerID, u64>::new();
+        let bad_messages = Arc::new(AtomicUsize::new(0));
+        let listener_bad_messages = bad_messages.clone();
+        let listener_socket = socket.clone();
+        let notice = Arc::new(Mutex::new(None));
+        let listener_notice = notice.clone();
+        let latencies = Arc::new(Mutex::new(BTreeMap::new()));
+        let listener_latencies = latencies.clone();
+        let desync = Arc::new(AtomicUsize::new(0));
+        let listener_desync = desync.clone();
+        let join_requests = Arc::new(Mutex::new(VecDeque::new()));
+        let listener_join_requests = join_requests.clone();
+        let replay_key = Arc::new(Mutex::new(None));
+        let listener_replay_key = replay_key.clone();
+        let left_acked = Arc::new(AtomicBool::new(false));
+        let listener_left_acked = left_acked.clone();
         let message_listener = EventListener::new(&socket, "message", move |event| {
-            let event = event
-                .dyn_ref::<web_sys::MessageEvent>()
-                .expect_throw("Bad message received");
-            let data = event.data();
-            let data = data
-                .dyn_ref::<js_sys::ArrayBuffer>()
-                .expect_throw("Bad message received");
-            let data = js_sys::Uint8Array::new(data);
-            let data = data.to_vec();
-            let message = deserialize(&data).expect_throw("Bad message received");
-            let reply = handle_incoming(message, message_state.clone(), player);
+            let size = frame_size(event);
+            reply_queue.record_received(size);
+            let message = match decode(event) {
+                Ok(message) => message,
+                Err(e) => {
+                    // one garbled message shouldn't take the whole game down with it
+                    let text = format!("Ignoring malformed message: {}", e);
+                    web_sys::console::warn_1(&JsValue::from_str(&text));
+                    diagnostics::record("malformed", e);
+                    let count = listener_bad_messages.fetch_add(1, Ordering::SeqCst) + 1;
+                    if count > MAX_BAD_MESSAGES {
+                        let error = format!("Disconnected after {} malformed messages", count);
+                        *message_state.write().unwrap_throw() = NetGameState::Error(error);
+                        let _ = listener_socket.close();
+                    }
+                    return;
+                }
+            };
+            let message = match message {
+                Incoming::Envelope(message) => {
+                    if let Message::State(_) = message.payload {
+                        reply_queue.record_state(size);
+                    }
+                    *message
+                }
+                Incoming::Presence(client, present) => {
+                    let reply = handle_presence(client, present, message_state.clone(), &reply_queue);
+                    if let Some(reply) = reply {
+                        reply_queue.send(reply);
+                    }
+                    return;
+                }
+                Incoming::Closed(reason) => {
+                    diagnostics::record("closed", String::new());
+                    let error = format!("The server closed this game: {}", reason);
+                    *message_state.write().unwrap_throw() = NetGameState::Error(error);
+                    return;
+                }
+                Incoming::Notice(text) => {
+                    *listener_notice.lock().unwrap() = Some(text);
+                    return;
+                }
+                Incoming::Latency(times) => {
+                    // other servers report on their own clients, so this only updates whoever's in it
+                    listener_latencies.lock().unwrap().extend(times);
+                    return;
+                }
+                Incoming::Archived(key) => {
+                    *listener_replay_key.lock().unwrap() = Some(key);
+                    return;
+                }
+                Incoming::LeftAck => {
+                    diagnostics::record("left", String::new());
+                    listener_left_acked.store(true, Ordering::SeqCst);
+                    return;
+                }
+            };
+            // drop retransmits and anything that arrives after a newer message from the same sender
+            let last = last_seq.entry(message.sender).or_insert(0);
+            if message.seq <= *last {
+                return;
+            }
+            *last = message.seq;
+            let reply = handle_incoming(
+                message,
+                message_state.clone(),
+                &reply_queue,
+                &listener_desync,
+                &listener_join_requests,
+            );
             if let Some(reply) = reply {
-                reply_queue.lock().unwrap().push_back(reply.into());
+                reply_queue.send(reply);
             }
         });
         let error_listener = EventListener::new(&socket, "close", move |event| {
@@ -144,24 +987,158 @@ impl NetHandler {
             message_listener: Some(message_listener),
             error_listener: Some(error_listener),
             queue,
+            bad_messages,
+            sim: NetSim::from_query(),
+            json,
+            notice,
+            latencies,
+            desync,
+            join_requests,
+            replay_key,
+            left: false,
+            left_acked,
+            finished: AtomicBool::new(false),
         }
     }
 
-    pub fn run_fake() -> NetHandler {
+    pub fn run_fake(player: PlayerID) -> NetHandler {
         NetHandler {
             socket: None,
             message_listener: None,
             error_listener: None,
-            queue: Default::default(),
+            queue: MessageQueue::new(player, false, false),
+            bad_messages: Default::default(),
+            sim: None,
+            json: false,
+            notice: Default::default(),
+            latencies: Default::default(),
+            desync: Default::default(),
+            join_requests: Default::default(),
+            replay_key: Default::default(),
+            left: false,
+            left_acked: Default::default(),
+            finished: Default::default(),
         }
     }
 
-    pub fn queue(&self) -> Arc<Mutex<VecDeque<MetaMessage>>> {
+    pub fn queue(&self) -> MessageQueue {
         self.queue.clone()
     }
 
-    pub fn send<M: Into<MetaMessage>>(&self, message: M) {
-        self.queue.lock().unwrap().push_back(message.into());
+    pub fn player_id(&self) -> PlayerID {
+        self.queue.sender()
+    }
+
+    pub fn bad_message_count(&self) -> usize {
+        self.bad_messages.load(Ordering::SeqCst)
+    }
+
+    pub fn send(&self, message: Message) {
+        self.queue.send(message);
+    }
+
+    /// Sends a message only to the given clients, leaving out everyone else in the game
+    pub fn send_to(&self, peers: Vec<PlayerID>, message: Message) {
+        self.queue.send_to(peers, message);
+    }
+
+    /// Gets the latest announcement from the server, if there's been one
+    pub fn notice(&self) -> Option<String> {
+        self.notice.lock().unwrap().clone()
+    }
+
+    /// Gets the latest round trip times the server has reported, by client
+    pub fn latencies(&self) -> BTreeMap<PlayerID, u32> {
+        self.latencies.lock().unwrap().clone()
+    }
+
+    /// Gets whoever's asked to join the game in progress and is waiting on the host, in the order they asked
+    pub fn join_requests(&self) -> Vec<Player> {
+        self.join_requests.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Takes the longest-waiting request to join the game in progress off the list, to be answered
+    pub fn take_join_request(&self) -> Option<Player> {
+        self.join_requests.lock().unwrap().pop_front()
+    }
+
+    /// Gets the link to download the finished game's replay from the server, once it's kept it
+    pub fn replay_link(&self) -> Option<String> {
+        self.replay_key.lock().unwrap().as_deref().map(replay_link)
+    }
+
+    /// Gets how much has gone over the connection in the last minute
+    pub fn traffic(&self) -> TrafficRates {
+        self.queue.traffic.lock().unwrap().rates()
+    }
+
+    /// Gets a warning if whole-state broadcasts have been large and frequent lately, once each time
+    /// they get that way
+    pub fn take_traffic_warning(&self) -> Option<String> {
+        self.queue.traffic.lock().unwrap().take_warning()
+    }
+
+    /// Gets the bad connection being simulated, if any
+    pub fn net_sim(&self) -> Option<&NetSim> {
+        self.sim.as_ref()
+    }
+
+    /// Checks if this is connected to the server, rather than standing in for it in a local game
+    pub fn is_online(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Checks if the host's board has disagreed with ours for a while
+    pub fn desynced(&self) -> bool {
+        self.desync.load(Ordering::SeqCst) >= DESYNC_STRIKES
+    }
+
+    /// Asks the host to set us straight after a desync
+    pub fn resync(&self) {
+        self.desync.store(0, Ordering::SeqCst);
+        self.queue.send(Message::RequestState);
+    }
+
+    /// Tells everyone in the game we're going, right away instead of on the next tick
+    pub fn leave(&mut self) {
+        if self.left {
+            return;
+        }
+        self.left = true;
+        self.queue.send(Message::PlayerLeft(self.player_id()));
+        self.queue.push(MetaMessage::Leave);
+        if let Some(socket) = &self.socket {
+            if socket.ready_state() != web_sys::WebSocket::OPEN {
+                return;
+            }
+            // this skips any simulated lag, since the socket's about to close anyway
+            while let Some(message) = self.queue.pop() {
+                self.send_now(socket, message);
+            }
+        }
+    }
+
+    /// Lets the server know the game is still wanted, even with nothing happening in it
+    pub fn keep_alive(&self) {
+        self.queue.push(MetaMessage::KeepAlive);
+    }
+
+    /// Lets the server know the game has started, so people trying to join can be warned
+    pub fn mark_started(&self) {
+        self.queue.push(MetaMessage::Started);
+    }
+
+    /// Gives the server a URL to tell about this game's lobby, start, and end
+    pub fn set_webhook(&self, url: String) {
+        self.queue.push(MetaMessage::SetWebhook(url));
+    }
+
+    /// Lets the server know how the game ended, and what happened in it, the first time this is called
+    pub fn mark_finished(&self, winner: String, scores: Vec<(String, u8)>, replay: String) {
+        if !self.finished.swap(true, Ordering::SeqCst) {
+            self.queue.push(MetaMessage::Finished { winner, scores });
+            self.queue.push(MetaMessage::Replay(replay));
+        }
     }
 
     pub fn drain_queue(&self) {
@@ -169,19 +1146,69 @@ impl NetHandler {
             if socket.ready_state() != web_sys::WebSocket::OPEN {
                 return;
             }
-            let mut queue = self.queue.lock().unwrap();
-            while let Some(message) = queue.pop_front() {
-                let mut data = serialize(&message).expect_throw("Bad message sent");
-                match socket.send_with_u8_array(&mut data) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        web_sys::console::error_1(&e);
-                    }
+            let mut outgoing = vec![];
+            while let Some(message) = self.queue.pop() {
+                match self.sim {
+                    Some(ref sim) => sim.hold(message),
+                    None => outgoing.push(message),
                 }
             }
+            if let Some(ref sim) = self.sim {
+                outgoing.extend(sim.release());
+            }
+            for message in outgoing {
+                self.send_now(socket, message);
+            }
         } else {
-            let mut queue = self.queue.lock().unwrap();
-            while let Some(_) = queue.pop_front() {}
+            while let Some(_) = self.queue.pop() {}
         }
     }
+
+    fn send_now(&self, socket: &web_sys::WebSocket, message: MetaMessage) {
+        diagnostics::record("sent", message.summary());
+        let result = if self.json {
+            let text = serde_json::to_string(&message).expect_throw("Bad message sent");
+            self.queue.record_sent(text.len());
+            socket.send_with_str(&text)
+        } else {
+            let mut data = serialize(&message).expect_throw("Bad message sent");
+            self.queue.record_sent(data.len());
+            socket.send_with_u8_array(&mut data)
+        };
+        match result {
+            Ok(_) => (),
+            Err(e) => {
+                web_sys::console::error_1(&e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::colors::Color;
+
+    use super::*;
+
+    #[test]
+    fn deflated_messages_inflate() {
+        let envelope = Envelope {
+            sender: 1,
+            seq: 2,
+            payload: Message::JoinLobby(Player::new("hello ".repeat(100), Color(0.2, 0.4, 0.6), 1)),
+        };
+        let data = match envelope.deflated() {
+            MetaMessage::Deflated(data) => data,
+            other => panic!("not deflated: {}", other.summary()),
+        };
+        let inflated = inflate(&data).unwrap();
+        assert_eq!(inflated, serialize(&envelope).unwrap());
+    }
+
+    #[test]
+    fn huge_messages_dont_inflate() {
+        let data = miniz_oxide::deflate::compress_to_vec(&vec![0; MAX_INFLATED_SIZE + 1], DEFLATE_LEVEL);
+        assert!(inflate(&data).is_err());
+        assert!(inflate(b"not deflated").is_err());
+    }
 }
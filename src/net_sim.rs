@@ -0,0 +1,86 @@
+//! Simulated bad connections, for seeing how the game copes with lag and lost messages
+
+use std::cell::RefCell;
+
+use rand::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::net::MetaMessage;
+
+/// Artificial delay, jitter, and loss applied to outgoing messages
+pub struct NetSim {
+    /// Milliseconds every message is held back
+    pub delay: f64,
+    /// Up to this many more milliseconds are added at random, so messages can arrive out of order
+    pub jitter: f64,
+    /// Chance of a game message being lost entirely, from 0 to 1
+    pub drop: f64,
+    held: RefCell<Vec<(f64, MetaMessage)>>,
+}
+
+impl NetSim {
+    /// Reads simulation settings from the page's query string, like `?lag=200&jitter=100&drop=0.1`
+    pub fn from_query() -> Option<NetSim> {
+        let window = web_sys::window().unwrap_throw();
+        let search = window.location().search().unwrap_throw();
+        let mut result = NetSim {
+            delay: 0.0,
+            jitter: 0.0,
+            drop: 0.0,
+            held: RefCell::new(vec![]),
+        };
+        let mut enabled = false;
+        for param in search.trim_start_matches('?').split('&') {
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value: f64 = match parts.next().and_then(|value| value.parse().ok()) {
+                Some(value) => value,
+                None => continue,
+            };
+            match key {
+                "lag" => result.delay = value.max(0.0),
+                "jitter" => result.jitter = value.max(0.0),
+                "drop" => result.drop = value.clamp(0.0, 1.0),
+                _ => continue,
+            }
+            enabled = true;
+        }
+        if enabled {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    /// Describes what's being simulated, for a warning banner
+    pub fn describe(&self) -> String {
+        format!(
+            "Simulating a bad connection: {}ms lag, {}ms jitter, {}% dropped",
+            self.delay,
+            self.jitter,
+            (self.drop * 100.0).round()
+        )
+    }
+
+    /// Holds on to a message until it's due to go out, or loses it
+    pub fn hold(&self, message: MetaMessage) {
+        let mut rng = thread_rng();
+        // losing server control messages would just leave the connection in limbo
+        if let MetaMessage::Message(_) = message {
+            if rng.gen::<f64>() < self.drop {
+                return;
+            }
+        }
+        let due = js_sys::Date::now() + self.delay + rng.gen::<f64>() * self.jitter;
+        self.held.borrow_mut().push((due, message));
+    }
+
+    /// Takes every held message that's due to go out, in the order they're due
+    pub fn release(&self) -> Vec<MetaMessage> {
+        let now = js_sys::Date::now();
+        let mut held = self.held.borrow_mut();
+        held.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        let due = held.iter().take_while(|(time, _)| *time <= now).count();
+        held.drain(..due).map(|(_, message)| message).collect()
+    }
+}
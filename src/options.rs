@@ -1,26 +1,182 @@
 extern crate toml;
 
+use std::cmp::Ordering;
 use std::sync::{RwLock, RwLockReadGuard};
 
 use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
 use wasm_bindgen::prelude::*;
 
+use crate::{BoardController, BoardSettings, Direction};
+use crate::net::GameID;
+use crate::tutorial::TutorialStep;
+
 #[derive(Deserialize, Clone, Serialize)]
 #[serde(default)]
 pub struct GameOptions {
+    /// Version of the layout these options were saved in, so older saves can be upgraded
+    pub version: u32,
     pub music_level: u8,
     pub sound_level: u8,
+    /// Volume of the wind and stone sounds in the background of a game
+    pub ambience_level: u8,
+    /// Whether to suggest which way to turn the loose tile
+    pub rotate_assist: bool,
+    /// Whether to draw the last few moves on the board
+    pub move_history: bool,
+    /// Whether to leave games and remove players without being asked first
+    pub skip_confirmations: bool,
+    /// Name of the theme to draw the board in, or empty for whichever fits the time of year
+    pub theme: String,
+    /// Where the server should POST events from games hosted here, or empty for nowhere
+    pub webhook_url: String,
+    /// How the tutorial speaks up as each step starts
+    pub tutorial_narration: Narration,
+    /// How far the board's turned on this screen, so its bottom can be nearest wherever we start
+    pub board_rotation: BoardRotation,
+}
+
+/// How the tutorial speaks up as each step starts, on top of the captions
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Narration {
+    /// Just the captions
+    Off,
+    /// A short tune for each step
+    #[default]
+    Stingers,
+    /// The tune, then the caption read out loud
+    Voice,
+}
+
+impl Narration {
+    /// Every kind of narration, with names to show for them
+    pub const ALL: [(Narration, &'static str); 3] = [
+        (Narration::Off, "Captions Only"),
+        (Narration::Stingers, "Stingers"),
+        (Narration::Voice, "Voice-Over"),
+    ];
+}
+
+/// How far the board gets turned on this screen, clockwise
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoardRotation {
+    /// North at the top, like everyone else by default
+    #[default]
+    Upright,
+    /// Turned a quarter of the way around, so west is at the top
+    Quarter,
+    /// Turned halfway around, so south is at the top
+    Half,
+    /// Turned three quarters of the way around, so east is at the top
+    ThreeQuarters,
+}
+
+impl BoardRotation {
+    /// Every rotation, with names to show for them
+    pub const ALL: [(BoardRotation, &'static str); 4] = [
+        (BoardRotation::Upright, "None"),
+        (BoardRotation::Quarter, "90\u{b0}"),
+        (BoardRotation::Half, "180\u{b0}"),
+        (BoardRotation::ThreeQuarters, "270\u{b0}"),
+    ];
+
+    /// Gets how many quarter turns clockwise this is
+    pub fn quarter_turns(self) -> u8 {
+        match self {
+            BoardRotation::Upright => 0,
+            BoardRotation::Quarter => 1,
+            BoardRotation::Half => 2,
+            BoardRotation::ThreeQuarters => 3,
+        }
+    }
+
+    /// Gets how far this turns the board clockwise, in radians
+    pub fn angle(self) -> f64 {
+        f64::from(self.quarter_turns()) * ::std::f64::consts::FRAC_PI_2
+    }
+
+    /// Checks if this leaves the board on its side, so its width runs up and down the screen
+    pub fn is_sideways(self) -> bool {
+        self.quarter_turns() % 2 == 1
+    }
+
+    /// Gets this as a turn, for working out which way on screen a direction on the board points
+    pub fn turn(self) -> Direction {
+        match self {
+            BoardRotation::Upright => Direction::North,
+            BoardRotation::Quarter => Direction::East,
+            BoardRotation::Half => Direction::South,
+            BoardRotation::ThreeQuarters => Direction::West,
+        }
+    }
+
+    /// Gets the turn that undoes this one, for working out which way on the board a direction
+    /// on screen points
+    pub fn undo(self) -> Direction {
+        match self {
+            BoardRotation::Upright => Direction::North,
+            BoardRotation::Quarter => Direction::West,
+            BoardRotation::Half => Direction::South,
+            BoardRotation::ThreeQuarters => Direction::East,
+        }
+    }
 }
 
 impl Default for GameOptions {
     fn default() -> Self {
         GameOptions {
+            version: OPTIONS_VERSION,
             music_level: 50,
             sound_level: 50,
+            ambience_level: 50,
+            rotate_assist: false,
+            move_history: false,
+            skip_confirmations: false,
+            theme: String::new(),
+            webhook_url: String::new(),
+            tutorial_narration: Narration::Stingers,
+            board_rotation: BoardRotation::Upright,
         }
     }
 }
 
+/// Version of the options layout saved by this build, which should go up along with a new entry in
+/// `MIGRATIONS` whenever a field is renamed or changes meaning
+const OPTIONS_VERSION: u32 = 1;
+
+/// Steps that each upgrade saved options by one version, with the first taking options saved before
+/// they had a version
+const MIGRATIONS: [fn(&mut Table); OPTIONS_VERSION as usize] = [migrate_unversioned];
+
+fn migrate_unversioned(_options: &mut Table) {
+    // every field saved before versioning still means the same thing in version 1
+}
+
+/// Upgrades saved options step by step from whatever version they were saved in to the current one,
+/// leaving options from a newer build marked with their own version
+fn migrate(mut options: Table) -> Table {
+    let version = options
+        .get("version")
+        .and_then(Value::as_integer)
+        .unwrap_or(0) as usize;
+    if version > OPTIONS_VERSION as usize {
+        return options;
+    }
+    for migration in MIGRATIONS.iter().skip(version) {
+        migration(&mut options);
+    }
+    options.insert("version".to_string(), Value::Integer(OPTIONS_VERSION.into()));
+    options
+}
+
+/// Parses saved options, upgrading them if they're from an older version
+fn parse_options(data: &str) -> Option<GameOptions> {
+    match data.parse::<Value>().ok()? {
+        Value::Table(options) => Value::Table(migrate(options)).try_into().ok(),
+        _ => None,
+    }
+}
+
 trait SuppressError<T> {
     fn suppress_error(self) -> Result<T, ()>;
 }
@@ -35,23 +191,185 @@ pub struct GameOptionsHandle {
     options: RwLock<GameOptions>,
 }
 
-fn read() -> Option<String> {
+fn read(key: &str) -> Option<String> {
+    let window = web_sys::window().unwrap_throw();
+    let local_storage = window.local_storage().unwrap_throw().unwrap_throw();
+    local_storage.get_item(key).unwrap_throw()
+}
+
+fn write(key: &str, settings: &str) {
     let window = web_sys::window().unwrap_throw();
     let local_storage = window.local_storage().unwrap_throw().unwrap_throw();
-    local_storage.get_item("settings").unwrap_throw()
+    local_storage.set_item(key, settings).unwrap_throw();
 }
 
-fn write(settings: &str) {
+fn remove(key: &str) {
     let window = web_sys::window().unwrap_throw();
     let local_storage = window.local_storage().unwrap_throw().unwrap_throw();
-    local_storage.set_item("settings", settings).unwrap_throw();
+    local_storage.remove_item(key).unwrap_throw();
+}
+
+/// Something the main menu can pick back up where it was left
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Continue {
+    /// A local game left partway through, which is saved separately
+    LocalGame,
+    /// The tutorial, from the given step
+    Tutorial(TutorialStep),
+    /// The lobby most recently hosted or joined
+    Lobby(GameID),
+}
+
+/// Gets whatever was most recently left partway through in this browser, if anything
+pub fn continue_target() -> Option<Continue> {
+    read("continue").and_then(|x| serde_json::from_str(&x).ok())
+}
+
+/// Remembers the given thing as the one to pick back up from the main menu
+pub fn save_continue_target(target: &Continue) {
+    let _ = serde_json::to_string(target)
+        .suppress_error()
+        .map(|data| write("continue", &data));
+}
+
+/// Forgets whatever was going to be picked back up from the main menu, once it's finished
+pub fn clear_continue_target() {
+    remove("continue");
+    remove("local_game");
+}
+
+/// Gets the lobby most recently hosted or joined in this browser, even if that game's finished,
+/// for rejoining after a refresh
+pub fn recent_lobby() -> Option<GameID> {
+    read("recent_lobby").and_then(|x| x.parse().ok())
+}
+
+/// Remembers the given lobby as the one most recently hosted or joined
+pub fn save_recent_lobby(game: GameID) {
+    write("recent_lobby", &game.to_string());
+}
+
+/// Gets the local game left partway through in this browser, if there is one
+pub fn saved_local_game() -> Option<BoardController> {
+    read("local_game").and_then(|x| serde_json::from_str(&x).ok())
+}
+
+/// Saves the given local game so it can be picked back up from the main menu
+pub fn save_local_game(board: &BoardController) {
+    let _ = serde_json::to_string(board)
+        .suppress_error()
+        .map(|data| write("local_game", &data));
+    save_continue_target(&Continue::LocalGame);
+}
+
+/// Gets the settings of the last game hosted in this browser, or the defaults if there wasn't one
+pub fn last_lobby_settings() -> BoardSettings {
+    read("lobby_settings")
+        .and_then(|x| toml::from_str::<BoardSettings>(&x).ok())
+        .filter(|settings| BoardSettings::check_size(settings.width, settings.height).is_ok())
+        .unwrap_or_default()
+}
+
+/// Remembers the given settings for the next game hosted in this browser
+pub fn save_lobby_settings(settings: &BoardSettings) {
+    // a fixed seed would make every game the same, and versions only mean anything within one lobby
+    let settings = BoardSettings {
+        seed: None,
+        version: 0,
+        ..settings.clone()
+    };
+    let _ = toml::to_string_pretty(&settings)
+        .suppress_error()
+        .map(|data| write("lobby_settings", &data));
+}
+
+/// Most players kept on the recent players list, not counting favorites, which stay however long
+/// ago they were played with
+const MAX_RECENT_PLAYERS: usize = 10;
+
+/// Someone played with in this browser, remembered by name since player IDs change every visit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecentPlayer {
+    /// Their name as of the last game
+    pub name: String,
+    /// When they were last played with, in seconds since the epoch
+    pub last_played: f64,
+    /// Whether they've been marked as a favorite, which keeps them on the list and at the top of it
+    pub favorite: bool,
+}
+
+fn sort_recent_players(players: &mut [RecentPlayer]) {
+    players.sort_by(|a, b| {
+        b.favorite
+            .cmp(&a.favorite)
+            .then(b.last_played.partial_cmp(&a.last_played).unwrap_or(Ordering::Equal))
+    });
+}
+
+fn save_recent_players(players: &[RecentPlayer]) {
+    let _ = serde_json::to_string(players)
+        .suppress_error()
+        .map(|data| write("recent_players", &data));
+}
+
+/// Gets the players played with in this browser, favorites first and then most recent first
+pub fn recent_players() -> Vec<RecentPlayer> {
+    let mut players: Vec<RecentPlayer> = read("recent_players")
+        .and_then(|x| serde_json::from_str(&x).ok())
+        .unwrap_or_default();
+    sort_recent_players(&mut players);
+    players
+}
+
+/// Remembers having just played with the given players
+pub fn remember_players(names: &[String]) {
+    let now = crate::now();
+    let mut players = recent_players();
+    for name in names {
+        match players.iter_mut().find(|player| &player.name == name) {
+            Some(player) => player.last_played = now,
+            None => players.push(RecentPlayer {
+                name: name.clone(),
+                last_played: now,
+                favorite: false,
+            }),
+        }
+    }
+    sort_recent_players(&mut players);
+    let mut kept = 0;
+    players.retain(|player| {
+        kept += !player.favorite as usize;
+        player.favorite || kept <= MAX_RECENT_PLAYERS
+    });
+    save_recent_players(&players);
+}
+
+/// Marks the given player as a favorite, or unmarks them if they already are one, giving back
+/// whether they're a favorite now
+pub fn toggle_favorite(name: &str) -> bool {
+    let mut players = recent_players();
+    let favorite = match players.iter_mut().find(|player| player.name == name) {
+        Some(player) => {
+            player.favorite = !player.favorite;
+            player.favorite
+        }
+        None => return false,
+    };
+    save_recent_players(&players);
+    favorite
 }
 
 impl GameOptionsHandle {
     fn new() -> Self {
-        let options = read()
-            .and_then(|x| toml::from_str(&x).ok())
+        let options: GameOptions = read("settings")
+            .and_then(|x| parse_options(&x))
             .unwrap_or_default();
+        if options.version > OPTIONS_VERSION {
+            crate::log(&format!(
+                "Options were saved by a newer version (layout {}, this is {}), so some may not come through",
+                options.version, OPTIONS_VERSION
+            ));
+        }
         GameOptionsHandle {
             options: RwLock::new(options),
         }
@@ -63,12 +381,65 @@ impl GameOptionsHandle {
 
     pub fn save(&self, options: &GameOptions) {
         *(self.options.write().unwrap()) = options.clone();
+        // writing these back would throw away whatever the newer version knew about,
+        // so they only last until the page is closed
+        if options.version > OPTIONS_VERSION {
+            crate::log("Options were saved by a newer version, so changes to them won't be kept");
+            return;
+        }
         let _ = toml::to_string_pretty(options)
             .suppress_error()
-            .map(|data| write(&data));
+            .map(|data| write("settings", &data));
     }
 }
 
 lazy_static! {
     pub static ref HANDLE: GameOptionsHandle = { GameOptionsHandle::new() };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unversioned_options_migrate() {
+        let data = r#"
+            music_level = 20
+            sound_level = 80
+            rotate_assist = true
+            theme = "Dusk"
+        "#;
+        let options = parse_options(data).unwrap();
+        assert_eq!(options.version, OPTIONS_VERSION);
+        assert_eq!(options.music_level, 20);
+        assert_eq!(options.sound_level, 80);
+        assert!(options.rotate_assist);
+        assert_eq!(options.theme, "Dusk");
+    }
+
+    #[test]
+    fn version_1_options_load() {
+        let data = r#"
+            version = 1
+            ambience_level = 10
+            skip_confirmations = true
+            webhook_url = "https://example.com/hook"
+        "#;
+        let options = parse_options(data).unwrap();
+        assert_eq!(options.version, 1);
+        assert_eq!(options.ambience_level, 10);
+        assert!(options.skip_confirmations);
+        assert_eq!(options.webhook_url, "https://example.com/hook");
+    }
+
+    #[test]
+    fn newer_options_keep_their_version() {
+        let data = r#"
+            version = 99
+            music_level = 30
+        "#;
+        let options = parse_options(data).unwrap();
+        assert_eq!(options.version, 99);
+        assert_eq!(options.music_level, 30);
+    }
+}
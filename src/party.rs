@@ -0,0 +1,83 @@
+//! Party mode, where everyone plans their turn at once and the host plays the plans out in turn order
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BoardController, BoardSettings, PlayerID};
+use crate::bot;
+
+/// Seconds everyone gets to plan, if there's no turn time limit
+const DEFAULT_PLANNING_TIME: u16 = 45;
+
+/// Seconds between plans being played out, so everyone can follow along
+pub const RESOLVE_STEP: f64 = 0.8;
+
+/// Gets the seconds everyone gets to plan each round with the given settings
+pub fn planning_time(settings: &BoardSettings) -> u16 {
+    match settings.turn_time_limit {
+        0 => DEFAULT_PLANNING_TIME,
+        limit => limit,
+    }
+}
+
+/// Where a round of party mode is at
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PartyRound {
+    /// Rounds finished so far, so a plan made for an earlier round can be told apart
+    pub round: u32,
+    /// Plans handed in so far this round
+    pub plans: BTreeMap<PlayerID, bot::Move>,
+    /// Players whose plans haven't been played out yet, or `None` while everyone's still planning
+    pub pending: Option<BTreeSet<PlayerID>>,
+}
+
+impl PartyRound {
+    /// Checks if the plans are being played out
+    pub fn is_resolving(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+/// A copy of the board that a local player is planning their turn on
+pub struct Draft {
+    /// Round the plan is for
+    pub round: u32,
+    /// How many events the copy's log had when it was made, so the plan can be picked out of it
+    pub start: usize,
+    /// The copy itself
+    pub board: Box<BoardController>,
+}
+
+impl Draft {
+    /// Copies the given party mode board for the given player to plan their turn on
+    pub fn new(board: &BoardController, round: u32, id: PlayerID) -> Draft {
+        Draft {
+            round,
+            start: board.log.events.len(),
+            board: Box::new(board.plan_draft(id)),
+        }
+    }
+
+    /// Gets the player planning their turn
+    pub fn planner(&self) -> PlayerID {
+        self.board.active_player_id()
+    }
+
+    /// Gets the plan, once the turn's been taken
+    pub fn finished_plan(&self) -> Option<bot::Move> {
+        self.board.finished_plan(self.start)
+    }
+}
+
+/// What this screen is up to in party mode
+#[derive(Default)]
+pub struct LocalPlanning {
+    /// Copy of the board a local player is planning their turn on, if anyone here still has to plan
+    pub draft: Option<Draft>,
+    /// Round each local player last handed in a plan for, so a state from before the host got it
+    /// doesn't send them back to planning
+    pub sent: BTreeMap<PlayerID, u32>,
+    /// When the host last played out a plan
+    pub step_at: f64,
+}
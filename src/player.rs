@@ -2,7 +2,9 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::ai::Difficulty;
 use crate::colors::Color;
+use crate::trace;
 
 /// The ID assigned to a player
 pub type PlayerID = u64;
@@ -18,6 +20,9 @@ pub struct Player {
     pub id: PlayerID,
     /// Parent player (player whose ID is attached to the game instance)
     pub parent: Option<PlayerID>,
+    /// How well this player plays, if the computer's playing for them
+    #[serde(default)]
+    pub bot: Option<Difficulty>,
 }
 
 impl Player {
@@ -28,9 +33,15 @@ impl Player {
             color,
             id,
             parent: None,
+            bot: None,
         }
     }
 
+    /// Create a new player who's joining someone else's game, with a placeholder name
+    pub fn new_guest(id: PlayerID) -> Player {
+        Player::new("Guesty McGuestface".into(), trace::random(), id)
+    }
+
     /// Create a new player with the given parent ID
     pub fn new_child(name: String, color: Color, id: PlayerID, parent: PlayerID) -> Player {
         Player {
@@ -38,6 +49,18 @@ impl Player {
             color,
             id,
             parent: Some(parent),
+            bot: None,
+        }
+    }
+
+    /// Create a new computer player, who lives with the host so the host can play for them
+    pub fn new_bot(name: String, color: Color, id: PlayerID, host: PlayerID, difficulty: Difficulty) -> Player {
+        Player {
+            name,
+            color,
+            id,
+            parent: Some(host),
+            bot: Some(difficulty),
         }
     }
 
@@ -45,4 +68,10 @@ impl Player {
     pub fn lives_with(&self, target: PlayerID) -> bool {
         self.id == target || self.parent == Some(target)
     }
+
+    /// Checks if somebody at the given player's screen plays as this player, which the computer
+    /// does instead for computer players
+    pub fn played_from(&self, target: PlayerID) -> bool {
+        self.bot.is_none() && self.lives_with(target)
+    }
 }
@@ -0,0 +1,528 @@
+//! Game log for post-game review
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Board, Direction, Player, PlayerID, Tile};
+use crate::board::PlayerToken;
+use crate::item::Item;
+use crate::world::World;
+
+/// Something that happened during a game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GameEvent {
+    /// The loose tile was inserted
+    Insert {
+        /// The loose tile, as rotated when it was inserted
+        tile: Tile,
+        /// Where it was inserted
+        position: (Direction, usize),
+    },
+    /// A player moved their token, ending their turn
+    Move {
+        /// Who moved
+        player: PlayerID,
+        /// Where they moved from (row, col)
+        #[serde(default)]
+        from: (usize, usize),
+        /// Where they moved to (row, col)
+        to: (usize, usize),
+        /// Where their next target was placed, if they reached one
+        next_target: Option<(usize, usize)>,
+    },
+    /// A player joined partway through the game
+    Join {
+        /// Who joined
+        player: PlayerID,
+        /// Where their token was placed (row, col)
+        position: (usize, usize),
+        /// Where their first target was placed
+        target: Option<(usize, usize)>,
+        /// Target cards they were left with after drawing their first
+        #[serde(default)]
+        deck: VecDeque<Item>,
+        /// Card for their first target
+        #[serde(default)]
+        card: Option<Item>,
+    },
+    /// A player's turn was skipped because they were away
+    Skip {
+        /// Who was skipped
+        player: PlayerID,
+    },
+    /// A player left partway through the game, taking their token and target with them
+    Leave {
+        /// Who left
+        player: PlayerID,
+    },
+    /// The board's outer ring of tiles collapsed
+    Collapse {
+        /// Where anyone whose target was on the outer ring got their new target (row, col)
+        targets: Vec<(PlayerID, (usize, usize))>,
+    },
+    /// Every plan for a round of party mode was played out, and the turn order moved along one
+    NewRound,
+    /// A player finished enough quick turns in a row to earn a bonus point
+    SpeedBonus {
+        /// Who earned it
+        player: PlayerID,
+    },
+    /// The layer the next player's token is on was brought into play, in portal mode
+    SwitchLayer {
+        /// Which layer
+        layer: usize,
+    },
+}
+
+/// Checks if the given event finishes a turn
+fn is_turn_end(event: &GameEvent) -> bool {
+    match event {
+        GameEvent::Move { .. } | GameEvent::Skip { .. } => true,
+        GameEvent::Insert { .. }
+        | GameEvent::Join { .. }
+        | GameEvent::Leave { .. }
+        | GameEvent::Collapse { .. }
+        | GameEvent::NewRound
+        | GameEvent::SpeedBonus { .. }
+        | GameEvent::SwitchLayer { .. } => false,
+    }
+}
+
+/// Everything needed to rebuild the board at any turn of a game
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameLog {
+    /// Board at the start of the game
+    pub initial_board: Board,
+    /// Layers set aside at the start of the game, in portal mode
+    #[serde(default)]
+    pub initial_world: Option<World>,
+    /// Turn order at the start of the game
+    pub initial_turn_order: Vec<PlayerID>,
+    /// Events, in the order they happened
+    pub events: Vec<GameEvent>,
+    /// Events left off the front, in a copy sent mid-game that only carries the last few turns
+    #[serde(default)]
+    pub skipped: usize,
+    /// Turns finished in the events left off the front
+    #[serde(default)]
+    pub skipped_turns: usize,
+}
+
+impl GameLog {
+    /// Starts a log for a game with the given initial state
+    pub fn new(initial_board: Board, initial_world: Option<World>, initial_turn_order: Vec<PlayerID>) -> GameLog {
+        GameLog {
+            initial_board,
+            initial_world,
+            initial_turn_order,
+            events: vec![],
+            skipped: 0,
+            skipped_turns: 0,
+        }
+    }
+
+    /// Gets the number of events over the whole game, counting any left off the front
+    pub fn event_count(&self) -> usize {
+        self.skipped + self.events.len()
+    }
+
+    /// Gets the events from the given index on, counting from the start of the game,
+    /// if this copy has all of them
+    pub fn events_since(&self, index: usize) -> Option<&[GameEvent]> {
+        self.events.get(index.checked_sub(self.skipped)?..)
+    }
+
+    /// Copies this log with only the events from the last few turns, and the turn in progress,
+    /// so sending it doesn't take longer the longer the game goes on
+    pub fn tail(&self, turns: usize) -> GameLog {
+        let mut kept = 0;
+        let mut turns_seen = 0;
+        for event in self.events.iter().rev() {
+            if is_turn_end(event) {
+                if turns_seen == turns {
+                    break;
+                }
+                turns_seen += 1;
+            }
+            kept += 1;
+        }
+        let cut = self.events.len() - kept;
+        GameLog {
+            initial_board: self.initial_board.clone(),
+            initial_world: self.initial_world.clone(),
+            initial_turn_order: self.initial_turn_order.clone(),
+            events: self.events[cut..].to_vec(),
+            skipped: self.skipped + cut,
+            skipped_turns: self.skipped_turns + self.events[..cut].iter().filter(|e| is_turn_end(e)).count(),
+        }
+    }
+
+    /// Fills in the events left off the front of this copy from an older copy of the same game,
+    /// returns whether it has the whole game now
+    pub fn splice(&mut self, older: &GameLog) -> bool {
+        if self.skipped == 0 {
+            return true;
+        }
+        if older.initial_turn_order != self.initial_turn_order || older.skipped > self.skipped {
+            return false;
+        }
+        let missing = match older.events.get(..self.skipped - older.skipped) {
+            Some(missing) => missing,
+            // the older copy doesn't go far enough to meet this one
+            None => return false,
+        };
+        let mut events = missing.to_vec();
+        events.append(&mut self.events);
+        self.events = events;
+        self.skipped = older.skipped;
+        self.skipped_turns = older.skipped_turns;
+        self.skipped == 0
+    }
+
+    /// Records an event
+    pub fn record(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    /// Gets the pushes and token moves from the last few turns, along with how many turns ago each
+    /// happened, so 0 is the turn in progress and 1 is the turn just finished
+    pub fn recent_moves(&self, turns: usize) -> Vec<(usize, &GameEvent)> {
+        let mut result = vec![];
+        let mut age = 0;
+        for event in self.events.iter().rev() {
+            match event {
+                GameEvent::Move { .. } | GameEvent::Skip { .. } => age += 1,
+                // anything from before the board shrank, or on another layer, would be drawn in the wrong place
+                GameEvent::Collapse { .. } | GameEvent::SwitchLayer { .. } => break,
+                GameEvent::Insert { .. }
+                | GameEvent::Join { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::NewRound
+                | GameEvent::SpeedBonus { .. } => (),
+            }
+            if age > turns {
+                break;
+            }
+            match event {
+                GameEvent::Insert { .. } | GameEvent::Move { .. } => result.push((age, event)),
+                GameEvent::Join { .. }
+                | GameEvent::Skip { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::NewRound
+                | GameEvent::SpeedBonus { .. }
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        result
+    }
+
+    /// Gets the number of completed turns
+    pub fn turn_count(&self) -> usize {
+        self.skipped_turns + self.events.iter().filter(|e| is_turn_end(e)).count()
+    }
+
+    /// Gets the turn (counting from 1) on which the given player's score first reached the given score,
+    /// or 0 if it already had at the start of the log
+    pub fn turn_reaching_score(&self, player: PlayerID, score: u8, wrap_tokens: bool) -> Option<usize> {
+        let score_on = |board: &Board| board.player_tokens.get(&player).map_or(0, |token| token.score);
+        let mut board = self.initial_board.clone();
+        let mut world = self.initial_world.clone();
+        if score_on(&board) >= score {
+            return Some(0);
+        }
+        let mut turn = 0;
+        for event in &self.events {
+            apply_event(&mut board, world.as_mut(), event, wrap_tokens);
+            match *event {
+                GameEvent::Move { .. } | GameEvent::Skip { .. } => {
+                    turn += 1;
+                    if score_on(&board) >= score {
+                        return Some(turn);
+                    }
+                }
+                // bonuses come in as the turn they're for ends, so they count toward it
+                GameEvent::SpeedBonus { .. } => {
+                    if score_on(&board) >= score {
+                        return Some(turn);
+                    }
+                }
+                GameEvent::Insert { .. }
+                | GameEvent::Join { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::NewRound
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        None
+    }
+
+    /// Rebuilds the board as it was after the given number of turns
+    pub fn board_after(&self, turns: usize, wrap_tokens: bool) -> Board {
+        self.state_after(turns, wrap_tokens).0
+    }
+
+    /// Rebuilds the board being played on, and the layers set aside in portal mode, as they were
+    /// after the given number of turns
+    pub fn state_after(&self, turns: usize, wrap_tokens: bool) -> (Board, Option<World>) {
+        let mut board = self.initial_board.clone();
+        let mut world = self.initial_world.clone();
+        let mut turns_left = turns;
+        for event in &self.events {
+            // the board collapses, bonuses come in, and the next layer comes into play as a turn ends,
+            // so those still count as part of the turn
+            let ending = matches!(
+                event,
+                GameEvent::Collapse { .. } | GameEvent::SpeedBonus { .. } | GameEvent::SwitchLayer { .. }
+            );
+            if turns_left == 0 && !ending {
+                break;
+            }
+            apply_event(&mut board, world.as_mut(), event, wrap_tokens);
+            match *event {
+                GameEvent::Move { .. } | GameEvent::Skip { .. } => turns_left -= 1,
+                GameEvent::Insert { .. }
+                | GameEvent::Join { .. }
+                | GameEvent::Leave { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::NewRound
+                | GameEvent::SpeedBonus { .. }
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        (board, world)
+    }
+
+    /// Gets the turn order as it was after the given number of turns
+    pub fn turn_order_after(&self, turns: usize) -> Vec<PlayerID> {
+        let mut turn_order = self.initial_turn_order.clone();
+        let mut turns_left = turns;
+        for event in &self.events {
+            if turns_left == 0 {
+                break;
+            }
+            match *event {
+                GameEvent::Move { .. } | GameEvent::Skip { .. } => {
+                    turn_order.rotate_left(1);
+                    turns_left -= 1;
+                }
+                GameEvent::Join { player, .. } => turn_order.push(player),
+                GameEvent::Leave { player } => turn_order.retain(|&p| p != player),
+                // going first is an advantage, so it moves along one every round of party mode
+                GameEvent::NewRound => turn_order.rotate_left(1),
+                GameEvent::Insert { .. }
+                | GameEvent::Collapse { .. }
+                | GameEvent::SpeedBonus { .. }
+                | GameEvent::SwitchLayer { .. } => (),
+            }
+        }
+        turn_order
+    }
+
+    /// Describes what happened on the given turn (counting from 1)
+    pub fn describe_turn(&self, turn: usize, players: &BTreeMap<PlayerID, Player>, wrap_tokens: bool) -> String {
+        if turn == 0 {
+            return "Start of game".to_string();
+        }
+        let mut turns = self.events.iter().filter_map(|e| match *e {
+            GameEvent::Move {
+                player,
+                to,
+                next_target,
+                ..
+            } => Some((player, Some(to), next_target)),
+            GameEvent::Skip { player } => Some((player, None, None)),
+            GameEvent::Insert { .. }
+            | GameEvent::Join { .. }
+            | GameEvent::Leave { .. }
+            | GameEvent::Collapse { .. }
+            | GameEvent::NewRound
+            | GameEvent::SpeedBonus { .. }
+            | GameEvent::SwitchLayer { .. } => None,
+        });
+        match turns.nth(turn - 1) {
+            Some((player, None, _)) => {
+                let name = players.get(&player).map_or("Someone", |p| p.name.as_str());
+                format!("Turn {}: {} was away and got skipped", turn, name)
+            }
+            Some((player, Some((row, col)), next_target)) => {
+                let name = players.get(&player).map_or("Someone", |p| p.name.as_str());
+                if next_target.is_some() {
+                    let score = self
+                        .board_after(turn, wrap_tokens)
+                        .player_tokens
+                        .get(&player)
+                        .map_or(0, |token| token.score);
+                    format!(
+                        "Turn {}: {} reached their target at row {}, column {} (score {})",
+                        turn,
+                        name,
+                        row + 1,
+                        col + 1,
+                        score
+                    )
+                } else {
+                    format!("Turn {}: {} moved", turn, name)
+                }
+            }
+            None => format!("Turn {}", turn),
+        }
+    }
+}
+
+/// Plays out a single event on the given board, and the layers set aside if it's portal mode
+fn apply_event(board: &mut Board, world: Option<&mut World>, event: &GameEvent, wrap_tokens: bool) {
+    match *event {
+        GameEvent::Insert { ref tile, position } => {
+            board.loose_tile = tile.clone();
+            board.loose_tile_position = position;
+            board.push_loose_tile(wrap_tokens);
+        }
+        GameEvent::Move {
+            player,
+            from,
+            to,
+            next_target,
+        } => {
+            board.spend_energy(player, from, to);
+            board.move_player(player, to);
+            if next_target.is_some() {
+                let (old_row, old_col) = to;
+                let cell = board.get_mut([old_col, old_row]);
+                cell.whose_target = None;
+                let golden = cell.golden;
+                let scoring = board.scoring;
+                if let Some(token) = board.player_tokens.get_mut(&player) {
+                    token.reached_target(scoring, golden);
+                }
+            }
+            board.finish_turn(player, Some(to));
+            match world {
+                Some(world) => {
+                    world.finish_turn(player);
+                    world.take_portal(board, player);
+                    // new targets go on the layer after the one the token ended up on
+                    if let (Some(target), Some(token)) = (next_target, board.player_tokens.get(&player)) {
+                        let layer = world.next_layer(token.layer);
+                        world.place_target(board, player, layer, target);
+                    }
+                }
+                None => {
+                    if let Some(target) = next_target {
+                        board.place_target(player, target);
+                    }
+                }
+            }
+            if next_target.is_some() {
+                board.draw_card(player);
+            }
+        }
+        GameEvent::Join {
+            player,
+            position,
+            target,
+            ref deck,
+            card,
+        } => {
+            let token = PlayerToken {
+                player_id: player,
+                position,
+                score: 0,
+                target_value: 0,
+                energy: board.energy_per_turn,
+                layer: board.layer,
+                deck: deck.clone(),
+                card,
+            };
+            board.player_tokens.insert(player, token);
+            if let Some(target) = target {
+                board.place_target(player, target);
+            }
+        }
+        GameEvent::Leave { player } => {
+            board.remove_player(player);
+            if let Some(world) = world {
+                world.remove_player(player);
+            }
+        }
+        GameEvent::Collapse { ref targets } => {
+            board.shrink();
+            for &(player, target) in targets {
+                board.place_target(player, target);
+                board.draw_card(player);
+            }
+        }
+        GameEvent::Skip { player } => {
+            board.finish_turn(player, None);
+            if let Some(world) = world {
+                world.finish_turn(player);
+            }
+        }
+        GameEvent::NewRound => (),
+        GameEvent::SpeedBonus { player } => {
+            if let Some(token) = board.player_tokens.get_mut(&player) {
+                token.earned_bonus();
+            }
+        }
+        GameEvent::SwitchLayer { layer } => {
+            if let Some(world) = world {
+                world.bring_into_play(board, layer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::board::Scoring;
+
+    use super::*;
+
+    fn log_of_skips(turns: usize) -> GameLog {
+        let cross = || Tile::try_from('┼').unwrap();
+        let board = Board {
+            cells: vec![cross()],
+            width: 1,
+            loose_tile: cross(),
+            loose_tile_position: (Direction::North, 0),
+            player_tokens: BTreeMap::new(),
+            tutorial_step: None,
+            scoring: Scoring::default(),
+            target_steal: false,
+            energy_per_turn: 0,
+            layer: 0,
+        };
+        let mut log = GameLog::new(board, None, vec![1, 2]);
+        for turn in 0..turns {
+            log.record(GameEvent::Skip { player: turn as PlayerID % 2 + 1 });
+        }
+        log
+    }
+
+    #[test]
+    fn tail_keeps_the_turn_count() {
+        let log = log_of_skips(10);
+        let tail = log.tail(3);
+        assert_eq!(tail.events.len(), 3);
+        assert_eq!(tail.event_count(), 10);
+        assert_eq!(tail.turn_count(), 10);
+        assert_eq!(tail.events_since(8).map(<[_]>::len), Some(2));
+        assert!(tail.events_since(2).is_none());
+    }
+
+    #[test]
+    fn splice_restores_the_full_log() {
+        let older = log_of_skips(8);
+        let mut tail = log_of_skips(10).tail(3);
+        assert!(tail.splice(&older));
+        assert_eq!(tail.events.len(), 10);
+        assert_eq!(tail.turn_count(), 10);
+        // a copy missing the skipped turns can't fill the gap
+        let mut tail = log_of_skips(10).tail(3);
+        assert!(!tail.splice(&log_of_skips(5)));
+    }
+}
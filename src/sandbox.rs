@@ -0,0 +1,28 @@
+//! Practice sandbox, with the turn rules turned off
+
+use std::sync::{Arc, RwLock};
+
+use crate::{Player, PlayerID};
+use crate::board_controller::{BoardController, BoardSettings};
+use crate::colors;
+use crate::menu::{ConnectedState, NetGameState};
+use crate::net;
+
+/// Sets up a sandbox board for one local player
+pub fn new_conn_state(player_id: PlayerID) -> ConnectedState {
+    let settings = BoardSettings {
+        hint_limit: 0,
+        sandbox: true,
+        ..BoardSettings::default()
+    };
+    let players = vec![Player::new(
+        "Player 1".to_string(),
+        colors::Color(0.2, 0.4, 0.6),
+        player_id,
+    )];
+    let board = BoardController::new(settings, players, player_id);
+    let state = NetGameState::Active(board);
+    let state = Arc::new(RwLock::new(state));
+    let sender = net::NetHandler::run_fake(player_id);
+    ConnectedState { sender, state }
+}
@@ -0,0 +1,103 @@
+//! Running the game in steady ticks, however often the browser gets around to calling us
+
+/// Seconds of game time in one tick
+pub const TICK: f64 = 1.0 / 60.0;
+
+/// Longest gap (in seconds) between callbacks that still counts in full, which is plenty for a
+/// background tab the browser only calls about once a second
+const MAX_GAP: f64 = 5.0;
+
+/// Seconds of game time a longer gap counts as, so a tab that was frozen or asleep doesn't
+/// fast-forward through everything it missed
+const MAX_CATCH_UP: f64 = 0.25;
+
+/// Things that happen every so often rather than every tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Task {
+    /// The host sends out a board digest, so guests can tell if they've fallen out of sync
+    Digest,
+}
+
+impl Task {
+    /// Every periodic task, to be scheduled from the start
+    const ALL: &'static [Task] = &[Task::Digest];
+
+    /// Seconds of game time between runs
+    fn period(self) -> f64 {
+        match self {
+            Task::Digest => 5.0,
+        }
+    }
+}
+
+/// Turns the time between timer callbacks into a whole number of ticks, and keeps track of when
+/// periodic tasks are due
+pub struct Scheduler {
+    /// When the last callback happened
+    last_call: Option<f64>,
+    /// Game time not yet run, which is always less than a tick after catching up
+    behind: f64,
+    /// Periodic tasks, and seconds of game time until each is next due
+    tasks: Vec<(Task, f64)>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler with every periodic task starting its first wait
+    pub fn new() -> Scheduler {
+        Scheduler {
+            last_call: None,
+            behind: 0.0,
+            tasks: Task::ALL.iter().map(|&task| (task, task.period())).collect(),
+        }
+    }
+
+    /// Catches up to the given time (in seconds), giving back how many ticks should run
+    pub fn ticks_due(&mut self, now: f64) -> u32 {
+        let last_call = self.last_call.replace(now).unwrap_or(now);
+        let gap = now - last_call;
+        self.behind += if gap > MAX_GAP { MAX_CATCH_UP } else { gap.max(0.0) };
+        let ticks = (self.behind / TICK).floor();
+        self.behind -= ticks * TICK;
+        ticks as u32
+    }
+
+    /// Moves periodic tasks along by one tick, giving back the ones that came due
+    pub fn tick_tasks(&mut self) -> Vec<Task> {
+        let mut due = vec![];
+        for (task, wait) in &mut self.tasks {
+            *wait -= TICK;
+            if *wait <= 0.0 {
+                *wait += task.period();
+                due.push(*task);
+            }
+        }
+        due
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_tabs_keep_up() {
+        let mut scheduler = Scheduler::new();
+        scheduler.ticks_due(0.0);
+        let ticks: u32 = (1..=10).map(|second| scheduler.ticks_due(second as f64)).sum();
+        assert!((599..=600).contains(&ticks), "{}", ticks);
+    }
+
+    #[test]
+    fn long_gaps_dont_fast_forward() {
+        let mut scheduler = Scheduler::new();
+        scheduler.ticks_due(0.0);
+        assert!(scheduler.ticks_due(3600.0) <= (MAX_CATCH_UP / TICK).ceil() as u32);
+        assert_eq!(scheduler.ticks_due(3599.0), 0);
+    }
+}
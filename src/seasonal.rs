@@ -0,0 +1,116 @@
+//! Limited-time events that change up the rules for a while, from the calendar in `seasonal.toml`
+
+use serde::{Deserialize, Serialize};
+
+/// A change to the rules an event brings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    /// Everyone gets this many more hints than the settings give
+    ExtraHints(u8),
+    /// This many tiles start out golden, and reaching a target on one scores double
+    DoubleScoreTiles(u8),
+    /// Keys and gamepads move the guide and highlight the opposite way, and rotate the other way
+    InvertedControls,
+}
+
+impl Modifier {
+    /// Describes this modifier in a few words, for a badge
+    pub fn describe(&self) -> String {
+        match *self {
+            Modifier::ExtraHints(1) => "+1 hint".to_string(),
+            Modifier::ExtraHints(count) => format!("+{} hints", count),
+            Modifier::DoubleScoreTiles(_) => "Golden tiles score double".to_string(),
+            Modifier::InvertedControls => "Inverted controls".to_string(),
+        }
+    }
+}
+
+/// An event that's on, and what it changes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SeasonalEvent {
+    /// Name to show on its badge
+    pub name: String,
+    /// What it changes
+    pub modifiers: Vec<Modifier>,
+}
+
+impl SeasonalEvent {
+    /// Describes this event and everything it changes, for a badge
+    pub fn badge(&self) -> String {
+        let modifiers: Vec<String> = self.modifiers.iter().map(Modifier::describe).collect();
+        format!("{}: {}", self.name, modifiers.join(", "))
+    }
+}
+
+/// An event as written in the calendar
+#[derive(Deserialize, Debug, Clone)]
+struct CalendarEntry {
+    name: String,
+    /// First day it's on, as "MM-DD"
+    from: String,
+    /// Last day it's on, as "MM-DD", which can be earlier in the year than the first to run over New Year's
+    until: String,
+    #[serde(default)]
+    extra_hints: u8,
+    #[serde(default)]
+    double_score_tiles: u8,
+    #[serde(default)]
+    inverted_controls: bool,
+}
+
+impl CalendarEntry {
+    /// Checks if this event is on for the given (month, day)
+    fn is_on(&self, today: (u32, u32)) -> bool {
+        match (parse_day(&self.from), parse_day(&self.until)) {
+            (Some(from), Some(until)) if from <= until => from <= today && today <= until,
+            (Some(from), Some(until)) => today >= from || today <= until,
+            _ => false,
+        }
+    }
+
+    fn event(&self) -> SeasonalEvent {
+        let mut modifiers = vec![];
+        if self.extra_hints > 0 {
+            modifiers.push(Modifier::ExtraHints(self.extra_hints));
+        }
+        if self.double_score_tiles > 0 {
+            modifiers.push(Modifier::DoubleScoreTiles(self.double_score_tiles));
+        }
+        if self.inverted_controls {
+            modifiers.push(Modifier::InvertedControls);
+        }
+        SeasonalEvent {
+            name: self.name.clone(),
+            modifiers,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Calendar {
+    #[serde(default)]
+    event: Vec<CalendarEntry>,
+}
+
+lazy_static! {
+    static ref CALENDAR: Calendar = toml::from_str(include_str!("seasonal.toml")).expect("Bad event calendar");
+}
+
+/// Reads "MM-DD" as (month, day)
+fn parse_day(text: &str) -> Option<(u32, u32)> {
+    let (month, day) = text.split_once('-')?;
+    Some((month.parse().ok()?, day.parse().ok()?))
+}
+
+/// Gets every event that's on today, by the local clock, leaving out any that wouldn't change anything
+pub fn active_events() -> Vec<SeasonalEvent> {
+    let now = js_sys::Date::new_0();
+    let today = (now.get_month() + 1, now.get_date());
+    CALENDAR
+        .event
+        .iter()
+        .filter(|entry| entry.is_on(today))
+        .map(CalendarEntry::event)
+        .filter(|event| !event.modifiers.is_empty())
+        .collect()
+}
@@ -0,0 +1,94 @@
+//! League play, where the same players play several boards in a row
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BoardController, Player, PlayerID};
+
+/// Most boards a series can have
+pub const MAX_SERIES_LENGTH: u8 = 9;
+
+/// Scores carried from one board of a series to the next
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeriesState {
+    /// How many boards the series has
+    pub length: u8,
+    /// How many boards have been finished
+    pub boards_played: u8,
+    /// Every player who's been in the series, in the first board's turn order
+    pub players: Vec<Player>,
+    /// Targets reached by each player, over every board so far
+    pub totals: BTreeMap<PlayerID, u32>,
+    /// Boards won by each player
+    pub board_wins: BTreeMap<PlayerID, u8>,
+    /// Players who've said they're ready for the next board
+    pub ready: BTreeSet<PlayerID>,
+}
+
+impl SeriesState {
+    /// Starts a series of the given length with the given players, if it's more than one board
+    pub fn new(length: u8, players: &[Player]) -> Option<SeriesState> {
+        if length <= 1 {
+            return None;
+        }
+        Some(SeriesState {
+            length,
+            boards_played: 0,
+            players: players.to_vec(),
+            totals: BTreeMap::new(),
+            board_wins: BTreeMap::new(),
+            ready: BTreeSet::new(),
+        })
+    }
+
+    /// Adds the results of the given finished board
+    pub fn record(&mut self, board: &BoardController, winner: PlayerID) {
+        for (id, token) in &board.board.player_tokens {
+            *self.totals.entry(*id).or_insert(0) += u32::from(token.score);
+        }
+        *self.board_wins.entry(winner).or_insert(0) += 1;
+        // anyone who joined partway through still belongs in the standings
+        for id in &board.turn_order {
+            if !self.players.iter().any(|p| p.id == *id) {
+                if let Some(player) = board.players.get(id) {
+                    self.players.push(player.clone());
+                }
+            }
+        }
+        self.boards_played += 1;
+        self.ready.clear();
+    }
+
+    /// Checks if every board has been played
+    pub fn is_over(&self) -> bool {
+        self.boards_played >= self.length
+    }
+
+    /// Lists players from first to last, as (player, total, boards won)
+    ///
+    /// Ties on total go to whoever won more boards, then to whoever came first in the first board.
+    pub fn standings(&self) -> Vec<(&Player, u32, u8)> {
+        let mut standings: Vec<_> = self
+            .players
+            .iter()
+            .map(|p| {
+                let total = self.totals.get(&p.id).cloned().unwrap_or(0);
+                let wins = self.board_wins.get(&p.id).cloned().unwrap_or(0);
+                (p, total, wins)
+            })
+            .collect();
+        // sorting is stable, so equal players stay in turn order
+        standings.sort_by_key(|&(_, total, wins)| Reverse((total, wins)));
+        standings
+    }
+
+    /// Gets the series champion, once every board has been played
+    pub fn champion(&self) -> Option<&Player> {
+        if !self.is_over() {
+            return None;
+        }
+        self.standings().first().map(|&(player, _, _)| player)
+    }
+}
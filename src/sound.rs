@@ -1,13 +1,24 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::Mutex;
 
+use rand::random;
+use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
-use web_sys::{AudioContext, GainNode, HtmlAudioElement};
+use web_sys::{
+    AudioBuffer, AudioContext, BiquadFilterType, GainNode, HtmlAudioElement, OscillatorType, Response,
+    SpeechSynthesisUtterance,
+};
 
 use crate::options;
+use crate::tutorial::TutorialStep;
 
 const MUSIC_VOLUME: f32 = 0.6;
 const SOUND_VOLUME: f32 = 0.4;
+const AMBIENCE_VOLUME: f32 = 0.5;
+/// How much of the ambience is left while the menu music is playing
+const AMBIENCE_DUCKING: f32 = 0.25;
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Music {
@@ -19,6 +30,15 @@ fn calc_gain(global_scale: f32, options_level: u8) -> f32 {
     global_scale * (f32::from(options_level)) / 100.0
 }
 
+fn calc_ambience_gain(options_level: u8, ducked: bool) -> f32 {
+    let gain = calc_gain(AMBIENCE_VOLUME, options_level);
+    if ducked {
+        gain * AMBIENCE_DUCKING
+    } else {
+        gain
+    }
+}
+
 fn ramp_gain(gain: web_sys::AudioParam, value: f32) {
     gain.exponential_ramp_to_value_at_time(value, 0.01).unwrap_throw();
 }
@@ -39,24 +59,223 @@ impl Music {
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Sound {
     YourTurn,
+    TimerTick,
+    TimerBuzzer,
+    Insert,
+    Move,
+    /// A short tune that starts a step of the tutorial, different for each one
+    Tutorial(TutorialStep),
 }
 
+/// A sound that's synthesized instead of loaded from a file
+struct Tone {
+    frequency: f32,
+    duration: f64,
+    shape: OscillatorType,
+}
+
+/// Decoded takes on each sound, shared with the callbacks that finish loading them
+type SoundBuffers = Rc<RefCell<HashMap<Sound, Vec<AudioBuffer>>>>;
+
 impl Sound {
-    fn load(self) -> HtmlAudioElement {
-        let path = match self {
-            Sound::YourTurn => "assets/TurnPing.wav",
-        };
+    const ALL: [Sound; 8] = [
+        Sound::YourTurn,
+        Sound::TimerTick,
+        Sound::TimerBuzzer,
+        Sound::Insert,
+        Sound::Move,
+        Sound::Tutorial(TutorialStep::First),
+        Sound::Tutorial(TutorialStep::Second),
+        Sound::Tutorial(TutorialStep::Third),
+    ];
 
-        HtmlAudioElement::new_with_src(path).unwrap_throw()
+    /// Files with takes on the sound, if it isn't synthesized
+    fn paths(self) -> &'static [&'static str] {
+        match self {
+            Sound::YourTurn => &["assets/TurnPing.wav"],
+            Sound::TimerTick | Sound::TimerBuzzer | Sound::Insert | Sound::Move | Sound::Tutorial(_) => &[],
+        }
+    }
+
+    fn tone(self) -> Option<Tone> {
+        match self {
+            Sound::YourTurn | Sound::Insert | Sound::Move | Sound::Tutorial(_) => None,
+            Sound::TimerTick => Some(Tone {
+                frequency: 880.0,
+                duration: 0.05,
+                shape: OscillatorType::Square,
+            }),
+            Sound::TimerBuzzer => Some(Tone {
+                frequency: 110.0,
+                duration: 0.6,
+                shape: OscillatorType::Sawtooth,
+            }),
+        }
+    }
+
+    /// How many different takes on the sound to synthesize, so it doesn't get tiresome when it's
+    /// heard every turn
+    fn variations(self) -> usize {
+        match self {
+            Sound::YourTurn | Sound::TimerTick | Sound::TimerBuzzer => 0,
+            Sound::Insert | Sound::Move => 4,
+            // a tune should sound the same every time, so it's recognizable
+            Sound::Tutorial(_) => 1,
+        }
+    }
+
+    /// How many semitones up or down each play can be pitched at random
+    fn detune(self) -> f32 {
+        match self {
+            Sound::YourTurn | Sound::TimerTick | Sound::TimerBuzzer | Sound::Tutorial(_) => 0.0,
+            Sound::Insert => 2.0,
+            Sound::Move => 3.0,
+        }
+    }
+
+    /// Synthesizes a take on the sound, which comes out a bit different every time
+    fn samples(self, sample_rate: f32) -> Vec<f32> {
+        match self {
+            Sound::YourTurn | Sound::TimerTick | Sound::TimerBuzzer => vec![],
+            Sound::Insert => {
+                // a row of stones grinding along, smoothed so it rumbles instead of hisses
+                let length = ((0.3 + random::<f32>() * 0.15) * sample_rate) as usize;
+                let attack = 0.03 * sample_rate;
+                let mut level = 0.0;
+                (0..length)
+                    .map(|i| {
+                        let i = i as f32;
+                        let envelope = if i < attack {
+                            i / attack
+                        } else {
+                            1.0 - (i - attack) / (length as f32 - attack)
+                        };
+                        level += ((random::<f32>() * 2.0 - 1.0) - level) * 0.1;
+                        level * envelope * 2.0
+                    })
+                    .collect()
+            }
+            Sound::Move => {
+                // a knock on stone, dying away quickly
+                let length = (0.12 * sample_rate) as usize;
+                let frequency = 180.0 + random::<f32>() * 80.0;
+                (0..length)
+                    .map(|i| {
+                        let t = i as f32 / sample_rate;
+                        let envelope = (-t * 40.0).exp();
+                        let body = (t * frequency * 2.0 * std::f32::consts::PI).sin();
+                        let click = (random::<f32>() * 2.0 - 1.0) * 0.3;
+                        (body + click) * envelope * 0.6
+                    })
+                    .collect()
+            }
+            Sound::Tutorial(step) => {
+                // bell-ish notes one after another, each ringing on under the next
+                let notes = stinger_notes(step);
+                let spacing = (0.14 * sample_rate) as usize;
+                let ring = (0.5 * sample_rate) as usize;
+                let mut result = vec![0.0; spacing * (notes.len() - 1) + ring];
+                for (i, &frequency) in notes.iter().enumerate() {
+                    let start = i * spacing;
+                    for (j, sample) in result[start..start + ring].iter_mut().enumerate() {
+                        let t = j as f32 / sample_rate;
+                        let envelope = (-t * 8.0).exp();
+                        let phase = t * frequency * 2.0 * std::f32::consts::PI;
+                        *sample += (phase.sin() + 0.3 * (phase * 2.0).sin()) * envelope * 0.4;
+                    }
+                }
+                result
+            }
+        }
+    }
+}
+
+/// Frequencies of the notes in the tune for a step of the tutorial, starting higher every step
+/// so it sounds like getting somewhere
+fn stinger_notes(step: TutorialStep) -> &'static [f32] {
+    match step {
+        // C E G
+        TutorialStep::First => &[523.25, 659.25, 783.99],
+        // D F# A
+        TutorialStep::Second => &[587.33, 739.99, 880.0],
+        // E G# B E
+        TutorialStep::Third => &[659.25, 830.61, 987.77, 1318.51],
+    }
+}
+
+/// A looping background sound, synthesized from noise
+#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+enum Ambience {
+    Wind,
+    StoneEcho,
+}
+
+impl Ambience {
+    const ALL: [Ambience; 2] = [Ambience::Wind, Ambience::StoneEcho];
+
+    /// Seconds before the loop repeats
+    fn duration(self) -> f32 {
+        match self {
+            Ambience::Wind => 8.0,
+            Ambience::StoneEcho => 11.0,
+        }
+    }
+
+    fn filter(self) -> (BiquadFilterType, f32) {
+        match self {
+            Ambience::Wind => (BiquadFilterType::Lowpass, 500.0),
+            Ambience::StoneEcho => (BiquadFilterType::Bandpass, 180.0),
+        }
+    }
+
+    fn samples(self, sample_rate: f32) -> Vec<f32> {
+        let length = (self.duration() * sample_rate) as usize;
+        match self {
+            Ambience::Wind => {
+                // brown noise, swelling and fading a couple times per loop
+                let mut level = 0.0;
+                (0..length)
+                    .map(|i| {
+                        level = (level + (random::<f32>() * 2.0 - 1.0) * 0.02) * 0.998;
+                        let swell = (i as f32 / length as f32 * 2.0 * std::f32::consts::PI * 2.0).sin();
+                        level * 3.0 * (0.7 + 0.3 * swell)
+                    })
+                    .collect()
+            }
+            Ambience::StoneEcho => {
+                // one grinding slide, then quieter copies of it bouncing back
+                let mut result = vec![0.0; length];
+                let slide_start = (1.0 * sample_rate) as usize;
+                let slide_length = (0.9 * sample_rate) as usize;
+                let echo_delay = (0.4 * sample_rate) as usize;
+                let slide: Vec<f32> = (0..slide_length)
+                    .map(|i| {
+                        let envelope = (i as f32 / slide_length as f32 * std::f32::consts::PI).sin();
+                        (random::<f32>() * 2.0 - 1.0) * envelope
+                    })
+                    .collect();
+                let mut volume = 0.5;
+                for echo in 0..5 {
+                    let start = slide_start + echo * echo_delay;
+                    for (sample, value) in result[start..].iter_mut().zip(slide.iter()) {
+                        *sample += value * volume;
+                    }
+                    volume *= 0.45;
+                }
+                result
+            }
+        }
     }
 }
 
 pub struct SoundEngine {
     context: AudioContext,
     music_sources: Mutex<HashMap<Music, HtmlAudioElement>>,
-    sound_sources: Mutex<HashMap<Sound, HtmlAudioElement>>,
+    sound_buffers: SoundBuffers,
     music_gain: GainNode,
     sound_gain: GainNode,
+    ambience_gain: GainNode,
+    ambience_started: Mutex<bool>,
     current_music: Mutex<Option<Music>>,
 }
 
@@ -81,12 +300,44 @@ impl SoundEngine {
         sound_gain
             .connect_with_audio_node(&context.destination())
             .unwrap_throw();
+        let ambience_gain = context
+            .create_gain()
+            .expect_throw("Failed to create ambience gain node");
+        ambience_gain
+            .gain()
+            .set_value(calc_ambience_gain(options::HANDLE.fetch().ambience_level, true));
+        ambience_gain
+            .connect_with_audio_node(&context.destination())
+            .unwrap_throw();
+        let sound_buffers = SoundBuffers::default();
+        for &snd in Sound::ALL.iter() {
+            for path in snd.paths() {
+                load_sound(&context, snd, path, sound_buffers.clone());
+            }
+        }
         SoundEngine {
             context,
             music_sources: Mutex::new(HashMap::new()),
-            sound_sources: Mutex::new(HashMap::new()),
+            sound_buffers,
             music_gain,
             sound_gain,
+            ambience_gain,
+            ambience_started: Mutex::new(false),
+            current_music: Mutex::new(None),
+        }
+    }
+
+    /// Creates an engine with nothing behind it, for tests that never make a sound
+    #[cfg(test)]
+    pub fn silent() -> SoundEngine {
+        SoundEngine {
+            context: JsValue::UNDEFINED.unchecked_into(),
+            music_sources: Mutex::new(HashMap::new()),
+            sound_buffers: SoundBuffers::default(),
+            music_gain: JsValue::UNDEFINED.unchecked_into(),
+            sound_gain: JsValue::UNDEFINED.unchecked_into(),
+            ambience_gain: JsValue::UNDEFINED.unchecked_into(),
+            ambience_started: Mutex::new(false),
             current_music: Mutex::new(None),
         }
     }
@@ -111,6 +362,7 @@ impl SoundEngine {
         }
         let mut music_sources = self.music_sources.lock().unwrap();
         if let Some(ref old_music) = *current_music {
+            // each track keeps its element, so pausing it here means it picks back up from here
             if let Some(old_source) = music_sources.get(old_music) {
                 old_source.pause().unwrap_throw();
             }
@@ -128,23 +380,104 @@ impl SoundEngine {
         });
         let _ = source.play().unwrap_throw();
         *current_music = Some(music);
+        if music == Music::InGame {
+            self.start_ambience();
+        }
+        ramp_gain(self.ambience_gain.gain(), calc_ambience_gain(options::HANDLE.fetch().ambience_level, music == Music::Menu));
+    }
+
+    /// Starts the ambience looping, the first time a game's played
+    fn start_ambience(&self) {
+        let mut ambience_started = self.ambience_started.lock().unwrap();
+        if *ambience_started {
+            return;
+        }
+        let sample_rate = self.context.sample_rate();
+        for &ambience in Ambience::ALL.iter() {
+            let buffer = self.make_buffer(ambience.samples(sample_rate));
+            let source = self.context.create_buffer_source().unwrap_throw();
+            source.set_buffer(Some(&buffer));
+            source.set_loop(true);
+            let filter = self.context.create_biquad_filter().unwrap_throw();
+            let (filter_type, frequency) = ambience.filter();
+            filter.set_type(filter_type);
+            filter.frequency().set_value(frequency);
+            source.connect_with_audio_node(&filter).unwrap_throw();
+            filter
+                .connect_with_audio_node(&self.ambience_gain)
+                .unwrap_throw();
+            source.start().unwrap_throw();
+        }
+        *ambience_started = true;
     }
 
     pub fn play_sound(&self, snd: Sound) {
         let _ = self.context.resume();
-        let mut sound_sources = self.sound_sources.lock().unwrap();
-        let source = sound_sources.entry(snd).or_insert_with(|| {
-            let source = snd.load();
-            let source_node = self
-                .context
-                .create_media_element_source(&source)
-                .unwrap_throw();
-            source_node
-                .connect_with_audio_node(&self.sound_gain)
-                .unwrap_throw();
-            source
-        });
-        let _ = source.play().unwrap_throw();
+        if let Some(tone) = snd.tone() {
+            self.play_tone(tone);
+            return;
+        }
+        let mut sound_buffers = self.sound_buffers.borrow_mut();
+        if snd.variations() > 0 {
+            sound_buffers.entry(snd).or_insert_with(|| {
+                (0..snd.variations())
+                    .map(|_| self.make_buffer(snd.samples(self.context.sample_rate())))
+                    .collect()
+            });
+        }
+        // a sound that's still loading just gets skipped
+        let buffers = match sound_buffers.get(&snd) {
+            Some(buffers) if !buffers.is_empty() => buffers,
+            _ => return,
+        };
+        // every play gets its own source, so the same sound can overlap itself
+        let buffer = &buffers[random::<usize>() % buffers.len()];
+        let source = self.context.create_buffer_source().unwrap_throw();
+        source.set_buffer(Some(buffer));
+        let semitones = (random::<f32>() * 2.0 - 1.0) * snd.detune();
+        source.playback_rate().set_value(2.0f32.powf(semitones / 12.0));
+        source
+            .connect_with_audio_node(&self.sound_gain)
+            .unwrap_throw();
+        source.start().unwrap_throw();
+    }
+
+    /// Reads the given text out loud, cutting off anything that was still being read, at the sound level
+    pub fn speak(&self, text: &str) {
+        let window = web_sys::window().unwrap_throw();
+        // not every browser can talk
+        let synthesis = match window.speech_synthesis() {
+            Ok(synthesis) => synthesis,
+            Err(_) => return,
+        };
+        let utterance = match SpeechSynthesisUtterance::new_with_text(text) {
+            Ok(utterance) => utterance,
+            Err(_) => return,
+        };
+        utterance.set_volume(f32::from(options::HANDLE.fetch().sound_level) / 100.0);
+        synthesis.cancel();
+        synthesis.speak(&utterance);
+    }
+
+    fn make_buffer(&self, mut samples: Vec<f32>) -> AudioBuffer {
+        let buffer = self
+            .context
+            .create_buffer(1, samples.len() as u32, self.context.sample_rate())
+            .unwrap_throw();
+        buffer.copy_to_channel(&mut samples, 0).unwrap_throw();
+        buffer
+    }
+
+    fn play_tone(&self, tone: Tone) {
+        let oscillator = self.context.create_oscillator().unwrap_throw();
+        oscillator.set_type(tone.shape);
+        oscillator.frequency().set_value(tone.frequency);
+        oscillator
+            .connect_with_audio_node(&self.sound_gain)
+            .unwrap_throw();
+        let start = self.context.current_time();
+        oscillator.start().unwrap_throw();
+        oscillator.stop_with_when(start + tone.duration).unwrap_throw();
     }
 
     pub fn fetch_volume(&self) {
@@ -154,9 +487,38 @@ impl SoundEngine {
     pub fn poke_options(&self, new_options: &options::GameOptions) {
         ramp_gain(self.music_gain.gain(), calc_gain(MUSIC_VOLUME, new_options.music_level));
         ramp_gain(self.sound_gain.gain(), calc_gain(SOUND_VOLUME, new_options.sound_level));
+        let ducked = *self.current_music.lock().unwrap() != Some(Music::InGame);
+        ramp_gain(self.ambience_gain.gain(), calc_ambience_gain(new_options.ambience_level, ducked));
     }
 }
 
+/// Fetches and decodes a take on a sound, adding it to the sound's takes once it's ready
+fn load_sound(context: &AudioContext, snd: Sound, path: &str, sound_buffers: SoundBuffers) {
+    let context = context.clone();
+    let on_response = Closure::once(move |response: JsValue| {
+        let response: Response = response.unchecked_into();
+        let on_data = Closure::once(move |data: JsValue| {
+            let on_decoded = Closure::once(move |buffer: JsValue| {
+                sound_buffers
+                    .borrow_mut()
+                    .entry(snd)
+                    .or_default()
+                    .push(buffer.unchecked_into());
+            });
+            let _ = context
+                .decode_audio_data(&data.unchecked_into())
+                .unwrap_throw()
+                .then(&on_decoded);
+            on_decoded.forget();
+        });
+        let _ = response.array_buffer().unwrap_throw().then(&on_data);
+        on_data.forget();
+    });
+    let window = web_sys::window().unwrap_throw();
+    let _ = window.fetch_with_str(path).then(&on_response);
+    on_response.forget();
+}
+
 impl Default for SoundEngine {
     fn default() -> Self {
         Self::new()
@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 use crate::PlayerID;
 
 /// Cardinal directions
-#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Direction {
     /// Up
     North,
@@ -124,6 +124,8 @@ pub enum Shape {
     I,
     /// Three connections (canonically North / East / South)
     T,
+    /// Four connections, the same whichever way it faces
+    Cross,
 }
 
 impl Shape {
@@ -132,6 +134,7 @@ impl Shape {
             Shape::L => vec![Direction::North, Direction::East],
             Shape::I => vec![Direction::North, Direction::South],
             Shape::T => vec![Direction::North, Direction::East, Direction::South],
+            Shape::Cross => Direction::all().to_vec(),
         }
     }
     fn walls(&self) -> Vec<Direction> {
@@ -139,16 +142,19 @@ impl Shape {
             Shape::L => vec![Direction::South, Direction::West],
             Shape::I => vec![Direction::East, Direction::West],
             Shape::T => vec![Direction::West],
+            Shape::Cross => vec![],
         }
     }
 }
 
 impl Distribution<Shape> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Shape {
-        match rng.gen_range(0, 3) {
-            0 => Shape::L,
-            1 => Shape::I,
-            2 => Shape::T,
+        // crossroads open up so much of the board that they only turn up once in a while
+        match rng.gen_range(0, 16) {
+            0..=4 => Shape::L,
+            5..=9 => Shape::I,
+            10..=14 => Shape::T,
+            15 => Shape::Cross,
             _ => panic!("Invalid shape generated"),
         }
     }
@@ -163,6 +169,15 @@ pub struct Tile {
     pub orientation: Direction,
     /// Player whose target is this tile
     pub whose_target: Option<PlayerID>,
+    /// Whether someone else landed on this target, so its player can't reach it on their next turn
+    #[serde(default)]
+    pub locked: bool,
+    /// Whether reaching a target on this tile scores double, during an event with golden tiles
+    #[serde(default)]
+    pub golden: bool,
+    /// Whether a token ending its move here goes through to the next layer, in portal mode
+    #[serde(default)]
+    pub portal: bool,
 }
 
 impl Tile {
@@ -197,6 +212,9 @@ impl Distribution<Tile> for Standard {
             shape,
             orientation,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         }
     }
 }
@@ -218,12 +236,16 @@ impl TryFrom<char> for Tile {
             '┬' => (T, East),
             '┤' => (T, South),
             '┴' => (T, West),
+            '┼' => (Cross, North),
             _ => return Err(()),
         };
         Ok(Tile {
             shape,
             orientation: dir,
             whose_target: None,
+            locked: false,
+            golden: false,
+            portal: false,
         })
     }
 }
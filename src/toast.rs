@@ -0,0 +1,152 @@
+//! Short notices that show up over everything for a few seconds, so things happening elsewhere in the
+//! game don't go by in silence
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{BoardSettings, Player, PlayerID};
+use crate::menu::NetGameState;
+
+/// Seconds a toast stays up
+const TOAST_TIME: f64 = 4.0;
+
+/// Most toasts up at once, with the oldest making way for new ones
+const MAX_TOASTS: usize = 4;
+
+/// A message that's up until the given time
+struct Toast {
+    text: String,
+    until: f64,
+}
+
+/// What the game looked like last tick, so changes can be announced
+#[derive(Default)]
+struct Seen {
+    /// Names of everyone else in the game, once there's been a lobby or board to get them from
+    players: Option<BTreeMap<PlayerID, String>>,
+    /// The settings up for editing, without their version, so only real changes count
+    settings: Option<String>,
+    /// Whether the connection was lost
+    lost: bool,
+    /// Whether the active player was about to push twice
+    double_pushing: bool,
+}
+
+/// Toasts that are up, and what's needed to know when to put up new ones
+#[derive(Default)]
+pub struct Toasts {
+    shown: VecDeque<Toast>,
+    seen: Option<Seen>,
+}
+
+impl Toasts {
+    /// Puts up a toast with the given text
+    pub fn push(&mut self, text: impl Into<String>) {
+        self.shown.push_back(Toast {
+            text: text.into(),
+            until: crate::now() + TOAST_TIME,
+        });
+        while self.shown.len() > MAX_TOASTS {
+            self.shown.pop_front();
+        }
+    }
+
+    /// Takes down any toasts that have been up long enough
+    pub fn expire(&mut self) {
+        let now = crate::now();
+        self.shown.retain(|toast| toast.until > now);
+    }
+
+    /// Gets the text of every toast that's up, oldest first
+    pub fn texts(&self) -> Vec<&str> {
+        self.shown.iter().map(|toast| toast.text.as_str()).collect()
+    }
+
+    /// Puts up toasts for anything that changed in the given game since last tick, not counting
+    /// the local player's own doings, or forgets what it had seen if there's no game
+    ///
+    /// `edited` is whether the local player changed the settings this tick.
+    pub fn watch(&mut self, state: Option<&NetGameState>, local_id: PlayerID, edited: bool) {
+        let state = match state {
+            Some(state) => state,
+            None => {
+                self.seen = None;
+                return;
+            }
+        };
+        let mut seen = self.seen.take().unwrap_or_default();
+
+        let players: Option<Vec<&Player>> = match state {
+            NetGameState::Lobby(ref info) => Some(std::iter::once(&info.host).chain(info.guests.iter()).collect()),
+            NetGameState::Active(ref board) => Some(board.players.values().collect()),
+            NetGameState::GameOver(ref info) => Some(info.final_board.players.values().collect()),
+            NetGameState::Connecting | NetGameState::Error(_) => None,
+        };
+        if let Some(players) = players {
+            let players: BTreeMap<PlayerID, String> = players
+                .into_iter()
+                .filter(|player| !player.lives_with(local_id))
+                .map(|player| (player.id, player.name.clone()))
+                .collect();
+            if let Some(ref old_players) = seen.players {
+                for (id, name) in &players {
+                    if !old_players.contains_key(id) {
+                        self.push(format!("{} joined", name));
+                    }
+                }
+                for (id, name) in old_players {
+                    if !players.contains_key(id) {
+                        self.push(format!("{} left", name));
+                    }
+                }
+            }
+            seen.players = Some(players);
+        }
+
+        let settings = state.settings().map(describe_settings);
+        if let (Some(old), Some(new), false) = (&seen.settings, &settings, edited) {
+            if old != new {
+                self.push("The board settings were changed");
+            }
+        }
+        seen.settings = settings;
+
+        let double_pushing = match state {
+            NetGameState::Active(ref board) => board.double_pushing,
+            _ => false,
+        };
+        if let (NetGameState::Active(ref board), false, true) = (state, seen.double_pushing, double_pushing) {
+            let player = board.active_player();
+            if !player.played_from(local_id) {
+                self.push(format!("{} is pushing twice this turn", player.name));
+            }
+        }
+        seen.double_pushing = double_pushing;
+
+        match state {
+            NetGameState::Error(_) => {
+                if !seen.lost {
+                    self.push("Connection lost");
+                }
+                seen.lost = true;
+            }
+            NetGameState::Lobby(_) | NetGameState::Active(_) | NetGameState::GameOver(_) => {
+                if seen.lost {
+                    self.push("Connection restored");
+                }
+                seen.lost = false;
+            }
+            NetGameState::Connecting => (),
+        }
+
+        self.seen = Some(seen);
+    }
+}
+
+/// Describes the given settings in a way that only changes when they really do
+fn describe_settings(settings: &BoardSettings) -> String {
+    let settings = BoardSettings {
+        version: 0,
+        ..settings.clone()
+    };
+    serde_json::to_string(&settings).unwrap_or_default()
+}
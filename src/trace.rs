@@ -0,0 +1,225 @@
+//! Recording local input, so a bug report can come with a way to make the bug happen again
+//!
+//! Load the game with `?record` to record, and the trace ends up in the diagnostic report.
+//! Load it with `?replay` and paste a trace in to play it back, after resizing the window to match.
+
+use std::sync::Mutex;
+
+use rand::distributions::Standard;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::PlayerID;
+
+/// One bit of input, with positions in CSS pixels from the top left of the window
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum TracedInput {
+    /// Click, or right click if the button is 2
+    Click(i32, i32, i16),
+    /// Mouse button going down
+    MouseDown(i32, i32, i16),
+    /// Mouse moving
+    MouseMove(i32, i32),
+    /// Key going down, by code and key
+    Key(String, String),
+}
+
+/// Everything needed to play a session back
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trace {
+    /// Seed the host's board generators are made from
+    pub seed: u64,
+    /// ID the local player started with
+    pub player_id: Option<PlayerID>,
+    /// Window size when recording started, in CSS pixels
+    pub window: (f64, f64),
+    /// Inputs, and the tick each happened on
+    pub inputs: Vec<(u32, TracedInput)>,
+}
+
+#[derive(PartialEq)]
+enum Mode {
+    Recording,
+    Replaying,
+}
+
+struct Tracer {
+    mode: Mode,
+    trace: Trace,
+    /// Ticks so far
+    tick: u32,
+    /// Inputs played back so far, when replaying
+    played: usize,
+    /// Random generators handed out so far, so each one is different but the same every time
+    rngs_made: u64,
+}
+
+lazy_static! {
+    static ref TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+}
+
+fn window_size() -> (f64, f64) {
+    let window = web_sys::window().unwrap_throw();
+    let width = window.inner_width().unwrap_throw().as_f64().unwrap_or_default();
+    let height = window.inner_height().unwrap_throw().as_f64().unwrap_or_default();
+    (width, height)
+}
+
+/// Starts recording or replaying if the page was loaded with `?record` or `?replay`
+pub fn start() {
+    let window = web_sys::window().unwrap_throw();
+    let search = window.location().search().unwrap_throw();
+    let (mode, trace) = match search.as_str() {
+        "?record" => {
+            let trace = Trace {
+                seed: rand::random(),
+                player_id: None,
+                window: window_size(),
+                inputs: vec![],
+            };
+            (Mode::Recording, trace)
+        }
+        "?replay" => {
+            let pasted = window.prompt_with_message("Paste an input trace to replay").ok().flatten();
+            let trace = match pasted.map(|pasted| serde_json::from_str::<Trace>(&pasted)) {
+                Some(Ok(trace)) => trace,
+                Some(Err(err)) => {
+                    crate::log(&format!("Couldn't read input trace: {}", err));
+                    return;
+                }
+                None => return,
+            };
+            if trace.window != window_size() {
+                crate::log(&format!(
+                    "Trace was recorded at {}x{}, so clicks may land somewhere else",
+                    trace.window.0,
+                    trace.window.1
+                ));
+            }
+            (Mode::Replaying, trace)
+        }
+        _ => return,
+    };
+    *TRACER.lock().unwrap() = Some(Tracer {
+        mode,
+        trace,
+        tick: 0,
+        played: 0,
+        rngs_made: 0,
+    });
+}
+
+/// Gets the local player's ID, which is the traced one when replaying and the given one otherwise
+pub fn player_id(id: PlayerID) -> PlayerID {
+    match *TRACER.lock().unwrap() {
+        Some(ref mut tracer) => *tracer.trace.player_id.get_or_insert(id),
+        None => id,
+    }
+}
+
+/// Gets a random generator that comes out the same every time the trace plays, if there's a trace
+pub fn rng() -> Option<StdRng> {
+    let mut tracer = TRACER.lock().unwrap();
+    let tracer = tracer.as_mut()?;
+    let seed = tracer.trace.seed.wrapping_add(tracer.rngs_made);
+    tracer.rngs_made += 1;
+    Some(StdRng::seed_from_u64(seed))
+}
+
+/// Picks something at random, which comes out the same every time the trace plays if there's a trace
+pub fn random<T>() -> T
+where
+    Standard: Distribution<T>,
+{
+    match rng() {
+        Some(mut rng) => rng.gen(),
+        None => rand::random(),
+    }
+}
+
+/// Records the given mouse event, if recording
+pub fn record_mouse(event: &web_sys::MouseEvent) {
+    let (x, y, button) = (event.client_x(), event.client_y(), event.button());
+    let input = match event.type_().as_str() {
+        "click" | "contextmenu" => TracedInput::Click(x, y, button),
+        "mousedown" => TracedInput::MouseDown(x, y, button),
+        "mousemove" => TracedInput::MouseMove(x, y),
+        _ => return,
+    };
+    record(input);
+}
+
+/// Records the given key event, if recording
+pub fn record_key(event: &web_sys::KeyboardEvent) {
+    record(TracedInput::Key(event.code(), event.key()));
+}
+
+fn record(input: TracedInput) {
+    if let Some(ref mut tracer) = *TRACER.lock().unwrap() {
+        if tracer.mode == Mode::Recording {
+            tracer.trace.inputs.push((tracer.tick, input));
+        }
+    }
+}
+
+/// Moves on to the next tick, giving back whatever inputs should be played during it
+pub fn tick() -> Vec<TracedInput> {
+    let mut tracer = TRACER.lock().unwrap();
+    let tracer = match *tracer {
+        Some(ref mut tracer) => tracer,
+        None => return vec![],
+    };
+    tracer.tick += 1;
+    if tracer.mode != Mode::Replaying {
+        return vec![];
+    }
+    let inputs = &tracer.trace.inputs[tracer.played..];
+    let due: Vec<TracedInput> = inputs
+        .iter()
+        .take_while(|(tick, _)| *tick <= tracer.tick)
+        .map(|(_, input)| input.clone())
+        .collect();
+    if !due.is_empty() {
+        tracer.played += due.len();
+        if tracer.played == tracer.trace.inputs.len() {
+            crate::log("Finished replaying input trace");
+        }
+    }
+    due
+}
+
+/// Plays the given input back, by firing it at whatever's at its position or at the given element
+/// for keys, so it goes through the same listeners a real one would
+pub fn dispatch(input: &TracedInput, main: &web_sys::Element) {
+    let document = main.owner_document().unwrap_throw();
+    let mouse = |event_type: &str, x: i32, y: i32, button: i16| {
+        let mut init = web_sys::MouseEventInit::new();
+        init.bubbles(true).cancelable(true).client_x(x).client_y(y).button(button);
+        let event = web_sys::MouseEvent::new_with_mouse_event_init_dict(event_type, &init).unwrap_throw();
+        if let Some(target) = document.element_from_point(x as f32, y as f32) {
+            target.dispatch_event(&event).unwrap_throw();
+        }
+    };
+    match *input {
+        TracedInput::Click(x, y, 2) => mouse("contextmenu", x, y, 2),
+        TracedInput::Click(x, y, button) => mouse("click", x, y, button),
+        TracedInput::MouseDown(x, y, button) => mouse("mousedown", x, y, button),
+        TracedInput::MouseMove(x, y) => mouse("mousemove", x, y, 0),
+        TracedInput::Key(ref code, ref key) => {
+            let mut init = web_sys::KeyboardEventInit::new();
+            init.bubbles(true).cancelable(true).code(code).key(key);
+            let event = web_sys::KeyboardEvent::new_with_keyboard_event_init_dict("keydown", &init).unwrap_throw();
+            main.dispatch_event(&event).unwrap_throw();
+        }
+    }
+}
+
+/// Gets the trace recorded so far, compactly, if recording
+pub fn export() -> Option<String> {
+    let tracer = TRACER.try_lock().ok()?;
+    match *tracer {
+        Some(ref tracer) if tracer.mode == Mode::Recording => serde_json::to_string(&tracer.trace).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,121 @@
+//! Counts of what's going over a game connection, for the debug overlay and for warning when
+//! whole-state broadcasts are eating up the connection
+
+use std::collections::VecDeque;
+
+/// Seconds of traffic rates are worked out over
+const WINDOW: f64 = 60.0;
+
+/// Bytes a whole-state broadcast has to take to count as large
+const LARGE_STATE: usize = 16 * 1024;
+
+/// Large whole-state broadcasts within the window it takes to warn about them
+const FREQUENT_STATES: usize = 12;
+
+/// How much went over the connection in the last minute
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrafficRates {
+    /// Bytes sent
+    pub sent: usize,
+    /// Bytes received
+    pub received: usize,
+    /// Whole-state broadcasts, either way
+    pub states: usize,
+    /// Bytes those whole-state broadcasts took
+    pub state_bytes: usize,
+}
+
+impl TrafficRates {
+    /// Describes these rates in a line, for the debug overlay
+    pub fn describe(&self) -> String {
+        let average = self.state_bytes.checked_div(self.states).unwrap_or(0);
+        format!(
+            "Last minute: {} sent, {} received, {} whole states averaging {}",
+            kilobytes(self.sent),
+            kilobytes(self.received),
+            self.states,
+            kilobytes(average),
+        )
+    }
+}
+
+fn kilobytes(bytes: usize) -> String {
+    format!("{:.1} KB", bytes as f64 / 1024.0)
+}
+
+/// Sizes of recent frames and whole-state broadcasts, each with when they went by
+#[derive(Default)]
+pub struct TrafficMeter {
+    sent: VecDeque<(f64, usize)>,
+    received: VecDeque<(f64, usize)>,
+    states: VecDeque<(f64, usize)>,
+    /// Whether the warning's gone up since broadcasts got heavy, so it only goes up once each time
+    warned: bool,
+}
+
+impl TrafficMeter {
+    /// Counts a frame sent to the server
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.sent.push_back((crate::now(), bytes));
+        self.forget_old();
+    }
+
+    /// Counts a frame received from the server
+    pub fn record_received(&mut self, bytes: usize) {
+        self.received.push_back((crate::now(), bytes));
+        self.forget_old();
+    }
+
+    /// Counts a whole-state broadcast, whichever way it went, on top of its frame
+    pub fn record_state(&mut self, bytes: usize) {
+        self.states.push_back((crate::now(), bytes));
+        self.forget_old();
+    }
+
+    fn forget_old(&mut self) {
+        let cutoff = crate::now() - WINDOW;
+        for entries in &mut [&mut self.sent, &mut self.received, &mut self.states] {
+            while matches!(entries.front(), Some(&(time, _)) if time < cutoff) {
+                entries.pop_front();
+            }
+        }
+    }
+
+    /// Gets how much went over the connection in the last minute
+    pub fn rates(&mut self) -> TrafficRates {
+        self.forget_old();
+        let total = |entries: &VecDeque<(f64, usize)>| entries.iter().map(|&(_, bytes)| bytes).sum();
+        TrafficRates {
+            sent: total(&self.sent),
+            received: total(&self.received),
+            states: self.states.len(),
+            state_bytes: total(&self.states),
+        }
+    }
+
+    /// Gets a warning if whole-state broadcasts have been large and frequent lately, once each time
+    /// they get that way
+    pub fn take_warning(&mut self) -> Option<String> {
+        self.forget_old();
+        let large: Vec<usize> = self
+            .states
+            .iter()
+            .map(|&(_, bytes)| bytes)
+            .filter(|&bytes| bytes >= LARGE_STATE)
+            .collect();
+        if large.len() < FREQUENT_STATES {
+            self.warned = false;
+            return None;
+        }
+        if self.warned {
+            return None;
+        }
+        self.warned = true;
+        let average = large.iter().sum::<usize>() / large.len();
+        Some(format!(
+            "Heavy traffic: {} whole-game updates of about {} each in the last minute",
+            large.len(),
+            kilobytes(average),
+        ))
+    }
+}
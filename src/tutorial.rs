@@ -4,16 +4,35 @@ use std::sync::{Arc, RwLock};
 use serde::{Deserialize, Serialize};
 
 use crate::{Board, Direction, Player, PlayerID};
+use crate::board::Scoring;
 use crate::board_controller::{BoardController, BoardSettings};
 use crate::colors;
 use crate::menu::{ConnectedState, NetGameState};
 use crate::net;
 
-pub fn new_conn_state(player_id: PlayerID) -> ConnectedState {
+pub fn new_conn_state(player_id: PlayerID, step: TutorialStep) -> ConnectedState {
     let settings = BoardSettings {
         score_limit: 1,
         width: 3,
         height: 3,
+        turn_time_limit: 0,
+        wrap_tokens: true,
+        seed: None,
+        min_target_distance: 0,
+        max_target_distance: 0,
+        hint_limit: 3,
+        sandbox: false,
+        series_length: 1,
+        collapse_every: 0,
+        fog_distance: 0,
+        party: false,
+        theme: None,
+        scoring: Scoring::Targets,
+        target_steal: false,
+        double_push: false,
+        portals: false,
+        speed_bonus: 0,
+        energy_per_turn: 0,
         version: 0,
     };
     let players = vec![Player::new(
@@ -22,14 +41,17 @@ pub fn new_conn_state(player_id: PlayerID) -> ConnectedState {
         player_id,
     )];
     let mut board = BoardController::new(settings, players, player_id);
-    TutorialStep::First.apply(&mut board.board);
+    // the tutorial plays out the same whatever events are on
+    board.events.clear();
+    step.apply(&mut board.board);
+    board.restart_log();
     let state = NetGameState::Active(board);
     let state = Arc::new(RwLock::new(state));
-    let sender = net::NetHandler::run_fake();
+    let sender = net::NetHandler::run_fake(player_id);
     ConnectedState { sender, state }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TutorialStep {
     /// Basic game structure (ideally, first move)
     First,
@@ -44,16 +66,16 @@ impl TutorialStep {
     pub fn apply(&self, board: &mut Board) {
         match *self {
             TutorialStep::First => {
-                board.cells = Board::parse_board(
+                board.set_rows(Board::parse_board(
                     r"
                     ───│───
                 ",
-                );
+                ));
                 board.loose_tile = '│'.try_into().unwrap();
                 board.loose_tile_position = (Direction::North, 1);
                 let players = board.player_tokens.keys().collect::<Vec<_>>();
                 let my_id = *players[0];
-                board.cells[0][6].whose_target = Some(my_id);
+                board.get_mut([6, 0]).whose_target = Some(my_id);
                 if let Some(token) = board.player_tokens.get_mut(&my_id) {
                     token.position = (0, 0);
                     token.score = 0;
@@ -61,7 +83,7 @@ impl TutorialStep {
                 board.tutorial_step = Some(TutorialStep::First);
             }
             TutorialStep::Second => {
-                board.cells = Board::parse_board(
+                board.set_rows(Board::parse_board(
                     r"
                     ┘┘┘┘┘┘┘
                     ┘┘┘┘┘┘┘
@@ -71,7 +93,7 @@ impl TutorialStep {
                     ┘┘┘┘┘┘┘
                     ┘┘┘┘┘┘┘
                 ",
-                );
+                ));
                 board.loose_tile = '┌'.try_into().unwrap();
                 board.loose_tile_position = (Direction::East, 2);
                 let players = board.player_tokens.keys().collect::<Vec<_>>();
@@ -84,7 +106,7 @@ impl TutorialStep {
                 board.tutorial_step = Some(TutorialStep::Second);
             }
             TutorialStep::Third => {
-                board.cells = Board::parse_board(
+                board.set_rows(Board::parse_board(
                     r"
                     ┌────┘┘
                     └─┐┘┘┘┘
@@ -94,12 +116,12 @@ impl TutorialStep {
                     ┘┘┘┘┘┘┘
                     ┘┘┘┘┘┘┘
                 ",
-                );
+                ));
                 board.loose_tile = '─'.try_into().unwrap();
                 board.loose_tile_position = (Direction::North, 2);
                 let players = board.player_tokens.keys().collect::<Vec<_>>();
                 let my_id = *players[0];
-                board.cells[2][2].whose_target = Some(my_id);
+                board.get_mut([2, 2]).whose_target = Some(my_id);
                 if let Some(token) = board.player_tokens.get_mut(&my_id) {
                     token.position = (6, 5);
                     token.score = 0;
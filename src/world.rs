@@ -0,0 +1,147 @@
+//! Boards linked by portal tiles, for portal mode
+
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{Board, PlayerID};
+use crate::board::TargetDistance;
+use crate::bot;
+
+/// Layers in a portal mode game
+pub const LAYERS: usize = 2;
+
+/// Tiles on a layer for every portal on it
+const TILES_PER_PORTAL: usize = 12;
+
+/// Every layer of a portal mode game but the one being played on
+///
+/// The board being played on stays out of here, so everything else can carry on treating it as
+/// the board, and it trades places with whichever layer the active player's token is on when their
+/// turn starts. Tokens all go along with the board being played on, whatever layer they're on,
+/// so the layers set aside never have any.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct World {
+    /// Layers set aside, in no particular order
+    pub layers: Vec<Board>,
+}
+
+impl World {
+    /// Builds the rest of the layers for a game on the given board, and scatters portals over all of them
+    pub fn new(board: &mut Board, distance: TargetDistance, rng: &mut impl Rng) -> World {
+        let portals = (board.cells.len() / TILES_PER_PORTAL).max(1);
+        board.place_portals(portals, rng);
+        let layers = (1..LAYERS)
+            .map(|index| {
+                let mut layer = Board::new(board.width(), board.height(), &BTreeMap::new(), distance, rng);
+                layer.layer = index;
+                layer.scoring = board.scoring;
+                layer.target_steal = board.target_steal;
+                layer.energy_per_turn = board.energy_per_turn;
+                layer.place_portals(portals, rng);
+                layer
+            })
+            .collect();
+        World { layers }
+    }
+
+    /// Gets how many layers there are, counting the one being played on
+    pub fn layer_count(&self) -> usize {
+        self.layers.len() + 1
+    }
+
+    /// Gets the layer that portals on the given layer lead to
+    pub fn next_layer(&self, layer: usize) -> usize {
+        (layer + 1) % self.layer_count()
+    }
+
+    fn layer_mut(&mut self, index: usize) -> Option<&mut Board> {
+        self.layers.iter_mut().find(|layer| layer.layer == index)
+    }
+
+    /// Brings the given layer into play, setting aside the board that was being played on,
+    /// returns whether anything changed
+    pub fn bring_into_play(&mut self, board: &mut Board, index: usize) -> bool {
+        let layer = match self.layer_mut(index) {
+            Some(layer) => layer,
+            // it's already in play
+            None => return false,
+        };
+        // the tokens go along with whichever board is being played on
+        std::mem::swap(&mut board.player_tokens, &mut layer.player_tokens);
+        std::mem::swap(board, layer);
+        true
+    }
+
+    /// Sends the given player through the portal their token is standing on, if it is,
+    /// returns whether it went through
+    pub fn take_portal(&self, board: &mut Board, id: PlayerID) -> bool {
+        let (row, col) = board.player_pos(id);
+        if !board.get([col, row]).portal {
+            return false;
+        }
+        let next = self.next_layer(board.layer);
+        match board.player_tokens.get_mut(&id) {
+            Some(token) => {
+                token.layer = next;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the given player's target off the board being played on and onto the layer after
+    /// the one their token is on, as near the same spot as it'll go, and gives back where it ended up
+    pub fn send_target(&mut self, board: &mut Board, id: PlayerID) -> Option<(usize, usize)> {
+        let pos = board.target_pos(id)?;
+        let index = self.next_layer(board.player_tokens.get(&id)?.layer);
+        let layer = match self.layer_mut(index) {
+            Some(layer) => layer,
+            // it's already on the right layer
+            None => return Some(pos),
+        };
+        // ties go to the lowest (row, col), so every client puts it in the same place
+        let spot = (0..layer.height())
+            .flat_map(|row| (0..layer.width()).map(move |col| (row, col)))
+            .filter(|&(row, col)| layer.get([col, row]).whose_target.is_none())
+            .min_by_key(|&spot| (bot::distance(spot, pos), spot));
+        let spot = match spot {
+            Some(spot) => spot,
+            // every tile over there is someone's target, so it'll have to stay where it is
+            None => return Some(pos),
+        };
+        layer.place_target(id, spot);
+        let (row, col) = pos;
+        board.get_mut([col, row]).whose_target = None;
+        board.value_target(id, spot);
+        Some(spot)
+    }
+
+    /// Places the given player's target at the given (row, col) on the given layer,
+    /// whether that's the board being played on or one set aside
+    pub fn place_target(&mut self, board: &mut Board, id: PlayerID, index: usize, pos: (usize, usize)) {
+        match self.layer_mut(index) {
+            Some(layer) => {
+                // the token's over on the board being played on, so that's where the value gets worked out
+                board.value_target(id, pos);
+                layer.place_target(id, pos);
+            }
+            None => board.place_target(id, pos),
+        }
+    }
+
+    /// Unlocks the given player's target on the layers set aside, as their turn ends
+    pub fn finish_turn(&mut self, id: PlayerID) {
+        for layer in &mut self.layers {
+            layer.finish_turn(id, None);
+        }
+    }
+
+    /// Takes the given player's target off the layers set aside
+    pub fn remove_player(&mut self, id: PlayerID) {
+        for layer in &mut self.layers {
+            layer.remove_player(id);
+        }
+    }
+}
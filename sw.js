@@ -0,0 +1,44 @@
+// Service worker for offline play: cache the game shell, fall back to the network for anything else
+var CACHE = 'dynamaze-v1';
+var ASSETS = [
+    './',
+    'index.html',
+    'manifest.webmanifest',
+    'pkg/dynamaze.js',
+    'pkg/dynamaze_bg.wasm',
+    'assets/BlueEther.mp3',
+    'assets/ElectricSweater.mp3',
+    'assets/TurnPing.wav',
+];
+
+self.addEventListener('install', function (event) {
+    event.waitUntil(caches.open(CACHE).then(function (cache) {
+        return cache.addAll(ASSETS);
+    }));
+});
+
+self.addEventListener('activate', function (event) {
+    event.waitUntil(caches.keys().then(function (keys) {
+        return Promise.all(keys.filter(function (key) {
+            return key !== CACHE;
+        }).map(function (key) {
+            return caches.delete(key);
+        }));
+    }));
+});
+
+self.addEventListener('fetch', function (event) {
+    if (event.request.method !== 'GET') {
+        return;
+    }
+    // prefer fresh copies so deploys show up, but serve the cache when the network is gone
+    event.respondWith(fetch(event.request).then(function (response) {
+        var copy = response.clone();
+        caches.open(CACHE).then(function (cache) {
+            cache.put(event.request, copy);
+        });
+        return response;
+    }).catch(function () {
+        return caches.match(event.request);
+    }));
+});